@@ -20,13 +20,19 @@ pub fn includes() -> Vec<PathBuf> {
 }
 
 pub fn build() {
+    build_with(cc::Build::new());
+}
+
+/// Build bento4 starting from a preconfigured [cc::Build](cc::Build),
+/// e.g. with the compiler and archiver of a cross compilation toolchain.
+pub fn build_with(mut build: cc::Build) {
     let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     println!(
         "cargo:rerun-if-changed={}",
         root.join("Bento4").to_string_lossy()
     );
 
-    cc::Build::new()
+    build
         .cpp(true)
         .warnings(false)
         .extra_warnings(false)
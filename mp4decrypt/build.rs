@@ -1,24 +1,34 @@
-use std::{env, path::Path, process};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
 
 fn main() {
     println!("cargo:rerun-if-changed=src/mp4decrypt.h");
     println!("cargo:rerun-if-changed=src/mp4decrypt.cpp");
 
-    let target = env::var("TARGET")
-        .expect("TARGET env variable not set by cargo?")
-        .replace('-', "_")
-        .to_uppercase();
+    let target_triple = env::var("TARGET").expect("TARGET env variable not set by cargo?");
+    let target = target_triple.replace('-', "_").to_uppercase();
 
     let target_bento4_dir_env = target.clone() + "_BENTO4_DIR";
     let bento4_dir_env = "BENTO4_DIR";
     let target_bento4_vendor_env = target + "_BENTO4_VENDOR";
     let bento4_vendor_env = "BENTO4_VENDOR";
 
+    println!("cargo:rerun-if-env-changed={}", target_bento4_dir_env);
     println!("cargo:rerun-if-env-changed={}", bento4_dir_env);
-    println!("cargo:rerun-if-env-changed={}", bento4_dir_env);
-    println!("cargo:rerun-if-env-changed={}", bento4_vendor_env);
+    println!("cargo:rerun-if-env-changed={}", target_bento4_vendor_env);
     println!("cargo:rerun-if-env-changed={}", bento4_vendor_env);
 
+    println!("cargo:rustc-env=MP4DECRYPT_TARGET={}", target_triple);
+
+    let mut build = cross_build(&target_triple);
+
+    if env::var_os("CARGO_FEATURE_NO_THREADS").is_some() {
+        single_threaded(&mut build);
+    }
+
     let includes;
 
     if let (Ok(bento4_dir), Err(_)) = (
@@ -37,6 +47,9 @@ fn main() {
             process::exit(1);
         }
 
+        println!("cargo:rustc-env=MP4DECRYPT_BENTO4_LINKAGE=system");
+        println!("cargo:rustc-env=MP4DECRYPT_BENTO4_DIR={}", bento4_dir);
+
         let bento4_lib = Path::new(&bento4_dir).join("lib");
 
         if bento4_lib.exists() {
@@ -54,12 +67,22 @@ fn main() {
         }
     } else {
         println!("Building Bento4 v{}", bento4_src::version());
-        bento4_src::build();
+        println!("cargo:rustc-env=MP4DECRYPT_BENTO4_LINKAGE=vendored");
+        println!(
+            "cargo:rustc-env=MP4DECRYPT_BENTO4_VERSION={}",
+            bento4_src::version()
+        );
+        bento4_src::build_with(build.clone());
         includes = bento4_src::includes();
     }
 
+    println!(
+        "cargo:rustc-env=MP4DECRYPT_BENTO4_SCHEMES={}",
+        protection_schemes(&includes).join(",")
+    );
+
     println!("Building mp4decrypt wrapper");
-    bento4_src::cc::Build::new()
+    build
         .cpp(true)
         .warnings(false)
         .extra_warnings(false)
@@ -74,3 +97,136 @@ fn main() {
     //     .write_to_file("bindings.rs")
     //     .unwrap();
 }
+
+/// Base build configuration for the target.
+///
+/// Android toolchains are found from `ANDROID_NDK_HOME` (or `ANDROID_NDK_ROOT`, `NDK_HOME`),
+/// targeting the API level from `ANDROID_PLATFORM` (default 21), unless `CXX` is set for the target.
+/// iOS SDKs are found by `cc` itself using `xcrun` (or `SDKROOT`).
+fn cross_build(target: &str) -> bento4_src::cc::Build {
+    let mut build = bento4_src::cc::Build::new();
+
+    if !target.contains("android") || has_target_env("CXX", target) {
+        return build;
+    }
+
+    for var in [
+        "ANDROID_NDK_HOME",
+        "ANDROID_NDK_ROOT",
+        "NDK_HOME",
+        "ANDROID_PLATFORM",
+    ] {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+
+    let Some(ndk) = ["ANDROID_NDK_HOME", "ANDROID_NDK_ROOT", "NDK_HOME"]
+        .iter()
+        .find_map(env::var_os)
+    else {
+        return build;
+    };
+
+    let host_tag = match env::consts::OS {
+        "linux" => "linux-x86_64",
+        "macos" => "darwin-x86_64",
+        "windows" => "windows-x86_64",
+        x => {
+            println!("cargo:warning=android ndk is not available for {} host", x);
+            return build;
+        }
+    };
+
+    let bin = PathBuf::from(ndk)
+        .join("toolchains/llvm/prebuilt")
+        .join(host_tag)
+        .join("bin");
+    let api_level = env::var("ANDROID_PLATFORM")
+        .map(|x| x.trim_start_matches("android-").to_owned())
+        .unwrap_or_else(|_| "21".to_owned());
+    let clang_target = match target {
+        "armv7-linux-androideabi" | "thumbv7neon-linux-androideabi" => "armv7a-linux-androideabi",
+        x => x,
+    };
+    let suffix = if env::consts::OS == "windows" {
+        ".cmd"
+    } else {
+        ""
+    };
+
+    build
+        .compiler(bin.join(format!("{}{}-clang++{}", clang_target, api_level, suffix)))
+        .archiver(bin.join(format!("llvm-ar{}", env::consts::EXE_SUFFIX)));
+    build
+}
+
+/// Configure the build for targets without threads (e.g. `wasm32-wasip1` or `wasm32-unknown-emscripten`),
+/// enabled by the `no-threads` feature. Only the vendored Bento4 and the wrapper are affected.
+///
+/// Bento4 never starts threads or takes locks, its only shared state being the static
+/// `AP4_DefaultAtomFactory::Instance_`, so none of its sources have to be left out. Threads are assumed by
+/// the C++ runtime instead: libc++ headers pull in `<pthread.h>` unless it is configured without threads,
+/// and function-local statics are guarded with `__cxa_guard_acquire`, which needs atomics.
+fn single_threaded(build: &mut bento4_src::cc::Build) {
+    build
+        .define("_LIBCPP_HAS_NO_THREADS", None)
+        .flag_if_supported("-fno-threadsafe-statics");
+}
+
+/// Scheme types declared as `AP4_PROTECTION_SCHEME_TYPE_*` constants by the Bento4 headers,
+/// e.g. `const AP4_UI32 AP4_PROTECTION_SCHEME_TYPE_CENC = AP4_ATOM_TYPE('c','e','n','c');`.
+fn protection_schemes(includes: &[PathBuf]) -> Vec<String> {
+    let mut schemes = Vec::new();
+
+    for entry in includes
+        .iter()
+        .filter_map(|x| fs::read_dir(x).ok())
+        .flatten()
+        .flatten()
+    {
+        let path = entry.path();
+
+        if path.extension().is_none_or(|x| x != "h") {
+            continue;
+        }
+
+        let Ok(header) = fs::read_to_string(&path) else {
+            continue;
+        };
+
+        for line in header.lines() {
+            let Some((_, args)) = line
+                .trim()
+                .strip_prefix("const AP4_UI32 AP4_PROTECTION_SCHEME_TYPE_")
+                .and_then(|x| x.split_once("AP4_ATOM_TYPE("))
+            else {
+                continue;
+            };
+
+            let fourcc = args
+                .split(')')
+                .next()
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|x| x.trim().strip_prefix('\'')?.strip_suffix('\''))
+                .collect::<String>();
+
+            if fourcc.len() == 4 && !schemes.contains(&fourcc) {
+                schemes.push(fourcc);
+            }
+        }
+    }
+
+    schemes.sort();
+    schemes
+}
+
+fn has_target_env(var: &str, target: &str) -> bool {
+    [
+        format!("{}_{}", var, target),
+        format!("{}_{}", var, target.replace('-', "_")),
+        format!("TARGET_{}", var),
+        var.to_owned(),
+    ]
+    .iter()
+    .any(|x| env::var_os(x).is_some())
+}
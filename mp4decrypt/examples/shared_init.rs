@@ -0,0 +1,32 @@
+use mp4decrypt::{KeyMap, SegmentDecryptor};
+use std::{fs, path::PathBuf, sync::Arc, thread};
+
+fn main() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+
+    // a single copy of the init segment, shared by every context
+    let init: Arc<[u8]> = fs::read(root.join("examples/sample/init.mp4"))
+        .unwrap()
+        .into();
+    let segment = fs::read(root.join("examples/sample/segment_0.m4s")).unwrap();
+
+    let mut keys = KeyMap::new();
+    keys.insert(
+        "eb676abbcb345e96bbcf616630f1a3da",
+        "100b6c20940f779a4589152b57d2dacb",
+    )
+    .unwrap();
+
+    thread::scope(|scope| {
+        for i in 0..4 {
+            let init = init.clone();
+            let (keys, segment) = (&keys, &segment);
+
+            scope.spawn(move || {
+                let mut decryptor = SegmentDecryptor::with_shared_init(init, keys).unwrap();
+                let decrypted_data = decryptor.decrypt_next(segment).unwrap();
+                println!("context {} decrypted {} bytes", i, decrypted_data.len());
+            });
+        }
+    });
+}
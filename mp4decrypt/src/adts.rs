@@ -0,0 +1,275 @@
+use crate::{
+    Error, KeyMap,
+    boxes::{self, Mp4Box},
+    decrypt, metadata, moof,
+    reader::{BitReader, Reader},
+};
+
+/// Decrypt an audio-only stream and return its AAC samples framed with ADTS headers,
+/// which can be played or muxed as a raw `.aac` file.
+///
+/// The first audio track is extracted and it must be an AAC track (LC, Main, SSR or LTP profile,
+/// HE-AAC is written as its LC core) with an explicit channel configuration.
+/// Only fragmented data streams are supported.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream, either a self-contained fragmented file or media segments.
+/// * `init` (optional) - Init segment of `data`, required if `data` doesn't contain a `moov` box.
+/// * `keys` - Keys for decrypting the data stream.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("audio_init.mp4").unwrap();
+/// let segment = std::fs::read("audio_0.m4s").unwrap();
+/// let aac = mp4decrypt::decrypt_audio_to_adts(&segment, Some(&init), &keys).unwrap();
+/// std::fs::write("audio.aac", aac).unwrap();
+/// ```
+pub fn decrypt_audio_to_adts(
+    data: &[u8],
+    init: Option<&[u8]>,
+    keys: &KeyMap,
+) -> Result<Vec<u8>, Error> {
+    let decrypted_data = if let Some(init) = init {
+        let mut decrypted_data = decrypt(init, keys, None)?;
+        decrypted_data.extend(decrypt(data, keys, Some(init))?);
+        decrypted_data
+    } else {
+        decrypt(data, keys, None)?
+    };
+
+    to_adts(&decrypted_data)
+}
+
+/// Frame the samples of the first audio track of a clear fragmented stream with ADTS headers.
+fn to_adts(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let Some(moov) = boxes::find(boxes::boxes(data), b"moov")? else {
+        return Err(Error::new_format("no moov box found in the data stream."));
+    };
+
+    let mut audio = None;
+
+    for trak in moov.children() {
+        let trak = trak?;
+
+        if &trak.box_type == b"trak" {
+            let track = metadata::read_trak(&trak)?;
+
+            if &track.handler == b"soun" {
+                audio = Some((track, trak));
+                break;
+            }
+        }
+    }
+
+    let Some((track, trak)) = audio else {
+        return Err(Error::new_format(
+            "no audio track found in the data stream.",
+        ));
+    };
+
+    let config = read_audio_config(&trak)?;
+    let trex_sizes = moof::read_trex_sizes(data)?;
+    let mut adts = Vec::new();
+    let mut fragmented = false;
+
+    for moof in boxes::boxes(data) {
+        let moof = moof?;
+
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+
+        fragmented = true;
+
+        for fragment in moof::read_track_fragments(&moof, &trex_sizes)? {
+            if fragment.track_id != track.track_id {
+                continue;
+            }
+
+            for sample in fragment.runs.iter().flat_map(|x| &x.samples) {
+                let sample = data
+                    .get(sample.offset as usize..(sample.offset + sample.size as u64) as usize)
+                    .ok_or_else(|| {
+                        Error::new_format(format!(
+                            "sample at offset {} of track {} is out of bounds.",
+                            sample.offset, track.track_id
+                        ))
+                    })?;
+
+                adts.extend(config.header(sample.len())?);
+                adts.extend(sample);
+            }
+        }
+    }
+
+    if !fragmented {
+        return Err(Error::new_format(
+            "only fragmented data streams can be converted to adts.",
+        ));
+    }
+
+    Ok(adts)
+}
+
+/// Fields of an AAC `AudioSpecificConfig` which are carried in ADTS headers.
+pub(crate) struct AudioConfig {
+    object_type: u8,
+    sampling_frequency_index: u8,
+    pub(crate) channel_configuration: u8,
+}
+
+impl AudioConfig {
+    /// ADTS header without CRC for a frame of `size` bytes.
+    fn header(&self, size: usize) -> Result<[u8; 7], Error> {
+        let length = size + 7;
+
+        if length > 0x1fff {
+            return Err(Error::new_format(format!(
+                "aac frame of {} bytes is too large for adts.",
+                size
+            )));
+        }
+
+        Ok([
+            0xff,
+            0xf1, // mpeg-4, layer 0, protection absent
+            ((self.object_type - 1) << 6)
+                | (self.sampling_frequency_index << 2)
+                | (self.channel_configuration >> 2),
+            ((self.channel_configuration & 0x3) << 6) | (length >> 11) as u8,
+            (length >> 3) as u8,
+            ((length & 0x7) << 5) as u8 | 0x1f, // buffer fullness 0x7ff
+            0xfc,
+        ])
+    }
+}
+
+/// Read and validate the AAC config from the `esds` box of a decrypted `mp4a` sample entry.
+pub(crate) fn read_audio_config(trak: &Mp4Box) -> Result<AudioConfig, Error> {
+    let stsd = [b"mdia", b"minf", b"stbl", b"stsd"]
+        .iter()
+        .try_fold(*trak, |parent, x| {
+            parent.child(x)?.ok_or_else(|| {
+                Error::new_format(format!("audio track has no {} box.", boxes::fourcc(x)))
+            })
+        })?;
+
+    let entry = stsd.children_after(8).next().transpose()?; // version, flags, entry_count
+
+    let Some(entry) = entry.filter(|x| &x.box_type == b"mp4a") else {
+        return Err(Error::new_format("audio track is not an aac (mp4a) track."));
+    };
+
+    let Some(esds) = boxes::find(entry.children_after(28), b"esds")? else {
+        return Err(Error::new_format("mp4a sample entry has no esds box."));
+    };
+
+    let mut reader = Reader::new(esds.payload());
+    reader.read_version_flags()?;
+
+    // ES_Descriptor
+    let mut reader = read_descriptor(&mut reader, 0x03)?;
+    reader.skip(2)?; // ES_ID
+    let flags = reader.read_u8()?;
+    if flags & 0x80 != 0 {
+        reader.skip(2)?; // dependsOn_ES_ID
+    }
+    if flags & 0x40 != 0 {
+        let url_length = reader.read_u8()?;
+        reader.skip(url_length as usize)?;
+    }
+    if flags & 0x20 != 0 {
+        reader.skip(2)?; // OCR_ES_Id
+    }
+
+    // DecoderConfigDescriptor
+    let mut reader = read_descriptor(&mut reader, 0x04)?;
+    let object_type_indication = reader.read_u8()?;
+
+    if !matches!(object_type_indication, 0x40 | 0x66 | 0x67 | 0x68) {
+        return Err(Error::new_format(format!(
+            "audio track is not an aac track (object type indication 0x{:02x}).",
+            object_type_indication
+        )));
+    }
+
+    reader.skip(12)?; // streamType, upStream, bufferSizeDB, maxBitrate, avgBitrate
+
+    // DecoderSpecificInfo
+    let mut reader = read_descriptor(&mut reader, 0x05)?;
+    let mut bits = BitReader::new(reader.read_bytes(reader.remaining())?);
+    let mut object_type = read_object_type(&mut bits)?;
+    let sampling_frequency_index = bits.read(4)? as u8;
+    if sampling_frequency_index == 0xf {
+        return Err(Error::new_format(
+            "aac track has an explicit sampling frequency which adts can't signal.",
+        ));
+    }
+    let channel_configuration = bits.read(4)? as u8;
+
+    // SBR and PS are signalled implicitly in adts.
+    if object_type == 5 || object_type == 29 {
+        bits.read(4)?; // extensionSamplingFrequencyIndex
+        object_type = read_object_type(&mut bits)?;
+    }
+
+    if !(1..=4).contains(&object_type) {
+        return Err(Error::new_format(format!(
+            "aac object type {} can't be signalled in adts.",
+            object_type
+        )));
+    }
+
+    if channel_configuration == 0 {
+        return Err(Error::new_format(
+            "aac track has no channel configuration which adts can't signal.",
+        ));
+    }
+
+    Ok(AudioConfig {
+        object_type,
+        sampling_frequency_index,
+        channel_configuration,
+    })
+}
+
+/// Read an expandable class descriptor of the given tag and return a reader over its contents.
+fn read_descriptor<'a>(reader: &mut Reader<'a>, tag: u8) -> Result<Reader<'a>, Error> {
+    let found = reader.read_u8()?;
+
+    if found != tag {
+        return Err(Error::new_format(format!(
+            "expected esds descriptor tag 0x{:02x} but found 0x{:02x}.",
+            tag, found
+        )));
+    }
+
+    let mut size = 0;
+
+    for _ in 0..4 {
+        let byte = reader.read_u8()?;
+        size = (size << 7) | (byte & 0x7f) as usize;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    Ok(Reader::new(reader.read_bytes(size)?))
+}
+
+/// Read an `audioObjectType`, including its escape value.
+fn read_object_type(bits: &mut BitReader) -> Result<u8, Error> {
+    match bits.read(5)? {
+        31 => Ok(32 + bits.read(6)? as u8),
+        x => Ok(x as u8),
+    }
+}
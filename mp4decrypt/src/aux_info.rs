@@ -0,0 +1,210 @@
+use crate::{
+    Error,
+    boxes::{self, Mp4Box},
+    metadata::{self, Mp4Metadata},
+    moof,
+    reader::Reader,
+};
+
+/// Sample auxiliary information parsed from the `saiz` and `saio` boxes of a fragment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuxInfo {
+    /// Tracks which have a `saiz` or a `saio` box, in the order of their `traf` (or `trak`) boxes.
+    pub tracks: Vec<TrackAuxInfo>,
+}
+
+/// Sample auxiliary information of a single track.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackAuxInfo {
+    pub track_id: u32,
+    /// Type of the auxiliary information, `None` if it isn't stored in the boxes.
+    /// For encryption info this is the protection scheme (`cenc`, `cbcs`) when present.
+    pub aux_info_type: Option<[u8; 4]>,
+    pub aux_info_type_parameter: Option<u32>,
+    /// Size of the auxiliary information (IV and subsamples) of every sample from the `saiz` box,
+    /// 0 if samples have different sizes which are stored in `sample_info_sizes`.
+    pub default_sample_info_size: u8,
+    pub sample_count: u32,
+    pub sample_info_sizes: Vec<u8>,
+    /// Offsets of the auxiliary information as stored in the `saio` box, one for all samples
+    /// or one for every `trun` box.
+    pub offsets: Vec<u64>,
+    /// Absolute offset in the data stream which `offsets` are relative to, the offset of the `moof` box
+    /// same as Bento4 reads them, or 0 for tracks of a non-fragmented file.
+    pub base_data_offset: u64,
+    /// Size of per sample IVs from the `tenc` box of the track, if it is known.
+    pub iv_size: Option<u8>,
+}
+
+impl TrackAuxInfo {
+    /// Size of the auxiliary information of a sample.
+    pub fn sample_info_size(&self, index: usize) -> Option<u8> {
+        match self.default_sample_info_size {
+            0 => self.sample_info_sizes.get(index).copied(),
+            x => (index < self.sample_count as usize).then_some(x),
+        }
+    }
+
+    /// Absolute offsets of the auxiliary information in the data stream.
+    pub fn absolute_offsets(&self) -> impl Iterator<Item = u64> + '_ {
+        self.offsets.iter().map(|x| self.base_data_offset + x)
+    }
+}
+
+/// Read the sample auxiliary information sizes and offsets from the `saiz` and `saio` boxes
+/// of the first fragment, or from the sample tables of a non-fragmented file,
+/// which is useful for diagnosing where the IVs of encrypted samples are read from.
+///
+/// Returns `None` if no track has a `saiz` or a `saio` box.
+///
+/// # Arguments
+///
+/// * `fragment` - Media segment, fragment or a complete file.
+/// * `init` (optional) - Init segment of `fragment`, used for reading default sample sizes and IV sizes.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let aux_info = mp4decrypt::read_aux_info(&segment, Some(&init)).unwrap().unwrap();
+/// let track = &aux_info.tracks[0];
+/// assert_eq!(track.track_id, 1);
+/// assert_eq!(track.sample_count, 100);
+/// assert_eq!(track.iv_size, Some(8));
+/// // IV, subsample count and a single subsample
+/// assert_eq!(track.sample_info_size(1), Some(8 + 2 + 6));
+///
+/// // IVs are stored in the senc box
+/// let offset = track.absolute_offsets().next().unwrap() as usize;
+/// assert_eq!(&segment[offset - 12..offset - 8], b"senc");
+///
+/// assert!(mp4decrypt::read_aux_info(&init, None).unwrap().is_none());
+/// ```
+pub fn read_aux_info(fragment: &[u8], init: Option<&[u8]>) -> Result<Option<AuxInfo>, Error> {
+    let init = init.unwrap_or(fragment);
+    let metadata = Mp4Metadata::parse(init)?;
+    let iv_size = |track_id| {
+        metadata
+            .tenc()
+            .iter()
+            .find(|x| x.track_id == track_id)
+            .map(|x| x.per_sample_iv_size)
+    };
+    let mut tracks = Vec::new();
+
+    if let Some(moof) = boxes::find(boxes::boxes(fragment), b"moof")? {
+        let trex_sizes = moof::read_trex_sizes(init)?;
+
+        for fragment in moof::read_track_fragments(&moof, &trex_sizes)? {
+            if let Some(track) = read_track(
+                &fragment.mp4_box,
+                fragment.track_id,
+                fragment.moof_offset,
+                iv_size(fragment.track_id),
+            )? {
+                tracks.push(track);
+            }
+        }
+    } else if let Some(moov) = boxes::find(boxes::boxes(fragment), b"moov")? {
+        for trak in moov.children() {
+            let trak = trak?;
+
+            if &trak.box_type != b"trak" {
+                continue;
+            }
+
+            let track_id = metadata::read_trak(&trak)?.track_id;
+            let Some(stbl) = [b"mdia", b"minf", b"stbl"].iter().try_fold(
+                Some(trak),
+                |parent, x| match parent {
+                    Some(parent) => parent.child(x),
+                    None => Ok(None),
+                },
+            )?
+            else {
+                continue;
+            };
+
+            if let Some(track) = read_track(&stbl, track_id, 0, iv_size(track_id))? {
+                tracks.push(track);
+            }
+        }
+    }
+
+    Ok((!tracks.is_empty()).then_some(AuxInfo { tracks }))
+}
+
+/// Read the `saiz` and `saio` boxes of a `traf` or `stbl` box.
+fn read_track(
+    parent: &Mp4Box,
+    track_id: u32,
+    base_data_offset: u64,
+    iv_size: Option<u8>,
+) -> Result<Option<TrackAuxInfo>, Error> {
+    let (saiz, saio) = (parent.child(b"saiz")?, parent.child(b"saio")?);
+
+    if saiz.is_none() && saio.is_none() {
+        return Ok(None);
+    }
+
+    let mut track = TrackAuxInfo {
+        track_id,
+        aux_info_type: None,
+        aux_info_type_parameter: None,
+        default_sample_info_size: 0,
+        sample_count: 0,
+        sample_info_sizes: Vec::new(),
+        offsets: Vec::new(),
+        base_data_offset,
+        iv_size,
+    };
+
+    if let Some(saiz) = saiz {
+        let mut reader = read_header(&saiz, &mut track)?;
+        track.default_sample_info_size = reader.read_u8()?;
+        track.sample_count = reader.read_u32()?;
+
+        if track.default_sample_info_size == 0 {
+            track.sample_info_sizes = reader.read_bytes(track.sample_count as usize)?.to_vec();
+        }
+    }
+
+    if let Some(saio) = saio {
+        let version = saio.payload().first().copied().unwrap_or_default();
+        let mut reader = read_header(&saio, &mut track)?;
+        let entry_count = reader.read_u32()?;
+        let offset_size = if version == 0 { 4 } else { 8 };
+
+        if entry_count as usize > reader.remaining() / offset_size {
+            return Err(Error::new_format(format!(
+                "saio box at offset {} is too small for {} entries.",
+                saio.offset, entry_count
+            )));
+        }
+
+        for _ in 0..entry_count {
+            track.offsets.push(if version == 0 {
+                reader.read_u32()? as u64
+            } else {
+                reader.read_u64()?
+            });
+        }
+    }
+
+    Ok(Some(track))
+}
+
+/// Read the full box header of a `saiz` or `saio` box, with the optional auxiliary information type.
+fn read_header<'a>(mp4_box: &Mp4Box<'a>, track: &mut TrackAuxInfo) -> Result<Reader<'a>, Error> {
+    let mut reader = Reader::new(mp4_box.payload());
+    let (_, flags) = reader.read_version_flags()?;
+
+    if flags & 0x01 != 0 {
+        track.aux_info_type = Some(reader.read_array()?);
+        track.aux_info_type_parameter = Some(reader.read_u32()?);
+    }
+
+    Ok(reader)
+}
@@ -0,0 +1,202 @@
+use crate::{Error, ErrorType, reader::Reader};
+
+/// A single box borrowed from an mp4 byte stream.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Mp4Box<'a> {
+    pub(crate) box_type: [u8; 4],
+    /// Offset of the box from the start of the walked data.
+    pub(crate) offset: usize,
+    pub(crate) header_size: usize,
+    /// Complete box including its header.
+    pub(crate) data: &'a [u8],
+}
+
+impl<'a> Mp4Box<'a> {
+    pub(crate) fn end(&self) -> usize {
+        self.offset + self.data.len()
+    }
+
+    pub(crate) fn payload(&self) -> &'a [u8] {
+        &self.data[self.header_size..]
+    }
+
+    /// Walk the boxes contained in the payload.
+    pub(crate) fn children(&self) -> Boxes<'a> {
+        self.children_after(0)
+    }
+
+    /// Walk the boxes contained in the payload, after skipping `fields_size` bytes of box fields.
+    pub(crate) fn children_after(&self, fields_size: usize) -> Boxes<'a> {
+        let payload = self.payload();
+        let fields_size = fields_size.min(payload.len());
+
+        Boxes {
+            data: &payload[fields_size..],
+            pos: 0,
+            base: self.offset + self.header_size + fields_size,
+            top_level: false,
+        }
+    }
+
+    /// Extended type of a `uuid` box.
+    pub(crate) fn user_type(&self) -> Option<[u8; 16]> {
+        if &self.box_type != b"uuid" {
+            return None;
+        }
+
+        self.data[self.header_size - 16..self.header_size]
+            .try_into()
+            .ok()
+    }
+
+    /// Find the first direct child box of the given type.
+    pub(crate) fn child(&self, box_type: &[u8; 4]) -> Result<Option<Mp4Box<'a>>, Error> {
+        find(self.children(), box_type)
+    }
+}
+
+/// Iterator over sibling boxes.
+#[derive(Clone)]
+pub(crate) struct Boxes<'a> {
+    data: &'a [u8],
+    pos: usize,
+    base: usize,
+    top_level: bool,
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = Result<Mp4Box<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+
+        let result = read_box(&self.data[self.pos..], self.base + self.pos, self.top_level).map(
+            |(box_type, header_size, size)| {
+                let mp4_box = Mp4Box {
+                    box_type,
+                    offset: self.base + self.pos,
+                    header_size,
+                    data: &self.data[self.pos..self.pos + size],
+                };
+                self.pos += size;
+                mp4_box
+            },
+        );
+
+        if result.is_err() {
+            // Stop walking after the first malformed box.
+            self.pos = self.data.len();
+        }
+
+        Some(result)
+    }
+}
+
+/// Walk the top level boxes of data.
+pub(crate) fn boxes(data: &[u8]) -> Boxes<'_> {
+    Boxes {
+        data,
+        pos: 0,
+        base: 0,
+        top_level: true,
+    }
+}
+
+/// Find the first box of the given type.
+pub(crate) fn find<'a>(boxes: Boxes<'a>, box_type: &[u8; 4]) -> Result<Option<Mp4Box<'a>>, Error> {
+    for mp4_box in boxes {
+        let mp4_box = mp4_box?;
+
+        if &mp4_box.box_type == box_type {
+            return Ok(Some(mp4_box));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Parse a box header and return its type, header size (including the extended type of `uuid` boxes)
+/// and total size.
+///
+/// A size of 0 (box extends to the end of data) is only accepted for `top_level` boxes.
+/// `offset` of the box is only used for the error message.
+pub(crate) fn read_box(
+    data: &[u8],
+    offset: usize,
+    top_level: bool,
+) -> Result<([u8; 4], usize, usize), Error> {
+    let box_type = data
+        .get(4..8)
+        .and_then(|x| x.try_into().ok())
+        .unwrap_or(*b"????");
+    let malformed = |msg: String| Error {
+        msg: format!("{} box at offset {} {}.", fourcc(&box_type), offset, msg),
+        err_type: ErrorType::MalformedBox {
+            box_type,
+            offset: offset as u64,
+        },
+        invalid_key: false,
+    };
+
+    let mut reader = Reader::new(data);
+    let (Ok(size), Ok(_)) = (reader.read_u32(), reader.read_array::<4>()) else {
+        return Err(malformed("has a truncated header".to_owned()));
+    };
+
+    let (mut header_size, size) = match size {
+        0 if top_level => (8, data.len() as u64),
+        1 => (
+            16,
+            reader
+                .read_u64()
+                .map_err(|_| malformed("has a truncated header".to_owned()))?,
+        ),
+        x => (8, x as u64),
+    };
+
+    if &box_type == b"uuid" {
+        header_size += 16;
+    }
+
+    if size < header_size as u64 || size > data.len() as u64 {
+        return Err(malformed(format!(
+            "has an invalid size of {} bytes, {} bytes are available",
+            size,
+            data.len()
+        )));
+    }
+
+    Ok((box_type, header_size, size as usize))
+}
+
+/// Printable representation of a box type.
+pub(crate) fn fourcc(box_type: &[u8; 4]) -> String {
+    box_type
+        .iter()
+        .map(|x| {
+            if x.is_ascii_graphic() || *x == b' ' {
+                *x as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// Append a box with the given payload, using a 64-bit size if required.
+pub(crate) fn write_box(out: &mut Vec<u8>, box_type: &[u8; 4], payload: &[u8]) {
+    let size = payload.len() as u64 + 8;
+
+    if size > u32::MAX as u64 {
+        out.extend_from_slice(&1_u32.to_be_bytes());
+        out.extend_from_slice(box_type);
+        out.extend_from_slice(&(size + 8).to_be_bytes());
+    } else {
+        out.extend_from_slice(&(size as u32).to_be_bytes());
+        out.extend_from_slice(box_type);
+    }
+
+    out.extend_from_slice(payload);
+}
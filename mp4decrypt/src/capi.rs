@@ -0,0 +1,299 @@
+use crate::{Error, ErrorType, KeyMap, mp4decrypt, mp4decrypt_into, panic_message, sink::BufSink};
+use core::ffi::{c_char, c_int, c_void};
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::Mutex,
+};
+
+/// Error returned by the C API.
+///
+/// `message` should be released with [mp4decrypt_free_error](mp4decrypt_free_error).
+#[repr(C)]
+pub struct DecryptError {
+    pub code: c_int,
+    pub message: *const c_char,
+}
+
+/// Message used when the actual error message can't be allocated.
+const ALLOCATION_FAILED: &CStr = c"failed to allocate error message";
+
+/// Handler registered with [mp4decrypt_set_panic_handler](mp4decrypt_set_panic_handler).
+static PANIC_HANDLER: Mutex<Option<extern "C" fn(*const c_char)>> = Mutex::new(None);
+
+/// Register a handler which is called with the panic message (a null terminated string, only valid during the call)
+/// whenever a panic is caught within the C API, before the function returns an error with code 6.
+/// Pass null to remove the handler.
+///
+/// Panics never unwind into the caller, this only makes them observable, e.g. for logging.
+/// The handler must not unwind and it may be called from any thread which calls the C API.
+///
+/// # Example
+///
+/// ```
+/// use std::ffi::{CStr, c_char};
+///
+/// extern "C" fn log_panic(msg: *const c_char) {
+///     eprintln!("mp4decrypt: {}", unsafe { CStr::from_ptr(msg) }.to_string_lossy());
+/// }
+///
+/// mp4decrypt::mp4decrypt_set_panic_handler(Some(log_panic));
+/// mp4decrypt::mp4decrypt_set_panic_handler(None);
+/// ```
+#[unsafe(no_mangle)]
+pub extern "C" fn mp4decrypt_set_panic_handler(handler: Option<extern "C" fn(*const c_char)>) {
+    *PANIC_HANDLER.lock().unwrap_or_else(|x| x.into_inner()) = handler;
+}
+
+/// Pass a panic message to the registered panic handler.
+fn report_panic(msg: &str) {
+    let handler = *PANIC_HANDLER.lock().unwrap_or_else(|x| x.into_inner());
+
+    if let Some(handler) = handler {
+        let msg = CString::new(msg.replace('\0', "?")).unwrap_or_default();
+        handler(msg.as_ptr());
+    }
+}
+
+/// Run the body of a C API function, catching any panic so that it doesn't unwind into the caller.
+/// A panic is reported to the panic handler and returned as an error.
+fn catch_panic(err_out: *mut DecryptError, f: impl FnOnce() -> c_int) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(x) => x,
+        Err(payload) => {
+            let msg = format!("panicked: {}", panic_message(&*payload));
+            report_panic(&msg);
+            unsafe { set_error(err_out, ErrorType::Panicked.code(), &msg) };
+            1
+        }
+    }
+}
+
+/// Store a decrypt error, reporting caught panics to the panic handler.
+unsafe fn set_decrypt_error(err_out: *mut DecryptError, err: &Error) {
+    if matches!(err.err_type, ErrorType::Panicked) {
+        report_panic(&err.msg);
+    }
+
+    unsafe { set_error(err_out, error_code(err), &err.msg) };
+}
+
+/// Store an error code and a copy of the message, with interior nul bytes replaced by `?`.
+///
+/// The message is allocated with `malloc`, falling back to a static message if allocation fails.
+unsafe fn set_error(err_out: *mut DecryptError, code: c_int, msg: &str) {
+    if err_out.is_null() {
+        return;
+    }
+
+    let message = unsafe { libc::malloc(msg.len() + 1) as *mut u8 };
+
+    let message = if message.is_null() {
+        ALLOCATION_FAILED.as_ptr()
+    } else {
+        unsafe {
+            for (i, x) in msg.bytes().enumerate() {
+                *message.add(i) = if x == 0 { b'?' } else { x };
+            }
+            *message.add(msg.len()) = 0;
+        }
+        message as *const c_char
+    };
+
+    unsafe {
+        (*err_out).code = code;
+        (*err_out).message = message;
+    }
+}
+
+/// Release the message of an error returned by [mp4decrypt_capi](mp4decrypt_capi).
+/// The message is set to null afterwards, so calling this twice is safe.
+///
+/// # Safety
+///
+/// `err` must be null or point to a [DecryptError](DecryptError) filled by this library.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mp4decrypt_free_error(err: *mut DecryptError) {
+    if err.is_null() {
+        return;
+    }
+
+    unsafe {
+        let message = (*err).message;
+
+        if !message.is_null() && message != ALLOCATION_FAILED.as_ptr() {
+            libc::free(message as *mut libc::c_void);
+        }
+
+        (*err).message = std::ptr::null();
+    }
+}
+
+/// C compatible wrapper around [mp4decrypt](mp4decrypt).
+///
+/// `keys_json` is a JSON object of kid key pairs. On success the decrypted data is
+/// allocated with `malloc` and should be released by the caller with `free`.
+/// On failure `err_out` (if not null) is filled and should be released with
+/// [mp4decrypt_free_error](mp4decrypt_free_error). Panics are caught and returned as errors with code 6,
+/// see [mp4decrypt_set_panic_handler](mp4decrypt_set_panic_handler).
+///
+/// # Safety
+///
+/// All non-null pointers must be valid for their given lengths and `keys_json`
+/// must point to a null terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mp4decrypt_capi(
+    data_ptr: *const u8,
+    data_len: usize,
+    keys_json: *const c_char,
+    fragments_ptr: *const u8,
+    fragments_len: usize,
+    out_ptr: *mut *mut u8,
+    out_len: *mut usize,
+    err_out: *mut DecryptError,
+) -> c_int {
+    catch_panic(err_out, || {
+        if data_ptr.is_null() || keys_json.is_null() || out_ptr.is_null() || out_len.is_null() {
+            return -1;
+        }
+
+        let (data, keys, fragments_info) = match unsafe {
+            read_input(data_ptr, data_len, keys_json, fragments_ptr, fragments_len)
+        } {
+            Ok(x) => x,
+            Err((code, msg)) => {
+                unsafe { set_error(err_out, code, msg) };
+                return code;
+            }
+        };
+
+        match mp4decrypt(data, &keys, fragments_info) {
+            Ok(output) => {
+                let len = output.len();
+                let buf = unsafe { libc::malloc(len) as *mut u8 };
+                if buf.is_null() {
+                    return -4;
+                }
+                unsafe {
+                    std::ptr::copy_nonoverlapping(output.as_ptr(), buf, len);
+                    *out_ptr = buf;
+                    *out_len = len;
+                }
+                0
+            }
+            Err(err) => {
+                unsafe { set_decrypt_error(err_out, &err) };
+                1
+            }
+        }
+    })
+}
+
+/// C compatible wrapper around [mp4decrypt_into](mp4decrypt_into), which passes the decrypted data to
+/// `chunk_cb` in one or more chunks instead of allocating it for the caller.
+///
+/// `chunk_cb` is called with `user_data`, a pointer to the chunk and its length. The chunk is only valid
+/// during the call. Arguments and return codes are same as [mp4decrypt_capi](mp4decrypt_capi).
+///
+/// # Safety
+///
+/// All non-null pointers must be valid for their given lengths and `keys_json`
+/// must point to a null terminated string. `chunk_cb` must not unwind.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mp4decrypt_capi_cb(
+    data_ptr: *const u8,
+    data_len: usize,
+    keys_json: *const c_char,
+    fragments_ptr: *const u8,
+    fragments_len: usize,
+    chunk_cb: Option<extern "C" fn(*mut c_void, *const u8, usize)>,
+    user_data: *mut c_void,
+    err_out: *mut DecryptError,
+) -> c_int {
+    catch_panic(err_out, || {
+        let Some(chunk_cb) = chunk_cb else {
+            return -1;
+        };
+
+        if data_ptr.is_null() || keys_json.is_null() {
+            return -1;
+        }
+
+        let (data, keys, fragments_info) = match unsafe {
+            read_input(data_ptr, data_len, keys_json, fragments_ptr, fragments_len)
+        } {
+            Ok(x) => x,
+            Err((code, msg)) => {
+                unsafe { set_error(err_out, code, msg) };
+                return code;
+            }
+        };
+
+        let mut sink = CallbackSink {
+            chunk_cb,
+            user_data,
+        };
+
+        let result = KeyMap::try_from(&keys)
+            .and_then(|keys| mp4decrypt_into(data, &keys, fragments_info, &mut sink));
+
+        match result {
+            Ok(_) => 0,
+            Err(err) => {
+                unsafe { set_decrypt_error(err_out, &err) };
+                1
+            }
+        }
+    })
+}
+
+/// Sink which forwards decrypted data to a C callback.
+struct CallbackSink {
+    chunk_cb: extern "C" fn(*mut c_void, *const u8, usize),
+    user_data: *mut c_void,
+}
+
+impl BufSink for CallbackSink {
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        (self.chunk_cb)(self.user_data, chunk.as_ptr(), chunk.len());
+        Ok(())
+    }
+}
+
+/// Data stream, kid key pairs and fragments info passed to the C API.
+type Input<'a> = (&'a [u8], HashMap<String, String>, Option<&'a [u8]>);
+
+/// Read the data stream, kid key pairs and fragments info passed to the C API.
+///
+/// # Safety
+///
+/// Same as [mp4decrypt_capi](mp4decrypt_capi), `data_ptr` and `keys_json` must not be null.
+unsafe fn read_input<'a>(
+    data_ptr: *const u8,
+    data_len: usize,
+    keys_json: *const c_char,
+    fragments_ptr: *const u8,
+    fragments_len: usize,
+) -> Result<Input<'a>, (c_int, &'static str)> {
+    let data = unsafe { std::slice::from_raw_parts(data_ptr, data_len) };
+
+    let keys_str = unsafe { CStr::from_ptr(keys_json) }
+        .to_str()
+        .map_err(|_| (-2, "Invalid UTF-8 in keys_json"))?;
+
+    let keys = serde_json::from_str(keys_str).map_err(|_| (-3, "Failed to parse keys JSON"))?;
+
+    let fragments_info = if !fragments_ptr.is_null() && fragments_len > 0 {
+        Some(unsafe { std::slice::from_raw_parts(fragments_ptr, fragments_len) })
+    } else {
+        None
+    };
+
+    Ok((data, keys, fragments_info))
+}
+
+fn error_code(err: &Error) -> c_int {
+    err.err_type.code()
+}
@@ -0,0 +1,90 @@
+use crate::{Error, KeyMap, SegmentDecryptor};
+
+/// Decrypted data of a DASH representation, see [decrypt_dash_representation](decrypt_dash_representation).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DashOutput {
+    /// Decrypted init segment followed by every decrypted media segment, i.e. a single playable file.
+    Concatenated(Vec<u8>),
+    /// Decrypted init segment and every decrypted media segment, in the order of the input segments.
+    PerSegment {
+        init: Vec<u8>,
+        segments: Vec<Vec<u8>>,
+    },
+}
+
+/// Decrypt the downloaded segments of a DASH representation using their shared init segment.
+///
+/// The init segment is parsed once and every media segment is decrypted against it,
+/// same as [SegmentDecryptor](crate::SegmentDecryptor), failing at the first segment which can't be decrypted.
+///
+/// # Memory Usage
+///
+/// Every decrypted segment is kept in memory until all of them are decrypted, so memory usage grows with
+/// the size of the representation (about the size of input segments, plus the input itself).
+/// For long representations, prefer [SegmentDecryptor](crate::SegmentDecryptor) and write every
+/// decrypted segment as soon as it is downloaded, or [decrypt_stream](crate::decrypt_stream) over
+/// an already concatenated file, which only buffers one fragment at a time.
+///
+/// # Arguments
+///
+/// * `init` - Init segment (`ftyp` + `moov`) of the representation.
+/// * `segments` - Media segments (`moof` + `mdat`) of the representation, in playback order.
+/// * `keys` - Keys for decrypting the segments.
+/// * `per_segment` - Return every decrypted segment on its own instead of concatenating them.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{DashOutput, KeyMap};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let output = mp4decrypt::decrypt_dash_representation(&init, &[&segment], &keys, false).unwrap();
+/// let mut data = init.clone();
+/// data.extend(&segment);
+/// let options = mp4decrypt::DecryptOptions::new();
+/// let decrypted_data = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+/// assert_eq!(output, DashOutput::Concatenated(decrypted_data.clone()));
+///
+/// let output = mp4decrypt::decrypt_dash_representation(&init, &[&segment], &keys, true).unwrap();
+/// let DashOutput::PerSegment { init, segments } = output else {
+///     unreachable!()
+/// };
+/// assert_eq!([init, segments.concat()].concat(), decrypted_data);
+/// ```
+pub fn decrypt_dash_representation(
+    init: &[u8],
+    segments: &[&[u8]],
+    keys: &KeyMap,
+    per_segment: bool,
+) -> Result<DashOutput, Error> {
+    let mut decryptor = SegmentDecryptor::new(init, keys)?;
+    let decrypted_init = decryptor.decrypt_init()?;
+
+    if per_segment {
+        let segments = segments
+            .iter()
+            .map(|x| decryptor.decrypt_next(x))
+            .collect::<Result<_, _>>()?;
+
+        return Ok(DashOutput::PerSegment {
+            init: decrypted_init,
+            segments,
+        });
+    }
+
+    let mut decrypted_data =
+        Vec::with_capacity(decrypted_init.len() + segments.iter().map(|x| x.len()).sum::<usize>());
+    decrypted_data.extend(decrypted_init);
+
+    for segment in segments {
+        decrypted_data.extend(decryptor.decrypt_next(segment)?);
+    }
+
+    Ok(DashOutput::Concatenated(decrypted_data))
+}
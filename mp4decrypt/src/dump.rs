@@ -0,0 +1,84 @@
+use crate::{
+    Error,
+    boxes::{self, Boxes},
+    metadata,
+    validate::CONTAINERS,
+};
+use std::io::Write;
+
+/// Print the box tree of an mp4 byte stream, one box per line indented by its depth, like Bento4 mp4dump.
+///
+/// Every line holds the box type, its size including the header and its offset from the start of `data`,
+/// e.g. `[tenc] size=32 offset=613`. Container boxes, `stsd` boxes and their sample entries are walked into,
+/// so that the structure of an encrypted stream can be compared with its decrypted output.
+/// `stpp` sample entries aren't walked into, as their child boxes follow strings of any length.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted or decrypted data stream.
+/// * `w` - Writer to print the box tree to.
+///
+/// # Errors
+///
+/// Returns an error if writing fails, or if a box is malformed.
+/// Boxes preceding the malformed box are printed before the error is returned.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let mut tree = Vec::new();
+/// mp4decrypt::debug_dump(&init, &mut tree).unwrap();
+///
+/// let tree = String::from_utf8(tree).unwrap();
+/// assert!(tree.starts_with("[ftyp]"));
+/// assert!(tree.contains("\n                  [tenc] size=32 offset=613\n"));
+/// ```
+pub fn debug_dump(data: &[u8], w: &mut impl Write) -> Result<(), Error> {
+    dump(boxes::boxes(data), 0, w)
+}
+
+fn dump(boxes: Boxes, depth: usize, w: &mut impl Write) -> Result<(), Error> {
+    for mp4_box in boxes {
+        let mp4_box = mp4_box?;
+
+        writeln!(
+            w,
+            "{:indent$}[{}] size={} offset={}",
+            "",
+            boxes::fourcc(&mp4_box.box_type),
+            mp4_box.data.len(),
+            mp4_box.offset,
+            indent = depth * 2
+        )?;
+
+        if let Some(fields_size) = children_offset(&mp4_box.box_type) {
+            dump(mp4_box.children_after(fields_size), depth + 1, w)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Size of the fields preceding the child boxes of container boxes, `stsd` boxes and sample entries,
+/// `None` for other boxes which aren't walked into.
+pub(crate) fn children_offset(box_type: &[u8; 4]) -> Option<usize> {
+    match box_type {
+        x if CONTAINERS.contains(&x) => Some(0),
+        // version, flags and entry_count
+        b"stsd" => Some(8),
+        x => entry_fields_size(x),
+    }
+}
+
+/// Size of the fields preceding child boxes of common clear and protected sample entries.
+///
+/// `stpp` entries aren't walked into, their child boxes follow null-terminated strings of any length.
+fn entry_fields_size(box_type: &[u8; 4]) -> Option<usize> {
+    match box_type {
+        b"avc1" | b"avc3" | b"hvc1" | b"hev1" | b"dvh1" | b"dvhe" | b"vp09" | b"av01" => Some(78),
+        b"mp4a" | b"ac-3" | b"ec-3" | b"ac-4" | b"Opus" | b"fLaC" => Some(28),
+        b"wvtt" => Some(8),
+        x => metadata::protected_entry_fields_size(x),
+    }
+}
@@ -0,0 +1,22 @@
+use crate::{
+    Error, boxes,
+    rewrite::{self, Replacement},
+};
+
+/// Remove the top level `emsg` boxes from a decrypted data stream,
+/// see [DecryptOptions::keep_emsg](crate::DecryptOptions::keep_emsg).
+///
+/// `fragments_info` holds the track defaults of fragments in `data`.
+pub(crate) fn strip_emsg(data: &mut Vec<u8>, fragments_info: Option<&[u8]>) -> Result<(), Error> {
+    let mut replacements = Vec::new();
+
+    for mp4_box in boxes::boxes(data) {
+        let mp4_box = mp4_box?;
+
+        if &mp4_box.box_type == b"emsg" {
+            replacements.push(Replacement::new(&mp4_box, Vec::new()));
+        }
+    }
+
+    rewrite::replace(data, fragments_info, replacements)
+}
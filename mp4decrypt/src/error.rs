@@ -3,14 +3,323 @@
 pub struct Error {
     pub msg: String,
     pub err_type: ErrorType,
+    /// Whether a KID, track ID or key is not in a valid format, e.g. a key which isn't 128-bit hex.
+    /// The error type stays [ErrorType::InvalidFormat](ErrorType::InvalidFormat).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{ErrorType, KeyMap};
+    ///
+    /// let error = KeyMap::new().insert("1", "100b6c20940f779a").unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::InvalidFormat));
+    /// assert!(error.invalid_key);
+    /// assert!(error.is_key_error());
+    /// ```
+    pub invalid_key: bool,
 }
 
 /// The type of error which can occur during decryption.
 #[derive(Debug)]
 pub enum ErrorType {
+    /// None of the candidate key maps produced valid media, holding the error of every candidate in order,
+    /// see [decrypt_try_keys](crate::decrypt_try_keys).
+    AllCandidatesFailed(Vec<Error>),
+    /// Keys were given but the input is already clear, see
+    /// [DecryptOptions::on_clear_input](crate::DecryptOptions::on_clear_input).
+    AlreadyDecrypted,
     DataTooLarge,
     Failed(i32),
     InvalidFormat,
+    /// The fragments info (init segment) doesn't describe the tracks of the fragments.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{ErrorType, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let mut media = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+    /// // track ID of the tfhd box, track 2 isn't part of the init segment
+    /// media[44..48].copy_from_slice(&2_u32.to_be_bytes());
+    ///
+    /// let options = mp4decrypt::DecryptOptions::new();
+    /// let error = mp4decrypt::mp4decrypt_with_options(&media, &keys, Some(&init), &options)
+    ///     .unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::InitMediaMismatch));
+    /// ```
+    InitMediaMismatch,
+    /// Reading the input or writing the output failed, with the kind of the I/O error.
+    /// The message of the error is the message of the I/O error.
+    Io(std::io::ErrorKind),
+    /// A box has a truncated header or its size doesn't fit within its parent, while parsing the input.
+    MalformedBox {
+        box_type: [u8; 4],
+        offset: u64,
+    },
+    MalformedOutput {
+        box_type: [u8; 4],
+        offset: u64,
+    },
+    /// An IV supplied from outside of the stream doesn't match the IV size declared in the `tenc` box,
+    /// see [TrackEncryption::check_iv](crate::TrackEncryption::check_iv).
+    IvLengthMismatch {
+        expected: usize,
+        got: usize,
+    },
+    /// A fragment of a track encrypted with per sample IVs has neither a `senc` box nor `saiz` and `saio` boxes
+    /// referencing the auxiliary information holding its IVs, holding its track ID.
+    MissingIvs {
+        track_id: u32,
+    },
+    /// A media segment was given without fragments info (init segment) and it has no `moov` box of its own.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{ErrorType, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+    ///
+    /// let options = mp4decrypt::DecryptOptions::new();
+    /// let error = mp4decrypt::mp4decrypt_with_options(&segment, &keys, None, &options).unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::MissingInitSegment));
+    ///
+    /// // init segment and media segment joined together are self-contained
+    /// let mut data = init.clone();
+    /// data.extend(&segment);
+    /// assert!(mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).is_ok());
+    /// ```
+    MissingInitSegment,
+    /// Concatenated periods (a new `moov` box after fragments) can't be decrypted as requested,
+    /// e.g. using fragments info. Without fragments info, every period is decrypted using its own `moov` box.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{ErrorType, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+    /// let mut period = init.clone();
+    /// period.extend(&segment);
+    ///
+    /// let options = mp4decrypt::DecryptOptions::new().validate_structure(true);
+    /// let decrypted_period = mp4decrypt::mp4decrypt_with_options(&period, &keys, None, &options).unwrap();
+    ///
+    /// // two periods
+    /// let mut data = period.clone();
+    /// data.extend(&period);
+    /// let decrypted_data = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+    /// assert_eq!(decrypted_data, [decrypted_period.clone(), decrypted_period].concat());
+    ///
+    /// let mut data = segment.clone();
+    /// data.extend(&period);
+    /// let error = mp4decrypt::mp4decrypt_with_options(&data, &keys, Some(&init), &options).unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::ReinitializationUnsupported));
+    /// ```
+    ReinitializationUnsupported,
+    /// No key was given for a protected track, holding its track ID and default KID.
+    MissingKeyForTrack(u32, Option<[u8; 16]>),
+    /// A track is protected with a DRM system which Bento4 can't decrypt, e.g. ChinaDRM,
+    /// holding the system ID of its `pssh` box.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{DecryptOptions, ErrorType, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let mut init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+    /// let options = DecryptOptions::new();
+    /// let chinadrm = 0x3d5e6d359b9a41e8b843dd3c6e72c42c_u128.to_be_bytes();
+    ///
+    /// // replace the system IDs of the PlayReady and Widevine pssh boxes
+    /// for offset in 0..init.len() - 4 {
+    ///     if &init[offset..offset + 4] == b"pssh" {
+    ///         init[offset + 8..offset + 24].copy_from_slice(&chinadrm);
+    ///     }
+    /// }
+    ///
+    /// let error = mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::UnsupportedDrmSystem(x) if x == chinadrm));
+    /// assert!(error.msg.contains("ChinaDRM"));
+    /// ```
+    UnsupportedDrmSystem([u8; 16]),
+    /// A protected track uses `seig` sample groups in a way which Bento4 can't decrypt, holding its track ID,
+    /// e.g. samples of a single fragment encrypted with different KIDs. Samples marked as unencrypted
+    /// by their sample group are passed through as they are.
+    UnsupportedSampleGroup {
+        track_id: u32,
+    },
+    /// A protected track has a protected sample entry after a clear one in its `stsd` box, which Bento4
+    /// can't match to the sample description index of fragments, holding its track ID.
+    /// Tracks with several sample entries which are all protected (or all clear) are supported.
+    UnsupportedSampleEntries {
+        track_id: u32,
+    },
+    /// Memory for the decrypted data stream couldn't be allocated, see [BufSink](crate::BufSink).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{BufSink, ErrorType, KeyMap};
+    ///
+    /// /// Sink which fails to reserve memory.
+    /// struct Full;
+    ///
+    /// impl BufSink for Full {
+    ///     fn write_chunk(&mut self, _: &[u8]) -> std::io::Result<()> {
+    ///         Err(std::io::ErrorKind::OutOfMemory.into())
+    ///     }
+    /// }
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let error = mp4decrypt::mp4decrypt_into(&init, &keys, None, &mut Full).unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::OutOfMemory));
+    /// ```
+    OutOfMemory,
+    /// The decrypted data stream exceeds [DecryptOptions::max_output_bytes](crate::DecryptOptions::max_output_bytes),
+    /// which is held by the error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{DecryptOptions, ErrorType, KeyMap};
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let options = DecryptOptions::new().max_output_bytes(64);
+    /// let error = mp4decrypt::mp4decrypt_with_options(&init, &KeyMap::new(), None, &options)
+    ///     .unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::OutputTooLarge { limit: 64 }));
+    /// ```
+    OutputTooLarge {
+        limit: u64,
+    },
+    /// A decrypted sample doesn't have valid codec framing, which usually means that a wrong key was used.
+    /// Holds the track ID and the offset of the sample in the decrypted data stream.
+    VerificationFailed {
+        track_id: u32,
+        offset: u64,
+    },
+    /// A panic was caught at the FFI boundary instead of unwinding into Bento4, the error message holds
+    /// the panic message.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{BufSink, ErrorType, KeyMap};
+    ///
+    /// struct Panicking;
+    ///
+    /// impl BufSink for Panicking {
+    ///     fn write_chunk(&mut self, _: &[u8]) -> std::io::Result<()> {
+    ///         panic!("sink is closed");
+    ///     }
+    /// }
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let error = mp4decrypt::mp4decrypt_into(&init, &KeyMap::new(), None, &mut Panicking).unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::Panicked));
+    /// assert!(error.msg.ends_with("sink is closed"));
+    /// ```
+    Panicked,
+}
+
+impl ErrorType {
+    /// Stable machine-readable key of the error type, e.g. for structured logging or for looking up
+    /// a localized message. Keys are lowercase snake case and never change once released,
+    /// the human-readable message stays in [Error::msg](Error::msg).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let error = mp4decrypt::KeyMap::new().insert("1", "invalid").unwrap_err();
+    /// assert_eq!(error.err_type.as_str(), "invalid_format");
+    /// assert_eq!(mp4decrypt::ErrorType::Failed(-1).as_str(), "bento4_error");
+    /// ```
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AllCandidatesFailed(_) => "all_candidates_failed",
+            Self::AlreadyDecrypted => "already_decrypted",
+            Self::DataTooLarge => "data_too_large",
+            Self::Failed(_) => "bento4_error",
+            Self::InvalidFormat => "invalid_format",
+            Self::InitMediaMismatch => "init_media_mismatch",
+            Self::Io(_) => "io_error",
+            Self::MalformedBox { .. } => "malformed_box",
+            Self::MalformedOutput { .. } => "malformed_output",
+            Self::IvLengthMismatch { .. } => "iv_length_mismatch",
+            Self::MissingIvs { .. } => "missing_ivs",
+            Self::MissingInitSegment => "missing_init_segment",
+            Self::ReinitializationUnsupported => "reinitialization_unsupported",
+            Self::MissingKeyForTrack(..) => "missing_key_for_track",
+            Self::UnsupportedDrmSystem(_) => "unsupported_drm_system",
+            Self::UnsupportedSampleGroup { .. } => "unsupported_sample_group",
+            Self::UnsupportedSampleEntries { .. } => "unsupported_sample_entries",
+            Self::OutOfMemory => "out_of_memory",
+            Self::OutputTooLarge { .. } => "output_too_large",
+            Self::VerificationFailed { .. } => "verification_failed",
+            Self::Panicked => "panicked",
+        }
+    }
+
+    /// Stable numeric code of the error type, the same as returned by the C API functions.
+    /// Bento4 failures ([ErrorType::Failed](ErrorType::Failed)) keep their own negative code.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let error = mp4decrypt::KeyMap::new().insert("1", "invalid").unwrap_err();
+    /// assert_eq!(error.err_type.code(), 1);
+    /// assert_eq!(mp4decrypt::ErrorType::Failed(-10).code(), -10);
+    /// ```
+    pub fn code(&self) -> i32 {
+        match *self {
+            Self::InvalidFormat => 1,
+            Self::DataTooLarge => 2,
+            Self::Failed(x) => x,
+            Self::Io(_) => 3,
+            Self::MalformedOutput { .. } => 4,
+            Self::InitMediaMismatch => 5,
+            Self::Panicked => 6,
+            Self::MissingKeyForTrack(..) => 7,
+            Self::UnsupportedDrmSystem(_) => 8,
+            Self::MissingInitSegment => 9,
+            Self::ReinitializationUnsupported => 10,
+            Self::OutOfMemory => 11,
+            Self::IvLengthMismatch { .. } => 12,
+            Self::MalformedBox { .. } => 13,
+            Self::VerificationFailed { .. } => 14,
+            Self::AllCandidatesFailed(_) => 15,
+            Self::UnsupportedSampleGroup { .. } => 16,
+            Self::MissingIvs { .. } => 17,
+            Self::AlreadyDecrypted => 19,
+            Self::UnsupportedSampleEntries { .. } => 20,
+            Self::OutputTooLarge { .. } => 21,
+        }
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -20,3 +329,94 @@ impl std::fmt::Display for Error {
 }
 
 impl std::error::Error for Error {}
+
+/// Convert an I/O error into [ErrorType::Io](ErrorType::Io), keeping its kind and message.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{Error, ErrorType};
+/// use std::io::ErrorKind;
+///
+/// fn read_init(path: &str) -> Result<Vec<u8>, Error> {
+///     Ok(std::fs::read(path)?)
+/// }
+///
+/// let error = read_init("missing/init.mp4").unwrap_err();
+/// assert!(matches!(error.err_type, ErrorType::Io(ErrorKind::NotFound)));
+/// assert!(error.to_string().starts_with("mp4decrypt-error: "));
+///
+/// let error = Error::from(std::io::Error::other("connection reset"));
+/// assert_eq!(error.to_string(), "mp4decrypt-error: connection reset");
+/// ```
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::new_io(err)
+    }
+}
+
+impl Error {
+    /// Whether the error is caused by the given keys rather than the input data, i.e. a KID or key
+    /// in an invalid format, a missing key or keys which don't decrypt the media correctly.
+    /// Useful for telling users to check their keys instead of reporting the file as corrupt.
+    pub fn is_key_error(&self) -> bool {
+        if self.invalid_key {
+            return true;
+        }
+
+        match &self.err_type {
+            ErrorType::MissingKeyForTrack(..) | ErrorType::VerificationFailed { .. } => true,
+            ErrorType::AllCandidatesFailed(errors) => {
+                !errors.is_empty() && errors.iter().all(|x| x.is_key_error())
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the input data is malformed or doesn't match its fragments info (init segment),
+    /// e.g. a truncated box, a missing init segment or encrypted samples without IVs.
+    pub fn is_format_error(&self) -> bool {
+        !self.invalid_key
+            && matches!(
+                self.err_type,
+                ErrorType::InvalidFormat
+                    | ErrorType::InitMediaMismatch
+                    | ErrorType::MalformedBox { .. }
+                    | ErrorType::MalformedOutput { .. }
+                    | ErrorType::MissingInitSegment
+                    | ErrorType::MissingIvs { .. }
+            )
+    }
+
+    /// Whether reading the input or writing the decrypted data failed.
+    pub fn is_io_error(&self) -> bool {
+        matches!(self.err_type, ErrorType::Io(_) | ErrorType::OutOfMemory)
+    }
+
+    /// Create a new invalid format error.
+    pub(crate) fn new_format<T: Into<String>>(msg: T) -> Self {
+        Self {
+            msg: msg.into(),
+            err_type: ErrorType::InvalidFormat,
+            invalid_key: false,
+        }
+    }
+
+    /// Create a new invalid format error caused by a KID, track ID or key.
+    pub(crate) fn new_key<T: Into<String>>(msg: T) -> Self {
+        Self {
+            msg: msg.into(),
+            err_type: ErrorType::InvalidFormat,
+            invalid_key: true,
+        }
+    }
+
+    /// Create a new io error.
+    pub(crate) fn new_io(err: std::io::Error) -> Self {
+        Self {
+            msg: err.to_string(),
+            err_type: ErrorType::Io(err.kind()),
+            invalid_key: false,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{Error, ErrorType, boxes, dump};
+use std::io::{self, ErrorKind, Read};
+
+/// A box event of [parse_boxes](parse_boxes).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxEvent {
+    /// A box starts. Events of its child boxes follow until the matching end event.
+    Start {
+        box_type: [u8; 4],
+        /// Offset of the box from the start of the stream.
+        offset: u64,
+        /// Size of the box including its header, 0 if it extends to the end of the stream.
+        size: u64,
+        /// Size of the header, including the 64-bit size and the extended type of `uuid` boxes.
+        header_size: u64,
+        /// Nesting depth, 0 for top level boxes.
+        depth: usize,
+    },
+    /// A box ends, after the events of every child box.
+    End {
+        box_type: [u8; 4],
+        offset: u64,
+        depth: usize,
+    },
+}
+
+/// Parse the box structure of an mp4 stream incrementally, calling `handler` with the start and end events of
+/// every box, e.g. for inspecting files too large to be loaded.
+///
+/// Container boxes, `stsd` boxes and their sample entries are walked into, same as
+/// [debug_dump](crate::debug_dump) does for data in memory. Payloads of other boxes are read and dropped as
+/// they come, so memory use doesn't depend on the size of the stream. Boxes with a 64-bit size are supported,
+/// and a top level box with a size of 0 extends to the end of the stream.
+///
+/// # Errors
+///
+/// Returns an error if reading fails, or if a box is malformed or truncated.
+/// Events of boxes preceding the malformed box are emitted before the error is returned.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::BoxEvent;
+///
+/// let file = std::fs::File::open("examples/sample/init.mp4").unwrap();
+/// let mut tencs = Vec::new();
+///
+/// mp4decrypt::parse_boxes(file, |event| {
+///     if let BoxEvent::Start { box_type: [b't', b'e', b'n', b'c'], offset, depth, .. } = event {
+///         tencs.push((offset, depth));
+///     }
+/// })
+/// .unwrap();
+/// assert_eq!(tencs, [(613, 9)]);
+/// ```
+pub fn parse_boxes<R: Read>(mut reader: R, mut handler: impl FnMut(BoxEvent)) -> Result<(), Error> {
+    // Type, offset and end of every open box, `None` if it extends to the end of the stream.
+    let mut open = Vec::<([u8; 4], u64, Option<u64>)>::new();
+    let mut pos = 0;
+
+    loop {
+        // Close every box ending here, child boxes never extend past their parent.
+        while let Some((box_type, offset, Some(end))) = open.last().copied()
+            && end == pos
+        {
+            open.pop();
+            handler(BoxEvent::End {
+                box_type,
+                offset,
+                depth: open.len(),
+            });
+        }
+
+        let mut header = [0; 8];
+        let read = read_full(&mut reader, &mut header)?;
+
+        if read == 0 && open.iter().all(|(_, _, end)| end.is_none()) {
+            break;
+        }
+
+        let box_type = header[4..].try_into().unwrap();
+
+        if read < header.len() {
+            return Err(truncated(&open, box_type, pos));
+        }
+
+        let mut header_size = 8;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 if open.is_empty() => None,
+            1 => {
+                let mut largesize = [0; 8];
+
+                if read_full(&mut reader, &mut largesize)? < largesize.len() {
+                    return Err(truncated(&open, box_type, pos));
+                }
+
+                header_size += 8;
+                Some(u64::from_be_bytes(largesize))
+            }
+            x => Some(x as u64),
+        };
+
+        if &box_type == b"uuid" {
+            if read_full(&mut reader, &mut [0; 16])? < 16 {
+                return Err(truncated(&open, box_type, pos));
+            }
+
+            header_size += 16;
+        }
+
+        let end = match size {
+            Some(size) if size < header_size => {
+                return Err(malformed(
+                    box_type,
+                    pos,
+                    &format!("has an invalid size of {} bytes", size),
+                ));
+            }
+            Some(size) => Some(pos + size),
+            None => None,
+        };
+
+        if let Some((_, _, Some(parent_end))) = open.last()
+            && end.is_none_or(|x| x > *parent_end)
+        {
+            return Err(malformed(box_type, pos, "extends past its parent box"));
+        }
+
+        handler(BoxEvent::Start {
+            box_type,
+            offset: pos,
+            size: size.unwrap_or_default(),
+            header_size,
+            depth: open.len(),
+        });
+
+        let payload_size = end.map(|x| x - pos - header_size);
+        let fields_size = dump::children_offset(&box_type)
+            .map(|x| x as u64)
+            .filter(|x| payload_size.is_none_or(|size| *x <= size));
+
+        let skipped = match (fields_size, payload_size) {
+            (Some(fields_size), _) => fields_size,
+            (None, Some(payload_size)) => payload_size,
+            (None, None) => u64::MAX,
+        };
+        let read =
+            io::copy(&mut (&mut reader).take(skipped), &mut io::sink()).map_err(Error::new_io)?;
+
+        if read < skipped && (fields_size.is_some() || payload_size.is_some()) {
+            return Err(truncated(&open, box_type, pos));
+        }
+
+        open.push((box_type, pos, end));
+        pos += header_size + read;
+
+        if fields_size.is_none() {
+            // Boxes which aren't walked into end right away.
+            pos = end.unwrap_or(pos);
+        }
+    }
+
+    while let Some((box_type, offset, _)) = open.pop() {
+        handler(BoxEvent::End {
+            box_type,
+            offset,
+            depth: open.len(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Read until `buf` is full or the stream ends, returns the number of bytes read.
+pub(crate) fn read_full(reader: &mut (impl Read + ?Sized), buf: &mut [u8]) -> Result<usize, Error> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(x) => read += x,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(Error::new_io(e)),
+        }
+    }
+
+    Ok(read)
+}
+
+fn malformed(box_type: [u8; 4], offset: u64, msg: &str) -> Error {
+    Error {
+        msg: format!(
+            "{} box at offset {} {}.",
+            boxes::fourcc(&box_type),
+            offset,
+            msg
+        ),
+        err_type: ErrorType::MalformedBox { box_type, offset },
+        invalid_key: false,
+    }
+}
+
+/// Error of a box whose header or payload is cut off by the end of the stream, reported for the outermost box
+/// which doesn't extend to the end of the stream.
+fn truncated(open: &[([u8; 4], u64, Option<u64>)], box_type: [u8; 4], offset: u64) -> Error {
+    let (box_type, offset) = open
+        .iter()
+        .find(|(_, _, end)| end.is_some())
+        .map_or((box_type, offset), |(x, y, _)| (*x, *y));
+    malformed(box_type, offset, "is truncated by the end of the stream")
+}
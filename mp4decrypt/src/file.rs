@@ -0,0 +1,225 @@
+use crate::{
+    DecryptOptions, Error, KeyMap, SegmentDecryptor, boxes, decrypt, mp4decrypt_with_options,
+    split,
+    verify::{self, VerifyResult},
+};
+use std::{
+    fs,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+/// Decrypt an encrypted mp4 file and write the decrypted data to another file.
+///
+/// With the `mmap` feature enabled, the input file is memory-mapped and passed to Bento4 without
+/// copying. Reading falls back to a buffered read if mapping isn't possible (e.g. special files).
+///
+/// # Arguments
+///
+/// * `input` - Path of encrypted file.
+/// * `output` - Path of decrypted file, it is overwritten if it already exists.
+/// * `keys` - Keys for decrypting the file.
+/// * `fragments_info` (optional) - Init segment of `input`, same as [mp4decrypt](crate::mp4decrypt).
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// mp4decrypt::decrypt_file("encrypted.mp4", "decrypted.mp4", &keys, None).unwrap();
+/// ```
+pub fn decrypt_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+) -> Result<(), Error> {
+    let file = File::open(input).map_err(Error::new_io)?;
+
+    #[cfg(feature = "mmap")]
+    let decrypted_data = {
+        // The mapping is kept alive until decryption returns.
+        match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(mmap) => decrypt(&mmap, keys, fragments_info)?,
+            Err(_) => decrypt(&read_file(file)?, keys, fragments_info)?,
+        }
+    };
+
+    #[cfg(not(feature = "mmap"))]
+    let decrypted_data = decrypt(&read_file(file)?, keys, fragments_info)?;
+
+    fs::write(output, decrypted_data).map_err(Error::new_io)
+}
+
+/// Decrypt every fragment of a fragmented file (or media segments) and write each one to its own file,
+/// e.g. for splitting a downloaded file back into media segments.
+///
+/// Every fragment holds a `moof` box, its `mdat` box and any box before the `moof` box which follows
+/// the previous fragment (e.g. `styp` and `sidx`). The init segment itself isn't written, see
+/// [decrypt_prefix](crate::decrypt_prefix) with zero fragments for decrypting it.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream.
+/// * `init` (optional) - Init segment, if `data` doesn't start with one.
+/// * `keys` - Keys for decrypting the fragments.
+/// * `out_dir` - Directory of the decrypted fragments, it is created if it doesn't exist.
+/// * `name_fn` - File name of a decrypted fragment from its index, starting from 0.
+///
+/// Returns the paths of the written files, in order.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::KeyMap;
+/// use std::path::Path;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let data = std::fs::read("encrypted.mp4").unwrap();
+/// let paths = mp4decrypt::decrypt_to_dir(&data, None, &keys, Path::new("segments"), |i| {
+///     format!("segment_{}.m4s", i)
+/// })
+/// .unwrap();
+/// ```
+pub fn decrypt_to_dir<F: Fn(usize) -> String>(
+    data: &[u8],
+    init: Option<&[u8]>,
+    keys: &KeyMap,
+    out_dir: &Path,
+    name_fn: F,
+) -> Result<Vec<PathBuf>, Error> {
+    let split = split::split_fragments(data)?;
+    let fragments_info = init.or((!split.init.is_empty()).then_some(split.init));
+    let mut paths = Vec::with_capacity(split.fragments.len());
+
+    fs::create_dir_all(out_dir).map_err(Error::new_io)?;
+
+    for (i, fragment) in split.fragments.iter().enumerate() {
+        let path = out_dir.join(name_fn(i));
+        fs::write(&path, decrypt(fragment, keys, fragments_info)?).map_err(Error::new_io)?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+/// Verify every media segment of a directory holding an init segment and its media segments, e.g. for checking
+/// a packaged asset against its keys.
+///
+/// The init segment is the file with a `moov` box and no `moof` box. Every other file is decrypted using it,
+/// unless it has its own `moov` box, and verified the same way as [decrypt_try_keys](crate::decrypt_try_keys):
+/// no track may be left encrypted and the samples of H.264, H.265 and AAC tracks must have valid codec framing.
+/// Files which don't start with an mp4 box (e.g. manifests) are skipped, and subdirectories aren't walked.
+/// With the `rayon` feature, segments are verified in parallel on the global rayon pool.
+///
+/// Returns the verification result of every media segment, sorted by path. An error is returned if the directory
+/// can't be read, it holds several init segments or its init segment can't be decrypted.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+/// use std::path::Path;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let results = mp4decrypt::verify_directory(Path::new("examples/sample"), &keys).unwrap();
+/// assert_eq!(results.len(), 1);
+/// assert!(results[0].0.ends_with("segment_0.m4s"));
+/// assert!(results[0].1.is_passed());
+/// ```
+pub fn verify_directory(dir: &Path, keys: &KeyMap) -> Result<Vec<(PathBuf, VerifyResult)>, Error> {
+    let mut paths = Vec::new();
+
+    for entry in fs::read_dir(dir).map_err(Error::new_io)? {
+        let entry = entry.map_err(Error::new_io)?;
+
+        if entry.file_type().map_err(Error::new_io)?.is_file() {
+            paths.push(entry.path());
+        }
+    }
+
+    paths.sort();
+
+    let mut init: Option<(PathBuf, Vec<u8>)> = None;
+    // Every media segment and whether it has its own moov box.
+    let mut segments = Vec::new();
+
+    for path in paths {
+        let data = fs::read(&path).map_err(Error::new_io)?;
+
+        if !matches!(boxes::boxes(&data).next(), Some(Ok(_))) {
+            continue;
+        }
+
+        let has = |box_type| matches!(boxes::find(boxes::boxes(&data), box_type), Ok(Some(_)));
+
+        match (has(b"moov"), has(b"moof")) {
+            (true, false) => match &init {
+                Some((x, _)) => {
+                    return Err(Error::new_format(format!(
+                        "directory {} has several init segments: {} and {}.",
+                        dir.display(),
+                        x.display(),
+                        path.display()
+                    )));
+                }
+                None => init = Some((path, data)),
+            },
+            (has_moov, _) => segments.push((path, has_moov)),
+        }
+    }
+
+    let init = init.map(|(_, x)| x);
+    // Track configs are read from the decrypted init segment, which has clear sample entries.
+    let decrypted_init = init
+        .as_deref()
+        .map(|x| SegmentDecryptor::new(x, keys)?.decrypt_init())
+        .transpose()?;
+    let options = DecryptOptions::new().validate_structure(true);
+
+    let verify_segment = |(path, has_moov): &(PathBuf, bool)| {
+        let (init, decrypted_init) = match has_moov {
+            true => (None, None),
+            false => (init.as_deref(), decrypted_init.as_deref()),
+        };
+        let result = fs::read(path).map_err(Error::new_io).and_then(|data| {
+            let decrypted_data = mp4decrypt_with_options(&data, keys, init, &options)?;
+            verify::verify_media(&decrypted_data, decrypted_init)
+        });
+
+        let result = match result {
+            Ok(()) => VerifyResult::Passed,
+            Err(x) => VerifyResult::Failed(x),
+        };
+        (path.clone(), result)
+    };
+
+    #[cfg(feature = "rayon")]
+    let results = {
+        use rayon::prelude::*;
+        segments.par_iter().map(verify_segment).collect()
+    };
+
+    #[cfg(not(feature = "rayon"))]
+    let results = segments.iter().map(verify_segment).collect();
+
+    Ok(results)
+}
+
+fn read_file(mut file: File) -> Result<Vec<u8>, Error> {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).map_err(Error::new_io)?;
+    Ok(data)
+}
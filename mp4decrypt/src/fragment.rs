@@ -0,0 +1,146 @@
+use crate::{
+    Error, KeyMap, SidxEntry, boxes, decrypt, decrypt_raw,
+    keys::{self, KeyPairs, KeyStrings},
+};
+use std::io::{Read, Seek, SeekFrom};
+
+/// Decrypt a single fragment located by a `sidx` entry without reading the whole stream.
+///
+/// # Arguments
+///
+/// * `reader` - Seekable encrypted stream, the same one which `entry` offsets refer to.
+/// * `entry` - Location of the fragment, see [read_sidx](crate::read_sidx).
+/// * `init` - Init segment (`ftyp` + `moov`) holding the track info of the fragment.
+/// * `keys` - Keys for decrypting the fragment.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::KeyMap;
+/// use std::fs::File;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let data = std::fs::read("video.mp4").unwrap();
+/// let index = mp4decrypt::read_sidx(&data).unwrap().unwrap();
+/// let init = &data[..index.entries[0].offset as usize];
+///
+/// let fragment = mp4decrypt::decrypt_fragment_at(
+///     File::open("video.mp4").unwrap(),
+///     &index.entries[3],
+///     init,
+///     &keys,
+/// )
+/// .unwrap();
+/// ```
+pub fn decrypt_fragment_at<R: Read + Seek>(
+    mut reader: R,
+    entry: &SidxEntry,
+    init: &[u8],
+    keys: &KeyMap,
+) -> Result<Vec<u8>, Error> {
+    reader
+        .seek(SeekFrom::Start(entry.offset))
+        .map_err(Error::new_io)?;
+
+    let mut fragment = Vec::new();
+    reader
+        .take(entry.size as u64)
+        .read_to_end(&mut fragment)
+        .map_err(Error::new_io)?;
+
+    if fragment.len() != entry.size as usize {
+        return Err(Error::new_format(format!(
+            "expected {} bytes of fragment at offset {} but only {} bytes are available.",
+            entry.size,
+            entry.offset,
+            fragment.len()
+        )));
+    }
+
+    if boxes::find(boxes::boxes(&fragment), b"moof")?.is_none() {
+        return Err(Error::new_format(format!(
+            "no moof box found in the fragment at offset {}.",
+            entry.offset
+        )));
+    }
+
+    decrypt(&fragment, keys, Some(init))
+}
+
+/// Decrypt a single fragment with track keys, passing it straight to Bento4 along with an init segment which
+/// the caller has already parsed, for custom pipelines where the checks of [mp4decrypt](crate::mp4decrypt)
+/// on every fragment cost too much.
+///
+/// Neither the fragment nor the init segment is parsed or validated and keys aren't matched with the tracks,
+/// so the caller must make sure that:
+///
+/// * `fragment` holds complete `moof` and `mdat` boxes, and no `moov` box.
+/// * `init` is the init segment (`ftyp` + `moov`) of the fragment, holding the protected sample entries and
+///   `trex` boxes of its tracks.
+/// * Every encrypted track of the fragment has a key in `track_keys`, otherwise Bento4 leaves it encrypted.
+/// * Tracks use a scheme supported by Bento4 and have no sample groups of unencrypted samples (`seig`), which
+///   are only restored by [mp4decrypt](crate::mp4decrypt). Sample entries with several `sinf` boxes must have
+///   the one to use first.
+///
+/// Malformed input is handled by Bento4 alone, like [decrypt_raw](crate::decrypt_raw).
+///
+/// # Arguments
+///
+/// * `fragment` - Encrypted fragment (`moof` + `mdat`).
+/// * `track_keys` - Track IDs and their 128-bit keys.
+/// * `init` - Init segment of the fragment.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+/// let key = mp4decrypt::parse_hex16("100b6c20940f779a4589152b57d2dacb").unwrap();
+///
+/// let decrypted_data = mp4decrypt::decrypt_one_fragment(&segment, &[(1, key)], &init).unwrap();
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert_track(1, key);
+/// let options = mp4decrypt::DecryptOptions::new();
+/// assert_eq!(
+///     decrypted_data,
+///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap()
+/// );
+/// ```
+pub fn decrypt_one_fragment(
+    fragment: &[u8],
+    track_keys: &[(u32, [u8; 16])],
+    init: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let key_pairs = KeyPairs(
+        track_keys
+            .iter()
+            .map(|(track_id, key)| (track_id.to_string(), keys::to_hex(key)))
+            .collect(),
+    );
+    let mut c_kids_holder = KeyStrings::default();
+    let mut c_keys_holder = KeyStrings::default();
+
+    for (id, key) in key_pairs.iter() {
+        c_kids_holder.push(id)?;
+        c_keys_holder.push(key)?;
+    }
+
+    let c_kids = c_kids_holder
+        .0
+        .iter()
+        .map(|x| x.as_c_str())
+        .collect::<Vec<_>>();
+    let c_keys = c_keys_holder
+        .0
+        .iter()
+        .map(|x| x.as_c_str())
+        .collect::<Vec<_>>();
+
+    decrypt_raw(fragment, &c_kids, &c_keys, Some(init))
+}
@@ -0,0 +1,419 @@
+use crate::{
+    Error, ErrorType,
+    boxes::{self, Mp4Box},
+    metadata::{self, Mp4Metadata, TrackEncryption},
+    moof::{self, FragmentSample},
+    protection::{self, SeigEntry},
+    reader::Reader,
+    verify,
+};
+use std::collections::HashMap;
+
+/// Position of a sample, which is the same in the encrypted and the decrypted data stream.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct SampleKey {
+    /// Ordinal of the `moof` box, `None` for samples described by the `moov` box.
+    moof: Option<usize>,
+    /// Ordinal of the `traf` box within its `moof`, or of the `trak` box within the `moov`.
+    track: usize,
+    sample: usize,
+}
+
+/// Samples of protected tracks which are marked as unencrypted by their `seig` sample group.
+///
+/// Bento4 ignores sample groups and decrypts these samples using the `tenc` box of their track,
+/// so they are copied back from the input once Bento4 is done.
+#[derive(Default)]
+pub(crate) struct ClearSamples(HashMap<SampleKey, FragmentSample>);
+
+/// A protected track with its `tenc` box and the `seig` sample group descriptions of its `stbl` box.
+struct ProtectedTrack {
+    tenc: TrackEncryption,
+    descriptions: Vec<SeigEntry>,
+}
+
+impl ClearSamples {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Find the unencrypted samples of a data stream.
+    ///
+    /// An [ErrorType::UnsupportedSampleGroup] error is returned for sample groups which Bento4 can't decrypt,
+    /// i.e. samples of a track fragment encrypted with different KIDs or a `senc` box whose layout depends on
+    /// the sample groups.
+    pub(crate) fn find(data: &[u8], fragments_info: Option<&[u8]>) -> Result<Self, Error> {
+        let mut clear_samples = Self::default();
+        let init = fragments_info.unwrap_or(data);
+
+        let Some(moov) = boxes::find(boxes::boxes(init), b"moov")? else {
+            return Ok(clear_samples);
+        };
+
+        let metadata = Mp4Metadata::parse(init)?;
+        let mut tracks = HashMap::new();
+
+        for (index, trak) in moov.children().enumerate() {
+            let trak = trak?;
+
+            if &trak.box_type != b"trak" {
+                continue;
+            }
+
+            let track_id = metadata::read_trak(&trak)?.track_id;
+            let Some(tenc) = metadata.tenc().iter().find(|x| x.track_id == track_id) else {
+                continue;
+            };
+            let stbl = [b"mdia", b"minf", b"stbl"]
+                .iter()
+                .try_fold(Some(trak), |parent, x| match parent {
+                    Some(parent) => parent.child(x),
+                    None => Ok(None),
+                })?;
+            let track = ProtectedTrack {
+                tenc: tenc.clone(),
+                descriptions: match stbl {
+                    Some(stbl) => read_descriptions(&stbl)?,
+                    None => Vec::new(),
+                },
+            };
+
+            // Samples of a non-fragmented file are grouped by the sbgp box of their stbl box.
+            if let (None, Some(stbl)) = (fragments_info, stbl)
+                && let Some(sbgp) = find_sbgp(&stbl)?
+            {
+                let samples = verify::read_stbl_samples(&trak)?;
+                clear_samples.insert(&track, &[], &sbgp, samples, None, index)?;
+            }
+
+            tracks.insert(track_id, track);
+        }
+
+        if tracks.is_empty() {
+            return Ok(clear_samples);
+        }
+
+        let trex_sizes = moof::read_trex_sizes(init)?;
+
+        for (index, moof) in boxes::boxes(data)
+            .filter(|x| x.as_ref().map_or(true, |x| &x.box_type == b"moof"))
+            .enumerate()
+        {
+            let moof = moof?;
+
+            if !moof
+                .children()
+                .map_while(Result::ok)
+                .any(|x| x.child(b"sbgp").is_ok_and(|x| x.is_some()))
+            {
+                continue;
+            }
+
+            for (track, fragment) in moof::read_track_fragments(&moof, &trex_sizes)?
+                .into_iter()
+                .enumerate()
+            {
+                let (Some(protected_track), Some(sbgp)) = (
+                    tracks.get(&fragment.track_id),
+                    find_sbgp(&fragment.mp4_box)?,
+                ) else {
+                    continue;
+                };
+
+                let local_descriptions = read_descriptions(&fragment.mp4_box)?;
+                let samples = fragment
+                    .runs
+                    .into_iter()
+                    .flat_map(|x| x.samples)
+                    .collect::<Vec<_>>();
+                let needs_senc_check = clear_samples.insert(
+                    protected_track,
+                    &local_descriptions,
+                    &sbgp,
+                    samples,
+                    Some(index),
+                    track,
+                )?;
+
+                let senc = match needs_senc_check {
+                    true => fragment.mp4_box.child(b"senc")?,
+                    false => None,
+                };
+
+                if let Some(senc) =
+                    senc.filter(|x| !senc_fits(x, protected_track.tenc.per_sample_iv_size))
+                {
+                    return Err(Error {
+                        msg: format!(
+                            "senc box of track {} at offset {} uses the IV sizes of its sample groups, \
+                             which Bento4 can't parse.",
+                            protected_track.tenc.track_id, senc.offset
+                        ),
+                        err_type: ErrorType::UnsupportedSampleGroup {
+                            track_id: protected_track.tenc.track_id,
+                        },
+                        invalid_key: false,
+                    });
+                }
+            }
+        }
+
+        Ok(clear_samples)
+    }
+
+    /// Insert the unencrypted samples of a track fragment (or a track), returning whether any sample
+    /// has a different IV size than the `tenc` box of its track.
+    ///
+    /// Every encrypted sample must use the same KID, since Bento4 decrypts a track fragment using a single key.
+    /// A KID which differs from the `tenc` box for every sample (key rotation) is fine, as long as its key is
+    /// given for the track ID or the default KID.
+    fn insert(
+        &mut self,
+        track: &ProtectedTrack,
+        local_descriptions: &[SeigEntry],
+        sbgp: &Mp4Box,
+        samples: Vec<FragmentSample>,
+        moof: Option<usize>,
+        track_index: usize,
+    ) -> Result<bool, Error> {
+        let tenc = &track.tenc;
+        let mut different_iv_size = false;
+        let mut fragment_kid = None;
+
+        for (sample, (location, group)) in samples
+            .into_iter()
+            .zip(read_sbgp(sbgp)?.chain(std::iter::repeat(0)))
+            .enumerate()
+        {
+            let entry = match group {
+                0 => None,
+                1..=0x10000 => Some(track.descriptions.get(group as usize - 1)),
+                _ => Some(local_descriptions.get(group as usize - 0x10001)),
+            }
+            .map(|x| {
+                x.ok_or_else(|| {
+                    Error::new_format(format!(
+                        "sbgp box at offset {} references a missing sample group description {}.",
+                        sbgp.offset, group
+                    ))
+                })
+            })
+            .transpose()?;
+
+            let (kid, per_sample_iv_size) = match entry {
+                Some(entry) if !entry.is_protected => {
+                    different_iv_size |= tenc.per_sample_iv_size != 0;
+                    self.0.insert(
+                        SampleKey {
+                            moof,
+                            track: track_index,
+                            sample,
+                        },
+                        location,
+                    );
+                    continue;
+                }
+                Some(entry) => (entry.kid, entry.per_sample_iv_size),
+                None => (tenc.kid, tenc.per_sample_iv_size),
+            };
+
+            different_iv_size |= per_sample_iv_size != tenc.per_sample_iv_size;
+
+            if *fragment_kid.get_or_insert(kid) != kid {
+                return Err(Error {
+                    msg: format!(
+                        "samples of track {} grouped by the sbgp box at offset {} are encrypted \
+                         with different KIDs, which Bento4 can't decrypt.",
+                        tenc.track_id, sbgp.offset
+                    ),
+                    err_type: ErrorType::UnsupportedSampleGroup {
+                        track_id: tenc.track_id,
+                    },
+                    invalid_key: false,
+                });
+            }
+        }
+
+        Ok(different_iv_size)
+    }
+
+    /// Copy the unencrypted samples from the input data stream to the decrypted data stream.
+    pub(crate) fn restore(
+        &self,
+        data: &[u8],
+        fragments_info: Option<&[u8]>,
+        decrypted_data: &mut [u8],
+    ) -> Result<(), Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let mut locations = Vec::with_capacity(self.0.len());
+        let init = match fragments_info {
+            Some(x) => x,
+            None => {
+                if let Some(moov) = boxes::find(boxes::boxes(decrypted_data), b"moov")? {
+                    for (index, trak) in moov.children().enumerate() {
+                        let trak = trak?;
+
+                        if &trak.box_type == b"trak" {
+                            self.locate(
+                                verify::read_stbl_samples(&trak)?,
+                                None,
+                                index,
+                                &mut locations,
+                            );
+                        }
+                    }
+                }
+
+                &*decrypted_data
+            }
+        };
+
+        let trex_sizes = moof::read_trex_sizes(init)?;
+
+        for (index, moof) in boxes::boxes(decrypted_data)
+            .filter(|x| x.as_ref().map_or(true, |x| &x.box_type == b"moof"))
+            .enumerate()
+        {
+            for (track, fragment) in moof::read_track_fragments(&moof?, &trex_sizes)?
+                .into_iter()
+                .enumerate()
+            {
+                let samples = fragment.runs.into_iter().flat_map(|x| x.samples);
+                self.locate(samples, Some(index), track, &mut locations);
+            }
+        }
+
+        if locations.len() != self.0.len() {
+            return Err(Error::new_format(
+                "unencrypted samples couldn't be located in the decrypted data stream.",
+            ));
+        }
+
+        for (input, output) in locations {
+            let sample = (input.size == output.size)
+                .then(|| {
+                    data.get(input.offset as usize..)?
+                        .get(..input.size as usize)
+                })
+                .flatten()
+                .ok_or_else(|| {
+                    Error::new_format(format!(
+                        "unencrypted sample at offset {} doesn't match its decrypted sample.",
+                        input.offset
+                    ))
+                })?;
+
+            decrypted_data
+                .get_mut(output.offset as usize..)
+                .and_then(|x| x.get_mut(..sample.len()))
+                .ok_or_else(|| {
+                    Error::new_format(format!(
+                        "decrypted sample at offset {} is out of bounds.",
+                        output.offset
+                    ))
+                })?
+                .copy_from_slice(sample);
+        }
+
+        Ok(())
+    }
+
+    /// Pair the unencrypted input samples of a track fragment (or a track) with their decrypted samples.
+    fn locate(
+        &self,
+        samples: impl IntoIterator<Item = FragmentSample>,
+        moof: Option<usize>,
+        track: usize,
+        locations: &mut Vec<(FragmentSample, FragmentSample)>,
+    ) {
+        for (sample, output) in samples.into_iter().enumerate() {
+            let key = SampleKey {
+                moof,
+                track,
+                sample,
+            };
+
+            if let Some(input) = self.0.get(&key) {
+                locations.push((input.clone(), output));
+            }
+        }
+    }
+}
+
+/// Read the `seig` sample group descriptions of a `stbl` or `traf` box.
+fn read_descriptions(mp4_box: &Mp4Box) -> Result<Vec<SeigEntry>, Error> {
+    for child in mp4_box.children() {
+        let child = child?;
+
+        if &child.box_type == b"sgpd"
+            && let Some(entries) = protection::read_seig_entries(&child)?
+        {
+            return Ok(entries);
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+/// Find the `sbgp` box with the `seig` grouping type of a `stbl` or `traf` box.
+fn find_sbgp<'a>(mp4_box: &Mp4Box<'a>) -> Result<Option<Mp4Box<'a>>, Error> {
+    for child in mp4_box.children() {
+        let child = child?;
+
+        if &child.box_type == b"sbgp" && child.payload().get(4..8) == Some(b"seig") {
+            return Ok(Some(child));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read the group description index of every sample from a `sbgp` box.
+fn read_sbgp<'a>(sbgp: &Mp4Box<'a>) -> Result<impl Iterator<Item = u32> + 'a, Error> {
+    let mut reader = Reader::new(sbgp.payload());
+    let (version, _) = reader.read_version_flags()?;
+    reader.skip(4)?; // grouping_type
+    if version == 1 {
+        reader.skip(4)?; // grouping_type_parameter
+    }
+    let entry_count = reader.read_u32()?;
+    let mut entries = Vec::with_capacity((entry_count as usize).min(reader.remaining() / 8));
+
+    for _ in 0..entry_count {
+        entries.push((reader.read_u32()?, reader.read_u32()?));
+    }
+
+    Ok(entries
+        .into_iter()
+        .flat_map(|(sample_count, group)| std::iter::repeat_n(group, sample_count as usize)))
+}
+
+/// Check whether a `senc` box can be parsed using the IV size of the `tenc` box, as Bento4 does.
+fn senc_fits(senc: &Mp4Box, iv_size: u8) -> bool {
+    let mut reader = Reader::new(senc.payload());
+    let (Ok((_, flags)), Ok(sample_count)) = (reader.read_version_flags(), reader.read_u32())
+    else {
+        return false;
+    };
+
+    for _ in 0..sample_count {
+        if reader.skip(iv_size as usize).is_err() {
+            return false;
+        }
+
+        if flags & 0x02 != 0 {
+            let Ok(subsample_count) = reader.read_u16() else {
+                return false;
+            };
+
+            if reader.skip(subsample_count as usize * 6).is_err() {
+                return false;
+            }
+        }
+    }
+
+    reader.remaining() == 0
+}
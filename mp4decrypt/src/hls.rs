@@ -0,0 +1,177 @@
+use crate::{Error, Scheme, keys};
+
+/// Encryption method of an HLS `#EXT-X-KEY` tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HlsKeyMethod {
+    /// Segments aren't encrypted.
+    None,
+    /// Sample encryption, which is the `cbcs` scheme for fragmented mp4 segments.
+    SampleAes,
+    /// Sample encryption in AES-CTR mode, which is the `cenc` scheme for fragmented mp4 segments.
+    SampleAesCtr,
+}
+
+impl HlsKeyMethod {
+    /// Protection scheme of fragmented mp4 segments using this method.
+    pub fn scheme(&self) -> Option<Scheme> {
+        match self {
+            Self::None => None,
+            Self::SampleAes => Some(Scheme::Cbcs),
+            Self::SampleAesCtr => Some(Scheme::Cenc),
+        }
+    }
+}
+
+/// A parsed HLS `#EXT-X-KEY` (or `#EXT-X-SESSION-KEY`) tag.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HlsKeyInfo {
+    pub method: HlsKeyMethod,
+    /// Where to fetch the key from, `None` only if the method is [HlsKeyMethod::None](HlsKeyMethod::None).
+    pub uri: Option<String>,
+    /// Constant IV of the segments, see [DecryptOptions::constant_ivs](crate::DecryptOptions::constant_ivs).
+    pub iv: Option<[u8; 16]>,
+    /// How the key is represented at the URI, `identity` (the raw 16 byte key) if it isn't given.
+    pub key_format: Option<String>,
+    pub key_format_versions: Option<String>,
+}
+
+/// Parse an HLS `#EXT-X-KEY` (or `#EXT-X-SESSION-KEY`) tag of a playlist.
+///
+/// Its URI, IV and key format are read from the attribute list, unknown attributes are ignored.
+/// The `AES-128` method (encryption of complete segments) is rejected, since segments have to be decrypted
+/// as a whole before they can be parsed as mp4, rather than by decrypting the samples of their boxes.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{DecryptOptions, HlsKeyMethod, Scheme};
+/// use std::collections::HashMap;
+///
+/// let info = mp4decrypt::parse_ext_x_key(
+///     r#"#EXT-X-KEY:METHOD=SAMPLE-AES,URI="https://example.com/key?a=1,2",IV=0x0A0B0C0D0E0F00010203040506070809,KEYFORMAT="identity""#,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(info.method, HlsKeyMethod::SampleAes);
+/// assert_eq!(info.method.scheme(), Some(Scheme::Cbcs));
+/// assert_eq!(info.uri.as_deref(), Some("https://example.com/key?a=1,2"));
+/// assert_eq!(info.key_format.as_deref(), Some("identity"));
+///
+/// // IV of the segments of track 1, whose key is fetched from the URI
+/// let iv = info.iv.unwrap();
+/// assert_eq!(iv[..4], [0x0a, 0x0b, 0x0c, 0x0d]);
+/// let options = DecryptOptions::new().constant_ivs(HashMap::from([(1, iv.to_vec())]));
+///
+/// assert!(mp4decrypt::parse_ext_x_key(r#"#EXT-X-KEY:METHOD=AES-128,URI="key.bin""#).is_err());
+/// assert!(mp4decrypt::parse_ext_x_key("#EXT-X-KEY:METHOD=NONE").is_ok());
+/// assert!(mp4decrypt::parse_ext_x_key("#EXT-X-KEY:METHOD=SAMPLE-AES").is_err());
+/// assert!(mp4decrypt::parse_ext_x_key(r#"#EXT-X-KEY:METHOD=SAMPLE-AES,URI="key.bin",IV=0x0A0B"#).is_err());
+/// assert!(mp4decrypt::parse_ext_x_key(r#"#EXT-X-KEY:METHOD=SAMPLE-AES,URI="key.bin"#).is_err());
+/// ```
+pub fn parse_ext_x_key(line: &str) -> Result<HlsKeyInfo, Error> {
+    let line = line.trim();
+    let attributes = line
+        .strip_prefix("#EXT-X-KEY:")
+        .or_else(|| line.strip_prefix("#EXT-X-SESSION-KEY:"))
+        .ok_or_else(|| Error::new_format(format!("{:?} is not an EXT-X-KEY tag.", line)))?;
+
+    let mut method = None;
+    let mut info = HlsKeyInfo {
+        method: HlsKeyMethod::None,
+        uri: None,
+        iv: None,
+        key_format: None,
+        key_format_versions: None,
+    };
+
+    for (name, value) in parse_attributes(attributes)? {
+        match name {
+            "METHOD" => method = Some(value),
+            "URI" => info.uri = Some(quoted(name, value)?.to_owned()),
+            "IV" => info.iv = Some(parse_iv(value)?),
+            "KEYFORMAT" => info.key_format = Some(quoted(name, value)?.to_owned()),
+            "KEYFORMATVERSIONS" => info.key_format_versions = Some(quoted(name, value)?.to_owned()),
+            _ => (),
+        }
+    }
+
+    info.method = match method {
+        Some("NONE") => HlsKeyMethod::None,
+        Some("SAMPLE-AES") => HlsKeyMethod::SampleAes,
+        Some("SAMPLE-AES-CTR") => HlsKeyMethod::SampleAesCtr,
+        Some("AES-128") => {
+            return Err(Error::new_format(
+                "AES-128 method encrypts complete segments rather than mp4 samples, decrypt the segments with AES-128-CBC instead.",
+            ));
+        }
+        Some(x) => {
+            return Err(Error::new_format(format!(
+                "unsupported EXT-X-KEY method {}.",
+                x
+            )));
+        }
+        None => return Err(Error::new_format("EXT-X-KEY tag has no METHOD attribute.")),
+    };
+
+    if info.method != HlsKeyMethod::None && info.uri.is_none() {
+        return Err(Error::new_format("EXT-X-KEY tag has no URI attribute."));
+    }
+
+    Ok(info)
+}
+
+/// Split an attribute list into names and raw values, keeping commas inside quoted strings.
+fn parse_attributes(attributes: &str) -> Result<Vec<(&str, &str)>, Error> {
+    let mut parsed = Vec::new();
+    let mut rest = attributes;
+
+    while !rest.is_empty() {
+        let (name, value) = rest.split_once('=').ok_or_else(|| {
+            Error::new_format(format!("EXT-X-KEY attribute {:?} has no value.", rest))
+        })?;
+
+        let end = if let Some(quoted) = value.strip_prefix('"') {
+            // including both quotes
+            quoted.find('"').map(|x| x + 2).ok_or_else(|| {
+                Error::new_format(format!(
+                    "EXT-X-KEY attribute {} has an unterminated quoted string.",
+                    name
+                ))
+            })?
+        } else {
+            value.find(',').unwrap_or(value.len())
+        };
+
+        parsed.push((name.trim(), &value[..end]));
+        rest = value[end..].trim_start_matches(',');
+    }
+
+    Ok(parsed)
+}
+
+fn quoted<'a>(name: &str, value: &'a str) -> Result<&'a str, Error> {
+    value
+        .strip_prefix('"')
+        .and_then(|x| x.strip_suffix('"'))
+        .ok_or_else(|| {
+            Error::new_format(format!(
+                "EXT-X-KEY attribute {} is not a quoted string.",
+                name
+            ))
+        })
+}
+
+fn parse_iv(value: &str) -> Result<[u8; 16], Error> {
+    let hex = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .filter(|x| x.len() == 32)
+        .ok_or_else(|| {
+            Error::new_format(format!(
+                "EXT-X-KEY IV {} is not a 128-bit hex value.",
+                value
+            ))
+        })?;
+
+    keys::parse_hex16(hex).map_err(|x| Error::new_format(x.msg))
+}
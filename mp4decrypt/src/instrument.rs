@@ -0,0 +1,57 @@
+use crate::{BufSink, RawError};
+
+/// Record a decryption of `data` with the [metrics](https://docs.rs/metrics) facade, passing a sink which
+/// counts the decrypted bytes to `decrypt`.
+///
+/// - `mp4decrypt_decryptions_total` counter, labeled with `result`, which is either `ok` or the
+///   [ErrorType::as_str](crate::ErrorType::as_str) key of the error.
+/// - `mp4decrypt_input_bytes_total` and `mp4decrypt_output_bytes_total` counters.
+/// - `mp4decrypt_duration_seconds` histogram.
+#[cfg(feature = "metrics")]
+pub(crate) fn record(
+    data: &[u8],
+    sink: &mut dyn BufSink,
+    decrypt: impl FnOnce(&mut dyn BufSink) -> Result<(), RawError>,
+) -> Result<(), RawError> {
+    let start = std::time::Instant::now();
+    let mut sink = CountingSink { sink, written: 0 };
+    let result = decrypt(&mut sink);
+    let elapsed = start.elapsed();
+
+    let label = match &result {
+        Ok(_) => "ok",
+        Err(e) => e.as_str(),
+    };
+
+    ::metrics::counter!("mp4decrypt_decryptions_total", "result" => label).increment(1);
+    ::metrics::counter!("mp4decrypt_input_bytes_total").increment(data.len() as u64);
+    ::metrics::counter!("mp4decrypt_output_bytes_total").increment(sink.written);
+    ::metrics::histogram!("mp4decrypt_duration_seconds").record(elapsed);
+    result
+}
+
+/// Nothing is recorded without the **metrics** feature.
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn record(
+    _: &[u8],
+    sink: &mut dyn BufSink,
+    decrypt: impl FnOnce(&mut dyn BufSink) -> Result<(), RawError>,
+) -> Result<(), RawError> {
+    decrypt(sink)
+}
+
+#[cfg(feature = "metrics")]
+struct CountingSink<'a> {
+    sink: &'a mut dyn BufSink,
+    written: u64,
+}
+
+#[cfg(feature = "metrics")]
+impl BufSink for CountingSink<'_> {
+    fn write_chunk(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.sink.write_chunk(chunk)?;
+        self.written += chunk.len() as u64;
+        Ok(())
+    }
+}
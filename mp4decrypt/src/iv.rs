@@ -0,0 +1,138 @@
+use crate::{
+    Error,
+    boxes::{self, Mp4Box},
+    metadata,
+};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+/// Data stream and fragments info, with constant IVs replaced in one of them.
+pub(crate) type Input<'a> = (Cow<'a, [u8]>, Option<Cow<'a, [u8]>>);
+
+/// Replace the constant IVs of the `tenc` boxes of the given tracks, in the fragments info if it is given
+/// or in every `moov` box of the data stream otherwise, since Bento4 only reads IVs from the stream itself.
+///
+/// Every track must be protected with a constant IV of the same size as the given IV.
+pub(crate) fn override_constant_ivs<'a>(
+    data: &'a [u8],
+    fragments_info: Option<&'a [u8]>,
+    ivs: &HashMap<u32, Vec<u8>>,
+) -> Result<Input<'a>, Error> {
+    if ivs.is_empty() {
+        return Ok((Cow::Borrowed(data), fragments_info.map(Cow::Borrowed)));
+    }
+
+    let mut found = HashSet::new();
+    let input = match fragments_info {
+        Some(x) => (
+            Cow::Borrowed(data),
+            Some(Cow::Owned(patch(x, ivs, &mut found)?)),
+        ),
+        None => (Cow::Owned(patch(data, ivs, &mut found)?), None),
+    };
+
+    let mut missing = ivs
+        .keys()
+        .filter(|x| !found.contains(*x))
+        .collect::<Vec<_>>();
+    missing.sort();
+
+    if let Some(track_id) = missing.first() {
+        return Err(Error::new_format(format!(
+            "constant iv was given for track {} which isn't in the init segment.",
+            track_id
+        )));
+    }
+
+    Ok(input)
+}
+
+/// Copy data with the constant IVs of every `moov` box replaced, collecting the tracks which were found.
+fn patch(
+    data: &[u8],
+    ivs: &HashMap<u32, Vec<u8>>,
+    found: &mut HashSet<u32>,
+) -> Result<Vec<u8>, Error> {
+    let mut patched = data.to_vec();
+
+    for moov in boxes::boxes(data) {
+        let moov = moov?;
+
+        if &moov.box_type != b"moov" {
+            continue;
+        }
+
+        for trak in moov.children() {
+            let trak = trak?;
+
+            if &trak.box_type != b"trak" {
+                continue;
+            }
+
+            let track_id = metadata::read_trak(&trak)?.track_id;
+            let Some(iv) = ivs.get(&track_id) else {
+                continue;
+            };
+
+            let tenc_boxes = find_tencs(&trak)?;
+
+            if tenc_boxes.is_empty() {
+                return Err(Error::new_format(format!(
+                    "constant iv was given for track {} which has no tenc box.",
+                    track_id
+                )));
+            }
+
+            // The IV applies to every protected sample entry of the track.
+            for tenc_box in tenc_boxes {
+                let tenc = metadata::read_tenc(&tenc_box, track_id)?;
+
+                if tenc.constant_iv.is_none() {
+                    return Err(Error::new_format(format!(
+                        "constant iv was given for track {} which uses per sample ivs.",
+                        track_id
+                    )));
+                }
+
+                tenc.check_iv(iv)?;
+
+                // version, flags, reserved, pattern, is_protected, per_sample_iv_size, kid and constant_iv_size
+                let offset = tenc_box.offset + tenc_box.header_size + 25;
+                patched[offset..offset + iv.len()].copy_from_slice(iv);
+            }
+
+            found.insert(track_id);
+        }
+    }
+
+    Ok(patched)
+}
+
+/// Find the `tenc` boxes of every protected sample entry of a track.
+fn find_tencs<'a>(trak: &Mp4Box<'a>) -> Result<Vec<Mp4Box<'a>>, Error> {
+    let Some(stsd) = [b"mdia", b"minf", b"stbl", b"stsd"].iter().try_fold(
+        Some(*trak),
+        |parent, x| match parent {
+            Some(parent) => parent.child(x),
+            None => Ok(None),
+        },
+    )?
+    else {
+        return Ok(Vec::new());
+    };
+
+    let mut tencs = Vec::new();
+
+    for entry in stsd.children_after(8) {
+        if let Some(sinf) = metadata::read_sinf(&entry?)?
+            && let Some(schi) = sinf.child(b"schi")?
+            && let Some(tenc) = schi.child(b"tenc")?
+        {
+            tencs.push(tenc);
+        }
+    }
+
+    Ok(tencs)
+}
@@ -0,0 +1,463 @@
+use crate::Error;
+use base64::{
+    Engine,
+    alphabet::URL_SAFE,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+};
+use std::{
+    collections::BTreeMap,
+    collections::HashMap,
+    ffi::CString,
+    fmt::{self, Write},
+    ops::Deref,
+    path::Path,
+};
+
+/// Base64url engine used by EME ClearKey, which accepts input with or without padding.
+const CLEARKEY_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Identifier of a decryption key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum KeyId {
+    /// Track ID, e.g. use 1 as the track index for dcf files.
+    Track(u32),
+    /// 128-bit KID, only applicable to some encryption methods like MPEG-CENC.
+    Kid([u8; 16]),
+}
+
+impl fmt::Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Track(x) => write!(f, "{}", x),
+            Self::Kid(x) => write!(f, "{}", to_hex(x)),
+        }
+    }
+}
+
+/// Validated map of decryption keys.
+///
+/// Hex input is normalized before validation by stripping `0x` prefixes, whitespace and dashes,
+/// so keys copied from logs or UUID formatted KIDs can be used directly.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{KeyId, KeyMap};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert(
+///     "0xEB676ABBCB345E96BBCF616630F1A3DA",
+///     "10 0b 6c 20 94 0f 77 9a 45 89 15 2b 57 d2 da cb\n",
+/// )
+/// .unwrap();
+/// keys.insert("eb676abb-cb34-5e96-bbcf-616630f1a3db", "100b6c20940f779a4589152b57d2dacb").unwrap();
+/// keys.insert("2", "100b6c20940f779a4589152b57d2dacb").unwrap();
+///
+/// let kid = mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3da").unwrap();
+/// let key = mp4decrypt::parse_hex16("100b6c20940f779a4589152b57d2dacb").unwrap();
+/// assert_eq!(keys.get(&KeyId::Kid(kid)), Some(&key));
+/// assert_eq!(keys.get(&KeyId::Track(2)), Some(&key));
+/// assert_eq!(keys.len(), 3);
+///
+/// assert!(keys.insert("eb676abbcb345e96bbcf616630f1a3", "100b6c20940f779a4589152b57d2dacb").is_err());
+/// assert!(keys.insert("1", "0x100b6c20940f779a4589152b57d2dacbff").is_err());
+/// ```
+///
+/// Keys are redacted from the [Debug](fmt::Debug) output so that they don't leak into logs,
+/// use [reveal](Self::reveal) when they are really needed. With the `zeroize` feature, keys are also
+/// zeroized when the map is dropped, along with every copy prepared for Bento4 once decryption returns.
+///
+/// ```
+/// let mut keys = mp4decrypt::KeyMap::new();
+/// keys.insert("2", "100b6c20940f779a4589152b57d2dacb").unwrap();
+///
+/// let debug = format!("{:?}", keys);
+/// assert_eq!(debug, "KeyMap { keys: {Track(2): <redacted>} }");
+/// assert!(!debug.contains("100b6c20940f779a4589152b57d2dacb"));
+/// assert!(!debug.contains("16, 11, 108"));
+///
+/// assert_eq!(format!("{:?}", keys.reveal()), r#"{Track(2): "100b6c20940f779a4589152b57d2dacb"}"#);
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct KeyMap {
+    keys: BTreeMap<KeyId, [u8; 16]>,
+}
+
+impl KeyMap {
+    /// Create an empty key map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse and insert a key.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Either a track ID in decimal or a 128-bit KID in hex.
+    /// * `key` - 128-bit key in hex.
+    pub fn insert(&mut self, id: &str, key: &str) -> Result<(), Error> {
+        let id = parse_key_id(id)?;
+        let key = parse_hex16(key).map_err(|_| Error::new_key("invalid hex format for key."))?;
+        self.keys.insert(id, key);
+        Ok(())
+    }
+
+    /// Read a key map from a `.keys` file, which holds one `KID:KEY` pair per line in hex, e.g.
+    /// `eb676abbcb345e96bbcf616630f1a3da:100b6c20940f779a4589152b57d2dacb`.
+    ///
+    /// Blank lines and comments starting with `#` are skipped, and spaces around and within the KID and the key
+    /// are allowed. A track ID in decimal can be used instead of the KID, same as [insert](Self::insert).
+    /// The first malformed line is reported with its line number, starting from 1.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let keys = mp4decrypt::KeyMap::from_keys_file("content.keys").unwrap();
+    /// ```
+    pub fn from_keys_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(Error::new_io)?;
+        let mut keys = Self::new();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let result = match line.split_once(':') {
+                Some((id, key)) => keys.insert(id.trim(), key.trim()),
+                None => Err(Error::new_key("expected a KID:KEY pair.")),
+            };
+
+            if let Err(e) = result {
+                return Err(Error {
+                    msg: format!("line {} of {}: {}", i + 1, path.display(), e.msg),
+                    err_type: e.err_type,
+                    invalid_key: e.invalid_key,
+                });
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Insert a key for a 128-bit KID.
+    pub fn insert_kid(&mut self, kid: [u8; 16], key: [u8; 16]) {
+        self.keys.insert(KeyId::Kid(kid), key);
+    }
+
+    /// Insert a key for a track ID.
+    pub fn insert_track(&mut self, track_id: u32, key: [u8; 16]) {
+        self.keys.insert(KeyId::Track(track_id), key);
+    }
+
+    /// Key of a KID or track ID, KIDs are matched as parsed bytes regardless of the hex format used on insert.
+    pub fn get(&self, id: &KeyId) -> Option<&[u8; 16]> {
+        self.keys.get(id)
+    }
+
+    /// Iterate over the KIDs (as parsed bytes, not the inserted hex) and track IDs with their keys, sorted by ID.
+    pub fn iter(&self) -> impl Iterator<Item = (&KeyId, &[u8; 16])> {
+        self.keys.iter()
+    }
+
+    /// Number of keys, counting KIDs and track IDs.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether no key was inserted.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Debug view of the keys in hex, which unlike the debug output of the map itself isn't redacted.
+    pub fn reveal(&self) -> impl fmt::Debug + '_ {
+        Revealed(self)
+    }
+
+    /// Kid key pairs in the string format expected by Bento4.
+    pub(crate) fn key_pairs(&self) -> KeyPairs {
+        KeyPairs(
+            self.keys
+                .iter()
+                .map(|(id, key)| (id.to_string(), to_hex(key)))
+                .collect(),
+        )
+    }
+}
+
+impl Drop for KeyMap {
+    fn drop(&mut self) {
+        for key in self.keys.values_mut() {
+            wipe(key);
+        }
+    }
+}
+
+/// Kid key pairs in the string format expected by Bento4, keys are wiped on drop.
+#[derive(Default)]
+pub(crate) struct KeyPairs(pub(crate) Vec<(String, String)>);
+
+impl Deref for KeyPairs {
+    type Target = [(String, String)];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for KeyPairs {
+    fn drop(&mut self) {
+        for (_, key) in &mut self.0 {
+            wipe(key);
+        }
+    }
+}
+
+/// Nul terminated strings passed to Bento4, which are wiped on drop since some of them are keys.
+#[derive(Default)]
+pub(crate) struct KeyStrings(pub(crate) Vec<CString>);
+
+impl KeyStrings {
+    pub(crate) fn push(&mut self, value: &str) -> Result<(), Error> {
+        // Reserve the nul byte upfront, so that the string isn't reallocated leaving a copy behind.
+        let mut bytes = Vec::with_capacity(value.len() + 1);
+        bytes.extend_from_slice(value.as_bytes());
+
+        match CString::new(bytes) {
+            Ok(x) => {
+                self.0.push(x);
+                Ok(())
+            }
+            Err(x) => {
+                wipe(&mut x.into_vec());
+                Err(Error::new_key("key contains a nul byte."))
+            }
+        }
+    }
+}
+
+impl Drop for KeyStrings {
+    fn drop(&mut self) {
+        for x in &mut self.0 {
+            wipe(&mut std::mem::take(x).into_bytes_with_nul());
+        }
+    }
+}
+
+/// Overwrite key material before it is freed, which is only done with the `zeroize` feature.
+#[cfg(feature = "zeroize")]
+pub(crate) fn wipe<Z: zeroize::Zeroize + ?Sized>(value: &mut Z) {
+    value.zeroize();
+}
+
+#[cfg(not(feature = "zeroize"))]
+pub(crate) fn wipe<Z: ?Sized>(_: &mut Z) {}
+
+impl fmt::Debug for KeyMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyMap")
+            .field("keys", &RedactedKeys(&self.keys))
+            .finish()
+    }
+}
+
+struct RedactedKeys<'a>(&'a BTreeMap<KeyId, [u8; 16]>);
+
+impl fmt::Debug for RedactedKeys<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.keys().map(|x| (x, Redacted)))
+            .finish()
+    }
+}
+
+/// Placeholder of a key in debug output.
+struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+struct Revealed<'a>(&'a KeyMap);
+
+impl fmt::Debug for Revealed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.keys.iter().map(|(id, key)| (id, to_hex(key))))
+            .finish()
+    }
+}
+
+impl TryFrom<&HashMap<String, String>> for KeyMap {
+    type Error = Error;
+
+    fn try_from(value: &HashMap<String, String>) -> Result<Self, Self::Error> {
+        let mut keys = Self::new();
+
+        for (id, key) in value {
+            keys.insert(id, key)?;
+        }
+
+        Ok(keys)
+    }
+}
+
+/// Serialized as a JSON object of kid key pairs, same as the one expected by the C API.
+///
+/// # Example
+///
+/// ```
+/// let keys: mp4decrypt::KeyMap = serde_json::from_str(
+///     r#"{"EB676ABBCB345E96BBCF616630F1A3DA": "100b6c20940f779a4589152b57d2dacb", "2": "100b6c20940f779a4589152b57d2dacb"}"#,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(
+///     serde_json::to_string(&keys).unwrap(),
+///     r#"{"2":"100b6c20940f779a4589152b57d2dacb","eb676abbcb345e96bbcf616630f1a3da":"100b6c20940f779a4589152b57d2dacb"}"#
+/// );
+///
+/// assert!(serde_json::from_str::<mp4decrypt::KeyMap>(r#"{"2": "100b6c"}"#).is_err());
+/// ```
+#[cfg(feature = "serde")]
+impl serde::Serialize for KeyMap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.key_pairs().iter().map(|(id, key)| (id, key)))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for KeyMap {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = HashMap::<String, String>::deserialize(deserializer)?;
+        Self::try_from(&value).map_err(|x| serde::de::Error::custom(x.msg))
+    }
+}
+
+/// Parse a 128-bit value from hex, after stripping `0x` prefix, whitespace and dashes.
+///
+/// Errors don't contain the value, as it may be a key.
+///
+/// # Example
+///
+/// ```
+/// let error = mp4decrypt::parse_hex16("100b6c20940f779a4589152b57d2dacx").unwrap_err();
+/// assert_eq!(error.msg, "128-bit hex value has an invalid character at position 31.");
+/// ```
+pub fn parse_hex16(value: &str) -> Result<[u8; 16], Error> {
+    let mut value = normalize_hex(value);
+    let result = decode_hex16(&value);
+    wipe(&mut value);
+    result
+}
+
+fn decode_hex16(value: &str) -> Result<[u8; 16], Error> {
+    if value.chars().count() != 32 {
+        return Err(Error::new_key(format!(
+            "128-bit hex value has {} characters instead of 32.",
+            value.chars().count()
+        )));
+    }
+
+    if let Some(position) = value.chars().position(|x| !x.is_ascii_hexdigit()) {
+        return Err(Error::new_key(format!(
+            "128-bit hex value has an invalid character at position {}.",
+            position
+        )));
+    }
+
+    let mut bytes = [0; 16];
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16)
+            .map_err(|_| Error::new_key("invalid hex format."))?;
+    }
+
+    Ok(bytes)
+}
+
+/// Convert a base64url encoded EME ClearKey `kid` (or `keyId`) to a 128-bit KID.
+///
+/// Both padded and unpadded forms are accepted.
+///
+/// # Example
+///
+/// ```
+/// let kid = mp4decrypt::clearkey_keyid_to_kid("62dqu8s0Xpa7z2FmMPGj2g").unwrap();
+/// assert_eq!(kid, mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3da").unwrap());
+/// assert_eq!(mp4decrypt::kid_to_clearkey_keyid(&kid), "62dqu8s0Xpa7z2FmMPGj2g");
+///
+/// assert!(mp4decrypt::clearkey_keyid_to_kid("62dqu8s0Xpa7z2FmMPGj2g==").is_ok());
+/// assert!(mp4decrypt::clearkey_keyid_to_kid("62dqu8s0Xpa7z2FmMPGj").is_err());
+/// ```
+pub fn clearkey_keyid_to_kid(b64url: &str) -> Result<[u8; 16], Error> {
+    let bytes = CLEARKEY_BASE64
+        .decode(b64url.trim())
+        .map_err(|_| Error::new_key(format!("{} is not a valid base64url value.", b64url)))?;
+
+    bytes.try_into().map_err(|_| {
+        Error::new_key(format!(
+            "{} is not a valid base64url encoded 128-bit value.",
+            b64url
+        ))
+    })
+}
+
+/// Convert a 128-bit KID to an unpadded base64url encoded EME ClearKey `kid` (or `keyId`).
+pub fn kid_to_clearkey_keyid(kid: &[u8; 16]) -> String {
+    CLEARKEY_BASE64.encode(kid)
+}
+
+pub(crate) fn parse_key_id(id: &str) -> Result<KeyId, Error> {
+    let trimmed = id.trim();
+
+    if !trimmed.is_empty() && trimmed.bytes().all(|x| x.is_ascii_digit()) && trimmed.len() < 32 {
+        return match trimmed.parse::<u32>() {
+            Ok(x) => Ok(KeyId::Track(x)),
+            Err(_) => Err(Error::new_key("invalid key id.")),
+        };
+    }
+
+    if normalize_hex(id).len() == 32 {
+        parse_hex16(id)
+            .map(KeyId::Kid)
+            .map_err(|_| Error::new_key("invalid hex format for key id."))
+    } else {
+        Err(Error::new_key("invalid key id."))
+    }
+}
+
+/// Strip `0x` prefix, whitespace and dashes. The string is allocated once and edited in place,
+/// since it may hold a key.
+fn normalize_hex(value: &str) -> String {
+    let mut normalized = String::with_capacity(value.len());
+    normalized.extend(value.chars().filter(|x| !x.is_whitespace() && *x != '-'));
+
+    if normalized.starts_with("0x") || normalized.starts_with("0X") {
+        normalized.drain(..2);
+    }
+
+    normalized
+}
+
+/// Hex encode into a single allocation, since bytes may be a key.
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+
+    hex
+}
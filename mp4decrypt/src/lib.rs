@@ -3,6 +3,16 @@
 //!
 //! Maximum supported stream size is around `4.29` G.B i.e. [u32::MAX](u32::MAX).
 //!
+//! ## Panics
+//!
+//! Functions return an [Error](Error) for malformed input instead of panicking, which the `no_panic` test
+//! checks against mutated and random inputs. The process can still be aborted on malformed input though:
+//!
+//! - Allocation failures abort as usual, e.g. for a sample table of a non-fragmented file claiming billions
+//!   of samples of a constant size, which are resolved in full e.g. by [quick_verify](quick_verify).
+//! - Bento4 may abort on malformed input it doesn't reject, e.g. on failed assertions of its box writer
+//!   for some init segments with inconsistent box sizes, if it is built with assertions enabled.
+//!
 //! ## Environment Variables
 //!
 //! A set of environment variables that can be used to find ap4 library from Bento4 installation.
@@ -13,19 +23,161 @@
 //!
 //! Additionally, these variables can be prefixed with the upper-cased target architecture (e.g. X86_64_UNKNOWN_LINUX_GNU_BENTO4_DIR),
 //! which can be useful when cross compiling.
+//!
+//! ## Cross Compiling
+//!
+//! When building vendored Bento4 for Android, the C++ compiler of the NDK is used if
+//! `ANDROID_NDK_HOME` (or `ANDROID_NDK_ROOT`, `NDK_HOME`) is set. The API level is read from `ANDROID_PLATFORM`
+//! and defaults to 21. Set `CXX_<target>` and `AR_<target>` to use a different toolchain.
+//!
+//! ```bash
+//! $ rustup target add aarch64-linux-android
+//! $ export ANDROID_NDK_HOME=$HOME/Android/Sdk/ndk/27.0.12077973
+//! $ export ANDROID_PLATFORM=24
+//! $ export CARGO_TARGET_AARCH64_LINUX_ANDROID_LINKER=$ANDROID_NDK_HOME/toolchains/llvm/prebuilt/linux-x86_64/bin/aarch64-linux-android24-clang
+//! $ cargo build --target aarch64-linux-android
+//! ```
+//!
+//! For iOS, the SDK is located using `xcrun` from Xcode command line tools, or `SDKROOT` if set.
+//!
+//! ```bash
+//! $ rustup target add aarch64-apple-ios
+//! $ export IPHONEOS_DEPLOYMENT_TARGET=13.0
+//! $ cargo build --target aarch64-apple-ios
+//! ```
+//!
+//! A prebuilt Bento4 can also be used per target, e.g. `AARCH64_LINUX_ANDROID_BENTO4_DIR=/opt/bento4-android`.
+//!
+//! ## Optional Features
+//!
+//! - **capi** (default): Export the C API (`mp4decrypt_capi`, `mp4decrypt_capi_cb`, `mp4decrypt_free_error` and
+//!   `mp4decrypt_set_panic_handler`).
+//!   Disable default features for Rust only builds, so that these symbols don't conflict with other C functions.
+//! - **metrics**: Record every decryption with the [metrics](https://docs.rs/metrics) facade, i.e. the
+//!   `mp4decrypt_decryptions_total` counter labeled with `result` (`ok` or the [ErrorType::as_str](ErrorType::as_str)
+//!   key of the error), the `mp4decrypt_input_bytes_total` and `mp4decrypt_output_bytes_total` counters and the
+//!   `mp4decrypt_duration_seconds` histogram. Decryptions made internally, e.g. by each candidate of
+//!   [decrypt_try_keys](decrypt_try_keys), are recorded as well. Nothing is recorded without this feature.
+//! - **mmap**: Memory-map input files in [decrypt_file](decrypt_file) instead of reading them.
+//! - **no-threads**: Build vendored Bento4 and the wrapper for targets without threads, such as `wasm32-wasip1`.
+//!   Bento4 itself never starts threads, only thread-safe initialization of statics and threading support of
+//!   libc++ are turned off. It has no effect when linking a Bento4 installation from `BENTO4_DIR`,
+//!   and shouldn't be combined with **rayon**.
+//! - **rayon**: Decrypt media segments in parallel using [rayon](https://docs.rs/rayon) with `decrypt_segments_parallel`,
+//!   bounded by [DecryptOptions::max_threads](DecryptOptions::max_threads), and verify the segments of
+//!   [verify_directory](verify_directory) in parallel.
+//! - **serde**: Implement `Serialize` and `Deserialize` for [KeyMap](KeyMap), [Scheme](Scheme) and [DecryptOptions](DecryptOptions).
+//! - **testing**: Assertions for test suites built on this crate in the [testing](testing) module, reporting the
+//!   first differing box of a mismatching decrypted output.
+//! - **zeroize**: Zeroize keys held by [KeyMap](KeyMap) and every copy passed to Bento4 once they are dropped,
+//!   and decrypt with keys requested on demand from a secure store using `decrypt_with_secure_keys`.
 
 #![allow(improper_ctypes)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
 
+mod adts;
+mod aux_info;
+mod boxes;
+#[cfg(feature = "capi")]
+mod capi;
+mod dash;
+mod dump;
+mod emsg;
 mod error;
+mod events;
+mod file;
+mod fragment;
+mod groups;
+mod hls;
+mod instrument;
+mod iv;
+mod keys;
+mod metadata;
+mod moof;
+mod mp4file;
+mod options;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod protection;
+mod pssh;
+mod reader;
+mod refragment;
+mod remux;
+mod report;
+mod rewrite;
+mod samples;
+mod scheme;
+#[cfg(feature = "zeroize")]
+mod secure;
+mod segment;
+mod sidx;
+mod sinf;
+mod sink;
+mod split;
+mod stream;
+#[cfg(feature = "testing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub mod testing;
+mod validate;
+mod verify;
+mod versions;
 
+pub use adts::decrypt_audio_to_adts;
+pub use aux_info::{AuxInfo, TrackAuxInfo, read_aux_info};
+#[cfg(feature = "capi")]
+#[cfg_attr(docsrs, doc(cfg(feature = "capi")))]
+pub use capi::{
+    DecryptError, mp4decrypt_capi, mp4decrypt_capi_cb, mp4decrypt_free_error,
+    mp4decrypt_set_panic_handler,
+};
+pub use dash::{DashOutput, decrypt_dash_representation};
+pub use dump::debug_dump;
 pub use error::{Error, ErrorType};
+pub use events::{BoxEvent, parse_boxes};
+pub use file::{decrypt_file, decrypt_to_dir, verify_directory};
+pub use fragment::{decrypt_fragment_at, decrypt_one_fragment};
+pub use hls::{HlsKeyInfo, HlsKeyMethod, parse_ext_x_key};
+pub use keys::{KeyId, KeyMap, clearkey_keyid_to_kid, kid_to_clearkey_keyid, parse_hex16};
+pub use metadata::{
+    FtypInfo, Mp4Metadata, TrackEncryption, TrackInfo, TrackKind, list_tracks, read_ftyp,
+};
+pub use mp4file::Mp4File;
+pub use options::{ClearInputPolicy, DecryptOptions, MissingKeyPolicy};
+#[cfg(feature = "rayon")]
+#[cfg_attr(docsrs, doc(cfg(feature = "rayon")))]
+pub use parallel::decrypt_segments_parallel;
+pub use protection::{collect_all_kids, is_encrypted, mp4decrypt_passthrough};
+pub use pssh::{PsshBox, PsshCoverage, extract_pssh, keys_cover_pssh, widevine_content_id};
+pub use refragment::decrypt_and_refragment;
+pub use remux::{decrypt_split_tracks, remap_track_ids};
+pub use report::{DecryptReport, MissingKey, TrackStats};
+pub use samples::{Sample, decrypt_samples};
+pub use scheme::{
+    BuildInfo, Capabilities, Linkage, Scheme, SchemeInfo, build_info, capabilities, crate_version,
+    linkage_info, supported_schemes,
+};
+#[cfg(feature = "zeroize")]
+#[cfg_attr(docsrs, doc(cfg(feature = "zeroize")))]
+pub use secure::decrypt_with_secure_keys;
+pub use segment::SegmentDecryptor;
+pub use sidx::{SegmentIndex, SidxEntry, read_sidx};
+pub use sinf::read_original_format;
+pub use sink::{BufSink, mp4decrypt_into, mp4decrypt_into_with_options};
+pub use split::{assemble, count_fragments, decrypt_from_fragment, decrypt_prefix, extract_init};
+pub use stream::{
+    AUTO_PEEK_SIZE, decrypt_auto, decrypt_auto_with_options, decrypt_stream, needs_fragments_info,
+};
+pub use verify::{VerifyResult, decrypt_try_keys, quick_verify};
 
-use core::ffi::{c_char, c_int, c_uchar, c_uint};
-use std::{collections::HashMap, ffi::CString, ffi::CStr};
-use std::ptr;
-
-use libc::malloc;
-
+use core::ffi::{c_char, c_int, c_uchar, c_uint, c_void};
+use keys::{KeyPairs, KeyStrings};
+use options::Progress;
+use sink::{LimitedSink, OutputLimit};
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    ops::{Deref, Range},
+};
 
 unsafe extern "C" {
     fn decrypt_in_memory(
@@ -34,8 +186,10 @@ unsafe extern "C" {
         keyids: *mut *const c_char,
         keys: *mut *const c_char,
         nkeys: c_int,
-        decrypted_data: *mut Vec<u8>,
-        callback: extern "C" fn(*mut Vec<u8>, *const c_uchar, c_uint),
+        decrypted_data: *mut c_void,
+        callback: extern "C" fn(*mut c_void, *const c_uchar, c_uint),
+        progress_callback: extern "C" fn(*mut c_void, c_uint, c_uint),
+        progress_interval: c_uint,
     ) -> c_int;
 
     fn decrypt_in_memory_with_fragments_info(
@@ -44,16 +198,81 @@ unsafe extern "C" {
         keyids: *mut *const c_char,
         keys: *mut *const c_char,
         nkeys: c_int,
-        decrypted_data: *mut Vec<u8>,
-        callback: extern "C" fn(*mut Vec<u8>, *const c_uchar, c_uint),
+        decrypted_data: *mut c_void,
+        callback: extern "C" fn(*mut c_void, *const c_uchar, c_uint),
+        progress_callback: extern "C" fn(*mut c_void, c_uint, c_uint),
+        progress_interval: c_uint,
         fragments_info_data: *const c_uchar,
         fragments_info_data_size: c_uint,
     ) -> c_int;
+
+    fn bento4_version_string() -> *const c_char;
 }
 
-extern "C" fn decrypt_callback(decrypted_stream: *mut Vec<u8>, data: *const c_uchar, size: c_uint) {
-    unsafe {
-        *decrypted_stream = std::slice::from_raw_parts(data, size as usize).to_vec();
+/// Destination of the decrypted data stream, used by [decrypt_callback](decrypt_callback).
+struct DecryptOutput<'a> {
+    sink: &'a mut dyn BufSink,
+    progress: Option<&'a Progress>,
+    error: Option<std::io::Error>,
+    /// Message of a caught panic.
+    panic: Option<String>,
+}
+
+/// Unwinding into Bento4 is undefined behavior, so a panic is caught and recorded instead,
+/// which is then reported as [ErrorType::Panicked](ErrorType::Panicked) once Bento4 returns.
+/// Note that allocation failures abort the process rather than panic, unless the sink allocates fallibly.
+extern "C" fn decrypt_callback(output: *mut c_void, data: *const c_uchar, size: c_uint) {
+    let output = unsafe { &mut *(output as *mut DecryptOutput) };
+
+    if output.error.is_some() || output.panic.is_some() {
+        return;
+    }
+
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        if size == 0 {
+            Ok(())
+        } else {
+            output
+                .sink
+                .write_chunk(unsafe { std::slice::from_raw_parts(data, size as usize) })
+        }
+    }));
+
+    match result {
+        Ok(Ok(_)) => (),
+        Ok(Err(e)) => output.error = Some(e),
+        Err(e) => output.panic = Some(panic_message(&*e)),
+    }
+}
+
+/// Called by Bento4 while processing samples, as requested by [DecryptOptions::on_progress](DecryptOptions::on_progress).
+/// A panic is caught and recorded just like in [decrypt_callback](decrypt_callback).
+extern "C" fn progress_callback(output: *mut c_void, step: c_uint, total: c_uint) {
+    let output = unsafe { &mut *(output as *mut DecryptOutput) };
+
+    let Some(progress) = output.progress else {
+        return;
+    };
+
+    if output.panic.is_some() {
+        return;
+    }
+
+    if let Err(e) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        (progress.callback)(step, total)
+    })) {
+        output.panic = Some(panic_message(&*e));
+    }
+}
+
+/// Read the message of a caught panic, which is usually a `&str` or a `String`.
+pub(crate) fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(x) => (*x).to_owned(),
+        None => match payload.downcast_ref::<String>() {
+            Some(x) => x.clone(),
+            None => "unknown panic payload".to_owned(),
+        },
     }
 }
 
@@ -69,6 +288,7 @@ extern "C" fn decrypt_callback(decrypted_stream: *mut Vec<u8>, data: *const c_uc
 ///   2. For Marlin IPMP/ACGK, use 0 as the track ID <br>
 ///   3. KIDs are only applicable to some encryption methods like MPEG-CENC <br>
 /// * `fragments_info` (optional) - Decrypt the fragments read from data stream, with track info read from this stream.
+///   Fragments of tracks which are not present in it are rejected with [ErrorType::InitMediaMismatch](ErrorType::InitMediaMismatch).
 ///
 /// # Example
 ///
@@ -82,178 +302,778 @@ extern "C" fn decrypt_callback(decrypted_stream: *mut Vec<u8>, data: *const c_uc
 ///
 /// let decrypted_data = mp4decrypt::mp4decrypt(&[0, 0, 0, 112], &kid_key_pairs, None).unwrap();
 /// ```
-
 pub fn mp4decrypt(
     data: &[u8],
     keys: &HashMap<String, String>,
     fragments_info: Option<&[u8]>,
 ) -> Result<Vec<u8>, Error> {
-    let mut data = data.to_vec();
+    decrypt(data, &KeyMap::try_from(keys)?, fragments_info)
+}
+
+/// Decrypt encrypted mp4 data stream using keys indexed only by 128-bit KIDs, which Bento4 matches with
+/// the default KIDs of tracks. This is the recommended way for MPEG-CENC content, since track IDs aren't needed.
+///
+/// See [mp4decrypt](mp4decrypt) for details about the arguments.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// let kid = mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3da").unwrap();
+/// let key = mp4decrypt::parse_hex16("100b6c20940f779a4589152b57d2dacb").unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+/// let decrypted_data =
+///     mp4decrypt::mp4decrypt_by_kid(&segment, Some(&init), &HashMap::from([(kid, key)])).unwrap();
+///
+/// // same as decrypting by track ID
+/// let mut keys = mp4decrypt::KeyMap::new();
+/// keys.insert_track(1, key);
+/// let options = mp4decrypt::DecryptOptions::new();
+/// assert_eq!(
+///     decrypted_data,
+///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap()
+/// );
+/// assert_ne!(decrypted_data[..4705], segment[..4705]);
+/// ```
+pub fn mp4decrypt_by_kid(
+    data: &[u8],
+    fragments_info: Option<&[u8]>,
+    kid_key: &HashMap<[u8; 16], [u8; 16]>,
+) -> Result<Vec<u8>, Error> {
+    let mut keys = KeyMap::new();
+
+    for (kid, key) in kid_key {
+        keys.insert_kid(*kid, *key);
+    }
+
+    decrypt(data, &keys, fragments_info)
+}
+
+/// Decrypt encrypted mp4 data stream which is split into several buffers, e.g. as read from the network.
+///
+/// Bento4 reads the data stream from a single contiguous buffer, and the boxes are parsed in place before
+/// decrypting, so the chunks are copied into one buffer unless there is only one chunk. This still saves
+/// concatenating them by hand, and the copy is the only one made. Chunks can be of any type dereferencing
+/// to `[u8]`, e.g. `&[u8]`, `Vec<u8>` or [IoSlice](std::io::IoSlice).
+///
+/// See [mp4decrypt](mp4decrypt) for details about the arguments.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{DecryptOptions, KeyMap};
+/// use std::io::IoSlice;
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let chunks = segment.chunks(1000).collect::<Vec<_>>();
+/// let decrypted_data = mp4decrypt::mp4decrypt_vectored(&chunks, &keys, Some(&init)).unwrap();
+///
+/// let options = DecryptOptions::new();
+/// assert_eq!(
+///     decrypted_data,
+///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap()
+/// );
+///
+/// let slices = chunks.iter().map(|x| IoSlice::new(x)).collect::<Vec<_>>();
+/// assert_eq!(
+///     decrypted_data,
+///     mp4decrypt::mp4decrypt_vectored(&slices, &keys, Some(&init)).unwrap()
+/// );
+/// ```
+pub fn mp4decrypt_vectored(
+    chunks: &[impl Deref<Target = [u8]>],
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    match chunks {
+        [data] => decrypt(data, keys, fragments_info),
+        _ => {
+            let size = chunks.iter().map(|x| x.len()).sum();
+            let mut data = Vec::with_capacity(size);
+
+            for chunk in chunks {
+                data.extend_from_slice(chunk);
+            }
+
+            decrypt(&data, keys, fragments_info)
+        }
+    }
+}
+
+/// Decrypt encrypted mp4 data stream embedded at a known range of a larger buffer, e.g. a file stored
+/// inside an archive, without copying it out first.
+///
+/// Offsets within the data stream (e.g. chunk offsets) are relative to the start of `range`, same as if the
+/// data stream was a file of its own. See [mp4decrypt](mp4decrypt) for details about the other arguments.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{DecryptOptions, KeyMap};
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let archive = [&[0xff; 100], &segment[..], &[0xff; 100]].concat();
+/// let range = 100..100 + segment.len();
+/// let decrypted_data = mp4decrypt::decrypt_range(&archive, range, &keys, Some(&init)).unwrap();
+///
+/// let options = DecryptOptions::new();
+/// assert_eq!(
+///     decrypted_data,
+///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap()
+/// );
+///
+/// assert!(mp4decrypt::decrypt_range(&archive, 100..archive.len() + 1, &keys, Some(&init)).is_err());
+/// ```
+pub fn decrypt_range(
+    data: &[u8],
+    range: Range<usize>,
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    let Some(data) = data.get(range.clone()) else {
+        return Err(Error::new_format(format!(
+            "range {}..{} is out of bounds of {} bytes of data.",
+            range.start,
+            range.end,
+            data.len()
+        )));
+    };
+
+    decrypt(data, keys, fragments_info)
+}
+
+/// Decrypt encrypted mp4 data stream using given keys, returning only the numeric code of an error.
+///
+/// The code is the same as [ErrorType::code](ErrorType::code) of the error returned by
+/// [mp4decrypt_with_options](mp4decrypt_with_options) with default options, but the message of a Bento4 failure
+/// is never formatted. This is meant for tight loops which expect many failures and only branch on the result,
+/// e.g. trying many candidate keys on the same segment. Errors found by the checks done before calling Bento4
+/// still build their message, since these don't depend on the keys and are usually found once.
+/// Use the functions returning [Error](Error) everywhere else, whose message tells what went wrong.
+///
+/// See [mp4decrypt](mp4decrypt) for details about the arguments.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{DecryptOptions, KeyMap};
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert_track(1, mp4decrypt::parse_hex16("100b6c20940f779a4589152b57d2dacb").unwrap());
+/// let decrypted_data = mp4decrypt::mp4decrypt_code(&segment, &keys, Some(&init)).unwrap();
+///
+/// let options = DecryptOptions::new();
+/// assert_eq!(
+///     decrypted_data,
+///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap()
+/// );
+///
+/// // fragments without init segment
+/// let error = mp4decrypt::mp4decrypt_with_options(&segment, &keys, None, &options).unwrap_err();
+/// assert_eq!(mp4decrypt::mp4decrypt_code(&segment, &keys, None), Err(error.err_type.code()));
+/// ```
+pub fn mp4decrypt_code(
+    data: &[u8],
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+) -> Result<Vec<u8>, i32> {
+    let mut decrypted_data = Vec::new();
+    let key_pairs = keys.key_pairs();
+    instrument::record(data, &mut decrypted_data, |sink| {
+        decrypt_key_pairs_code_into(data, &key_pairs, fragments_info, None, sink)
+    })
+    .map_err(|x| x.code())?;
+    Ok(decrypted_data)
+}
+
+pub(crate) fn decrypt(
+    data: &[u8],
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    decrypt_key_pairs(data, &keys.key_pairs(), fragments_info)
+}
+
+/// Decrypt using kid key pairs which are passed to Bento4 as they are.
+pub(crate) fn decrypt_key_pairs(
+    data: &[u8],
+    key_pairs: &[(String, String)],
+    fragments_info: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    let mut decrypted_data = Vec::new();
+    decrypt_key_pairs_into(data, key_pairs, fragments_info, None, &mut decrypted_data)?;
+    Ok(decrypted_data)
+}
+
+pub(crate) fn decrypt_key_pairs_into(
+    data: &[u8],
+    key_pairs: &[(String, String)],
+    fragments_info: Option<&[u8]>,
+    progress: Option<&Progress>,
+    sink: &mut dyn BufSink,
+) -> Result<(), Error> {
+    instrument::record(data, sink, |sink| {
+        decrypt_key_pairs_code_into(data, key_pairs, fragments_info, progress, sink)
+    })
+    .map_err(RawError::into_error)
+}
+
+/// Same as [decrypt_key_pairs_into](decrypt_key_pairs_into), but Bento4 failures are returned as they are.
+fn decrypt_key_pairs_code_into(
+    data: &[u8],
+    key_pairs: &[(String, String)],
+    fragments_info: Option<&[u8]>,
+    progress: Option<&Progress>,
+    sink: &mut dyn BufSink,
+) -> Result<(), RawError> {
+    let periods = split::split_periods(data);
+
+    match fragments_info {
+        Some(_) if periods.len() > 1 => {
+            return Err(Error {
+                msg: "data stream has its own init segment (moov box) after fragments, \
+                      which can't be decrypted using fragments info."
+                    .to_owned(),
+                err_type: ErrorType::ReinitializationUnsupported,
+                invalid_key: false,
+            }
+            .into());
+        }
+        Some(fragments_info) => validate::check_init_media(data, fragments_info)?,
+        None if periods.len() > 1 => {
+            // Every period is decrypted using its own init segment.
+            for period in periods {
+                decrypt_key_pairs_code_into(period, key_pairs, None, progress, sink)?;
+            }
+
+            return Ok(());
+        }
+        None => validate::check_self_contained(data)?,
+    }
+
+    validate::check_sample_encryption(data, fragments_info.unwrap_or(data))?;
+    validate::check_sample_entries(fragments_info.unwrap_or(data), key_pairs)?;
+    pssh::check_drm_system(fragments_info.unwrap_or(data))?;
+
+    let mut c_kids_holder = KeyStrings::default();
+    let mut c_keys_holder = KeyStrings::default();
+
+    for (id, key) in key_pairs {
+        c_kids_holder.push(id)?;
+        c_keys_holder.push(key)?;
+    }
+
+    let c_kids = c_kids_holder
+        .0
+        .iter()
+        .map(|x| x.as_c_str())
+        .collect::<Vec<_>>();
+    let c_keys = c_keys_holder
+        .0
+        .iter()
+        .map(|x| x.as_c_str())
+        .collect::<Vec<_>>();
+
+    // Bento4 only reads and removes the first sinf box of a sample entry.
+    let multiple_sinf = sinf::MultipleSinf::find(data);
+    let reordered_data = sinf::prefer_supported_sinf(data);
+    let data = reordered_data.as_deref().unwrap_or(data);
+    let reordered_info = fragments_info.and_then(sinf::prefer_supported_sinf);
+    let fragments_info = reordered_info.as_deref().or(fragments_info);
+
+    let clear_samples = groups::ClearSamples::find(data, fragments_info)?;
+
+    if clear_samples.is_empty() && multiple_sinf.is_empty() {
+        return decrypt_raw_code_into(data, &c_kids, &c_keys, fragments_info, progress, sink);
+    }
+
+    // Bento4 ignores sample groups, so unencrypted samples are copied back after decryption.
+    let mut decrypted_data = Vec::new();
+    decrypt_raw_code_into(
+        data,
+        &c_kids,
+        &c_keys,
+        fragments_info,
+        progress,
+        &mut decrypted_data,
+    )?;
+    clear_samples.restore(data, fragments_info, &mut decrypted_data)?;
+    multiple_sinf.strip(&mut decrypted_data, fragments_info)?;
+    Ok(sink.write_chunk(&decrypted_data).map_err(sink_error)?)
+}
+
+/// Decrypt encrypted mp4 data stream by passing everything straight to Bento4.
+///
+/// Unlike [mp4decrypt](mp4decrypt), no parsing or validation is done on the keys and the data stream,
+/// so this can be used in tight loops where the input has already been validated by the caller.
+/// Input must be well-formed, malformed keys or streams are handled by Bento4 alone.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream.
+/// * `c_kids` - Track IDs in decimal or 128-bit KIDs in hex, in the same order as `c_keys`.
+/// * `c_keys` - 128-bit keys in hex.
+/// * `fragments_info` (optional) - Decrypt the fragments read from data stream, with track info read from this stream.
+///
+/// # Example
+///
+/// ```no_run
+/// let kid = c"eb676abbcb345e96bbcf616630f1a3da";
+/// let key = c"100b6c20940f779a4589152b57d2dacb";
+///
+/// let decrypted_data = mp4decrypt::decrypt_raw(&[0, 0, 0, 112], &[kid], &[key], None).unwrap();
+/// ```
+pub fn decrypt_raw(
+    data: &[u8],
+    c_kids: &[&CStr],
+    c_keys: &[&CStr],
+    fragments_info: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+    let mut decrypted_data = Vec::new();
+    decrypt_raw_into(
+        data,
+        c_kids,
+        c_keys,
+        fragments_info,
+        None,
+        &mut decrypted_data,
+    )?;
+    Ok(decrypted_data)
+}
+
+pub(crate) fn decrypt_raw_into(
+    data: &[u8],
+    c_kids: &[&CStr],
+    c_keys: &[&CStr],
+    fragments_info: Option<&[u8]>,
+    progress: Option<&Progress>,
+    sink: &mut dyn BufSink,
+) -> Result<(), Error> {
+    instrument::record(data, sink, |sink| {
+        decrypt_raw_code_into(data, c_kids, c_keys, fragments_info, progress, sink)
+    })
+    .map_err(RawError::into_error)
+}
+
+/// Error of [decrypt_raw_code_into](decrypt_raw_code_into), whose message is only built for Bento4 failures
+/// once it's needed, see [mp4decrypt_code](mp4decrypt_code).
+enum RawError {
+    Bento4(c_int),
+    Error(Error),
+}
+
+impl From<Error> for RawError {
+    fn from(err: Error) -> Self {
+        Self::Error(err)
+    }
+}
+
+impl RawError {
+    fn code(&self) -> i32 {
+        match self {
+            Self::Bento4(100..=102) => ErrorType::InvalidFormat.code(),
+            Self::Bento4(x) => *x,
+            Self::Error(x) => x.err_type.code(),
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bento4(100..=102) => ErrorType::InvalidFormat.as_str(),
+            Self::Bento4(x) => ErrorType::Failed(*x).as_str(),
+            Self::Error(x) => x.err_type.as_str(),
+        }
+    }
+
+    fn into_error(self) -> Error {
+        match self {
+            Self::Bento4(100) => Error::new_key("invalid hex format for key id."),
+            Self::Bento4(101) => Error::new_key("invalid key id."),
+            Self::Bento4(102) => Error::new_key("invalid hex format for key."),
+            Self::Bento4(x) => Error {
+                msg: format!("failed to decrypt data with error code {}.", x),
+                err_type: ErrorType::Failed(x),
+                invalid_key: false,
+            },
+            Self::Error(x) => x,
+        }
+    }
+}
+
+fn decrypt_raw_code_into(
+    data: &[u8],
+    c_kids: &[&CStr],
+    c_keys: &[&CStr],
+    fragments_info: Option<&[u8]>,
+    progress: Option<&Progress>,
+    sink: &mut dyn BufSink,
+) -> Result<(), RawError> {
+    if c_kids.len() != c_keys.len() {
+        return Err(Error::new_format(format!(
+            "got {} key ids but {} keys.",
+            c_kids.len(),
+            c_keys.len()
+        ))
+        .into());
+    }
+
     let data_size = u32::try_from(data.len()).map_err(|_| Error {
         msg: "the input data stream is too large.".to_owned(),
         err_type: ErrorType::DataTooLarge,
+        invalid_key: false,
     })?;
 
-    let mut c_kids_holder = vec![];
-    let mut c_keys_holder = vec![];
-    let mut c_kids = vec![];
-    let mut c_keys = vec![];
-
-    for (i, (kid, key)) in keys.iter().enumerate() {
-        c_kids_holder.push(CString::new(kid.to_owned()).unwrap());
-        c_keys_holder.push(CString::new(key.to_owned()).unwrap());
-        c_kids.push(c_kids_holder[i].as_ptr());
-        c_keys.push(c_keys_holder[i].as_ptr());
-    }
+    let mut c_kids = c_kids.iter().map(|x| x.as_ptr()).collect::<Vec<_>>();
+    let mut c_keys = c_keys.iter().map(|x| x.as_ptr()).collect::<Vec<_>>();
+    let nkeys = c_int::try_from(c_kids.len()).map_err(|_| Error::new_format("too many keys."))?;
 
-    let mut decrypted_data: Box<Vec<u8>> = Box::default();
+    let mut output = DecryptOutput {
+        sink,
+        progress,
+        error: None,
+        panic: None,
+    };
+    let output_ptr = &mut output as *mut DecryptOutput as *mut c_void;
+    let progress_interval = progress.map_or(0, |x| x.interval);
 
     let result = unsafe {
         if let Some(fragments_info_data) = fragments_info {
             let fragments_info_data_size =
                 u32::try_from(fragments_info_data.len()).map_err(|_| Error {
-                    msg: "the fragments info data stream is too large."
-                        .to_owned(),
+                    msg: "the fragments info data stream is too large.".to_owned(),
                     err_type: ErrorType::DataTooLarge,
+                    invalid_key: false,
                 })?;
 
             decrypt_in_memory_with_fragments_info(
-                data.as_mut_ptr(),
+                data.as_ptr(),
                 data_size,
                 c_kids.as_mut_ptr(),
                 c_keys.as_mut_ptr(),
-                1,
-                &mut *decrypted_data,
+                nkeys,
+                output_ptr,
                 decrypt_callback,
+                progress_callback,
+                progress_interval,
                 fragments_info_data.as_ptr(),
                 fragments_info_data_size,
             )
         } else {
             decrypt_in_memory(
-                data.as_mut_ptr(),
+                data.as_ptr(),
                 data_size,
                 c_kids.as_mut_ptr(),
                 c_keys.as_mut_ptr(),
-                1,
-                &mut *decrypted_data,
+                nkeys,
+                output_ptr,
                 decrypt_callback,
+                progress_callback,
+                progress_interval,
             )
         }
     };
 
-    if result == 0 {
-        Ok(*decrypted_data)
+    if let Some(panic) = output.panic {
+        Err(Error {
+            msg: format!(
+                "panicked while storing the decrypted data stream or reporting progress: {}",
+                panic
+            ),
+            err_type: ErrorType::Panicked,
+            invalid_key: false,
+        }
+        .into())
+    } else if let Some(e) = output.error {
+        Err(sink_error(e).into())
+    } else if result == 0 {
+        Ok(())
     } else {
-        Err(match result {
-            100 => Error {
-                msg: "invalid hex format for key id.".to_owned(),
-                err_type: ErrorType::InvalidFormat,
-            },
-            101 => Error {
-                msg: "invalid key id.".to_owned(),
-                err_type: ErrorType::InvalidFormat,
-            },
-            102 => Error {
-                msg: "invalid hex format for key.".to_owned(),
-                err_type: ErrorType::InvalidFormat,
-            },
-            x => Error {
-                msg: format!(
-                    "failed to decrypt data with error code {}.",
-                    x
-                ),
-                err_type: ErrorType::Failed(x),
-            },
-        })
+        Err(RawError::Bento4(result))
     }
 }
 
-#[repr(C)]
-pub struct DecryptError {
-    pub code: c_int,
-    pub message: *const c_char,
+/// Map an error returned by a [BufSink](BufSink) while storing the decrypted data stream.
+fn sink_error(e: std::io::Error) -> Error {
+    if let Some(x) = e.get_ref().and_then(|x| x.downcast_ref::<OutputLimit>()) {
+        return x.to_error();
+    }
+
+    match e.kind() {
+        std::io::ErrorKind::OutOfMemory => Error {
+            msg: "failed to allocate memory for the decrypted data stream.".to_owned(),
+            err_type: ErrorType::OutOfMemory,
+            invalid_key: false,
+        },
+        _ => Error::new_io(e),
+    }
+}
+
+/// Decrypt encrypted mp4 data stream using given keys and options.
+///
+/// See [mp4decrypt](mp4decrypt) for details about the arguments.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::{DecryptOptions, KeyMap};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let options = DecryptOptions::new().validate_structure(true);
+/// let decrypted_data =
+///     mp4decrypt::mp4decrypt_with_options(&[0, 0, 0, 112], &keys, None, &options).unwrap();
+/// ```
+pub fn mp4decrypt_with_options(
+    data: &[u8],
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+    options: &DecryptOptions,
+) -> Result<Vec<u8>, Error> {
+    mp4decrypt_with_report(data, keys, fragments_info, options).map(|(x, _)| x)
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn mp4decrypt_capi(
-    data_ptr: *const u8,
-    data_len: usize,
-    keys_json: *const c_char,
-    fragments_ptr: *const u8,
-    fragments_len: usize,
-    out_ptr: *mut *mut u8,
-    out_len: *mut usize,
-    err_out: *mut DecryptError,
-) -> c_int {
-    if data_ptr.is_null() || keys_json.is_null() || out_ptr.is_null() || out_len.is_null() {
-        return -1;
+/// Decrypt encrypted mp4 data stream using given keys and options,
+/// and return a report about the decryption along with the decrypted data.
+///
+/// See [mp4decrypt](mp4decrypt) for details about the arguments.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::{DecryptOptions, KeyMap, MissingKeyPolicy};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let data = std::fs::read("encrypted.mp4").unwrap();
+/// let options = DecryptOptions::new().on_missing_key(MissingKeyPolicy::WarnReport);
+/// let (decrypted_data, report) =
+///     mp4decrypt::mp4decrypt_with_report(&data, &keys, None, &options).unwrap();
+///
+/// for missing in report.missing_keys {
+///     println!("track {} is still encrypted", missing.track_id);
+/// }
+/// ```
+pub fn mp4decrypt_with_report(
+    data: &[u8],
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+    options: &DecryptOptions,
+) -> Result<(Vec<u8>, DecryptReport), Error> {
+    let (key_pairs, mut report) = prepare_report(data, keys, fragments_info, options)?;
+    let (data, fragments_info) =
+        iv::override_constant_ivs(data, fragments_info, &options.constant_ivs)?;
+    let fragments_info = fragments_info.as_deref();
+    let mut decrypted_data = Vec::new();
+    decrypt_key_pairs_into(
+        &data,
+        &key_pairs,
+        fragments_info,
+        options.progress.as_ref(),
+        &mut LimitedSink::new(&mut decrypted_data, options.max_output_bytes),
+    )?;
+
+    post_process(&mut decrypted_data, fragments_info, options, &mut report)?;
+    Ok((decrypted_data, report))
+}
+
+/// A step run on the whole decrypted output once decryption is done.
+type PostProcessStep = fn(&mut Vec<u8>, Option<&[u8]>, &mut DecryptReport) -> Result<(), Error>;
+
+/// Steps of [post_process](post_process) enabled by `options`, in the order they are run.
+fn post_process_steps(options: &DecryptOptions) -> Vec<PostProcessStep> {
+    let mut steps = Vec::<PostProcessStep>::new();
+
+    if options.strip_pssh {
+        steps.push(|data, fragments_info, _| pssh::strip_pssh(data, fragments_info));
     }
 
-    let data = unsafe { std::slice::from_raw_parts(data_ptr, data_len) };
-
-    let keys_str = unsafe {
-        match CStr::from_ptr(keys_json).to_str() {
-            Ok(s) => s,
-            Err(_) => {
-                if !err_out.is_null() {
-                    let msg = CString::new("Invalid UTF-8 in keys_json").unwrap();
-                    unsafe {
-                        (*err_out).code = -2;
-                        (*err_out).message = msg.into_raw();
-                    }
-                }
-                return -2;
-            }
-        }
+    if !options.keep_emsg {
+        steps.push(|data, fragments_info, _| emsg::strip_emsg(data, fragments_info));
+    }
+
+    if options.normalize_box_versions {
+        steps.push(|data, fragments_info, report| {
+            report.mixed_box_versions = versions::normalize_box_versions(data, fragments_info)?;
+            Ok(())
+        });
+    }
+
+    if options.validate_structure {
+        steps.push(|data, fragments_info, _| validate::validate_structure(data, fragments_info));
+    }
+
+    if options.compute_crc32 {
+        steps.push(|data, fragments_info, report| {
+            report::compute_crc32(data, fragments_info, &mut report.tracks)
+        });
+    }
+
+    steps
+}
+
+/// True if `options` enable any step of [post_process](post_process), which needs the whole decrypted
+/// output rather than chunks of it.
+pub(crate) fn needs_post_processing(options: &DecryptOptions) -> bool {
+    !post_process_steps(options).is_empty()
+}
+
+/// Rewrite, check and hash the decrypted output as per `options`.
+pub(crate) fn post_process(
+    decrypted_data: &mut Vec<u8>,
+    fragments_info: Option<&[u8]>,
+    options: &DecryptOptions,
+    report: &mut DecryptReport,
+) -> Result<(), Error> {
+    for step in post_process_steps(options) {
+        step(decrypted_data, fragments_info, report)?;
+    }
+
+    Ok(())
+}
+
+/// Check the keys as per `options` before decrypting, returning the kid key pairs to decrypt
+/// with and the report filled so far.
+pub(crate) fn prepare_report(
+    data: &[u8],
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+    options: &DecryptOptions,
+) -> Result<(KeyPairs, DecryptReport), Error> {
+    let mut report = DecryptReport::default();
+    let mut raw_key_pairs = options.raw_key_pairs()?;
+    // Data and init segment of every period.
+    let periods = match fragments_info {
+        Some(x) => vec![(data, x)],
+        None => split::split_periods(data)
+            .into_iter()
+            .map(|x| (x, x))
+            .collect(),
     };
 
-    let keys: HashMap<String, String> = match serde_json::from_str(keys_str) {
-        Ok(k) => k,
-        Err(_) => {
-            if !err_out.is_null() {
-                let msg = CString::new("Failed to parse keys JSON").unwrap();
-                unsafe {
-                    (*err_out).code = -3;
-                    (*err_out).message = msg.into_raw();
-                }
-            }
-            return -3;
+    if options.on_clear_input != ClearInputPolicy::Ignore
+        && (!keys.is_empty() || !raw_key_pairs.is_empty())
+        && protection::is_clear(data)?
+        && fragments_info.map_or(Ok(true), protection::is_clear)?
+    {
+        if options.on_clear_input == ClearInputPolicy::Fail {
+            return Err(Error {
+                msg: "keys were given but the data stream is already clear (decrypted).".to_owned(),
+                err_type: ErrorType::AlreadyDecrypted,
+                invalid_key: false,
+            });
         }
-    };
 
-    let fragments_info = if !fragments_ptr.is_null() && fragments_len > 0 {
-        Some(unsafe { std::slice::from_raw_parts(fragments_ptr, fragments_len) })
-    } else {
-        None
-    };
+        report.clear_input = true;
+    }
 
-    match mp4decrypt(data, &keys, fragments_info) {
-        Ok(output) => {
-            let len = output.len();
-            let buf = unsafe { libc::malloc(len) as *mut u8 };
-            if buf.is_null() {
-                return -4;
-            }
-            unsafe {
-                std::ptr::copy_nonoverlapping(output.as_ptr(), buf, len);
-                *out_ptr = buf;
-                *out_len = len;
+    // Data which can't be parsed is left for Bento4 to handle.
+    for (_, init) in &periods {
+        let _ = report::split_kids(init, keys, &raw_key_pairs, &mut report);
+    }
+
+    report.duration_seconds = report::duration_seconds(&periods).unwrap_or_default();
+
+    if options.on_missing_key != MissingKeyPolicy::Ignore {
+        let mut missing_keys = Vec::new();
+
+        for (_, init) in &periods {
+            for x in report::find_missing_keys(init, keys, &raw_key_pairs)? {
+                if !missing_keys.contains(&x) {
+                    missing_keys.push(x);
+                }
             }
-            0
         }
-        Err(err) => {
-            if !err_out.is_null() {
-                let msg = CString::new(err.msg).unwrap();
-                unsafe {
-                    (*err_out).code = match err.err_type {
-                        ErrorType::InvalidFormat => 1,
-                        ErrorType::DataTooLarge => 2,
-                        ErrorType::Failed(x) => x,
-                    };
-                    (*err_out).message = msg.into_raw();
-                }
+
+        if let (MissingKeyPolicy::Fail, Some(x)) = (options.on_missing_key, missing_keys.first()) {
+            return Err(Error {
+                msg: match x.kid {
+                    Some(kid) => format!(
+                        "no key found for track {} (kid {}).",
+                        x.track_id,
+                        keys::to_hex(&kid)
+                    ),
+                    None => format!("no key found for track {}.", x.track_id),
+                },
+                err_type: ErrorType::MissingKeyForTrack(x.track_id, x.kid),
+                invalid_key: false,
+            });
+        }
+
+        report.missing_keys = missing_keys;
+    }
+
+    if options.collect_stats || options.compute_crc32 {
+        for (data, init) in &periods {
+            for (track_id, stats) in report::collect_stats(data, init, keys, &raw_key_pairs)? {
+                report
+                    .tracks
+                    .entry(track_id)
+                    .and_modify(|x| {
+                        x.samples_decrypted += stats.samples_decrypted;
+                        x.bytes_decrypted += stats.bytes_decrypted;
+                    })
+                    .or_insert(stats);
             }
-            1
         }
     }
+
+    let mut key_pairs = keys.key_pairs();
+    key_pairs.0.append(&mut raw_key_pairs.0);
+    Ok((key_pairs, report))
+}
+
+/// Decrypt encrypted mp4 data stream and return the statistics of every decrypted track.
+///
+/// This is a shorthand for [mp4decrypt_with_report](mp4decrypt_with_report) with
+/// [DecryptOptions::collect_stats](DecryptOptions::collect_stats) enabled, discarding the decrypted data.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("init.mp4").unwrap();
+/// let segment = std::fs::read("segment_0.m4s").unwrap();
+///
+/// for (track_id, stats) in mp4decrypt::decrypt_stats(&segment, Some(&init), &keys).unwrap() {
+///     println!(
+///         "track {}: {} samples ({} bytes) using {:?}",
+///         track_id, stats.samples_decrypted, stats.bytes_decrypted, stats.scheme
+///     );
+/// }
+/// ```
+pub fn decrypt_stats(
+    data: &[u8],
+    init: Option<&[u8]>,
+    keys: &KeyMap,
+) -> Result<HashMap<u32, TrackStats>, Error> {
+    let options = DecryptOptions::new().collect_stats(true);
+    mp4decrypt_with_report(data, keys, init, &options).map(|(_, x)| x.tracks)
 }
@@ -0,0 +1,600 @@
+use crate::{
+    Error, ErrorType, PsshBox, Scheme, SchemeInfo,
+    boxes::{self, Mp4Box},
+    protection::{PIFF_PSSH, PIFF_TRACK_ENCRYPTION},
+    pssh,
+    reader::Reader,
+};
+
+/// Classification of a track from its `hdlr` handler type.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TrackKind {
+    Video,
+    Audio,
+    Subtitle,
+    Metadata,
+    /// Any other handler type, holding its raw four-cc.
+    Unknown(String),
+}
+
+impl TrackKind {
+    /// Map a `hdlr` handler type to a track kind.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::TrackKind;
+    ///
+    /// assert_eq!(TrackKind::from_handler(b"vide"), TrackKind::Video);
+    /// assert_eq!(TrackKind::from_handler(b"text"), TrackKind::Subtitle);
+    /// assert_eq!(TrackKind::from_handler(b"hint"), TrackKind::Unknown("hint".to_owned()));
+    /// ```
+    pub fn from_handler(handler: &[u8; 4]) -> Self {
+        match handler {
+            b"vide" => Self::Video,
+            b"soun" => Self::Audio,
+            b"subt" | b"text" | b"sbtl" => Self::Subtitle,
+            b"meta" => Self::Metadata,
+            x => Self::Unknown(boxes::fourcc(x)),
+        }
+    }
+}
+
+/// Track information read from a `moov` box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackInfo {
+    pub track_id: u32,
+    pub kind: TrackKind,
+    /// Raw `hdlr` handler type.
+    pub handler: [u8; 4],
+    pub timescale: u32,
+    /// Type of the first sample entry, e.g. `encv` or `avc1`.
+    pub sample_entry: [u8; 4],
+    /// Types of every sample entry of the `stsd` box in order, usually just `sample_entry`.
+    /// Tracks with codec switches or both clear and protected variants have more than one.
+    pub sample_entries: Vec<[u8; 4]>,
+    /// Protection scheme from the `schm` box of the first protected sample entry, if it is a known one.
+    pub scheme: Option<Scheme>,
+    /// Scheme type and version from the `schm` box of the first protected sample entry.
+    pub scheme_info: Option<SchemeInfo>,
+    /// Default KID from the `tenc` box of the first protected sample entry.
+    pub default_kid: Option<[u8; 16]>,
+}
+
+impl TrackInfo {
+    /// True if any sample entry is a protected one (`encv`, `enca` etc.).
+    pub fn is_protected(&self) -> bool {
+        self.sample_entries.iter().any(is_protected_entry)
+    }
+}
+
+pub(crate) fn is_protected_entry(sample_entry: &[u8; 4]) -> bool {
+    matches!(
+        sample_entry,
+        b"encv" | b"enca" | b"enct" | b"encs" | b"encm" | b"encf"
+    )
+}
+
+/// Default encryption parameters of a track from its `tenc` box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackEncryption {
+    pub track_id: u32,
+    /// Number of encrypted blocks in the pattern, 0 if pattern encryption isn't used.
+    pub crypt_byte_block: u8,
+    /// Number of clear blocks in the pattern.
+    pub skip_byte_block: u8,
+    pub is_protected: bool,
+    /// Size of per sample IVs in bytes, 0 if `constant_iv` is used instead.
+    pub per_sample_iv_size: u8,
+    pub kid: [u8; 16],
+    pub constant_iv: Option<Vec<u8>>,
+}
+
+impl TrackEncryption {
+    /// Size of the IVs used for decrypting samples, either per sample or constant.
+    pub fn iv_size(&self) -> u8 {
+        match &self.constant_iv {
+            Some(x) if self.per_sample_iv_size == 0 => x.len() as u8,
+            _ => self.per_sample_iv_size,
+        }
+    }
+
+    /// Check that an IV supplied from outside of the stream has the same size as the IVs of this track,
+    /// since an IV of a different size silently produces a wrong output.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{ErrorType, Mp4Metadata};
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let metadata = Mp4Metadata::parse(&init).unwrap();
+    /// let tenc = &metadata.tenc()[0];
+    ///
+    /// assert!(tenc.check_iv(&[0; 8]).is_ok());
+    /// let error = tenc.check_iv(&[0; 16]).unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::IvLengthMismatch { expected: 8, got: 16 }));
+    /// ```
+    pub fn check_iv(&self, iv: &[u8]) -> Result<(), Error> {
+        let expected = self.iv_size() as usize;
+
+        if iv.len() == expected {
+            return Ok(());
+        }
+
+        Err(Error {
+            msg: format!(
+                "track {} uses {} byte IVs but a {} byte IV was given.",
+                self.track_id,
+                expected,
+                iv.len()
+            ),
+            err_type: ErrorType::IvLengthMismatch {
+                expected,
+                got: iv.len(),
+            },
+            invalid_key: false,
+        })
+    }
+}
+
+/// Metadata of an init segment or a self-contained file, read in a single walk over its boxes.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{Mp4Metadata, Scheme};
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let metadata = Mp4Metadata::parse(&init).unwrap();
+///
+/// assert_eq!(metadata.scheme(), Some(Scheme::Cenc));
+/// assert_eq!(
+///     metadata.kids(),
+///     [mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3da").unwrap()]
+/// );
+/// assert_eq!(metadata.tracks().len(), 1);
+/// assert_eq!(metadata.tenc()[0].per_sample_iv_size, 8);
+/// assert_eq!(metadata.pssh().len(), 2);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Mp4Metadata {
+    pub(crate) tracks: Vec<TrackInfo>,
+    pub(crate) tenc: Vec<TrackEncryption>,
+    pub(crate) pssh: Vec<PsshBox>,
+}
+
+impl Mp4Metadata {
+    /// Read the tracks of the `moov` box, and every `pssh` box at the top level or inside `moov` and `moof` boxes.
+    ///
+    /// A malformed box after the `moov` box ends the walk, so that a truncated file can still be read.
+    pub fn parse(init: &[u8]) -> Result<Self, Error> {
+        let mut metadata = Self::default();
+        let mut has_moov = false;
+
+        for mp4_box in boxes::boxes(init) {
+            let mp4_box = match mp4_box {
+                Ok(x) => x,
+                Err(_) if has_moov => break,
+                Err(e) => return Err(e),
+            };
+
+            match &mp4_box.box_type {
+                b"pssh" => metadata.pssh.push(pssh::read_pssh(mp4_box.payload())?),
+                b"uuid" if mp4_box.user_type() == Some(PIFF_PSSH) => {
+                    metadata.pssh.push(pssh::read_pssh(mp4_box.payload())?)
+                }
+                b"moov" | b"moof" => {
+                    // Only tracks of the first moov box are read.
+                    let read_tracks = &mp4_box.box_type == b"moov" && !has_moov;
+                    has_moov |= read_tracks;
+
+                    for child in mp4_box.children() {
+                        let child = child?;
+
+                        match &child.box_type {
+                            b"pssh" => metadata.pssh.push(pssh::read_pssh(child.payload())?),
+                            b"uuid" if child.user_type() == Some(PIFF_PSSH) => {
+                                metadata.pssh.push(pssh::read_pssh(child.payload())?)
+                            }
+                            b"trak" if read_tracks => {
+                                let (track, tenc) = read_trak_encryption(&child)?;
+                                metadata.tracks.push(track);
+                                metadata.tenc.extend(tenc);
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// Scheme of the first protected track.
+    pub fn scheme(&self) -> Option<Scheme> {
+        self.tracks
+            .iter()
+            .filter(|x| x.is_protected())
+            .find_map(|x| x.scheme)
+    }
+
+    /// Scheme type and version of the first protected track which has a `schm` box.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{Mp4Metadata, Scheme};
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let metadata = Mp4Metadata::parse(&init).unwrap();
+    /// let scheme_info = metadata.scheme_info().unwrap();
+    ///
+    /// assert_eq!(scheme_info.scheme, Some(Scheme::Cenc));
+    /// assert_eq!(scheme_info.scheme_version, 0x00010000);
+    /// assert_eq!(scheme_info.version(), (1, 0));
+    /// assert_eq!(scheme_info.scheme_uri, None);
+    /// ```
+    pub fn scheme_info(&self) -> Option<&SchemeInfo> {
+        self.tracks
+            .iter()
+            .filter(|x| x.is_protected())
+            .find_map(|x| x.scheme_info.as_ref())
+    }
+
+    /// Default KIDs of the protected tracks, without duplicates.
+    pub fn kids(&self) -> Vec<[u8; 16]> {
+        let mut kids = Vec::new();
+
+        for kid in self
+            .tracks
+            .iter()
+            .filter(|x| x.is_protected())
+            .filter_map(|x| x.default_kid)
+        {
+            if !kids.contains(&kid) {
+                kids.push(kid);
+            }
+        }
+
+        kids
+    }
+
+    /// Tracks declared in the `moov` box, see [list_tracks](list_tracks).
+    pub fn tracks(&self) -> &[TrackInfo] {
+        &self.tracks
+    }
+
+    /// Encryption parameters of the protected tracks which have a `tenc` box.
+    pub fn tenc(&self) -> &[TrackEncryption] {
+        &self.tenc
+    }
+
+    /// Parsed `pssh` boxes, see [extract_pssh](crate::extract_pssh).
+    pub fn pssh(&self) -> &[PsshBox] {
+        &self.pssh
+    }
+}
+
+/// List the tracks declared in the `moov` box of an init segment or a self-contained file.
+///
+/// Returns an empty list if there is no `moov` box. Use [Mp4Metadata](Mp4Metadata) for reading
+/// more than just the tracks.
+pub fn list_tracks(data: &[u8]) -> Result<Vec<TrackInfo>, Error> {
+    Mp4Metadata::parse(data).map(|x| x.tracks)
+}
+
+/// File type read from an `ftyp` box, or a segment type read from an `styp` box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FtypInfo {
+    pub major_brand: [u8; 4],
+    pub minor_version: u32,
+    pub compatible_brands: Vec<[u8; 4]>,
+}
+
+impl FtypInfo {
+    /// True if the major brand or any compatible brand matches.
+    pub fn has_brand(&self, brand: &[u8; 4]) -> bool {
+        &self.major_brand == brand || self.compatible_brands.contains(brand)
+    }
+}
+
+/// Read the brands of the `ftyp` (or `styp`) box at the start of data, e.g. for telling CMAF (`cmfc`),
+/// DASH (`dash`) and Smooth Streaming (`piff`) content apart.
+///
+/// An error is returned if data doesn't start with an `ftyp` or `styp` box.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let ftyp = mp4decrypt::read_ftyp(&init).unwrap();
+///
+/// assert_eq!(&ftyp.major_brand, b"isom");
+/// assert_eq!(ftyp.minor_version, 1);
+/// assert_eq!(ftyp.compatible_brands, [*b"isom", *b"avc1", *b"iso5", *b"iso6"]);
+/// assert!(ftyp.has_brand(b"iso6"));
+///
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+/// assert!(mp4decrypt::read_ftyp(&segment).is_err());
+/// ```
+pub fn read_ftyp(data: &[u8]) -> Result<FtypInfo, Error> {
+    let ftyp = match boxes::boxes(data).next().transpose()? {
+        Some(x) if matches!(&x.box_type, b"ftyp" | b"styp") => x,
+        _ => {
+            return Err(Error::new_format(
+                "data stream doesn't start with an ftyp or styp box.",
+            ));
+        }
+    };
+
+    let mut reader = Reader::new(ftyp.payload());
+    let major_brand = reader.read_array()?;
+    let minor_version = reader.read_u32()?;
+    let mut compatible_brands = Vec::with_capacity(reader.remaining() / 4);
+
+    while reader.remaining() >= 4 {
+        compatible_brands.push(reader.read_array()?);
+    }
+
+    Ok(FtypInfo {
+        major_brand,
+        minor_version,
+        compatible_brands,
+    })
+}
+
+pub(crate) fn read_trak(trak: &Mp4Box) -> Result<TrackInfo, Error> {
+    read_trak_encryption(trak).map(|(x, _)| x)
+}
+
+/// Read a track along with the encryption parameters of every protected sample entry which has a `tenc` box.
+pub(crate) fn read_trak_encryption(
+    trak: &Mp4Box,
+) -> Result<(TrackInfo, Vec<TrackEncryption>), Error> {
+    let tkhd = required(trak, b"tkhd")?;
+    let mut reader = Reader::new(tkhd.payload());
+    let (version, _) = reader.read_version_flags()?;
+    reader.skip(if version == 1 { 16 } else { 8 })?; // creation_time, modification_time
+    let track_id = reader.read_u32()?;
+
+    let mdia = required(trak, b"mdia")?;
+
+    let mdhd = required(&mdia, b"mdhd")?;
+    let mut reader = Reader::new(mdhd.payload());
+    let (version, _) = reader.read_version_flags()?;
+    reader.skip(if version == 1 { 16 } else { 8 })?; // creation_time, modification_time
+    let timescale = reader.read_u32()?;
+
+    let hdlr = required(&mdia, b"hdlr")?;
+    let mut reader = Reader::new(hdlr.payload());
+    reader.skip(8)?; // version, flags, pre_defined
+    let handler = reader.read_array::<4>()?;
+
+    let stsd = required(&required(&required(&mdia, b"minf")?, b"stbl")?, b"stsd")?;
+    let mut reader = Reader::new(stsd.payload());
+    reader.read_version_flags()?;
+    let entry_count = reader.read_u32()?;
+    let mut sample_entries = Vec::new();
+    let mut protections = Vec::new();
+
+    for entry in stsd.children_after(8).take(entry_count as usize) {
+        let entry = entry?;
+        sample_entries.push(entry.box_type);
+        protections.push(read_protection(&entry, track_id)?);
+    }
+
+    let tencs = protections
+        .iter()
+        .filter_map(|x| x.tenc.clone())
+        .collect::<Vec<_>>();
+    let Protection {
+        scheme,
+        scheme_info,
+        tenc,
+    } = protections
+        .into_iter()
+        .find(|x| x.scheme_info.is_some() || x.tenc.is_some())
+        .unwrap_or_default();
+
+    let track = TrackInfo {
+        track_id,
+        kind: TrackKind::from_handler(&handler),
+        handler,
+        timescale,
+        sample_entry: sample_entries.first().copied().unwrap_or([0; 4]),
+        sample_entries,
+        scheme,
+        scheme_info,
+        default_kid: tenc.as_ref().map(|x| x.kid),
+    };
+
+    Ok((track, tencs))
+}
+
+/// Protection of a sample entry.
+#[derive(Default)]
+struct Protection {
+    scheme: Option<Scheme>,
+    scheme_info: Option<SchemeInfo>,
+    tenc: Option<TrackEncryption>,
+}
+
+/// Read the scheme from `sinf/schm` box and the encryption parameters from `sinf/schi/tenc` box of a sample entry.
+fn read_protection(entry: &Mp4Box, track_id: u32) -> Result<Protection, Error> {
+    let Some(sinf) = read_sinf(entry)? else {
+        return Ok(Protection::default());
+    };
+
+    let scheme_info = match sinf.child(b"schm")? {
+        Some(schm) => Some(read_schm(&schm)?),
+        None => None,
+    };
+    let scheme = scheme_info.as_ref().and_then(|x| x.scheme);
+
+    let Some(schi) = sinf.child(b"schi")? else {
+        return Ok(Protection {
+            scheme,
+            scheme_info,
+            tenc: None,
+        });
+    };
+
+    let Some(tenc) = schi.child(b"tenc")? else {
+        return read_piff_tenc(&schi, track_id).map(|tenc| match tenc {
+            // Smooth Streaming content may not declare the scheme in a schm box.
+            Some(x) => Protection {
+                scheme: scheme.or(Some(Scheme::Piff)),
+                scheme_info,
+                tenc: Some(x),
+            },
+            None => Protection {
+                scheme,
+                scheme_info,
+                tenc: None,
+            },
+        });
+    };
+
+    Ok(Protection {
+        scheme,
+        scheme_info,
+        tenc: Some(read_tenc(&tenc, track_id)?),
+    })
+}
+
+/// Read the encryption parameters of a `tenc` box.
+pub(crate) fn read_tenc(tenc: &Mp4Box, track_id: u32) -> Result<TrackEncryption, Error> {
+    let mut reader = Reader::new(tenc.payload());
+    let (version, _) = reader.read_version_flags()?;
+    reader.skip(1)?; // reserved
+    let pattern = reader.read_u8()?;
+    let (crypt_byte_block, skip_byte_block) = if version == 0 {
+        (0, 0)
+    } else {
+        (pattern >> 4, pattern & 0x0f)
+    };
+    let is_protected = reader.read_u8()? == 1;
+    let per_sample_iv_size = reader.read_u8()?;
+    let kid = reader.read_array::<16>()?;
+    let constant_iv = if is_protected && per_sample_iv_size == 0 {
+        let size = reader.read_u8()?;
+        Some(reader.read_bytes(size as usize)?.to_vec())
+    } else {
+        None
+    };
+
+    Ok(TrackEncryption {
+        track_id,
+        crypt_byte_block,
+        skip_byte_block,
+        is_protected,
+        per_sample_iv_size,
+        kid,
+        constant_iv,
+    })
+}
+
+fn read_schm(schm: &Mp4Box) -> Result<SchemeInfo, Error> {
+    let mut reader = Reader::new(schm.payload());
+    let (_, flags) = reader.read_version_flags()?;
+    let scheme_type = reader.read_array::<4>()?;
+    let scheme_version = reader.read_u32()?;
+    let scheme_uri = if flags & 0x01 != 0 {
+        let uri = reader.read_bytes(reader.remaining())?;
+        let uri = uri.split(|x| *x == 0).next().unwrap_or_default();
+        Some(String::from_utf8_lossy(uri).into_owned())
+    } else {
+        None
+    };
+
+    Ok(SchemeInfo {
+        scheme: Scheme::from_fourcc(&scheme_type),
+        scheme_type,
+        scheme_version,
+        flags,
+        scheme_uri,
+    })
+}
+
+/// Read the PIFF track encryption `uuid` box, which only differs from a version 0 `tenc` box by
+/// having a 24-bit algorithm ID (0 for clear, 1 for AES-CTR and 2 for AES-CBC) instead of `is_protected`.
+fn read_piff_tenc(schi: &Mp4Box, track_id: u32) -> Result<Option<TrackEncryption>, Error> {
+    for child in schi.children() {
+        let child = child?;
+
+        if child.user_type() != Some(PIFF_TRACK_ENCRYPTION) {
+            continue;
+        }
+
+        let mut reader = Reader::new(child.payload());
+        reader.read_version_flags()?;
+        let algorithm_id = reader.read_array::<3>()?;
+        let per_sample_iv_size = reader.read_u8()?;
+
+        return Ok(Some(TrackEncryption {
+            track_id,
+            crypt_byte_block: 0,
+            skip_byte_block: 0,
+            is_protected: algorithm_id != [0; 3],
+            per_sample_iv_size,
+            kid: reader.read_array::<16>()?,
+            constant_iv: None,
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Find the `sinf` box of a protected sample entry.
+///
+/// An entry may hold several `sinf` boxes of different protection schemes, the first one whose scheme is
+/// supported is preferred over the first one.
+pub(crate) fn read_sinf<'a>(entry: &Mp4Box<'a>) -> Result<Option<Mp4Box<'a>>, Error> {
+    let Some(fields_size) = protected_entry_fields_size(&entry.box_type) else {
+        return Ok(None);
+    };
+
+    let mut first = None;
+
+    for child in entry.children_after(fields_size) {
+        let child = child?;
+
+        if &child.box_type != b"sinf" {
+            continue;
+        }
+
+        if let Some(schm) = child.child(b"schm")?
+            && read_schm(&schm)?.scheme.is_some()
+        {
+            return Ok(Some(child));
+        }
+
+        first.get_or_insert(child);
+    }
+
+    Ok(first)
+}
+
+/// Size of the fields preceding child boxes of a protected sample entry.
+pub(crate) fn protected_entry_fields_size(box_type: &[u8; 4]) -> Option<usize> {
+    match box_type {
+        b"encv" => Some(78),
+        b"enca" => Some(28),
+        b"enct" | b"encs" | b"encm" | b"encf" => Some(8),
+        _ => None,
+    }
+}
+
+fn required<'a>(parent: &Mp4Box<'a>, box_type: &[u8; 4]) -> Result<Mp4Box<'a>, Error> {
+    parent.child(box_type)?.ok_or_else(|| {
+        Error::new_format(format!(
+            "{} box at offset {} has no {} box.",
+            boxes::fourcc(&parent.box_type),
+            parent.offset,
+            boxes::fourcc(box_type)
+        ))
+    })
+}
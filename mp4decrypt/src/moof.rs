@@ -0,0 +1,223 @@
+use crate::{
+    Error,
+    boxes::{self, Mp4Box},
+    reader::Reader,
+};
+use std::collections::HashMap;
+
+/// Maximum number of samples which `trun` boxes without per-sample fields can describe in a `moof` box.
+/// Their sample count isn't bounded by the size of the box, so a malformed box could claim billions of
+/// samples otherwise.
+const MAX_SAMPLES_WITHOUT_FIELDS: u64 = 1 << 24;
+
+/// A sample stored in the `mdat` box following a `moof` box.
+#[derive(Clone, Debug)]
+pub(crate) struct FragmentSample {
+    /// Absolute offset of the sample data.
+    pub(crate) offset: u64,
+    pub(crate) size: u32,
+}
+
+/// A parsed `traf` box.
+pub(crate) struct TrackFragment<'a> {
+    pub(crate) mp4_box: Mp4Box<'a>,
+    pub(crate) track_id: u32,
+    /// Absolute offset which data offsets of the `trun` boxes are relative to.
+    pub(crate) base_data_offset: u64,
+    /// Absolute offset of the `moof` box, which data offsets of the `saio` box are relative to.
+    /// Bento4 ignores the base data offset of the `tfhd` box for them.
+    pub(crate) moof_offset: u64,
+    pub(crate) runs: Vec<TrackRun<'a>>,
+}
+
+/// A parsed `trun` box.
+pub(crate) struct TrackRun<'a> {
+    pub(crate) mp4_box: Mp4Box<'a>,
+    pub(crate) samples: Vec<FragmentSample>,
+}
+
+/// Read default sample sizes of every track from the `trex` boxes of a `moov`.
+pub(crate) fn read_trex_sizes(data: &[u8]) -> Result<HashMap<u32, u32>, Error> {
+    let mut sizes = HashMap::new();
+
+    let Some(moov) = boxes::find(boxes::boxes(data), b"moov")? else {
+        return Ok(sizes);
+    };
+
+    let Some(mvex) = boxes::find(moov.children(), b"mvex")? else {
+        return Ok(sizes);
+    };
+
+    for trex in mvex.children() {
+        let trex = trex?;
+
+        if &trex.box_type == b"trex" {
+            let mut reader = Reader::new(trex.payload());
+            reader.read_version_flags()?;
+            let track_id = reader.read_u32()?;
+            reader.skip(8)?; // default_sample_description_index, default_sample_duration
+            sizes.insert(track_id, reader.read_u32()?);
+        }
+    }
+
+    Ok(sizes)
+}
+
+/// Resolve the location of every sample described by the `traf` boxes of a `moof`, the same way Bento4 does.
+///
+/// Bento4 differs from the specification for data offsets which some packagers rely on:
+/// * The base data offset is the `moof` box if the `tfhd` box has none, also for every `traf` box after
+///   the first one and whether the default-base-is-moof flag is set or not.
+/// * Samples of a `trun` box whose data offset resolves to the `moof` box, i.e. it has no data offset or
+///   a data offset of 0, follow the samples of the previous `trun` box of the `traf` box. Samples of the
+///   first one start right after the header of an `mdat` box following the `moof` box.
+pub(crate) fn read_track_fragments<'a>(
+    moof: &Mp4Box<'a>,
+    trex_sizes: &HashMap<u32, u32>,
+) -> Result<Vec<TrackFragment<'a>>, Error> {
+    let mut fragments = Vec::new();
+    let moof_offset = moof.offset as u64;
+    let mut samples_without_fields = 0;
+
+    for traf in moof.children() {
+        let traf = traf?;
+
+        if &traf.box_type != b"traf" {
+            continue;
+        }
+
+        let Some(tfhd) = boxes::find(traf.children(), b"tfhd")? else {
+            return Err(Error::new_format(format!(
+                "traf box at offset {} has no tfhd box.",
+                traf.offset
+            )));
+        };
+
+        let mut reader = Reader::new(tfhd.payload());
+        let (_, flags) = reader.read_version_flags()?;
+        let track_id = reader.read_u32()?;
+        let base_data_offset = if flags & 0x01 != 0 {
+            Some(reader.read_u64()?)
+        } else {
+            None
+        };
+        if flags & 0x02 != 0 {
+            reader.skip(4)?; // sample_description_index
+        }
+        if flags & 0x08 != 0 {
+            reader.skip(4)?; // default_sample_duration
+        }
+        let default_size = if flags & 0x10 != 0 {
+            Some(reader.read_u32()?)
+        } else {
+            trex_sizes.get(&track_id).copied()
+        };
+
+        let base = base_data_offset.unwrap_or(moof_offset);
+        // payload of an mdat box with a 32-bit size
+        let mut data_end = moof.end() as u64 + 8;
+        let mut runs = Vec::new();
+
+        for trun in traf.children() {
+            let trun = trun?;
+
+            if &trun.box_type != b"trun" {
+                continue;
+            }
+
+            let mut reader = Reader::new(trun.payload());
+            let (_, flags) = reader.read_version_flags()?;
+            let sample_count = reader.read_u32()?;
+            let mut offset = if flags & 0x01 != 0 {
+                base.checked_add_signed(reader.read_u32()? as i32 as i64)
+                    .ok_or_else(|| {
+                        Error::new_format(format!(
+                            "trun box at offset {} has an invalid data offset.",
+                            trun.offset
+                        ))
+                    })?
+            } else {
+                base
+            };
+
+            if offset == moof_offset {
+                offset = data_end;
+            }
+            if flags & 0x04 != 0 {
+                reader.skip(4)?; // first_sample_flags
+            }
+
+            let field_size = [0x100, 0x200, 0x400, 0x800]
+                .iter()
+                .filter(|x| flags & **x != 0)
+                .count()
+                * 4;
+
+            if (sample_count as usize).saturating_mul(field_size) > reader.remaining() {
+                return Err(Error::new_format(format!(
+                    "trun box at offset {} is too small for {} samples.",
+                    trun.offset, sample_count
+                )));
+            }
+
+            if field_size == 0 {
+                samples_without_fields += sample_count as u64;
+
+                if samples_without_fields > MAX_SAMPLES_WITHOUT_FIELDS {
+                    return Err(Error::new_format(format!(
+                        "trun box at offset {} has more samples than supported.",
+                        trun.offset
+                    )));
+                }
+            }
+
+            let mut samples = Vec::with_capacity(sample_count as usize);
+
+            for _ in 0..sample_count {
+                if flags & 0x100 != 0 {
+                    reader.skip(4)?; // sample_duration
+                }
+                let size = if flags & 0x200 != 0 {
+                    reader.read_u32()?
+                } else {
+                    default_size.ok_or_else(|| {
+                        Error::new_format(format!(
+                            "sample size of track {} is not defined.",
+                            track_id
+                        ))
+                    })?
+                };
+                if flags & 0x400 != 0 {
+                    reader.skip(4)?; // sample_flags
+                }
+                if flags & 0x800 != 0 {
+                    reader.skip(4)?; // sample_composition_time_offset
+                }
+
+                samples.push(FragmentSample { offset, size });
+                offset = offset.checked_add(size as u64).ok_or_else(|| {
+                    Error::new_format(format!(
+                        "trun box at offset {} has an invalid data offset.",
+                        trun.offset
+                    ))
+                })?;
+            }
+
+            data_end = offset;
+            runs.push(TrackRun {
+                mp4_box: trun,
+                samples,
+            });
+        }
+
+        fragments.push(TrackFragment {
+            mp4_box: traf,
+            track_id,
+            base_data_offset: base,
+            moof_offset,
+            runs,
+        });
+    }
+
+    Ok(fragments)
+}
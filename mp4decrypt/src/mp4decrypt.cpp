@@ -12,6 +12,37 @@
 #include "Ap4.h"
 #include "mp4decrypt.h"
 
+const char* bento4_version_string(void) {
+    return AP4_VERSION_STRING;
+}
+
+// overwrite a parsed key once it is copied into the key map, volatile so that it isn't optimized away
+static void wipe_key(unsigned char key[16]) {
+    volatile unsigned char* bytes = key;
+    for (int i = 0; i < 16; i++) {
+        bytes[i] = 0;
+    }
+}
+
+// report every interval-th step and the last step, like --show-progress of mp4decrypt reports every step
+class CallbackProgressListener : public AP4_Processor::ProgressListener {
+public:
+    CallbackProgressListener(void* context, rust_progress_callback callback, unsigned int interval) :
+        m_Context(context), m_Callback(callback), m_Interval(interval) {}
+
+    AP4_Result OnProgress(unsigned int step, unsigned int total) {
+        if (step % m_Interval == 0 || step == total) {
+            m_Callback(m_Context, step, total);
+        }
+        return AP4_SUCCESS;
+    }
+
+private:
+    void*                  m_Context;
+    rust_progress_callback m_Callback;
+    unsigned int           m_Interval;
+};
+
 int decrypt_in_memory(
     const unsigned char data[],
     unsigned int data_size,
@@ -19,7 +50,9 @@ int decrypt_in_memory(
     const char* keys[],
     int nkeys,
     void* decrypted_data,
-    rust_store_callback callback
+    rust_store_callback callback,
+    rust_progress_callback progress_callback,
+    unsigned int progress_interval
 ) {
     // create a key map object to hold keys
     AP4_ProtectionKeyMap key_map;
@@ -49,6 +82,7 @@ int decrypt_in_memory(
         } else {
             key_map.SetKeyForKid(kid, key, 16);
         }
+        wipe_key(key);
     }
 
     AP4_MemoryByteStream* input = new AP4_MemoryByteStream(data, data_size);
@@ -105,7 +139,8 @@ int decrypt_in_memory(
 
     AP4_MemoryByteStream* output = new AP4_MemoryByteStream();
     AP4_AtomFactory atom_factory;
-    AP4_Result result = processor->Process(*input, *output, NULL, atom_factory);
+    CallbackProgressListener listener(decrypted_data, progress_callback, progress_interval);
+    AP4_Result result = processor->Process(*input, *output, progress_interval ? &listener : NULL, atom_factory);
     if (AP4_FAILED(result)) {
         return result;
     }
@@ -126,6 +161,8 @@ int decrypt_in_memory_with_fragments_info(
     int nkeys,
     void* decrypted_data,
     rust_store_callback callback,
+    rust_progress_callback progress_callback,
+    unsigned int progress_interval,
     const unsigned char fragments_info_data[],
     unsigned int fragments_info_data_size
 ) {
@@ -157,6 +194,7 @@ int decrypt_in_memory_with_fragments_info(
         } else {
             key_map.SetKeyForKid(kid, key, 16);
         }
+        wipe_key(key);
     }
 
     AP4_MemoryByteStream* fragments_info = new AP4_MemoryByteStream(fragments_info_data, fragments_info_data_size);
@@ -214,7 +252,8 @@ int decrypt_in_memory_with_fragments_info(
     AP4_MemoryByteStream* input = new AP4_MemoryByteStream(data, data_size);
     AP4_MemoryByteStream* output = new AP4_MemoryByteStream();
     AP4_AtomFactory atom_factory;
-    AP4_Result result = processor->Process(*input, *output, *fragments_info, NULL, atom_factory);
+    CallbackProgressListener listener(decrypted_data, progress_callback, progress_interval);
+    AP4_Result result = processor->Process(*input, *output, *fragments_info, progress_interval ? &listener : NULL, atom_factory);
     if (AP4_FAILED(result)) {
         return result;
     }
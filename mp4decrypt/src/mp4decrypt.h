@@ -7,6 +7,7 @@ extern "C"
 #endif
 
     typedef void (*rust_store_callback)(void *, const unsigned char *data, unsigned int length);
+    typedef void (*rust_progress_callback)(void *, unsigned int step, unsigned int total);
     int decrypt_in_memory(
         const unsigned char data[],
         unsigned int data_size,
@@ -14,7 +15,9 @@ extern "C"
         const char* keys[],
         int nkeys,
         void* decrypted_data,
-        rust_store_callback callback
+        rust_store_callback callback,
+        rust_progress_callback progress_callback,
+        unsigned int progress_interval
     );
     int decrypt_in_memory_with_fragments_info(
         const unsigned char data[],
@@ -24,9 +27,12 @@ extern "C"
         int nkeys,
         void* decrypted_data,
         rust_store_callback callback,
+        rust_progress_callback progress_callback,
+        unsigned int progress_interval,
         const unsigned char fragments_info_data[],
         unsigned int fragments_info_data_size
     );
+    const char* bento4_version_string(void);
 
 #ifdef __cplusplus
 }
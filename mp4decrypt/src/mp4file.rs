@@ -0,0 +1,129 @@
+use crate::{
+    DecryptOptions, Error, KeyMap, Mp4Metadata, PsshBox, TrackInfo, mp4decrypt_with_options,
+    protection,
+};
+use std::{borrow::Cow, fmt};
+
+/// An mp4 data stream parsed once, for reading its tracks and protection and decrypting it through a single
+/// value rather than the free functions, each of which parses the data stream again.
+///
+/// The data stream is borrowed by [new](Self::new) and `TryFrom<&[u8]>`, and owned by `TryFrom<Vec<u8>>`.
+/// Its `moov` box and `pssh` boxes are read when it is created, same as [Mp4Metadata](Mp4Metadata) does.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{KeyMap, Mp4File};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let mut data = std::fs::read("examples/sample/init.mp4").unwrap();
+/// data.extend(std::fs::read("examples/sample/segment_0.m4s").unwrap());
+///
+/// let file = Mp4File::new(&data).unwrap();
+/// assert_eq!(file.tracks().len(), 1);
+/// assert_eq!(file.pssh().len(), 2);
+/// assert!(file.is_encrypted());
+///
+/// let decrypted_file = Mp4File::try_from(file.decrypt(&keys).unwrap()).unwrap();
+/// assert!(!decrypted_file.tracks()[0].is_protected());
+/// // pssh boxes are kept
+/// assert!(decrypted_file.is_encrypted());
+/// ```
+#[derive(Clone)]
+pub struct Mp4File<'a> {
+    data: Cow<'a, [u8]>,
+    metadata: Mp4Metadata,
+    encrypted: bool,
+}
+
+impl<'a> Mp4File<'a> {
+    /// Parse a borrowed data stream, either an init segment, a self-contained file or media segments.
+    pub fn new(data: &'a [u8]) -> Result<Self, Error> {
+        Self::parse(Cow::Borrowed(data))
+    }
+
+    fn parse(data: Cow<'a, [u8]>) -> Result<Self, Error> {
+        Ok(Self {
+            metadata: Mp4Metadata::parse(&data)?,
+            encrypted: protection::is_encrypted(&data)?,
+            data,
+        })
+    }
+
+    /// Tracks of the `moov` box, see [list_tracks](crate::list_tracks).
+    pub fn tracks(&self) -> &[TrackInfo] {
+        self.metadata.tracks()
+    }
+
+    /// True if the data stream has any protection box, see [is_encrypted](crate::is_encrypted).
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Parsed `pssh` boxes, see [extract_pssh](crate::extract_pssh).
+    pub fn pssh(&self) -> &[PsshBox] {
+        self.metadata.pssh()
+    }
+
+    /// Tracks, track encryption and `pssh` boxes read from the data stream.
+    pub fn metadata(&self) -> &Mp4Metadata {
+        &self.metadata
+    }
+
+    /// Raw bytes of the data stream.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Decrypt the data stream with default options, see [mp4decrypt_with_options](crate::mp4decrypt_with_options).
+    ///
+    /// Media segments without a `moov` box need their init segment, decrypt them with
+    /// [mp4decrypt_with_options](crate::mp4decrypt_with_options) instead.
+    pub fn decrypt(&self, keys: &KeyMap) -> Result<Vec<u8>, Error> {
+        self.decrypt_with_options(keys, &DecryptOptions::new())
+    }
+
+    /// Decrypt the data stream with the given options.
+    pub fn decrypt_with_options(
+        &self,
+        keys: &KeyMap,
+        options: &DecryptOptions,
+    ) -> Result<Vec<u8>, Error> {
+        mp4decrypt_with_options(&self.data, keys, None, options)
+    }
+
+    /// Take the owned data stream, copying it if it is borrowed.
+    pub fn into_data(self) -> Vec<u8> {
+        self.data.into_owned()
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for Mp4File<'a> {
+    type Error = Error;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        Self::new(value)
+    }
+}
+
+impl TryFrom<Vec<u8>> for Mp4File<'static> {
+    type Error = Error;
+
+    fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::parse(Cow::Owned(value))
+    }
+}
+
+impl fmt::Debug for Mp4File<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Skip the bytes of the data stream.
+        f.debug_struct("Mp4File")
+            .field("len", &self.data.len())
+            .field("metadata", &self.metadata)
+            .field("encrypted", &self.encrypted)
+            .finish()
+    }
+}
@@ -0,0 +1,626 @@
+use crate::{Error, keys::KeyPairs};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// Options for customizing decryption.
+///
+/// # Example
+///
+/// ```
+/// let options = mp4decrypt::DecryptOptions::new().validate_structure(true);
+/// ```
+///
+/// Keys passed with [raw_bento4_args](Self::raw_bento4_args) are redacted from the [Debug](fmt::Debug) output.
+///
+/// ```
+/// let options = mp4decrypt::DecryptOptions::new()
+///     .raw_bento4_args(vec!["--key".to_owned(), "1:100b6c20940f779a4589152b57d2dacb".to_owned()]);
+///
+/// let debug = format!("{:?}", options);
+/// assert!(debug.contains(r#"raw_bento4_args: ["--key", "1:<redacted>"]"#));
+/// assert!(!debug.contains("100b6c20940f779a4589152b57d2dacb"));
+/// ```
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct DecryptOptions {
+    pub(crate) buffer_limit: usize,
+    pub(crate) chunk_size: usize,
+    pub(crate) collect_stats: bool,
+    pub(crate) compute_crc32: bool,
+    pub(crate) constant_ivs: HashMap<u32, Vec<u8>>,
+    pub(crate) flush_cmaf_chunks: bool,
+    pub(crate) keep_emsg: bool,
+    pub(crate) max_output_bytes: Option<usize>,
+    pub(crate) max_threads: usize,
+    pub(crate) normalize_box_versions: bool,
+    pub(crate) on_clear_input: ClearInputPolicy,
+    pub(crate) on_missing_key: MissingKeyPolicy,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) progress: Option<Progress>,
+    pub(crate) raw_bento4_args: Vec<String>,
+    pub(crate) strip_pssh: bool,
+    pub(crate) validate_structure: bool,
+}
+
+impl Default for DecryptOptions {
+    fn default() -> Self {
+        Self {
+            buffer_limit: 8 * 1024 * 1024,
+            chunk_size: 0,
+            collect_stats: false,
+            compute_crc32: false,
+            constant_ivs: HashMap::new(),
+            flush_cmaf_chunks: false,
+            keep_emsg: true,
+            max_output_bytes: None,
+            max_threads: 0,
+            normalize_box_versions: false,
+            on_clear_input: ClearInputPolicy::default(),
+            on_missing_key: MissingKeyPolicy::default(),
+            progress: None,
+            raw_bento4_args: Vec::new(),
+            strip_pssh: false,
+            validate_structure: false,
+        }
+    }
+}
+
+impl fmt::Debug for DecryptOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keep the id of <id>:<k> arguments.
+        let raw_bento4_args = self
+            .raw_bento4_args
+            .iter()
+            .map(|x| match x.split_once(':') {
+                Some((id, _)) => format!("{}:<redacted>", id),
+                None => x.to_owned(),
+            })
+            .collect::<Vec<_>>();
+
+        f.debug_struct("DecryptOptions")
+            .field("buffer_limit", &self.buffer_limit)
+            .field("chunk_size", &self.chunk_size)
+            .field("collect_stats", &self.collect_stats)
+            .field("compute_crc32", &self.compute_crc32)
+            .field("constant_ivs", &self.constant_ivs)
+            .field("flush_cmaf_chunks", &self.flush_cmaf_chunks)
+            .field("keep_emsg", &self.keep_emsg)
+            .field("max_output_bytes", &self.max_output_bytes)
+            .field("max_threads", &self.max_threads)
+            .field("normalize_box_versions", &self.normalize_box_versions)
+            .field("on_clear_input", &self.on_clear_input)
+            .field("on_missing_key", &self.on_missing_key)
+            .field("progress", &self.progress)
+            .field("raw_bento4_args", &raw_bento4_args)
+            .field("strip_pssh", &self.strip_pssh)
+            .field("validate_structure", &self.validate_structure)
+            .finish()
+    }
+}
+
+/// Progress callback of [DecryptOptions::on_progress](DecryptOptions::on_progress).
+#[derive(Clone)]
+pub(crate) struct Progress {
+    pub(crate) interval: u32,
+    pub(crate) callback: Arc<dyn Fn(u32, u32) + Send + Sync>,
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What to do when a protected track has no matching key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MissingKeyPolicy {
+    /// Leave the track encrypted without checking it.
+    #[default]
+    Ignore,
+    /// Leave the track encrypted and list it in [DecryptReport::missing_keys](crate::DecryptReport::missing_keys).
+    WarnReport,
+    /// Return [ErrorType::MissingKeyForTrack](crate::ErrorType::MissingKeyForTrack) before decrypting.
+    Fail,
+}
+
+/// What to do when keys are given but the input is already clear, e.g. media which was decrypted twice.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClearInputPolicy {
+    /// Pass the input through as it is.
+    #[default]
+    Ignore,
+    /// Pass the input through and set [DecryptReport::clear_input](crate::DecryptReport::clear_input).
+    WarnReport,
+    /// Return [ErrorType::AlreadyDecrypted](crate::ErrorType::AlreadyDecrypted) before decrypting.
+    Fail,
+}
+
+impl DecryptOptions {
+    /// Create options with default values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create options for decrypting content which is encrypted again afterwards, e.g. for re-keying it,
+    /// which keep the structure of the data stream as intact as possible.
+    ///
+    /// These are the default options with [validate_structure](Self::validate_structure) enabled and
+    /// [MissingKeyPolicy::Fail](MissingKeyPolicy::Fail), so that no track is left encrypted to be encrypted twice.
+    /// Boxes are never removed on top of what Bento4 removes, so the output of a fragmented data stream is:
+    ///
+    /// * Top level boxes other than `moof` and `mdat` (e.g. `ftyp`, `styp`, `sidx` and `emsg`) in their places.
+    /// * The `moov` box with every `pssh` box, and sample entries (`encv`, `enca` etc.) replaced by their
+    ///   original format without the `sinf` box.
+    /// * Every `moof` box with its `pssh` boxes, the `tfdt` and `trun` boxes in their original versions,
+    ///   without the `senc`, `saiz` and `saio` boxes. PIFF sample encryption `uuid` boxes are kept.
+    /// * A single `mdat` box right after every `moof` box, holding the decrypted samples in the same order
+    ///   and of the same sizes, with the data offsets of the `trun` boxes pointing into it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{DecryptOptions, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let mut data = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// data.extend(std::fs::read("examples/sample/segment_0.m4s").unwrap());
+    ///
+    /// let options = DecryptOptions::for_reencryption();
+    /// let decrypted_data = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+    ///
+    /// let mut tree = Vec::new();
+    /// mp4decrypt::debug_dump(&decrypted_data, &mut tree).unwrap();
+    /// let tree = String::from_utf8(tree).unwrap();
+    /// assert!(tree.contains("[avc1]") && !tree.contains("[sinf]") && !tree.contains("[senc]"));
+    /// assert_eq!(mp4decrypt::extract_pssh(&decrypted_data).unwrap().len(), 2);
+    ///
+    /// // a track without a key
+    /// let error = mp4decrypt::mp4decrypt_with_options(&data, &KeyMap::new(), None, &options).unwrap_err();
+    /// assert!(matches!(error.err_type, mp4decrypt::ErrorType::MissingKeyForTrack(..)));
+    /// ```
+    pub fn for_reencryption() -> Self {
+        Self::new()
+            .validate_structure(true)
+            .on_missing_key(MissingKeyPolicy::Fail)
+    }
+
+    /// Forward key arguments to Bento4 in the same syntax as its `mp4decrypt` command line tool,
+    /// i.e. `--key <id>:<k>` (as one or two arguments) or just `<id>:<k>`.
+    /// These keys are passed without any normalization, after the keys given to the decrypt function.
+    /// `--show-progress` is accepted and ignored (see [on_progress](Self::on_progress)), any other argument is rejected at decryption.
+    ///
+    /// This is an advanced and unstable escape hatch for edge cases which aren't covered by
+    /// [KeyMap](crate::KeyMap) yet. It may change or be removed in any release.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{DecryptOptions, KeyMap};
+    ///
+    /// let options = DecryptOptions::new().raw_bento4_args(vec![
+    ///     "--key".to_owned(),
+    ///     "1:100b6c20940f779a4589152b57d2dacb".to_owned(),
+    /// ]);
+    ///
+    /// // invalid arguments are referred to by index
+    /// let data = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let options = DecryptOptions::new().raw_bento4_args(vec![
+    ///     "--key".to_owned(),
+    ///     "1;100b6c20940f779a4589152b57d2dacb".to_owned(),
+    /// ]);
+    /// let error = mp4decrypt::mp4decrypt_with_options(&data, &KeyMap::new(), None, &options).unwrap_err();
+    /// assert_eq!(error.msg, "bento4 key argument at index 1 is not in <id>:<k> format.");
+    /// ```
+    pub fn raw_bento4_args(mut self, args: Vec<String>) -> Self {
+        self.raw_bento4_args = args;
+        self
+    }
+
+    /// Maximum size of decrypted data buffered by [decrypt_stream](crate::decrypt_stream) before it is
+    /// written to the writer. Defaults to 8 MiB, use 0 to write every fragment as soon as it is decrypted.
+    pub fn buffer_limit(mut self, limit: usize) -> Self {
+        self.buffer_limit = limit;
+        self
+    }
+
+    /// Size of the chunks handed to the sink by [mp4decrypt_into_with_options](crate::mp4decrypt_into_with_options)
+    /// and written to the writer by [decrypt_stream](crate::decrypt_stream), e.g. to match the block size of a socket.
+    /// Smaller chunks are coalesced and larger ones are split, so that every chunk except the last one has
+    /// exactly this size. Defaults to 0, which passes the decrypted data as is (the whole output at once).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let options = mp4decrypt::DecryptOptions::new().chunk_size(64 * 1024);
+    /// ```
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = size;
+        self
+    }
+
+    /// Make [decrypt_stream](crate::decrypt_stream) decrypt every CMAF chunk as soon as it is read, then write it
+    /// and flush the writer, instead of waiting for the next `moof` box and buffering up to
+    /// [buffer_limit](Self::buffer_limit). This is meant for republishing low-latency DASH and HLS streams,
+    /// where the next chunk may only be available much later. Disabled by default.
+    ///
+    /// A chunk is a `moof` box together with the boxes before it which follow the previous chunk (e.g. `styp`,
+    /// `prft` or `emsg`), and ends with the first `mdat` box after the `moof` box. A `moof` box followed by
+    /// another `moof` box ends a chunk as well. Data held back for [chunk_size](Self::chunk_size) is written at every
+    /// chunk boundary, even if it is smaller than a chunk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{DecryptOptions, KeyMap};
+    /// use std::io::Write;
+    ///
+    /// /// Writer counting flushes.
+    /// #[derive(Default)]
+    /// struct Counting(Vec<u8>, usize);
+    ///
+    /// impl Write for Counting {
+    ///     fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+    ///         self.0.write(data)
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         self.1 += 1;
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+    /// let data = [&init, &segment, &segment[..]].concat();
+    ///
+    /// let options = DecryptOptions::new().flush_cmaf_chunks(true);
+    /// let mut writer = Counting::default();
+    /// mp4decrypt::decrypt_stream(data.as_slice(), &mut writer, &keys, &options).unwrap();
+    ///
+    /// // one flush per chunk, and one at the end
+    /// assert_eq!(writer.1, 3);
+    /// let expected = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+    /// assert_eq!(writer.0, expected);
+    /// ```
+    pub fn flush_cmaf_chunks(mut self, flush: bool) -> Self {
+        self.flush_cmaf_chunks = flush;
+        self
+    }
+
+    /// Fill [DecryptReport::tracks](crate::DecryptReport::tracks) with the number of samples and bytes
+    /// decrypted for every protected track which has a key. This is disabled by default since it requires
+    /// an additional pass over the sample tables.
+    pub fn collect_stats(mut self, collect: bool) -> Self {
+        self.collect_stats = collect;
+        self
+    }
+
+    /// Compute a CRC-32 (IEEE) over the decrypted samples of every decrypted track into
+    /// [TrackStats::crc32](crate::TrackStats::crc32), which implies [collect_stats](Self::collect_stats).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{DecryptOptions, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+    ///
+    /// let options = DecryptOptions::new().compute_crc32(true);
+    /// let (_, report) =
+    ///     mp4decrypt::mp4decrypt_with_report(&segment, &keys, Some(&init), &options).unwrap();
+    /// assert!(report.tracks[&1].crc32.is_some());
+    /// ```
+    pub fn compute_crc32(mut self, compute: bool) -> Self {
+        self.compute_crc32 = compute;
+        self
+    }
+
+    /// Replace the constant IVs declared in the `tenc` boxes of tracks with these IVs, by track ID,
+    /// for content where the init segment doesn't carry the IVs which were used for encrypting its tracks.
+    /// Every track must be protected with a constant IV (no per sample IVs) and every IV must have the same size
+    /// as the constant IV of its track, otherwise decryption fails with
+    /// [ErrorType::IvLengthMismatch](crate::ErrorType::IvLengthMismatch).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    ///
+    /// let options = mp4decrypt::DecryptOptions::new().constant_ivs(HashMap::from([
+    ///     (1, vec![0x0a; 16]),
+    ///     (2, vec![0x0b; 16]),
+    /// ]));
+    /// ```
+    pub fn constant_ivs(mut self, ivs: HashMap<u32, Vec<u8>>) -> Self {
+        self.constant_ivs = ivs;
+        self
+    }
+
+    /// Maximum size of the decrypted data stream, after which decryption is aborted with
+    /// [ErrorType::OutputTooLarge](crate::ErrorType::OutputTooLarge), e.g. for untrusted input on a server.
+    /// Output is usually as large as the input, but a manipulated box structure can inflate it.
+    /// Defaults to unlimited.
+    ///
+    /// The size is checked as Bento4 passes the decrypted data back, before anything beyond the limit
+    /// reaches the sink. It applies to the complete output of [decrypt_stream](crate::decrypt_stream),
+    /// not to every fragment.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let options = mp4decrypt::DecryptOptions::new().max_output_bytes(256 * 1024 * 1024);
+    /// ```
+    pub fn max_output_bytes(mut self, limit: usize) -> Self {
+        self.max_output_bytes = Some(limit);
+        self
+    }
+
+    /// Maximum number of threads used by the parallel functions of the `rayon` feature, e.g.
+    /// [decrypt_segments_parallel](crate::decrypt_segments_parallel). When set, every call builds its own
+    /// thread pool with this many threads instead of using the global rayon pool, so that a batch can't take
+    /// over every core of a shared machine. Defaults to 0, which uses the global pool.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let options = mp4decrypt::DecryptOptions::new().max_threads(4);
+    /// ```
+    pub fn max_threads(mut self, threads: usize) -> Self {
+        self.max_threads = threads;
+        self
+    }
+
+    /// Rewrite `tfdt` and `trun` boxes of mixed versions within the output to a single version, listing those
+    /// which can't be in [DecryptReport::mixed_box_versions](crate::DecryptReport::mixed_box_versions).
+    /// Disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let options = mp4decrypt::DecryptOptions::new().normalize_box_versions(true);
+    /// ```
+    pub fn normalize_box_versions(mut self, normalize: bool) -> Self {
+        self.normalize_box_versions = normalize;
+        self
+    }
+
+    /// Set what to do when a protected track has no matching key, by its track ID or default KID.
+    /// Defaults to [MissingKeyPolicy::Ignore](MissingKeyPolicy::Ignore).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{DecryptOptions, MissingKeyPolicy};
+    ///
+    /// let options = DecryptOptions::new().on_missing_key(MissingKeyPolicy::Fail);
+    /// ```
+    pub fn on_missing_key(mut self, policy: MissingKeyPolicy) -> Self {
+        self.on_missing_key = policy;
+        self
+    }
+
+    /// Set what to do when keys are given but neither the input nor its fragments info has any protection box
+    /// (other than `pssh` boxes which are left in decrypted data), which usually means that it was already decrypted.
+    /// Defaults to [ClearInputPolicy::Ignore](ClearInputPolicy::Ignore).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{ClearInputPolicy, DecryptOptions, ErrorType, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let mut data = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// data.extend(std::fs::read("examples/sample/segment_0.m4s").unwrap());
+    ///
+    /// let options = DecryptOptions::new().on_clear_input(ClearInputPolicy::Fail);
+    /// let decrypted_data = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+    ///
+    /// // decrypted twice
+    /// let error = mp4decrypt::mp4decrypt_with_options(&decrypted_data, &keys, None, &options).unwrap_err();
+    /// assert!(matches!(error.err_type, ErrorType::AlreadyDecrypted));
+    ///
+    /// let options = DecryptOptions::new().on_clear_input(ClearInputPolicy::WarnReport);
+    /// let (_, report) = mp4decrypt::mp4decrypt_with_report(&decrypted_data, &keys, None, &options).unwrap();
+    /// assert!(report.clear_input);
+    /// ```
+    pub fn on_clear_input(mut self, policy: ClearInputPolicy) -> Self {
+        self.on_clear_input = policy;
+        self
+    }
+
+    /// Call `callback` with the number of processed samples and the total number of samples while Bento4
+    /// decrypts a non-fragmented file, which is what `--show-progress` of Bento4 mp4decrypt prints.
+    /// It is called every `interval` samples and once more for the last sample, so a smaller interval gives
+    /// smoother progress for a large file in exchange for more calls. An interval of 0 turns it off,
+    /// which is the default.
+    ///
+    /// Bento4 doesn't report progress of fragmented files, decrypt them one fragment at a time using
+    /// [SegmentDecryptor](crate::SegmentDecryptor) or [decrypt_stream](crate::decrypt_stream) instead.
+    /// The callback isn't serialized with the `serde` feature.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use mp4decrypt::{DecryptOptions, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("1", "100b6c20940f779a4589152b57d2dacb").unwrap();
+    ///
+    /// let data = std::fs::read("video.mp4").unwrap();
+    /// let options = DecryptOptions::new().on_progress(1000, |step, total| {
+    ///     println!("decrypted {} of {} samples", step, total);
+    /// });
+    /// let decrypted_data = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+    /// ```
+    pub fn on_progress(
+        mut self,
+        interval: u32,
+        callback: impl Fn(u32, u32) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = (interval != 0).then(|| Progress {
+            interval,
+            callback: Arc::new(callback),
+        });
+        self
+    }
+
+    /// Keep the top level event message (`emsg`) boxes, e.g. ad markers of DASH segments, in the decrypted output.
+    /// Enabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{DecryptOptions, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+    ///
+    /// // version 0 emsg box with empty strings, timescale, presentation time delta, duration and id
+    /// let mut emsg = vec![0, 0, 0, 34];
+    /// emsg.extend(b"emsg");
+    /// emsg.extend([0; 26]);
+    /// let data = [&emsg, &segment[..]].concat();
+    ///
+    /// let options = DecryptOptions::new();
+    /// let decrypted_data = mp4decrypt::mp4decrypt_with_options(&data, &keys, Some(&init), &options).unwrap();
+    /// assert!(decrypted_data.starts_with(&emsg));
+    ///
+    /// let options = DecryptOptions::new().keep_emsg(false);
+    /// let decrypted_data = mp4decrypt::mp4decrypt_with_options(&data, &keys, Some(&init), &options).unwrap();
+    /// assert_eq!(
+    ///     decrypted_data,
+    ///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap()
+    /// );
+    /// ```
+    pub fn keep_emsg(mut self, keep: bool) -> Self {
+        self.keep_emsg = keep;
+        self
+    }
+
+    /// Remove the `pssh` boxes of `moov` and `moof` boxes from the decrypted output, including those of tracks
+    /// left encrypted. Disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{DecryptOptions, KeyMap};
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let options = DecryptOptions::new().strip_pssh(true);
+    /// let decrypted_init = mp4decrypt::mp4decrypt_with_options(&init, &keys, None, &options).unwrap();
+    ///
+    /// assert_eq!(mp4decrypt::extract_pssh(&init).unwrap().len(), 2);
+    /// assert!(mp4decrypt::extract_pssh(&decrypted_init).unwrap().is_empty());
+    /// ```
+    pub fn strip_pssh(mut self, strip: bool) -> Self {
+        self.strip_pssh = strip;
+        self
+    }
+
+    /// Walk the decrypted output and return [ErrorType::MalformedOutput](crate::ErrorType::MalformedOutput)
+    /// if any box size is inconsistent or sample data doesn't lie within its `mdat` box.
+    /// This is disabled by default since it requires an additional pass over the output.
+    pub fn validate_structure(mut self, validate: bool) -> Self {
+        self.validate_structure = validate;
+        self
+    }
+}
+
+impl DecryptOptions {
+    /// Validate raw Bento4 arguments and extract the kid key pairs from them.
+    pub(crate) fn raw_key_pairs(&self) -> Result<KeyPairs, Error> {
+        let mut key_pairs = KeyPairs::default();
+        let mut args = self.raw_bento4_args.iter().enumerate();
+
+        // Arguments are referred to by index in errors, as they may hold keys.
+        while let Some((mut index, arg)) = args.next() {
+            let value = match arg.as_str() {
+                "--show-progress" => continue,
+                "--key" => {
+                    let (value_index, value) = args.next().ok_or_else(|| {
+                        Error::new_key("missing value after --key bento4 argument.")
+                    })?;
+                    index = value_index;
+                    value
+                }
+                x if x.starts_with("--key=") => &x[6..],
+                x if !x.starts_with('-') => x,
+                _ => {
+                    return Err(Error::new_format(format!(
+                        "unsupported bento4 argument at index {}.",
+                        index
+                    )));
+                }
+            };
+
+            if value.is_empty() || !value.bytes().all(|x| x.is_ascii_graphic()) {
+                return Err(Error::new_key(format!(
+                    "bento4 key argument at index {} contains invalid characters.",
+                    index
+                )));
+            }
+
+            match value.split_once(':') {
+                Some((id, key)) if !id.is_empty() && !key.is_empty() && !key.contains(':') => {
+                    key_pairs.0.push((id.to_owned(), key.to_owned()));
+                }
+                _ => {
+                    return Err(Error::new_key(format!(
+                        "bento4 key argument at index {} is not in <id>:<k> format.",
+                        index
+                    )));
+                }
+            }
+        }
+
+        Ok(key_pairs)
+    }
+
+    /// Run `f` in a thread pool limited to [max_threads](Self::max_threads), or in the global pool if unset.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn install<T: Send, F: FnOnce() -> T + Send>(&self, f: F) -> Result<T, Error> {
+        if self.max_threads == 0 {
+            return Ok(f());
+        }
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.max_threads)
+            .build()
+            .map_err(|e| Error {
+                msg: format!(
+                    "couldn't build a thread pool of {} threads: {}.",
+                    self.max_threads, e
+                ),
+                err_type: crate::ErrorType::Io(std::io::ErrorKind::Other),
+                invalid_key: false,
+            })?;
+        Ok(pool.install(f))
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{DecryptOptions, Error, KeyMap, SegmentDecryptor};
+use rayon::prelude::*;
+
+/// Decrypt the media segments of a single representation in parallel using their shared init segment.
+///
+/// This is the parallel counterpart of [decrypt_dash_representation](crate::decrypt_dash_representation)
+/// with `per_segment` set. The init segment is parsed once and the same keys are used for every segment.
+/// Segments are decrypted on the global rayon pool, or on a pool of
+/// [max_threads](crate::DecryptOptions::max_threads) threads built for this call.
+///
+/// Returns the decrypted init segment and every decrypted media segment, in the order of the input segments.
+/// If more than one segment can't be decrypted, the error of any one of them is returned.
+///
+/// # Arguments
+///
+/// * `init` - Init segment (`ftyp` + `moov`) of the representation.
+/// * `segments` - Media segments (`moof` + `mdat`) of the representation.
+/// * `keys` - Keys for decrypting the segments.
+/// * `options` - Decryption options, only [max_threads](crate::DecryptOptions::max_threads) is used.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{DashOutput, DecryptOptions, KeyMap};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+/// let segments = [segment.as_slice(); 4];
+///
+/// let options = DecryptOptions::new().max_threads(2);
+/// let (decrypted_init, decrypted_segments) =
+///     mp4decrypt::decrypt_segments_parallel(&init, &segments, &keys, &options).unwrap();
+///
+/// let output = mp4decrypt::decrypt_dash_representation(&init, &segments, &keys, true).unwrap();
+/// assert_eq!(
+///     output,
+///     DashOutput::PerSegment {
+///         init: decrypted_init,
+///         segments: decrypted_segments,
+///     }
+/// );
+/// ```
+pub fn decrypt_segments_parallel(
+    init: &[u8],
+    segments: &[&[u8]],
+    keys: &KeyMap,
+    options: &DecryptOptions,
+) -> Result<(Vec<u8>, Vec<Vec<u8>>), Error> {
+    let decryptor = SegmentDecryptor::new(init, keys)?;
+    let decrypted_init = decryptor.decrypt_init()?;
+    let decrypted_segments = options.install(|| {
+        segments
+            .par_iter()
+            .map(|x| decryptor.decrypt_segment(x))
+            .collect::<Result<Vec<_>, _>>()
+    })??;
+
+    Ok((decrypted_init, decrypted_segments))
+}
@@ -0,0 +1,265 @@
+use crate::{
+    Error, KeyMap,
+    boxes::{self, Mp4Box},
+    decrypt, metadata,
+    reader::Reader,
+};
+use std::collections::HashSet;
+
+/// Extended type of the PIFF sample encryption `uuid` box, used instead of `senc` box.
+pub(crate) const PIFF_SAMPLE_ENCRYPTION: [u8; 16] =
+    0xa2394f525a9b4f14a2446c427c648df4_u128.to_be_bytes();
+/// Extended type of the PIFF track encryption `uuid` box, used instead of `tenc` box.
+pub(crate) const PIFF_TRACK_ENCRYPTION: [u8; 16] =
+    0x8974dbce7be74c5184f97148f9882554_u128.to_be_bytes();
+/// Extended type of the PIFF protection system specific header `uuid` box, used instead of `pssh` box.
+pub(crate) const PIFF_PSSH: [u8; 16] = 0xd08a4f1810f34a82b6c832d8aba183d3_u128.to_be_bytes();
+
+/// Boxes which are walked while looking for protection boxes.
+const CONTAINERS: [&[u8; 4]; 8] = [
+    b"mdia", b"minf", b"moof", b"moov", b"stbl", b"traf", b"trak", b"mvex",
+];
+
+/// Check whether an init segment, media segment or a complete file contains any protection box
+/// (`sinf`, `senc`, `tenc` or `pssh`), so that decryption can be skipped for clear content.
+/// PIFF (legacy Smooth Streaming) `uuid` boxes used instead of `senc`, `tenc` and `pssh` boxes are also detected.
+///
+/// Walking stops at the first malformed box, so a truncated trailing box is ignored.
+/// An error is returned if data doesn't start with a valid mp4 box.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// assert!(mp4decrypt::is_encrypted(&init).unwrap());
+///
+/// let clear = [0, 0, 0, 16, b'f', b't', b'y', b'p', b'i', b's', b'o', b'm', 0, 0, 0, 1];
+/// assert!(!mp4decrypt::is_encrypted(&clear).unwrap());
+///
+/// assert!(mp4decrypt::is_encrypted(b"#EXTM3U\n").is_err());
+///
+/// // moof/traf/uuid box with the PIFF sample encryption extended type
+/// let mut piff = [0, 0, 0, 48, b'm', b'o', b'o', b'f', 0, 0, 0, 40, b't', b'r', b'a', b'f'].to_vec();
+/// piff.extend([0, 0, 0, 32, b'u', b'u', b'i', b'd']);
+/// piff.extend(0xa2394f525a9b4f14a2446c427c648df4_u128.to_be_bytes());
+/// piff.extend([0; 8]); // version, flags, sample_count
+/// assert!(mp4decrypt::is_encrypted(&piff).unwrap());
+/// ```
+pub fn is_encrypted(data: &[u8]) -> Result<bool, Error> {
+    find_protection(data, true)
+}
+
+/// Check whether data stream has no protection box left, ignoring boxes which are left as they are
+/// in the decrypted data stream, i.e. whether it is clear or already decrypted.
+pub(crate) fn is_clear(data: &[u8]) -> Result<bool, Error> {
+    find_protection(data, false).map(|x| !x)
+}
+
+/// Check whether data stream has any protection box, optionally ignoring `pssh` and PIFF sample encryption boxes
+/// which are left as they are in the decrypted data stream.
+fn find_protection(data: &[u8], include_leftovers: bool) -> Result<bool, Error> {
+    let mut top_level = boxes::boxes(data).peekable();
+
+    let is_mp4 = top_level.peek().is_some_and(|x| {
+        x.as_ref().is_ok_and(|x| {
+            x.box_type
+                .iter()
+                .all(|x| x.is_ascii_graphic() || *x == b' ')
+        })
+    });
+
+    if !is_mp4 {
+        return Err(Error::new_format("data stream is not a valid mp4 stream."));
+    }
+
+    Ok(top_level
+        .map_while(Result::ok)
+        .any(|x| has_protection(&x, include_leftovers)))
+}
+
+/// Collect every KID referenced by a fragmented file or a media segment, e.g. for finding the keys needed by
+/// content with key rotation.
+///
+/// This includes the default KIDs of protected tracks from the `tenc` boxes of every `moov` box, and the KIDs
+/// of protected sample groups from the `seig` sample group description (`sgpd`) boxes of every `moof` and `moov` box.
+/// Note that `senc` boxes only hold IVs and subsamples, so they don't reference any KID.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+/// let default_kid = mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3da").unwrap();
+///
+/// let data = [init, segment].concat();
+/// assert_eq!(mp4decrypt::collect_all_kids(&data).unwrap(), [default_kid].into());
+/// ```
+pub fn collect_all_kids(data: &[u8]) -> Result<HashSet<[u8; 16]>, Error> {
+    let mut kids = HashSet::new();
+
+    for mp4_box in boxes::boxes(data) {
+        let mp4_box = mp4_box?;
+
+        match &mp4_box.box_type {
+            b"moov" => {
+                for trak in mp4_box.children() {
+                    let trak = trak?;
+
+                    if &trak.box_type != b"trak" {
+                        continue;
+                    }
+
+                    let track = metadata::read_trak(&trak)?;
+
+                    if let (true, Some(kid)) = (track.is_protected(), track.default_kid) {
+                        kids.insert(kid);
+                    }
+                }
+
+                collect_group_kids(&mp4_box, &mut kids)?;
+            }
+            b"moof" => collect_group_kids(&mp4_box, &mut kids)?,
+            _ => (),
+        }
+    }
+
+    Ok(kids)
+}
+
+/// Collect the KIDs of protected `seig` sample group entries from every `sgpd` box within a container.
+fn collect_group_kids(mp4_box: &Mp4Box, kids: &mut HashSet<[u8; 16]>) -> Result<(), Error> {
+    for child in mp4_box.children() {
+        let child = child?;
+
+        match &child.box_type {
+            b"sgpd" => read_seig_kids(&child, kids)?,
+            x if CONTAINERS.contains(&x) => collect_group_kids(&child, kids)?,
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn read_seig_kids(sgpd: &Mp4Box, kids: &mut HashSet<[u8; 16]>) -> Result<(), Error> {
+    for entry in read_seig_entries(sgpd)?.unwrap_or_default() {
+        if entry.is_protected {
+            kids.insert(entry.kid);
+        }
+    }
+
+    Ok(())
+}
+
+/// An entry of a `seig` sample group description, which overrides the `tenc` box for the samples of its group.
+pub(crate) struct SeigEntry {
+    pub(crate) is_protected: bool,
+    pub(crate) per_sample_iv_size: u8,
+    pub(crate) kid: [u8; 16],
+}
+
+/// Read the entries of a `sgpd` box, `None` if its grouping type isn't `seig`.
+pub(crate) fn read_seig_entries(sgpd: &Mp4Box) -> Result<Option<Vec<SeigEntry>>, Error> {
+    let mut reader = Reader::new(sgpd.payload());
+    let (version, _) = reader.read_version_flags()?;
+
+    if &reader.read_array()? != b"seig" {
+        return Ok(None);
+    }
+
+    let default_length = if version == 1 { reader.read_u32()? } else { 0 };
+    if version >= 2 {
+        reader.skip(4)?; // default_sample_description_index
+    }
+    let entry_count = reader.read_u32()?;
+    let mut entries = Vec::with_capacity((entry_count as usize).min(reader.remaining() / 20));
+
+    for _ in 0..entry_count {
+        let length = if version == 1 && default_length == 0 {
+            reader.read_u32()?
+        } else {
+            default_length
+        };
+
+        reader.skip(2)?; // reserved, crypt_byte_block, skip_byte_block
+        let is_protected = reader.read_u8()? == 1;
+        let per_sample_iv_size = reader.read_u8()?;
+        let kid = reader.read_array()?;
+        let mut read = 20;
+
+        if is_protected && per_sample_iv_size == 0 {
+            let constant_iv_size = reader.read_u8()?;
+            reader.skip(constant_iv_size as usize)?;
+            read += 1 + constant_iv_size as u32;
+        }
+
+        if length > read {
+            reader.skip((length - read) as usize)?;
+        }
+
+        entries.push(SeigEntry {
+            is_protected,
+            per_sample_iv_size,
+            kid,
+        });
+    }
+
+    Ok(Some(entries))
+}
+
+/// Pass clear (unencrypted) data stream through the complete decryption path without any keys,
+/// returning an error if it is actually encrypted. Unlike [is_encrypted](is_encrypted), `pssh` and PIFF sample
+/// encryption boxes are ignored since they are also present in decrypted data streams.
+///
+/// This is meant for integration testing of downstream pipelines using clear fixtures, not for production use.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let mut data = std::fs::read("examples/sample/init.mp4").unwrap();
+/// data.extend(std::fs::read("examples/sample/segment_0.m4s").unwrap());
+/// assert!(mp4decrypt::mp4decrypt_passthrough(&data).is_err());
+///
+/// let options = mp4decrypt::DecryptOptions::new();
+/// let clear = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+/// assert_eq!(mp4decrypt::mp4decrypt_passthrough(&clear).unwrap(), clear);
+/// ```
+pub fn mp4decrypt_passthrough(data: &[u8]) -> Result<Vec<u8>, Error> {
+    if find_protection(data, false)? {
+        return Err(Error::new_format(
+            "data stream is encrypted, it can't be passed through without keys.",
+        ));
+    }
+
+    decrypt(data, &KeyMap::new(), None)
+}
+
+fn has_protection(mp4_box: &Mp4Box, include_leftovers: bool) -> bool {
+    match &mp4_box.box_type {
+        b"sinf" | b"senc" | b"tenc" => true,
+        b"pssh" => include_leftovers,
+        b"uuid" => match mp4_box.user_type() {
+            Some(PIFF_TRACK_ENCRYPTION) => true,
+            Some(PIFF_SAMPLE_ENCRYPTION | PIFF_PSSH) => include_leftovers,
+            _ => false,
+        },
+        // Protected sample entries always contain a sinf box.
+        b"stsd" => mp4_box.children_after(8).map_while(Result::ok).any(|x| {
+            matches!(
+                &x.box_type,
+                b"encv" | b"enca" | b"enct" | b"encs" | b"encm" | b"encf"
+            )
+        }),
+        x if CONTAINERS.contains(&x) => mp4_box
+            .children()
+            .map_while(Result::ok)
+            .any(|x| has_protection(&x, include_leftovers)),
+        _ => false,
+    }
+}
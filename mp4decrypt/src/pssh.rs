@@ -0,0 +1,415 @@
+use crate::{
+    Error, ErrorType, KeyId, KeyMap, Mp4Metadata, boxes, keys,
+    reader::Reader,
+    rewrite::{self, Replacement},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+/// Well known DRM system IDs, their names and whether Bento4 can decrypt their content given the keys.
+/// Adobe Primetime uses its own `adkm` scheme and ChinaDRM the SM4 cipher, which Bento4 doesn't implement.
+const SYSTEMS: [([u8; 16], &str, bool); 10] = [
+    (uuid(0xedef8ba979d64acea3c827dcd51d21ed), "Widevine", true),
+    (uuid(0x9a04f07998404286ab92e65be0885f95), "PlayReady", true),
+    (uuid(0x94ce86fb07ff4f43adb893d2fa968ca2), "FairPlay", true),
+    (uuid(0x5e629af538da4063897797ffbd9902d4), "Marlin", true),
+    (uuid(0x1077efecc0b24d02ace33c1e52e2fb4b), "ClearKey", true),
+    (
+        uuid(0xe2719d58a985b3c9781ab030af78d30e),
+        "ClearKey (DASH-IF)",
+        true,
+    ),
+    (
+        uuid(0xf239e769efa348509c16a903c6932efb),
+        "Adobe Primetime",
+        false,
+    ),
+    (uuid(0xadb41c242dbf4a6d958b4457c0d27b95), "Nagra", true),
+    (uuid(0x9a27dd82fde247258cbc4234aa06ec09), "Verimatrix", true),
+    (uuid(0x3d5e6d359b9a41e8b843dd3c6e72c42c), "ChinaDRM", false),
+];
+
+const fn uuid(value: u128) -> [u8; 16] {
+    value.to_be_bytes()
+}
+
+/// A parsed `pssh` box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PsshBox {
+    pub version: u8,
+    pub system_id: [u8; 16],
+    /// KIDs listed in a version 1 box.
+    pub kids: Vec<[u8; 16]>,
+    /// DRM system specific data.
+    pub data: Vec<u8>,
+}
+
+impl PsshBox {
+    /// Name of the DRM system, if it is a well known one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let names = mp4decrypt::extract_pssh(&init)
+    ///     .unwrap()
+    ///     .iter()
+    ///     .filter_map(|x| x.system_name())
+    ///     .collect::<Vec<_>>();
+    /// assert_eq!(names, ["PlayReady", "Widevine"]);
+    /// ```
+    pub fn system_name(&self) -> Option<&'static str> {
+        system_name(&self.system_id)
+    }
+
+    /// KIDs listed in a version 1 box, along with the KIDs found in the system specific data of
+    /// Widevine (`key_id` fields of `WidevinePsshData`) and PlayReady (`KID` elements of the `WRMHEADER`) boxes.
+    /// System specific data which can't be parsed is skipped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+    /// let kid = mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3da").unwrap();
+    ///
+    /// for pssh in mp4decrypt::extract_pssh(&init).unwrap() {
+    ///     assert_eq!(pssh.key_ids(), [kid]);
+    /// }
+    /// ```
+    pub fn key_ids(&self) -> Vec<[u8; 16]> {
+        let mut kids = self.kids.clone();
+        let found = match self.system_name() {
+            Some("Widevine") => widevine_kids(&self.data),
+            Some("PlayReady") => playready_kids(&self.data),
+            _ => Vec::new(),
+        };
+
+        for kid in found {
+            if !kids.contains(&kid) {
+                kids.push(kid);
+            }
+        }
+
+        kids
+    }
+}
+
+/// KIDs of a pssh box of an init segment, as checked by [keys_cover_pssh](keys_cover_pssh).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PsshCoverage {
+    /// Every KID of the pssh boxes, see [PsshBox::key_ids](PsshBox::key_ids).
+    pub kids: Vec<[u8; 16]>,
+    /// KIDs without a key.
+    pub missing_kids: Vec<[u8; 16]>,
+}
+
+impl PsshCoverage {
+    /// Whether there is a key for every KID.
+    pub fn is_covered(&self) -> bool {
+        self.missing_kids.is_empty()
+    }
+}
+
+/// Check that there is a key for every KID declared by the `pssh` boxes of an init segment,
+/// before requesting or decrypting anything.
+///
+/// A KID is covered by a key for the same KID, or by a key for the track whose default KID it is.
+/// The KIDs are read using [PsshBox::key_ids](PsshBox::key_ids), so that KIDs are only found in the
+/// system specific data of Widevine and PlayReady boxes.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let kid = mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3da").unwrap();
+/// let key = mp4decrypt::parse_hex16("100b6c20940f779a4589152b57d2dacb").unwrap();
+///
+/// let coverage = mp4decrypt::keys_cover_pssh(&init, &KeyMap::new()).unwrap();
+/// assert!(!coverage.is_covered());
+/// assert_eq!(coverage.missing_kids, [kid]);
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert_kid(kid, key);
+/// assert!(mp4decrypt::keys_cover_pssh(&init, &keys).unwrap().is_covered());
+///
+/// // the default kid of track 1
+/// let mut keys = KeyMap::new();
+/// keys.insert_track(1, key);
+/// assert!(mp4decrypt::keys_cover_pssh(&init, &keys).unwrap().is_covered());
+/// ```
+pub fn keys_cover_pssh(init: &[u8], keys: &KeyMap) -> Result<PsshCoverage, Error> {
+    let metadata = Mp4Metadata::parse(init)?;
+    let mut coverage = PsshCoverage::default();
+
+    for kid in metadata.pssh.iter().flat_map(|x| x.key_ids()) {
+        if coverage.kids.contains(&kid) {
+            continue;
+        }
+
+        let covered = keys.get(&KeyId::Kid(kid)).is_some()
+            || metadata.tracks.iter().any(|x| {
+                x.default_kid == Some(kid) && keys.get(&KeyId::Track(x.track_id)).is_some()
+            });
+
+        if !covered {
+            coverage.missing_kids.push(kid);
+        }
+
+        coverage.kids.push(kid);
+    }
+
+    Ok(coverage)
+}
+
+/// Read the repeated `key_id` field (2) of a `WidevinePsshData` protobuf message.
+fn widevine_kids(data: &[u8]) -> Vec<[u8; 16]> {
+    widevine_fields(data, 2)
+        .into_iter()
+        .filter_map(|x| x.try_into().ok())
+        .collect()
+}
+
+/// Read every value of a length delimited field of a `WidevinePsshData` protobuf message,
+/// stopping at the first malformed field.
+fn widevine_fields(data: &[u8], field_number: u64) -> Vec<&[u8]> {
+    let mut reader = Reader::new(data);
+    let mut values = Vec::new();
+
+    let read_varint = |reader: &mut Reader| {
+        let mut value = 0_u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = reader.read_u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(Error::new_format("varint is too long."))
+    };
+
+    while reader.remaining() > 0 {
+        let Ok(tag) = read_varint(&mut reader) else {
+            break;
+        };
+
+        let field = match tag & 0x07 {
+            0 => read_varint(&mut reader).map(|_| None),
+            1 => reader.skip(8).map(|_| None),
+            2 => read_varint(&mut reader).and_then(|x| reader.read_bytes(x as usize).map(Some)),
+            5 => reader.skip(4).map(|_| None),
+            _ => break,
+        };
+
+        match field {
+            Ok(Some(value)) if tag >> 3 == field_number => values.push(value),
+            Ok(_) => (),
+            Err(_) => break,
+        }
+    }
+
+    values
+}
+
+/// Read the `KID` elements of the `WRMHEADER` records of a PlayReady object, which are either
+/// `<KID>value</KID>` (version 4.0) or `<KID VALUE="value" ... />` (version 4.1 and later).
+fn playready_kids(data: &[u8]) -> Vec<[u8; 16]> {
+    let le_u16 = |pos: usize| {
+        data.get(pos..pos + 2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]))
+    };
+    let mut kids = Vec::new();
+    let mut pos = 6; // length, record_count
+
+    while let (Some(record_type), Some(record_size)) = (le_u16(pos), le_u16(pos + 2)) {
+        let Some(record) = data.get(pos + 4..pos + 4 + record_size as usize) else {
+            break;
+        };
+        pos += 4 + record_size as usize;
+
+        // rights management header
+        if record_type != 1 {
+            continue;
+        }
+
+        let header = String::from_utf16_lossy(
+            &record
+                .chunks_exact(2)
+                .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                .collect::<Vec<_>>(),
+        );
+
+        for (start, _) in header.match_indices("<KID") {
+            let element = &header[start + 4..];
+            let value = if let Some(element) = element.strip_prefix('>') {
+                element.split("</KID>").next()
+            } else if element.starts_with([' ', '/']) {
+                let tag = element.split('>').next().unwrap_or_default();
+                tag.split_once("VALUE=\"")
+                    .and_then(|(_, x)| x.split('"').next())
+            } else {
+                None // <KIDS>
+            };
+
+            // KIDs are GUIDs, whose first three fields are little endian.
+            if let Some(value) = value
+                && let Ok(guid) = STANDARD.decode(value.trim())
+                && let Ok::<[u8; 16], _>(mut kid) = guid.try_into()
+            {
+                kid[..4].reverse();
+                kid[4..6].reverse();
+                kid[6..8].reverse();
+
+                if !kids.contains(&kid) {
+                    kids.push(kid);
+                }
+            }
+        }
+    }
+
+    kids
+}
+
+/// Read the content ID of the Widevine `pssh` box of an init segment, i.e. the `content_id` field (4) of its
+/// `WidevinePsshData` protobuf message, e.g. for matching license requests with the content.
+/// A KID can't be derived from a key, but the Widevine `pssh` box lists the KIDs of its content ID,
+/// see [PsshBox::key_ids](PsshBox::key_ids).
+///
+/// Returns `None` if there is no Widevine `pssh` box or it has no content ID. The first Widevine `pssh` box
+/// holding a content ID is used.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let content_id = mp4decrypt::widevine_content_id(&init).unwrap();
+/// assert_eq!(content_id.as_deref(), Some(&b"fkj3ljaSdfalkr3j"[..]));
+///
+/// // no pssh boxes
+/// let mut keys = mp4decrypt::KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+/// let options = mp4decrypt::DecryptOptions::new().strip_pssh(true);
+/// let decrypted_init = mp4decrypt::mp4decrypt_with_options(&init, &keys, None, &options).unwrap();
+/// assert_eq!(mp4decrypt::widevine_content_id(&decrypted_init).unwrap(), None);
+/// ```
+pub fn widevine_content_id(init: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    Ok(extract_pssh(init)?
+        .iter()
+        .filter(|x| x.system_name() == Some("Widevine"))
+        .find_map(|x| widevine_fields(&x.data, 4).first().map(|x| x.to_vec())))
+}
+
+/// Read every `pssh` box at the top level or inside `moov` and `moof` boxes.
+///
+/// Use [Mp4Metadata](crate::Mp4Metadata) for reading more than just the `pssh` boxes.
+pub fn extract_pssh(data: &[u8]) -> Result<Vec<PsshBox>, Error> {
+    Mp4Metadata::parse(data).map(|x| x.pssh)
+}
+
+pub(crate) fn read_pssh(payload: &[u8]) -> Result<PsshBox, Error> {
+    let mut reader = Reader::new(payload);
+    let (version, _) = reader.read_version_flags()?;
+    let system_id = reader.read_array::<16>()?;
+    let mut kids = Vec::new();
+
+    if version > 0 {
+        let kid_count = reader.read_u32()?;
+
+        if kid_count as usize > reader.remaining() / 16 {
+            return Err(Error::new_format(format!(
+                "pssh box is too small for {} kids.",
+                kid_count
+            )));
+        }
+
+        for _ in 0..kid_count {
+            kids.push(reader.read_array::<16>()?);
+        }
+    }
+
+    let data_size = reader.read_u32()?;
+
+    Ok(PsshBox {
+        version,
+        system_id,
+        kids,
+        data: reader.read_bytes(data_size as usize)?.to_vec(),
+    })
+}
+
+fn system_name(system_id: &[u8; 16]) -> Option<&'static str> {
+    find_system(system_id).map(|(_, name, _)| *name)
+}
+
+fn find_system(system_id: &[u8; 16]) -> Option<&'static ([u8; 16], &'static str, bool)> {
+    SYSTEMS.iter().find(|(id, ..)| id == system_id)
+}
+
+/// Reject an init segment having a protected track and a `pssh` box of a DRM system which Bento4 can't
+/// decrypt, if the scheme of the track is unknown or no `pssh` box belongs to a system which Bento4 can decrypt.
+///
+/// Data which can't be parsed is left for Bento4 to handle.
+pub(crate) fn check_drm_system(init: &[u8]) -> Result<(), Error> {
+    let Ok(metadata) = Mp4Metadata::parse(init) else {
+        return Ok(());
+    };
+
+    let Some(track) = metadata.tracks.iter().find(|x| x.is_protected()) else {
+        return Ok(());
+    };
+
+    let systems = metadata
+        .pssh
+        .iter()
+        .filter_map(|x| find_system(&x.system_id))
+        .collect::<Vec<_>>();
+
+    let Some((system_id, name, _)) = systems.iter().find(|(_, _, supported)| !supported) else {
+        return Ok(());
+    };
+
+    if track.scheme.is_some() && systems.iter().any(|(_, _, supported)| *supported) {
+        return Ok(());
+    }
+
+    Err(Error {
+        msg: format!(
+            "track {} is protected with {} drm system ({}), which can't be decrypted.",
+            track.track_id,
+            name,
+            keys::to_hex(system_id)
+        ),
+        err_type: ErrorType::UnsupportedDrmSystem(*system_id),
+        invalid_key: false,
+    })
+}
+
+/// Remove the `pssh` boxes of `moov` and `moof` boxes from a decrypted data stream,
+/// see [DecryptOptions::strip_pssh](crate::DecryptOptions::strip_pssh).
+///
+/// `fragments_info` holds the track defaults of fragments in `data`.
+pub(crate) fn strip_pssh(data: &mut Vec<u8>, fragments_info: Option<&[u8]>) -> Result<(), Error> {
+    let mut replacements = Vec::new();
+
+    for parent in boxes::boxes(data) {
+        let parent = parent?;
+
+        if &parent.box_type != b"moov" && &parent.box_type != b"moof" {
+            continue;
+        }
+
+        for child in parent.children() {
+            let child = child?;
+
+            if &child.box_type == b"pssh" {
+                replacements.push(Replacement::new(&child, Vec::new()));
+            }
+        }
+    }
+
+    rewrite::replace(data, fragments_info, replacements)
+}
@@ -0,0 +1,116 @@
+use crate::Error;
+
+/// Big endian reader over a byte slice.
+#[derive(Clone)]
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub(crate) fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.remaining() {
+            return Err(Error::new_format(format!(
+                "unexpected end of data while reading {} bytes at offset {}.",
+                len, self.pos
+            )));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn skip(&mut self, len: usize) -> Result<(), Error> {
+        self.read_bytes(len).map(|_| ())
+    }
+
+    pub(crate) fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut buf = [0; N];
+        buf.copy_from_slice(self.read_bytes(N)?);
+        Ok(buf)
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    pub(crate) fn read_u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.read_array()?))
+    }
+
+    /// Read the version and flags of a full box.
+    pub(crate) fn read_version_flags(&mut self) -> Result<(u8, u32), Error> {
+        let value = self.read_u32()?;
+        Ok(((value >> 24) as u8, value & 0x00ff_ffff))
+    }
+}
+
+/// Most significant bit first reader.
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn read(&mut self, bits: usize) -> Result<u32, Error> {
+        let mut value = 0;
+
+        for _ in 0..bits {
+            let byte = self
+                .data
+                .get(self.pos / 8)
+                .ok_or_else(|| Error::new_format("unexpected end of data while reading bits."))?;
+            value = (value << 1) | ((byte >> (7 - self.pos % 8)) & 1) as u32;
+            self.pos += 1;
+        }
+
+        Ok(value)
+    }
+
+    /// Read an unsigned Exp-Golomb code.
+    pub(crate) fn read_ue(&mut self) -> Result<u32, Error> {
+        let mut leading_zeros = 0;
+
+        while self.read(1)? == 0 {
+            leading_zeros += 1;
+
+            if leading_zeros > 31 {
+                return Err(Error::new_format("invalid exp-golomb code."));
+            }
+        }
+
+        Ok(((1_u64 << leading_zeros) - 1 + self.read(leading_zeros)? as u64) as u32)
+    }
+
+    /// Read a signed Exp-Golomb code.
+    pub(crate) fn read_se(&mut self) -> Result<i32, Error> {
+        let value = self.read_ue()? as i64;
+
+        Ok(if value % 2 == 1 {
+            (value + 1) / 2
+        } else {
+            -(value / 2)
+        } as i32)
+    }
+}
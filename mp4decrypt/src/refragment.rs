@@ -0,0 +1,230 @@
+use crate::{
+    Error, ErrorType, KeyMap, boxes, decrypt, metadata, moof,
+    samples::{self, SampleTiming},
+    split,
+};
+
+/// Decrypt a fragmented data stream and split its samples into new fragments of a target duration, e.g. for
+/// repackaging content into uniform segments.
+///
+/// Returns the decrypted init segment, followed by every new media segment (`moof` + `mdat`). A new segment
+/// starts at the first sync sample at or after every multiple of `segment_seconds` from the first sample,
+/// so segments are only as uniform as the sync samples of the track allow. Every `moof` box holds a single
+/// `trun` box with the duration, size, flags and composition time offset of every sample, and a `tfdt` box.
+///
+/// # Limitations
+///
+/// * Only fragmented data streams with a single track are supported.
+/// * Boxes other than `moof` and `mdat` (e.g. `sidx`, `styp` and `emsg`) are dropped, and the init segment
+///   is kept as Bento4 decrypts it.
+/// * Segments are written by this crate rather than Bento4's fragmenter, which isn't part of the Bento4 library.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream, either a self-contained fragmented file or media segments.
+/// * `init` (optional) - Init segment of `data`, required if `data` doesn't contain a `moov` box.
+/// * `keys` - Keys for decrypting the data stream.
+/// * `segment_seconds` - Target duration of every new segment.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let segments = mp4decrypt::decrypt_and_refragment(&segment, Some(&init), &keys, 10.0).unwrap();
+/// assert_eq!(segments.len(), 2);
+///
+/// // the same samples as the decrypted sample fragment
+/// let samples = mp4decrypt::decrypt_samples(&segment, Some(&init), &keys, 1)
+///     .unwrap()
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// let refragmented = mp4decrypt::decrypt_samples(&segments[1], Some(&segments[0]), &keys, 1)
+///     .unwrap()
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(samples, refragmented);
+/// ```
+pub fn decrypt_and_refragment(
+    data: &[u8],
+    init: Option<&[u8]>,
+    keys: &KeyMap,
+    segment_seconds: f64,
+) -> Result<Vec<Vec<u8>>, Error> {
+    if !(segment_seconds > 0.0 && segment_seconds.is_finite()) {
+        return Err(Error::new_format(format!(
+            "segment duration of {} seconds is invalid.",
+            segment_seconds
+        )));
+    }
+
+    let decrypted_data = decrypt(data, keys, init)?;
+    let decrypted_init = match init {
+        Some(x) => decrypt(x, keys, None)?,
+        None => split::split_fragments(&decrypted_data)?.init.to_vec(),
+    };
+
+    if decrypted_init.is_empty() {
+        return Err(Error {
+            msg: "data stream has no init segment (moov box) and no init segment was given."
+                .to_owned(),
+            err_type: ErrorType::MissingInitSegment,
+            invalid_key: false,
+        });
+    }
+
+    let tracks = metadata::list_tracks(&decrypted_init)?;
+    let [track] = tracks.as_slice() else {
+        return Err(Error::new_format(format!(
+            "only data streams with a single track can be re-fragmented, this one has {} tracks.",
+            tracks.len()
+        )));
+    };
+
+    let trex = samples::read_trex_defaults(&decrypted_init)?;
+    let trex_sizes = moof::read_trex_sizes(&decrypted_init)?;
+    // Decode time, timing and data of every sample.
+    let mut samples = Vec::new();
+    let mut next_dts = 0;
+
+    for moof in boxes::boxes(&decrypted_data) {
+        let moof = moof?;
+
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+
+        for fragment in moof::read_track_fragments(&moof, &trex_sizes)? {
+            let timing = samples::read_fragment_timing(&fragment, &trex)?;
+            next_dts = timing.base_media_decode_time.unwrap_or(next_dts);
+
+            let fragment_samples = fragment.runs.iter().flat_map(|x| &x.samples);
+
+            for (sample, timing) in fragment_samples.zip(timing.samples) {
+                let start = sample.offset as usize;
+                let Some(sample_data) = decrypted_data.get(start..start + sample.size as usize)
+                else {
+                    return Err(Error::new_format(format!(
+                        "sample data of track {} lies outside the data stream.",
+                        fragment.track_id
+                    )));
+                };
+
+                let dts = next_dts;
+                next_dts += timing.duration as u64;
+                samples.push((dts, timing, sample_data));
+            }
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(Error::new_format(
+            "data stream has no fragments to be re-fragmented.",
+        ));
+    }
+
+    let segment_duration = ((segment_seconds * track.timescale as f64).round() as u64).max(1);
+    let first_dts = samples[0].0;
+    let mut segments = vec![decrypted_init];
+    let mut current = Vec::new();
+    let mut boundary = first_dts;
+
+    for sample in samples {
+        // sample_is_non_sync_sample
+        let is_sync = sample.1.flags & 0x010000 == 0;
+
+        if is_sync && sample.0 >= boundary {
+            if !current.is_empty() {
+                segments.push(write_segment(
+                    segments.len() as u32,
+                    track.track_id,
+                    &current,
+                ));
+                current.clear();
+            }
+
+            // Skip the boundaries which have been passed without a sync sample.
+            boundary +=
+                (sample.0 - boundary) / segment_duration * segment_duration + segment_duration;
+        }
+
+        current.push(sample);
+    }
+
+    segments.push(write_segment(
+        segments.len() as u32,
+        track.track_id,
+        &current,
+    ));
+    Ok(segments)
+}
+
+/// Write a `moof` and `mdat` box holding the given samples.
+fn write_segment(
+    sequence_number: u32,
+    track_id: u32,
+    samples: &[(u64, SampleTiming, &[u8])],
+) -> Vec<u8> {
+    let mut mfhd = 0_u32.to_be_bytes().to_vec();
+    mfhd.extend_from_slice(&sequence_number.to_be_bytes());
+
+    // default-base-is-moof
+    let mut tfhd = 0x020000_u32.to_be_bytes().to_vec();
+    tfhd.extend_from_slice(&track_id.to_be_bytes());
+
+    let mut tfdt = (1_u32 << 24).to_be_bytes().to_vec();
+    tfdt.extend_from_slice(&samples[0].0.to_be_bytes());
+
+    // Signed composition time offsets need version 1.
+    let version = samples.iter().any(|x| x.1.composition_offset < 0) as u32;
+    // data-offset, sample-duration, sample-size, sample-flags and sample-composition-time-offsets
+    let mut trun = (version << 24 | 0x000f01).to_be_bytes().to_vec();
+    trun.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+    let data_offset_pos = trun.len();
+    trun.extend_from_slice(&[0; 4]);
+
+    for (_, timing, data) in samples {
+        trun.extend_from_slice(&timing.duration.to_be_bytes());
+        trun.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        trun.extend_from_slice(&timing.flags.to_be_bytes());
+        trun.extend_from_slice(&(timing.composition_offset as u32).to_be_bytes());
+    }
+
+    let mdat_payload = samples
+        .iter()
+        .flat_map(|x| x.2)
+        .copied()
+        .collect::<Vec<_>>();
+    let mdat_header_size = if mdat_payload.len() as u64 + 8 > u32::MAX as u64 {
+        16
+    } else {
+        8
+    };
+
+    // Every box of the moof box has a 32-bit size, the data offset points past the mdat header.
+    let moof_size =
+        8 + (8 + mfhd.len()) + 8 + (8 + tfhd.len()) + (8 + tfdt.len()) + (8 + trun.len());
+    let data_offset = (moof_size + mdat_header_size) as u32;
+    trun[data_offset_pos..data_offset_pos + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+    let mut traf = Vec::new();
+    boxes::write_box(&mut traf, b"tfhd", &tfhd);
+    boxes::write_box(&mut traf, b"tfdt", &tfdt);
+    boxes::write_box(&mut traf, b"trun", &trun);
+
+    let mut moof = Vec::new();
+    boxes::write_box(&mut moof, b"mfhd", &mfhd);
+    boxes::write_box(&mut moof, b"traf", &traf);
+
+    let mut segment = Vec::with_capacity(moof_size + mdat_header_size + mdat_payload.len());
+    boxes::write_box(&mut segment, b"moof", &moof);
+    boxes::write_box(&mut segment, b"mdat", &mdat_payload);
+    segment
+}
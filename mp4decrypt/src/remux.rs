@@ -0,0 +1,379 @@
+use crate::{
+    Error, KeyMap,
+    boxes::{self, Mp4Box},
+    decrypt, metadata,
+    moof::{self, TrackFragment},
+    reader::Reader,
+    validate::CONTAINERS,
+};
+use std::collections::HashMap;
+
+/// Extended type of the PIFF sample encryption `uuid` box.
+const PIFF_SAMPLE_ENCRYPTION: [u8; 16] = [
+    0xa2, 0x39, 0x4f, 0x52, 0x5a, 0x9b, 0x4f, 0x14, 0xa2, 0x44, 0x6c, 0x42, 0x7c, 0x64, 0x8d, 0xf4,
+];
+
+/// Decrypt encrypted mp4 data stream and demux every track into its own single track mp4.
+///
+/// The decrypted stream is remuxed as follows:
+///
+/// 1. The `ftyp` box is copied and the `moov` box only keeps the `trak` and `trex` boxes of the track.
+/// 2. Each `moof` box only keeps the `traf` box of the track, followed by a new `mdat` box holding
+///    just the samples of that track. Sample data offsets are rewritten relative to the new `moof`.
+/// 3. Auxiliary encryption info (`senc`, `saiz`, `saio` and PIFF `uuid`) boxes are dropped.
+///
+/// Only fragmented streams are supported, since sample tables in `stbl` box are copied as is.
+/// Other top level boxes like `sidx`, `styp` or `mfra` are not carried over as their offsets
+/// would no longer be valid.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream, either a self-contained fragmented file or media segments.
+/// * `init` (optional) - Init segment of `data`, required if `data` doesn't contain a `moov` box.
+/// * `keys` - Keys for decrypting the data stream.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("init.mp4").unwrap();
+/// let segment = std::fs::read("segment_0.m4s").unwrap();
+///
+/// for (track_id, data) in mp4decrypt::decrypt_split_tracks(&segment, Some(&init), &keys).unwrap() {
+///     std::fs::write(format!("track_{}.mp4", track_id), data).unwrap();
+/// }
+/// ```
+pub fn decrypt_split_tracks(
+    data: &[u8],
+    init: Option<&[u8]>,
+    keys: &KeyMap,
+) -> Result<HashMap<u32, Vec<u8>>, Error> {
+    let decrypted_data = if let Some(init) = init {
+        let mut decrypted_data = decrypt(init, keys, None)?;
+        decrypted_data.extend(decrypt(data, keys, Some(init))?);
+        decrypted_data
+    } else {
+        decrypt(data, keys, None)?
+    };
+
+    split_tracks(&decrypted_data)
+}
+
+/// Demux a clear fragmented stream into single track streams.
+pub(crate) fn split_tracks(data: &[u8]) -> Result<HashMap<u32, Vec<u8>>, Error> {
+    let top_level = boxes::boxes(data).collect::<Result<Vec<_>, _>>()?;
+    let trex_sizes = moof::read_trex_sizes(data)?;
+
+    let Some(moov) = top_level.iter().find(|x| &x.box_type == b"moov") else {
+        return Err(Error::new_format("no moov box found in the data stream."));
+    };
+
+    if !top_level.iter().any(|x| &x.box_type == b"moof")
+        && top_level.iter().any(|x| &x.box_type == b"mdat")
+    {
+        return Err(Error::new_format(
+            "only fragmented data streams can be split into tracks.",
+        ));
+    }
+
+    let ftyp = top_level.iter().find(|x| &x.box_type == b"ftyp");
+    let mut outputs = HashMap::new();
+
+    for trak in moov.children() {
+        let trak = trak?;
+
+        if &trak.box_type == b"trak" {
+            let track_id = metadata::read_trak(&trak)?.track_id;
+            let mut output = ftyp.map(|x| x.data.to_vec()).unwrap_or_default();
+            write_moov(&mut output, moov, track_id)?;
+            outputs.insert(track_id, output);
+        }
+    }
+
+    for moof in top_level.iter().filter(|x| &x.box_type == b"moof") {
+        let mfhd = moof.child(b"mfhd")?;
+
+        for fragment in moof::read_track_fragments(moof, &trex_sizes)? {
+            if let Some(output) = outputs.get_mut(&fragment.track_id) {
+                write_fragment(output, data, mfhd.as_ref(), &fragment)?;
+            }
+        }
+    }
+
+    Ok(outputs)
+}
+
+fn write_moov(out: &mut Vec<u8>, moov: &Mp4Box, track_id: u32) -> Result<(), Error> {
+    let mut payload = Vec::new();
+
+    for child in moov.children() {
+        let child = child?;
+
+        match &child.box_type {
+            b"trak" => {
+                if metadata::read_trak(&child)?.track_id == track_id {
+                    payload.extend_from_slice(child.data);
+                }
+            }
+            b"mvex" => {
+                let mut mvex = Vec::new();
+
+                for x in child.children() {
+                    let x = x?;
+
+                    if &x.box_type != b"trex"
+                        || Reader::new(&x.payload()[4..]).read_u32()? == track_id
+                    {
+                        mvex.extend_from_slice(x.data);
+                    }
+                }
+
+                boxes::write_box(&mut payload, b"mvex", &mvex);
+            }
+            _ => payload.extend_from_slice(child.data),
+        }
+    }
+
+    boxes::write_box(out, b"moov", &payload);
+    Ok(())
+}
+
+fn write_fragment(
+    out: &mut Vec<u8>,
+    data: &[u8],
+    mfhd: Option<&Mp4Box>,
+    fragment: &TrackFragment,
+) -> Result<(), Error> {
+    let mut traf = Vec::new();
+    let mut offset_positions = Vec::new();
+    let mut runs = fragment.runs.iter();
+
+    for child in fragment.mp4_box.children() {
+        let child = child?;
+
+        match &child.box_type {
+            b"tfhd" => {
+                let mut reader = Reader::new(child.payload());
+                let (version, flags) = reader.read_version_flags()?;
+                let track_id = reader.read_u32()?;
+                if flags & 0x01 != 0 {
+                    reader.skip(8)?; // base_data_offset
+                }
+
+                let mut tfhd = Vec::new();
+                let flags = (flags & !0x01) | 0x020000;
+                tfhd.extend_from_slice(&((version as u32) << 24 | flags).to_be_bytes());
+                tfhd.extend_from_slice(&track_id.to_be_bytes());
+                tfhd.extend_from_slice(reader.read_bytes(reader.remaining())?);
+                boxes::write_box(&mut traf, b"tfhd", &tfhd);
+            }
+            b"trun" => {
+                let Some(run) = runs.next() else {
+                    continue;
+                };
+
+                let mut reader = Reader::new(run.mp4_box.payload());
+                let (version, flags) = reader.read_version_flags()?;
+                let sample_count = reader.read_u32()?;
+                if flags & 0x01 != 0 {
+                    reader.skip(4)?; // data_offset
+                }
+
+                let mut trun = Vec::new();
+                trun.extend_from_slice(&((version as u32) << 24 | flags | 0x01).to_be_bytes());
+                trun.extend_from_slice(&sample_count.to_be_bytes());
+                // data offset is filled once the moof size is known
+                offset_positions.push(traf.len() + 16);
+                trun.extend_from_slice(&[0; 4]);
+                trun.extend_from_slice(reader.read_bytes(reader.remaining())?);
+                boxes::write_box(&mut traf, b"trun", &trun);
+            }
+            b"senc" | b"saiz" | b"saio" => (),
+            b"uuid" if child.payload().starts_with(&PIFF_SAMPLE_ENCRYPTION) => (),
+            _ => traf.extend_from_slice(child.data),
+        }
+    }
+
+    let mut moof = Vec::new();
+    if let Some(mfhd) = mfhd {
+        moof.extend_from_slice(mfhd.data);
+    }
+    let traf_start = moof.len() + 8;
+    boxes::write_box(&mut moof, b"traf", &traf);
+
+    let mut mdat = Vec::new();
+    let mdat_start = moof.len() + 8 + 8;
+
+    for (run, position) in fragment.runs.iter().zip(offset_positions) {
+        let data_offset = u32::try_from(mdat_start + mdat.len())
+            .map_err(|_| Error::new_format("fragment is too large to be remuxed."))?;
+        let position = traf_start + position;
+        moof[position..position + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+        for sample in &run.samples {
+            let start = sample.offset as usize;
+            let end = start + sample.size as usize;
+            mdat.extend_from_slice(data.get(start..end).ok_or_else(|| {
+                Error::new_format(format!(
+                    "sample data of track {} lies outside the data stream.",
+                    fragment.track_id
+                ))
+            })?);
+        }
+    }
+
+    boxes::write_box(out, b"moof", &moof);
+    boxes::write_box(out, b"mdat", &mdat);
+    Ok(())
+}
+
+/// Renumber the tracks of a decrypted data stream in place, e.g. before muxing tracks of several sources
+/// which use the same track IDs. Tracks missing from `mapping` keep their ID.
+///
+/// Track IDs are rewritten in `tkhd`, `tref`, `trex`, `tfhd`, `tfra` and `sidx` (reference ID) boxes,
+/// and the next track ID of the `mvhd` box is raised above the new track IDs if needed. Since every box keeps
+/// its size, sample offsets stay valid. Track IDs must stay unique and can't be 0.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{KeyMap, Mp4Metadata};
+/// use std::collections::HashMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let mut decrypted_init = mp4decrypt::mp4decrypt(&init, &HashMap::new(), None).unwrap();
+/// mp4decrypt::remap_track_ids(&mut decrypted_init, &HashMap::from([(1, 3)])).unwrap();
+///
+/// let metadata = Mp4Metadata::parse(&decrypted_init).unwrap();
+/// assert_eq!(metadata.tracks()[0].track_id, 3);
+///
+/// assert!(mp4decrypt::remap_track_ids(&mut decrypted_init, &HashMap::from([(3, 0)])).is_err());
+/// ```
+pub fn remap_track_ids(data: &mut [u8], mapping: &HashMap<u32, u32>) -> Result<(), Error> {
+    if mapping.values().any(|x| *x == 0) {
+        return Err(Error::new_format("track ids can't be remapped to 0."));
+    }
+
+    let mut patches = Vec::new();
+    let mut next_track_ids = Vec::new();
+    track_id_patches(
+        boxes::boxes(data),
+        None,
+        mapping,
+        &mut patches,
+        &mut next_track_ids,
+    )?;
+
+    // New ID of every track by the offset of its moov box, which must be unique within the moov box.
+    let mut track_ids: HashMap<usize, Vec<u32>> = HashMap::new();
+
+    for (pos, value, moov) in &patches {
+        if let Some(moov) = moov {
+            let track_ids = track_ids.entry(*moov).or_default();
+
+            if track_ids.contains(value) {
+                return Err(Error::new_format(format!(
+                    "more than one track would have track id {}.",
+                    value
+                )));
+            }
+
+            track_ids.push(*value);
+        }
+
+        data[*pos..*pos + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    for (pos, moov) in next_track_ids {
+        let Some(max) = track_ids.get(&moov).and_then(|x| x.iter().max()) else {
+            continue;
+        };
+        let next_track_id = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap());
+
+        if next_track_id <= *max {
+            data[pos..pos + 4].copy_from_slice(&max.saturating_add(1).to_be_bytes());
+        }
+    }
+
+    Ok(())
+}
+
+/// Position and new value of every track ID, walking into container boxes. Track IDs of `tkhd` boxes are marked
+/// with the offset of their `moov` box, whether remapped or not, and positions of the next track ID of `mvhd` boxes
+/// are collected separately along with the offset of their `moov` box.
+fn track_id_patches(
+    boxes: boxes::Boxes,
+    moov: Option<usize>,
+    mapping: &HashMap<u32, u32>,
+    patches: &mut Vec<(usize, u32, Option<usize>)>,
+    next_track_ids: &mut Vec<(usize, usize)>,
+) -> Result<(), Error> {
+    for mp4_box in boxes {
+        let mp4_box = mp4_box?;
+        let moov = match &mp4_box.box_type {
+            b"moov" => Some(mp4_box.offset),
+            _ => moov,
+        };
+        let start = mp4_box.offset + mp4_box.header_size;
+        let mut reader = Reader::new(mp4_box.payload());
+        let mut patch = |reader: &mut Reader, pos: usize, moov: Option<usize>| {
+            let track_id = reader.read_u32()?;
+            patches.push((
+                pos,
+                mapping.get(&track_id).copied().unwrap_or(track_id),
+                moov,
+            ));
+            Ok::<_, Error>(())
+        };
+
+        match &mp4_box.box_type {
+            b"mvhd" => {
+                let (version, _) = reader.read_version_flags()?;
+                // times, timescale and duration, rate, volume, reserved, matrix and pre_defined
+                let fields_size = if version == 1 { 28 } else { 16 } + 4 + 2 + 10 + 36 + 24;
+                reader.skip(fields_size)?;
+                reader.read_u32()?;
+                if let Some(moov) = moov {
+                    next_track_ids.push((start + 4 + fields_size, moov));
+                }
+            }
+            b"tkhd" => {
+                let (version, _) = reader.read_version_flags()?;
+                let times_size = if version == 1 { 16 } else { 8 };
+                reader.skip(times_size)?;
+                patch(&mut reader, start + 4 + times_size, moov)?;
+            }
+            b"tfhd" | b"trex" | b"tfra" | b"sidx" => {
+                reader.read_version_flags()?;
+                patch(&mut reader, start + 4, None)?;
+            }
+            // track reference type boxes, each holding a list of track IDs
+            b"tref" => {
+                for reference in mp4_box.children() {
+                    let reference = reference?;
+                    let start = reference.offset + reference.header_size;
+                    let mut reader = Reader::new(reference.payload());
+
+                    for i in 0..reference.payload().len() / 4 {
+                        patch(&mut reader, start + i * 4, None)?;
+                    }
+                }
+            }
+            x if CONTAINERS.contains(&x) => {
+                track_id_patches(mp4_box.children(), moov, mapping, patches, next_track_ids)?
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
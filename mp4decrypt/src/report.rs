@@ -0,0 +1,426 @@
+use crate::{
+    Error, KeyId, KeyMap, Scheme, TrackInfo, boxes, keys, metadata, moof, reader::Reader, samples,
+    split, verify,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Details about a decryption, see [mp4decrypt_with_report](crate::mp4decrypt_with_report).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecryptReport {
+    /// Protected tracks which are left encrypted because no key matches them.
+    /// Only filled with [MissingKeyPolicy::WarnReport](crate::MissingKeyPolicy::WarnReport).
+    pub missing_keys: Vec<MissingKey>,
+    /// Statistics of every decrypted track by track ID.
+    /// Only filled with [DecryptOptions::collect_stats](crate::DecryptOptions::collect_stats).
+    pub tracks: HashMap<u32, TrackStats>,
+    /// Keys were given but the input has no protection box, i.e. it is clear or already decrypted.
+    /// Only set with [ClearInputPolicy::WarnReport](crate::ClearInputPolicy::WarnReport).
+    pub clear_input: bool,
+    /// Default KIDs of protected tracks which are decrypted because a key matches them.
+    pub decrypted_kids: Vec<[u8; 16]>,
+    /// Default KIDs of protected tracks which are left encrypted because no key matches them.
+    /// The output is still a valid mp4 file, in which these tracks keep their protection boxes.
+    pub encrypted_kids: Vec<[u8; 16]>,
+    /// Box types of the output which still mix versions 0 and 1, because they can't be rewritten as a single version.
+    /// Only checked with [DecryptOptions::normalize_box_versions](crate::DecryptOptions::normalize_box_versions).
+    pub mixed_box_versions: Vec<[u8; 4]>,
+    /// Total media duration in seconds, for checking it against the expected duration since a wildly wrong one
+    /// often means that the input is malformed. It is read from the sample timing of the fragments (`tfdt` and
+    /// `trun` boxes) of the longest track, or the `mvhd` box of a non-fragmented input, and summed over periods.
+    /// `None` if it isn't known, e.g. for the init segment of a live stream.
+    pub duration_seconds: Option<f64>,
+}
+
+/// Statistics of a decrypted track.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrackStats {
+    pub samples_decrypted: u32,
+    pub bytes_decrypted: u64,
+    pub scheme: Scheme,
+    /// CRC-32 (IEEE) of the decrypted samples of the track in the order they are stored,
+    /// only computed with [DecryptOptions::compute_crc32](crate::DecryptOptions::compute_crc32).
+    pub crc32: Option<u32>,
+}
+
+/// A protected track without a matching key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MissingKey {
+    pub track_id: u32,
+    /// Default KID of the track, if it is declared in a `tenc` box.
+    pub kid: Option<[u8; 16]>,
+}
+
+/// Find the protected tracks of an init segment which aren't matched by any key.
+pub(crate) fn find_missing_keys(
+    init: &[u8],
+    keys: &KeyMap,
+    raw_key_pairs: &[(String, String)],
+) -> Result<Vec<MissingKey>, Error> {
+    let key_ids = key_ids(keys, raw_key_pairs);
+
+    Ok(metadata::list_tracks(init)?
+        .into_iter()
+        .filter(|x| x.is_protected() && !has_key(x, &key_ids))
+        .map(|x| MissingKey {
+            track_id: x.track_id,
+            kid: x.default_kid,
+        })
+        .collect())
+}
+
+/// Add the default KIDs of protected tracks of an init segment to the KIDs of `report` which are
+/// decrypted or left encrypted, depending on whether a key matches them.
+pub(crate) fn split_kids(
+    init: &[u8],
+    keys: &KeyMap,
+    raw_key_pairs: &[(String, String)],
+    report: &mut DecryptReport,
+) -> Result<(), Error> {
+    let key_ids = key_ids(keys, raw_key_pairs);
+
+    for track in metadata::list_tracks(init)? {
+        let (true, Some(kid)) = (track.is_protected(), track.default_kid) else {
+            continue;
+        };
+
+        let kids = if has_key(&track, &key_ids) {
+            &mut report.decrypted_kids
+        } else {
+            &mut report.encrypted_kids
+        };
+
+        if !kids.contains(&kid) {
+            kids.push(kid);
+        }
+    }
+
+    Ok(())
+}
+
+/// Count the samples of every protected track of `init` which is matched by a key.
+///
+/// Samples are read from the `moof` boxes of `data`, or the `stsz` boxes of a non-fragmented `data`.
+pub(crate) fn collect_stats(
+    data: &[u8],
+    init: &[u8],
+    keys: &KeyMap,
+    raw_key_pairs: &[(String, String)],
+) -> Result<HashMap<u32, TrackStats>, Error> {
+    let key_ids = key_ids(keys, raw_key_pairs);
+    let mut stats = HashMap::new();
+
+    for track in metadata::list_tracks(init)? {
+        if let (true, Some(scheme)) = (has_key(&track, &key_ids), track.scheme) {
+            stats.insert(
+                track.track_id,
+                TrackStats {
+                    samples_decrypted: 0,
+                    bytes_decrypted: 0,
+                    scheme,
+                    crc32: None,
+                },
+            );
+        }
+    }
+
+    if let Some(moov) = boxes::find(boxes::boxes(data), b"moov")? {
+        for trak in moov.children() {
+            let trak = trak?;
+
+            if &trak.box_type != b"trak" {
+                continue;
+            }
+
+            let track_id = metadata::read_trak(&trak)?.track_id;
+
+            if let (Some(x), Some((count, size))) = (stats.get_mut(&track_id), read_stsz(&trak)?) {
+                x.samples_decrypted += count;
+                x.bytes_decrypted += size;
+            }
+        }
+    }
+
+    let trex_sizes = moof::read_trex_sizes(init)?;
+
+    for moof in boxes::boxes(data) {
+        let moof = moof?;
+
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+
+        for fragment in moof::read_track_fragments(&moof, &trex_sizes)? {
+            if let Some(x) = stats.get_mut(&fragment.track_id) {
+                for sample in fragment.runs.iter().flat_map(|x| &x.samples) {
+                    x.samples_decrypted += 1;
+                    x.bytes_decrypted += sample.size as u64;
+                }
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Total media duration of the periods of a data stream in seconds, see
+/// [DecryptReport::duration_seconds](DecryptReport::duration_seconds).
+///
+/// `periods` holds the data and init segment of every period.
+pub(crate) fn duration_seconds(periods: &[(&[u8], &[u8])]) -> Result<Option<f64>, Error> {
+    let mut total = 0.0;
+
+    for (data, init) in periods {
+        match period_duration(data, init)? {
+            Some(x) => total += x,
+            None => return Ok(None),
+        }
+    }
+
+    Ok((!periods.is_empty()).then_some(total))
+}
+
+fn period_duration(data: &[u8], init: &[u8]) -> Result<Option<f64>, Error> {
+    let timescales = metadata::list_tracks(init)?
+        .into_iter()
+        .map(|x| (x.track_id, x.timescale))
+        .collect::<HashMap<_, _>>();
+    let trex = samples::read_trex_defaults(init)?;
+    let trex_sizes = moof::read_trex_sizes(init)?;
+    // Earliest and latest decode times of every track, and the decode time after its last fragment.
+    let mut times = HashMap::<u32, (u64, u64, u64)>::new();
+
+    for moof in boxes::boxes(data) {
+        let moof = moof?;
+
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+
+        for fragment in moof::read_track_fragments(&moof, &trex_sizes)? {
+            let timing = samples::read_fragment_timing(&fragment, &trex)?;
+            let (start, end, next) = times.entry(fragment.track_id).or_insert((u64::MAX, 0, 0));
+
+            // Fragments without a tfdt box continue from the previous one.
+            let first = timing.base_media_decode_time.unwrap_or(*next);
+            *next = first.saturating_add(timing.duration());
+            *start = (*start).min(first);
+            *end = (*end).max(*next);
+        }
+    }
+
+    if !times.is_empty() {
+        return Ok(times
+            .iter()
+            .filter_map(
+                |(track_id, (start, end, _))| match timescales.get(track_id) {
+                    Some(&timescale) if timescale != 0 => {
+                        Some((end - start) as f64 / timescale as f64)
+                    }
+                    _ => None,
+                },
+            )
+            .reduce(f64::max));
+    }
+
+    let Some(mvhd) = boxes::find(boxes::boxes(init), b"moov")?
+        .map(|x| x.child(b"mvhd"))
+        .transpose()?
+        .flatten()
+    else {
+        return Ok(None);
+    };
+
+    let mut reader = Reader::new(mvhd.payload());
+    let (version, _) = reader.read_version_flags()?;
+    reader.skip(if version == 1 { 16 } else { 8 })?; // creation_time, modification_time
+    let timescale = reader.read_u32()?;
+    let duration = match version {
+        1 => reader.read_u64()?,
+        _ => match reader.read_u32()? {
+            u32::MAX => u64::MAX,
+            x => x as u64,
+        },
+    };
+
+    // A duration of all ones is unknown, and live streams usually have none.
+    if timescale == 0 || duration == 0 || duration == u64::MAX {
+        return Ok(None);
+    }
+
+    Ok(Some(duration as f64 / timescale as f64))
+}
+
+/// Compute the CRC-32 of the decrypted samples of every track in `stats`, from the decrypted data stream.
+///
+/// Samples are located in the same way as [collect_stats](collect_stats), using `fragments_info` if it is given
+/// or the init segment of every period of `decrypted_data` otherwise.
+pub(crate) fn compute_crc32(
+    decrypted_data: &[u8],
+    fragments_info: Option<&[u8]>,
+    stats: &mut HashMap<u32, TrackStats>,
+) -> Result<(), Error> {
+    let mut crcs = HashMap::new();
+    let mut update = |track_id, offset: u64, size: u32| -> Result<(), Error> {
+        if !stats.contains_key(&track_id) {
+            return Ok(());
+        }
+
+        let sample = decrypted_data
+            .get(offset as usize..offset as usize + size as usize)
+            .ok_or_else(|| {
+                Error::new_format(format!(
+                    "sample of track {} at offset {} is outside of the decrypted data stream.",
+                    track_id, offset
+                ))
+            })?;
+        let crc = crcs.entry(track_id).or_insert(0);
+        *crc = crc32_update(*crc, sample);
+        Ok(())
+    };
+
+    let periods = match fragments_info {
+        Some(x) => vec![(decrypted_data, x)],
+        None => split::split_periods(decrypted_data)
+            .into_iter()
+            .map(|x| (x, x))
+            .collect(),
+    };
+
+    for (data, init) in periods {
+        // Offsets within a period are relative to the period.
+        let base = data.as_ptr() as u64 - decrypted_data.as_ptr() as u64;
+
+        if let Some(moov) = boxes::find(boxes::boxes(data), b"moov")? {
+            for trak in moov.children() {
+                let trak = trak?;
+
+                if &trak.box_type != b"trak" {
+                    continue;
+                }
+
+                let track_id = metadata::read_trak(&trak)?.track_id;
+
+                for sample in verify::read_stbl_samples(&trak)? {
+                    update(track_id, base + sample.offset, sample.size)?;
+                }
+            }
+        }
+
+        let trex_sizes = moof::read_trex_sizes(init)?;
+
+        for moof in boxes::boxes(data) {
+            let moof = moof?;
+
+            if &moof.box_type != b"moof" {
+                continue;
+            }
+
+            for fragment in moof::read_track_fragments(&moof, &trex_sizes)? {
+                for sample in fragment.runs.iter().flat_map(|x| &x.samples) {
+                    update(fragment.track_id, base + sample.offset, sample.size)?;
+                }
+            }
+        }
+    }
+
+    for (track_id, x) in stats.iter_mut() {
+        x.crc32 = Some(crcs.get(track_id).copied().unwrap_or_default());
+    }
+
+    Ok(())
+}
+
+/// Lookup table of the reflected CRC-32 (IEEE) polynomial.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+/// Continue a CRC-32 with more data, starting from 0.
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+
+    for byte in data {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+fn key_ids(keys: &KeyMap, raw_key_pairs: &[(String, String)]) -> HashSet<KeyId> {
+    keys.iter()
+        .map(|(id, _)| *id)
+        .chain(
+            raw_key_pairs
+                .iter()
+                .filter_map(|(id, _)| keys::parse_key_id(id).ok()),
+        )
+        .collect()
+}
+
+fn has_key(track: &TrackInfo, key_ids: &HashSet<KeyId>) -> bool {
+    key_ids.contains(&KeyId::Track(track.track_id))
+        || track
+            .default_kid
+            .is_some_and(|kid| key_ids.contains(&KeyId::Kid(kid)))
+}
+
+/// Read the sample count and total size of samples from the `stsz` box of a track.
+fn read_stsz(trak: &boxes::Mp4Box) -> Result<Option<(u32, u64)>, Error> {
+    let Some(stsz) = [b"mdia", b"minf", b"stbl", b"stsz"].iter().try_fold(
+        Some(*trak),
+        |parent, x| match parent {
+            Some(parent) => parent.child(x),
+            None => Ok(None),
+        },
+    )?
+    else {
+        return Ok(None);
+    };
+
+    let mut reader = Reader::new(stsz.payload());
+    reader.read_version_flags()?;
+    let sample_size = reader.read_u32()?;
+    let sample_count = reader.read_u32()?;
+
+    if sample_size != 0 {
+        return Ok(Some((
+            sample_count,
+            sample_size as u64 * sample_count as u64,
+        )));
+    }
+
+    if sample_count as usize > reader.remaining() / 4 {
+        return Err(Error::new_format(format!(
+            "stsz box at offset {} is too small for {} samples.",
+            stsz.offset, sample_count
+        )));
+    }
+
+    let mut size = 0;
+
+    for _ in 0..sample_count {
+        size += reader.read_u32()? as u64;
+    }
+
+    Ok(Some((sample_count, size)))
+}
@@ -0,0 +1,270 @@
+use crate::{
+    Error,
+    boxes::{self, Boxes, Mp4Box},
+    moof,
+    reader::Reader,
+    validate::CONTAINERS,
+};
+use std::collections::HashMap;
+
+/// A range of a data stream which is replaced with other bytes, e.g. a box which is removed or rewritten.
+pub(crate) struct Replacement {
+    pub(crate) offset: usize,
+    pub(crate) size: usize,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Replacement {
+    /// Replace a complete box.
+    pub(crate) fn new(mp4_box: &Mp4Box, data: Vec<u8>) -> Self {
+        Self {
+            offset: mp4_box.offset,
+            size: mp4_box.data.len(),
+            data,
+        }
+    }
+
+    fn delta(&self) -> i64 {
+        self.data.len() as i64 - self.size as i64
+    }
+}
+
+/// Replace ranges of a data stream, which must not overlap, and resize every box holding them.
+///
+/// Sample offsets of `stco`, `co64`, `tfhd`, `trun` and `saio` boxes are moved by the added or removed bytes.
+/// Index boxes (`sidx`, `ssix` and `mfra`) are dropped once the size of a range changes, since they would no
+/// longer match the data stream. `fragments_info` holds the track defaults of fragments in `data`.
+pub(crate) fn replace(
+    data: &mut Vec<u8>,
+    fragments_info: Option<&[u8]>,
+    mut replacements: Vec<Replacement>,
+) -> Result<(), Error> {
+    if replacements.is_empty() {
+        return Ok(());
+    }
+
+    let top_level = boxes::boxes(data).collect::<Result<Vec<_>, _>>()?;
+    // Position, size and new value of every moved field.
+    let mut patches = Vec::new();
+    let resized = replacements.iter().any(|x| x.delta() != 0);
+
+    if resized {
+        for x in top_level
+            .iter()
+            .filter(|x| matches!(&x.box_type, b"sidx" | b"ssix" | b"mfra"))
+        {
+            replacements.push(Replacement::new(x, Vec::new()));
+        }
+    }
+
+    replacements.sort_unstable_by_key(|x| x.offset);
+
+    if resized {
+        // Offset of a byte of `data` once the ranges before it are replaced.
+        let moved = |offset: u64| {
+            offset.saturating_add_signed(
+                replacements
+                    .iter()
+                    .filter(|x| (x.offset + x.size) as u64 <= offset)
+                    .map(Replacement::delta)
+                    .sum::<i64>(),
+            )
+        };
+        let trex_sizes = moof::read_trex_sizes(fragments_info.unwrap_or(data))?;
+
+        for parent in &top_level {
+            match &parent.box_type {
+                b"moov" => chunk_offset_patches(parent, &moved, &mut patches)?,
+                b"moof" => fragment_patches(parent, &trex_sizes, &moved, &mut patches)?,
+                _ => (),
+            }
+        }
+
+        size_patches(boxes::boxes(data), data, &replacements, &mut patches)?;
+    }
+
+    for (pos, width, value) in patches {
+        let value = value.to_be_bytes();
+        data[pos..pos + width].copy_from_slice(&value[8 - width..]);
+    }
+
+    let size = data.len() as i64 + replacements.iter().map(Replacement::delta).sum::<i64>();
+    let mut replaced = Vec::with_capacity(size as usize);
+    let mut pos = 0;
+
+    for x in replacements {
+        replaced.extend_from_slice(&data[pos..x.offset]);
+        replaced.extend(x.data);
+        pos = x.offset + x.size;
+    }
+
+    replaced.extend_from_slice(&data[pos..]);
+    *data = replaced;
+    Ok(())
+}
+
+/// Resize every box holding a replaced range, walking into container boxes.
+fn size_patches(
+    boxes: Boxes,
+    data: &[u8],
+    replacements: &[Replacement],
+    patches: &mut Vec<(usize, usize, u64)>,
+) -> Result<(), Error> {
+    for mp4_box in boxes {
+        let mp4_box = mp4_box?;
+        let inner = replacements
+            .iter()
+            .filter(|x| x.offset > mp4_box.offset && x.offset + x.size <= mp4_box.end())
+            .collect::<Vec<_>>();
+
+        if inner.is_empty() {
+            continue;
+        }
+
+        let (pos, width) = if mp4_box.data[..4] == [0, 0, 0, 1] {
+            (mp4_box.offset + 8, 8) // largesize
+        } else {
+            (mp4_box.offset, 4)
+        };
+
+        let size = match width {
+            8 => u64::from_be_bytes(data[pos..pos + 8].try_into().unwrap()),
+            _ => u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as u64,
+        };
+
+        // A size of 0 extends to the end of data.
+        if size != 0 {
+            let size = size
+                .checked_add_signed(inner.iter().map(|x| x.delta()).sum())
+                .filter(|x| width == 8 || *x <= u32::MAX as u64)
+                .ok_or_else(|| {
+                    Error::new_format(format!(
+                        "{} box at offset {} is too large to be rewritten.",
+                        boxes::fourcc(&mp4_box.box_type),
+                        mp4_box.offset
+                    ))
+                })?;
+            patches.push((pos, width, size));
+        }
+
+        if CONTAINERS.contains(&&mp4_box.box_type) {
+            size_patches(mp4_box.children(), data, replacements, patches)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Move the chunk offsets of every track of a `moov` box.
+fn chunk_offset_patches(
+    moov: &Mp4Box,
+    moved: &impl Fn(u64) -> u64,
+    patches: &mut Vec<(usize, usize, u64)>,
+) -> Result<(), Error> {
+    for trak in moov.children() {
+        let trak = trak?;
+
+        if &trak.box_type != b"trak" {
+            continue;
+        }
+
+        let Some(stbl) = [b"mdia", b"minf", b"stbl"]
+            .iter()
+            .try_fold(Some(trak), |parent, x| match parent {
+                Some(parent) => parent.child(x),
+                None => Ok(None),
+            })?
+        else {
+            continue;
+        };
+
+        for table in stbl.children() {
+            let table = table?;
+            let width = match &table.box_type {
+                b"stco" => 4,
+                b"co64" => 8,
+                _ => continue,
+            };
+
+            let mut reader = Reader::new(table.payload());
+            reader.read_version_flags()?;
+            let entry_count = reader.read_u32()? as usize;
+            let start = table.offset + table.header_size + 8;
+
+            for i in 0..entry_count {
+                let value = match width {
+                    8 => reader.read_u64()?,
+                    _ => reader.read_u32()? as u64,
+                };
+                patches.push((start + i * width, width, moved(value)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Move the base data offsets of `tfhd` boxes and the data offsets of `trun` and `saio` boxes of a `moof` box.
+fn fragment_patches(
+    moof: &Mp4Box,
+    trex_sizes: &HashMap<u32, u32>,
+    moved: &impl Fn(u64) -> u64,
+    patches: &mut Vec<(usize, usize, u64)>,
+) -> Result<(), Error> {
+    for fragment in moof::read_track_fragments(moof, trex_sizes)? {
+        // Data offsets are relative to the base data offset or the moof box, which are moved as well.
+        let relative = |base: u64, offset: i64| {
+            let target = base.checked_add_signed(offset).ok_or_else(|| {
+                Error::new_format(format!(
+                    "traf box at offset {} has an invalid data offset.",
+                    fragment.mp4_box.offset
+                ))
+            })?;
+            Ok::<_, Error>(moved(target) as i64 - moved(base) as i64)
+        };
+
+        for child in fragment.mp4_box.children() {
+            let child = child?;
+            let mut reader = Reader::new(child.payload());
+            let (version, flags) = reader.read_version_flags()?;
+            let start = child.offset + child.header_size + 4;
+
+            match &child.box_type {
+                b"tfhd" if flags & 0x01 != 0 => {
+                    reader.skip(4)?; // track_ID
+                    patches.push((start + 4, 8, moved(reader.read_u64()?)));
+                }
+                b"trun" if flags & 0x01 != 0 => {
+                    reader.skip(4)?; // sample_count
+                    let offset =
+                        relative(fragment.base_data_offset, reader.read_u32()? as i32 as i64)?;
+                    patches.push((start + 4, 4, offset as i32 as u32 as u64));
+                }
+                b"saio" => {
+                    if flags & 0x01 != 0 {
+                        reader.skip(8)?; // aux_info_type, aux_info_type_parameter
+                    }
+
+                    let entry_count = reader.read_u32()? as usize;
+                    let width = if version == 0 { 4 } else { 8 };
+                    let start = start + 4 + if flags & 0x01 != 0 { 8 } else { 0 };
+
+                    for i in 0..entry_count {
+                        let offset = match width {
+                            8 => reader.read_u64()? as i64,
+                            _ => reader.read_u32()? as i64,
+                        };
+                        patches.push((
+                            start + i * width,
+                            width,
+                            relative(fragment.moof_offset, offset)? as u64,
+                        ));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use crate::{
+    Error, ErrorType, KeyMap, boxes, decrypt, metadata,
+    moof::{self, TrackFragment},
+    reader::Reader,
+    split,
+};
+use std::collections::{HashMap, VecDeque};
+
+/// A decrypted sample of a track, see [decrypt_samples](decrypt_samples).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// Decrypted elementary stream data, e.g. length prefixed NAL units of H.264 video.
+    pub data: Vec<u8>,
+    /// Decode time in the timescale of the track.
+    pub dts: u64,
+    /// Presentation time in the timescale of the track, i.e. the decode time plus the composition time offset.
+    pub pts: u64,
+    /// Whether the sample is a sync sample (key frame).
+    pub is_sync: bool,
+}
+
+/// Decrypt the samples of a track of a fragmented data stream one fragment at a time, as the iterator advances.
+///
+/// Decode times start at the `tfdt` box of every fragment, or continue from the previous fragment if it has none.
+/// Sample durations, composition time offsets and flags are read from the `trun` boxes, falling back to the
+/// defaults of the `tfhd` and `trex` boxes. Only fragmented data streams are supported, and the iterator
+/// stops after the first error.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream, either a self-contained fragmented file or media segments.
+/// * `init` (optional) - Init segment of `data`, required if `data` doesn't contain a `moov` box.
+/// * `keys` - Keys for decrypting the data stream.
+/// * `track_id` - Track whose samples are returned.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{DecryptOptions, KeyMap};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let samples = mp4decrypt::decrypt_samples(&segment, Some(&init), &keys, 1)
+///     .unwrap()
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert!(samples[0].is_sync);
+/// assert!(samples.windows(2).all(|x| x[0].dts < x[1].dts));
+///
+/// // samples are stored one after another in the mdat box
+/// let options = DecryptOptions::new();
+/// let decrypted_data =
+///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap();
+/// let mdat = samples.iter().flat_map(|x| x.data.clone()).collect::<Vec<_>>();
+/// assert!(decrypted_data.ends_with(&mdat));
+/// ```
+pub fn decrypt_samples<'a>(
+    data: &'a [u8],
+    init: Option<&'a [u8]>,
+    keys: &'a KeyMap,
+    track_id: u32,
+) -> Result<impl Iterator<Item = Result<Sample, Error>> + 'a, Error> {
+    let fragments = split::split_fragments(data)?;
+    let init = match init {
+        Some(x) => x,
+        None if !fragments.init.is_empty() => fragments.init,
+        None => {
+            return Err(Error {
+                msg: "data stream has no init segment (moov box) and no init segment was given."
+                    .to_owned(),
+                err_type: ErrorType::MissingInitSegment,
+                invalid_key: false,
+            });
+        }
+    };
+
+    if !metadata::list_tracks(init)?
+        .iter()
+        .any(|x| x.track_id == track_id)
+    {
+        return Err(Error::new_format(format!(
+            "track {} is not found in the init segment.",
+            track_id
+        )));
+    }
+
+    if fragments.fragments.is_empty() && boxes::find(boxes::boxes(data), b"mdat")?.is_some() {
+        return Err(Error::new_format(
+            "only samples of fragmented data streams can be decrypted.",
+        ));
+    }
+
+    Ok(Samples {
+        fragments: fragments.fragments.into(),
+        init,
+        keys,
+        track_id,
+        trex: read_trex_defaults(init)?,
+        trex_sizes: moof::read_trex_sizes(init)?,
+        samples: VecDeque::new(),
+        next_dts: 0,
+        failed: false,
+    })
+}
+
+/// Iterator of [decrypt_samples](decrypt_samples).
+struct Samples<'a> {
+    /// Fragments which aren't decrypted yet.
+    fragments: VecDeque<&'a [u8]>,
+    init: &'a [u8],
+    keys: &'a KeyMap,
+    track_id: u32,
+    /// Default sample duration and flags of every track.
+    trex: HashMap<u32, (u32, u32)>,
+    trex_sizes: HashMap<u32, u32>,
+    /// Samples of the last decrypted fragment.
+    samples: VecDeque<Sample>,
+    /// Decode time of the sample following the last decrypted fragment.
+    next_dts: u64,
+    failed: bool,
+}
+
+impl Iterator for Samples<'_> {
+    type Item = Result<Sample, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.samples.is_empty() && !self.failed {
+            let fragment = self.fragments.pop_front()?;
+
+            if let Err(e) = self.read_fragment(fragment) {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+
+        self.samples.pop_front().map(Ok)
+    }
+}
+
+impl Samples<'_> {
+    fn read_fragment(&mut self, fragment: &[u8]) -> Result<(), Error> {
+        let decrypted_data = decrypt(fragment, self.keys, Some(self.init))?;
+
+        for moof in boxes::boxes(&decrypted_data) {
+            let moof = moof?;
+
+            if &moof.box_type != b"moof" {
+                continue;
+            }
+
+            for fragment in moof::read_track_fragments(&moof, &self.trex_sizes)? {
+                if fragment.track_id == self.track_id {
+                    self.read_track_fragment(&decrypted_data, &fragment)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_track_fragment(&mut self, data: &[u8], fragment: &TrackFragment) -> Result<(), Error> {
+        let timing = read_fragment_timing(fragment, &self.trex)?;
+
+        if let Some(x) = timing.base_media_decode_time {
+            self.next_dts = x;
+        }
+
+        let samples = fragment.runs.iter().flat_map(|x| &x.samples);
+
+        for (sample, timing) in samples.zip(timing.samples) {
+            let start = sample.offset as usize;
+            let Some(sample_data) = data.get(start..start + sample.size as usize) else {
+                return Err(Error::new_format(format!(
+                    "sample data of track {} lies outside the data stream.",
+                    fragment.track_id
+                )));
+            };
+
+            self.samples.push_back(Sample {
+                data: sample_data.to_vec(),
+                dts: self.next_dts,
+                pts: self
+                    .next_dts
+                    .saturating_add_signed(timing.composition_offset),
+                // sample_is_non_sync_sample
+                is_sync: timing.flags & 0x010000 == 0,
+            });
+            self.next_dts += timing.duration as u64;
+        }
+
+        Ok(())
+    }
+}
+
+/// Timing of the samples of a track fragment.
+pub(crate) struct FragmentTiming {
+    /// Decode time of the first sample from the `tfdt` box.
+    pub(crate) base_media_decode_time: Option<u64>,
+    /// Every sample of the `trun` boxes, in order.
+    pub(crate) samples: Vec<SampleTiming>,
+}
+
+pub(crate) struct SampleTiming {
+    pub(crate) duration: u32,
+    pub(crate) flags: u32,
+    pub(crate) composition_offset: i64,
+}
+
+impl FragmentTiming {
+    pub(crate) fn duration(&self) -> u64 {
+        self.samples.iter().map(|x| x.duration as u64).sum()
+    }
+}
+
+/// Read the timing of the samples of a track fragment from its `tfdt` and `trun` boxes, falling back to the
+/// defaults of the `tfhd` box and `trex` (see [read_trex_defaults](read_trex_defaults)).
+pub(crate) fn read_fragment_timing(
+    fragment: &TrackFragment,
+    trex: &HashMap<u32, (u32, u32)>,
+) -> Result<FragmentTiming, Error> {
+    let (mut default_duration, mut default_flags) =
+        trex.get(&fragment.track_id).copied().unwrap_or_default();
+
+    if let Some(tfhd) = fragment.mp4_box.child(b"tfhd")? {
+        let mut reader = Reader::new(tfhd.payload());
+        let (_, flags) = reader.read_version_flags()?;
+        reader.skip(4)?; // track_ID
+        if flags & 0x01 != 0 {
+            reader.skip(8)?; // base_data_offset
+        }
+        if flags & 0x02 != 0 {
+            reader.skip(4)?; // sample_description_index
+        }
+        if flags & 0x08 != 0 {
+            default_duration = reader.read_u32()?;
+        }
+        if flags & 0x10 != 0 {
+            reader.skip(4)?; // default_sample_size
+        }
+        if flags & 0x20 != 0 {
+            default_flags = reader.read_u32()?;
+        }
+    }
+
+    let base_media_decode_time = match fragment.mp4_box.child(b"tfdt")? {
+        Some(tfdt) => {
+            let mut reader = Reader::new(tfdt.payload());
+            let (version, _) = reader.read_version_flags()?;
+            Some(match version {
+                1 => reader.read_u64()?,
+                _ => reader.read_u32()? as u64,
+            })
+        }
+        None => None,
+    };
+
+    let mut samples = Vec::new();
+
+    for run in &fragment.runs {
+        let mut reader = Reader::new(run.mp4_box.payload());
+        let (version, flags) = reader.read_version_flags()?;
+        reader.skip(4)?; // sample_count
+        if flags & 0x01 != 0 {
+            reader.skip(4)?; // data_offset
+        }
+        let first_sample_flags = if flags & 0x04 != 0 {
+            Some(reader.read_u32()?)
+        } else {
+            None
+        };
+
+        for i in 0..run.samples.len() {
+            let duration = if flags & 0x100 != 0 {
+                reader.read_u32()?
+            } else {
+                default_duration
+            };
+            if flags & 0x200 != 0 {
+                reader.skip(4)?; // sample_size
+            }
+            let sample_flags = match (flags & 0x400 != 0, first_sample_flags) {
+                (true, _) => reader.read_u32()?,
+                (false, Some(x)) if i == 0 => x,
+                (false, _) => default_flags,
+            };
+            let composition_offset = match (flags & 0x800 != 0, version) {
+                (false, _) => 0,
+                (true, 0) => reader.read_u32()? as i64,
+                (true, _) => reader.read_u32()? as i32 as i64,
+            };
+
+            samples.push(SampleTiming {
+                duration,
+                flags: sample_flags,
+                composition_offset,
+            });
+        }
+    }
+
+    Ok(FragmentTiming {
+        base_media_decode_time,
+        samples,
+    })
+}
+
+/// Read default sample durations and flags of every track from the `trex` boxes of a `moov`.
+pub(crate) fn read_trex_defaults(init: &[u8]) -> Result<HashMap<u32, (u32, u32)>, Error> {
+    let mut defaults = HashMap::new();
+
+    let Some(mvex) = boxes::find(boxes::boxes(init), b"moov")?
+        .map(|x| x.child(b"mvex"))
+        .transpose()?
+        .flatten()
+    else {
+        return Ok(defaults);
+    };
+
+    for trex in mvex.children() {
+        let trex = trex?;
+
+        if &trex.box_type == b"trex" {
+            let mut reader = Reader::new(trex.payload());
+            reader.read_version_flags()?;
+            let track_id = reader.read_u32()?;
+            reader.skip(4)?; // default_sample_description_index
+            let duration = reader.read_u32()?;
+            reader.skip(4)?; // default_sample_size
+            defaults.insert(track_id, (duration, reader.read_u32()?));
+        }
+    }
+
+    Ok(defaults)
+}
@@ -0,0 +1,211 @@
+use std::sync::OnceLock;
+
+/// Protection scheme of an encrypted track, as declared in the `schm` box.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Scheme {
+    /// MPEG-CENC AES-CTR full sample encryption.
+    Cenc,
+    /// MPEG-CENC AES-CTR subsample pattern encryption.
+    Cens,
+    /// MPEG-CENC AES-CBC full sample encryption.
+    Cbc1,
+    /// MPEG-CENC AES-CBC subsample pattern encryption.
+    Cbcs,
+    /// Protected Interoperable File Format (Smooth Streaming), also assumed for tracks having
+    /// a PIFF track encryption `uuid` box without a `schm` box.
+    Piff,
+    /// OMA DCF.
+    OmaDcf,
+    /// ISMACryp.
+    IsmaCryp,
+    /// Marlin IPMP AES-CBC track key.
+    MarlinAcbc,
+    /// Marlin IPMP AES-CTR group key.
+    MarlinAcgk,
+}
+
+/// Protection schemes known to this crate, whether or not the linked Bento4 can decrypt them.
+const SCHEMES: [Scheme; 9] = [
+    Scheme::Cenc,
+    Scheme::Cens,
+    Scheme::Cbc1,
+    Scheme::Cbcs,
+    Scheme::Piff,
+    Scheme::OmaDcf,
+    Scheme::IsmaCryp,
+    Scheme::MarlinAcbc,
+    Scheme::MarlinAcgk,
+];
+
+impl Scheme {
+    /// Four-cc of the scheme type.
+    pub const fn fourcc(&self) -> [u8; 4] {
+        match self {
+            Self::Cenc => *b"cenc",
+            Self::Cens => *b"cens",
+            Self::Cbc1 => *b"cbc1",
+            Self::Cbcs => *b"cbcs",
+            Self::Piff => *b"piff",
+            Self::OmaDcf => *b"odkm",
+            Self::IsmaCryp => *b"iAEC",
+            Self::MarlinAcbc => *b"ACBC",
+            Self::MarlinAcgk => *b"ACGK",
+        }
+    }
+
+    /// Map a scheme type four-cc to a scheme.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::Scheme;
+    ///
+    /// assert_eq!(Scheme::from_fourcc(b"cbcs"), Some(Scheme::Cbcs));
+    /// assert_eq!(Scheme::from_fourcc(b"adkm"), None);
+    /// ```
+    pub fn from_fourcc(fourcc: &[u8; 4]) -> Option<Self> {
+        SCHEMES.into_iter().find(|x| &x.fourcc() == fourcc)
+    }
+}
+
+/// Protection scheme as declared in a `schm` box, along with its version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemeInfo {
+    /// Known scheme of `scheme_type`, `None` for other schemes.
+    pub scheme: Option<Scheme>,
+    pub scheme_type: [u8; 4],
+    /// Version of the scheme, e.g. `0x00010000` (1.0) for `cenc` and `cbcs`.
+    pub scheme_version: u32,
+    /// Flags of the `schm` box, `scheme_uri` is present if the lowest bit is set.
+    pub flags: u32,
+    pub scheme_uri: Option<String>,
+}
+
+impl SchemeInfo {
+    /// Major and minor version of the scheme, from the upper and lower 16 bits of `scheme_version`.
+    pub fn version(&self) -> (u16, u16) {
+        (
+            (self.scheme_version >> 16) as u16,
+            self.scheme_version as u16,
+        )
+    }
+}
+
+/// Features of this build.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Maximum size of a data stream which can be decrypted at once.
+    pub max_stream_size: u64,
+    /// Protection schemes which can be decrypted, see [supported_schemes](supported_schemes).
+    pub schemes: &'static [Scheme],
+    /// True if input files are memory-mapped (`mmap` feature).
+    pub has_mmap: bool,
+}
+
+/// Protection schemes which the linked Bento4 can decrypt, i.e. the scheme types declared by its headers
+/// at build time.
+///
+/// # Example
+///
+/// ```
+/// assert!(mp4decrypt::supported_schemes().contains(&mp4decrypt::Scheme::Cenc));
+/// ```
+pub fn supported_schemes() -> &'static [Scheme] {
+    static SUPPORTED: OnceLock<Vec<Scheme>> = OnceLock::new();
+
+    SUPPORTED.get_or_init(|| {
+        let fourccs = env!("MP4DECRYPT_BENTO4_SCHEMES").split(',');
+        SCHEMES
+            .into_iter()
+            .filter(|x| fourccs.clone().any(|y| y.as_bytes() == x.fourcc()))
+            .collect()
+    })
+}
+
+/// Query the features of this build.
+///
+/// # Example
+///
+/// ```
+/// let capabilities = mp4decrypt::capabilities();
+/// assert!(capabilities.schemes.contains(&mp4decrypt::Scheme::Cbcs));
+/// ```
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        max_stream_size: u32::MAX as u64,
+        schemes: supported_schemes(),
+        has_mmap: cfg!(feature = "mmap"),
+    }
+}
+
+/// Source of the linked Bento4 library, see [linkage_info](linkage_info).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Linkage {
+    /// Built from the vendored source of `bento4-src` crate.
+    Vendored { version: String },
+    /// Prebuilt library found in `BENTO4_DIR` (or its target-prefixed variant) at build time.
+    System { path: Option<String> },
+}
+
+/// Source of the Bento4 library linked at build time, for diagnosing build differences.
+///
+/// # Example
+///
+/// ```
+/// match mp4decrypt::linkage_info() {
+///     mp4decrypt::Linkage::Vendored { version } => println!("vendored bento4 v{}", version),
+///     mp4decrypt::Linkage::System { path } => println!("system bento4 at {:?}", path),
+/// }
+/// ```
+pub fn linkage_info() -> Linkage {
+    match env!("MP4DECRYPT_BENTO4_LINKAGE") {
+        "vendored" => Linkage::Vendored {
+            version: option_env!("MP4DECRYPT_BENTO4_VERSION")
+                .unwrap_or_default()
+                .to_owned(),
+        },
+        _ => Linkage::System {
+            path: option_env!("MP4DECRYPT_BENTO4_DIR").map(|x| x.to_owned()),
+        },
+    }
+}
+
+/// Version of this crate.
+pub const fn crate_version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// Version and linkage of this build, for including in bug reports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Version of this crate, see [crate_version](crate_version).
+    pub crate_version: &'static str,
+    /// Version of the linked Bento4 library, as reported by it (`AP4_VERSION_STRING`).
+    pub bento4_version: String,
+    /// Source of the linked Bento4 library, see [linkage_info](linkage_info).
+    pub linkage: Linkage,
+    /// Target triple which this crate was built for.
+    pub target: &'static str,
+}
+
+/// Query the version and linkage of this build.
+///
+/// # Example
+///
+/// ```
+/// let info = mp4decrypt::build_info();
+/// assert_eq!(info.crate_version, mp4decrypt::crate_version());
+/// assert!(!info.bento4_version.is_empty());
+/// println!("{:?}", info);
+/// ```
+pub fn build_info() -> BuildInfo {
+    let bento4_version = unsafe { std::ffi::CStr::from_ptr(crate::bento4_version_string()) };
+
+    BuildInfo {
+        crate_version: crate_version(),
+        bento4_version: bento4_version.to_string_lossy().into_owned(),
+        linkage: linkage_info(),
+        target: env!("MP4DECRYPT_TARGET"),
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{
+    Error, decrypt_key_pairs,
+    keys::{self, KeyPairs},
+    protection,
+};
+use zeroize::Zeroizing;
+
+/// Decrypt encrypted mp4 data stream with keys requested on demand, e.g. from an HSM or an OS keychain,
+/// so that they are kept in process memory only while decrypting.
+///
+/// The provider is called once for every KID referenced by the `tenc` and `seig` sample group boxes
+/// of `init` and `data` (see [collect_all_kids](crate::collect_all_kids)), and tracks whose key isn't
+/// provided are left encrypted. Returned keys and the hex encoded copies prepared for Bento4 are zeroized
+/// once decryption returns. Bento4 keeps its own copy of the keys while decrypting, which is freed without
+/// being zeroized.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream.
+/// * `init` (optional) - Init segment of `data`, same as `fragments_info` of [mp4decrypt](crate::mp4decrypt).
+/// * `provider` - Key of a KID, or `None` if it isn't available.
+///
+/// # Example
+///
+/// ```
+/// use zeroize::Zeroizing;
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let decrypted_data = mp4decrypt::decrypt_with_secure_keys(&segment, Some(&init), |kid| {
+///     // look up the key in a secure store
+///     (kid == &mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3da").unwrap())
+///         .then(|| Zeroizing::new(mp4decrypt::parse_hex16("100b6c20940f779a4589152b57d2dacb").unwrap()))
+/// })
+/// .unwrap();
+///
+/// let mut keys = mp4decrypt::KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+/// let options = mp4decrypt::DecryptOptions::new();
+/// assert_eq!(
+///     decrypted_data,
+///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap()
+/// );
+/// ```
+pub fn decrypt_with_secure_keys<F: Fn(&[u8; 16]) -> Option<Zeroizing<[u8; 16]>>>(
+    data: &[u8],
+    init: Option<&[u8]>,
+    provider: F,
+) -> Result<Vec<u8>, Error> {
+    let mut kids = protection::collect_all_kids(data)?;
+    if let Some(init) = init {
+        kids.extend(protection::collect_all_kids(init)?);
+    }
+
+    let mut kids = kids.into_iter().collect::<Vec<_>>();
+    kids.sort();
+
+    let mut key_pairs = KeyPairs::default();
+
+    for kid in &kids {
+        if let Some(key) = provider(kid) {
+            key_pairs.0.push((keys::to_hex(kid), keys::to_hex(&*key)));
+        }
+    }
+
+    decrypt_key_pairs(data, &key_pairs, init)
+}
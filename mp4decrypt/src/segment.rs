@@ -0,0 +1,146 @@
+use crate::{Error, KeyMap, decrypt_raw, groups::ClearSamples, keys::KeyStrings, pssh, validate};
+use std::{collections::HashSet, sync::Arc};
+
+/// Decryption context for the media segments of a single representation.
+///
+/// The init segment is parsed once when the context is created and reused for every segment.
+/// Keys can be replaced with [set_keys](Self::set_keys) between segments, which is useful for
+/// live streams where keys rotate per segment.
+///
+/// Contexts created with [with_shared_init](Self::with_shared_init) hold the init segment as an [Arc],
+/// so that many contexts (e.g. one per key set or per thread) share a single copy of it.
+///
+/// # Thread Safety
+///
+/// `SegmentDecryptor` is [Send] and [Sync]. Decrypting and updating keys take `&mut self`,
+/// so a key update can never race with a decryption in progress. Wrap it in a
+/// [Mutex](std::sync::Mutex) to share a single context between threads.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::{KeyMap, SegmentDecryptor};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("init.mp4").unwrap();
+/// let mut decryptor = SegmentDecryptor::new(&init, &keys).unwrap();
+/// let mut decrypted_data = decryptor.decrypt_init().unwrap();
+///
+/// for i in 0..10 {
+///     let segment = std::fs::read(format!("segment_{}.m4s", i)).unwrap();
+///     decrypted_data.extend(decryptor.decrypt_next(&segment).unwrap());
+///
+///     // the next segment is protected with a new key
+///     let mut keys = KeyMap::new();
+///     keys.insert("1", "100b6c20940f779a4589152b57d2dacb").unwrap();
+///     decryptor.set_keys(&keys).unwrap();
+/// }
+/// ```
+pub struct SegmentDecryptor {
+    init: Arc<[u8]>,
+    track_ids: Option<HashSet<u32>>,
+    c_kids: KeyStrings,
+    c_keys: KeyStrings,
+}
+
+impl SegmentDecryptor {
+    /// Create a new context from an init segment (`ftyp` + `moov`).
+    pub fn new(init: &[u8], keys: &KeyMap) -> Result<Self, Error> {
+        Self::with_shared_init(Arc::from(init), keys)
+    }
+
+    /// Create a new context from a shared init segment (`ftyp` + `moov`), without copying it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use mp4decrypt::{KeyMap, SegmentDecryptor};
+    /// use std::sync::Arc;
+    ///
+    /// let mut keys = KeyMap::new();
+    /// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+    ///     .unwrap();
+    ///
+    /// let init: Arc<[u8]> = std::fs::read("examples/sample/init.mp4").unwrap().into();
+    /// let first = SegmentDecryptor::with_shared_init(init.clone(), &keys).unwrap();
+    /// let second = SegmentDecryptor::with_shared_init(first.shared_init(), &keys).unwrap();
+    /// assert_eq!(Arc::strong_count(&init), 3);
+    /// ```
+    pub fn with_shared_init(init: Arc<[u8]>, keys: &KeyMap) -> Result<Self, Error> {
+        pssh::check_drm_system(&init)?;
+
+        let mut decryptor = Self {
+            track_ids: validate::read_init_track_ids(&init),
+            init,
+            c_kids: KeyStrings::default(),
+            c_keys: KeyStrings::default(),
+        };
+
+        decryptor.set_keys(keys)?;
+        Ok(decryptor)
+    }
+
+    /// Init segment of this context, which can be shared with other contexts.
+    pub fn shared_init(&self) -> Arc<[u8]> {
+        self.init.clone()
+    }
+
+    /// Replace the keys used for decrypting the next segments.
+    pub fn set_keys(&mut self, keys: &KeyMap) -> Result<(), Error> {
+        validate::check_sample_entries(&self.init, &keys.key_pairs())?;
+
+        let mut c_kids = KeyStrings::default();
+        let mut c_keys = KeyStrings::default();
+
+        for (id, key) in keys.key_pairs().iter() {
+            c_kids.push(id)?;
+            c_keys.push(key)?;
+        }
+
+        self.c_kids = c_kids;
+        self.c_keys = c_keys;
+        Ok(())
+    }
+
+    /// Decrypt the init segment, which should be written before any decrypted segment.
+    pub fn decrypt_init(&self) -> Result<Vec<u8>, Error> {
+        self.decrypt(&self.init, None)
+    }
+
+    /// Decrypt the next media segment (`moof` + `mdat`) using the current keys.
+    pub fn decrypt_next(&mut self, segment: &[u8]) -> Result<Vec<u8>, Error> {
+        self.decrypt_segment(segment)
+    }
+
+    /// Decrypt a media segment using the current keys, shared by parallel decryption.
+    pub(crate) fn decrypt_segment(&self, segment: &[u8]) -> Result<Vec<u8>, Error> {
+        if let Some(track_ids) = &self.track_ids {
+            validate::check_track_ids(segment, track_ids)?;
+        }
+
+        validate::check_sample_encryption(segment, &self.init)?;
+        self.decrypt(segment, Some(&self.init))
+    }
+
+    fn decrypt(&self, data: &[u8], fragments_info: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+        let c_kids = self
+            .c_kids
+            .0
+            .iter()
+            .map(|x| x.as_c_str())
+            .collect::<Vec<_>>();
+        let c_keys = self
+            .c_keys
+            .0
+            .iter()
+            .map(|x| x.as_c_str())
+            .collect::<Vec<_>>();
+        let clear_samples = ClearSamples::find(data, fragments_info)?;
+        let mut decrypted_data = decrypt_raw(data, &c_kids, &c_keys, fragments_info)?;
+        clear_samples.restore(data, fragments_info, &mut decrypted_data)?;
+        Ok(decrypted_data)
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{Error, boxes, reader::Reader};
+
+/// Segment index parsed from a `sidx` box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentIndex {
+    pub reference_id: u32,
+    pub timescale: u32,
+    pub earliest_presentation_time: u64,
+    /// Fragments referenced by this index, in presentation order.
+    pub entries: Vec<SidxEntry>,
+}
+
+/// A fragment referenced by a `sidx` box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SidxEntry {
+    /// Offset of the referenced fragment from the start of the data containing the `sidx` box.
+    pub offset: u64,
+    /// Size of the referenced fragment in bytes.
+    pub size: u32,
+    /// Duration of the referenced fragment in `timescale` units.
+    pub duration: u32,
+    /// True if this entry references another `sidx` box instead of media.
+    pub references_index: bool,
+    pub starts_with_sap: bool,
+    pub sap_type: u8,
+    pub sap_delta_time: u32,
+}
+
+impl SegmentIndex {
+    /// Duration of an entry in seconds.
+    pub fn duration_secs(&self, entry: &SidxEntry) -> f64 {
+        entry.duration as f64 / self.timescale.max(1) as f64
+    }
+}
+
+/// Read the segment index from the first top level `sidx` box.
+/// Both version 0 (32-bit) and version 1 (64-bit) boxes are supported.
+///
+/// Returns `None` if the data doesn't contain a `sidx` box.
+pub fn read_sidx(data: &[u8]) -> Result<Option<SegmentIndex>, Error> {
+    let Some(sidx) = boxes::find(boxes::boxes(data), b"sidx")? else {
+        return Ok(None);
+    };
+
+    let mut reader = Reader::new(sidx.payload());
+    let (version, _) = reader.read_version_flags()?;
+    let reference_id = reader.read_u32()?;
+    let timescale = reader.read_u32()?;
+
+    let (earliest_presentation_time, first_offset) = if version == 0 {
+        (reader.read_u32()? as u64, reader.read_u32()? as u64)
+    } else {
+        (reader.read_u64()?, reader.read_u64()?)
+    };
+
+    reader.skip(2)?; // reserved
+    let reference_count = reader.read_u16()?;
+
+    let invalid_offset = || {
+        Error::new_format(format!(
+            "sidx box at offset {} has an invalid first offset.",
+            sidx.offset
+        ))
+    };
+    // Offsets are relative to the first byte after the sidx box.
+    let mut offset = (sidx.end() as u64)
+        .checked_add(first_offset)
+        .ok_or_else(invalid_offset)?;
+    let mut entries = Vec::with_capacity(reference_count as usize);
+
+    for _ in 0..reference_count {
+        let reference = reader.read_u32()?;
+        let duration = reader.read_u32()?;
+        let sap = reader.read_u32()?;
+        let size = reference & 0x7fff_ffff;
+
+        entries.push(SidxEntry {
+            offset,
+            size,
+            duration,
+            references_index: reference >> 31 == 1,
+            starts_with_sap: sap >> 31 == 1,
+            sap_type: ((sap >> 28) & 0x7) as u8,
+            sap_delta_time: sap & 0x0fff_ffff,
+        });
+        offset = offset.checked_add(size as u64).ok_or_else(invalid_offset)?;
+    }
+
+    Ok(Some(SegmentIndex {
+        reference_id,
+        timescale,
+        earliest_presentation_time,
+        entries,
+    }))
+}
@@ -0,0 +1,240 @@
+use crate::{
+    Error,
+    boxes::{self, Mp4Box},
+    metadata,
+    reader::Reader,
+    rewrite::{self, Replacement},
+};
+
+/// Read the original format of the sample entries of a track from the `sinf/frma` box, i.e. the sample entry
+/// type which replaces `encv`, `enca` etc. once the track is decrypted.
+///
+/// If a sample entry holds several `sinf` boxes of different protection schemes, the original format is
+/// read from the one used for decryption, the first one whose scheme is supported. The sample entry type is
+/// returned as it is if the track isn't protected.
+///
+/// # Arguments
+///
+/// * `init` - Init segment or self-contained file holding the track.
+/// * `track_id` - Track whose original format is returned.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let original_format = mp4decrypt::read_original_format(&init, 1).unwrap();
+/// assert_eq!(&original_format, b"avc1");
+/// ```
+pub fn read_original_format(init: &[u8], track_id: u32) -> Result<[u8; 4], Error> {
+    let Some(moov) = boxes::find(boxes::boxes(init), b"moov")? else {
+        return Err(Error::new_format("init segment has no moov box."));
+    };
+
+    for trak in moov.children() {
+        let trak = trak?;
+
+        if &trak.box_type != b"trak" || metadata::read_trak(&trak)?.track_id != track_id {
+            continue;
+        }
+
+        let entries = read_stsd_entries(&trak)?;
+
+        for entry in &entries {
+            let Some(sinf) = metadata::read_sinf(entry)? else {
+                continue;
+            };
+
+            let Some(frma) = sinf.child(b"frma")? else {
+                return Err(Error::new_format(format!(
+                    "{} sample entry of track {} has no frma box.",
+                    boxes::fourcc(&entry.box_type),
+                    track_id
+                )));
+            };
+
+            return Reader::new(frma.payload()).read_array::<4>();
+        }
+
+        return entries.first().map(|x| x.box_type).ok_or_else(|| {
+            Error::new_format(format!("track {} has no sample entries.", track_id))
+        });
+    }
+
+    Err(Error::new_format(format!(
+        "track {} is not found in the init segment.",
+        track_id
+    )))
+}
+
+/// Sample entries of a track.
+fn read_stsd_entries<'a>(trak: &Mp4Box<'a>) -> Result<Vec<Mp4Box<'a>>, Error> {
+    match read_stsd(trak)? {
+        // version, flags and entry_count
+        Some(stsd) => stsd.children_after(8).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn read_stsd<'a>(trak: &Mp4Box<'a>) -> Result<Option<Mp4Box<'a>>, Error> {
+    [b"mdia", b"minf", b"stbl", b"stsd"]
+        .iter()
+        .try_fold(Some(*trak), |parent, x| match parent {
+            Some(parent) => parent.child(x),
+            None => Ok(None),
+        })
+}
+
+/// A sample entry along with the positions of its `moov`, `trak` and the entry itself, and its `stsd` box.
+type SampleEntry<'a> = ((usize, usize, usize), Mp4Box<'a>, Mp4Box<'a>);
+
+/// Every sample entry of a data stream.
+fn sample_entries(data: &[u8]) -> Result<Vec<SampleEntry<'_>>, Error> {
+    let mut entries = Vec::new();
+    let moovs = boxes::boxes(data).filter(|x| !matches!(x, Ok(x) if &x.box_type != b"moov"));
+
+    for (i, moov) in moovs.enumerate() {
+        let traks = moov?
+            .children()
+            .filter(|x| !matches!(x, Ok(x) if &x.box_type != b"trak"));
+
+        for (j, trak) in traks.enumerate() {
+            let Some(stsd) = read_stsd(&trak?)? else {
+                continue;
+            };
+
+            for (k, entry) in stsd.children_after(8).enumerate() {
+                entries.push(((i, j, k), stsd, entry?));
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// `sinf` boxes of a sample entry.
+fn sinf_boxes<'a>(entry: &Mp4Box<'a>, fields_size: usize) -> Result<Vec<Mp4Box<'a>>, Error> {
+    entry
+        .children_after(fields_size)
+        .filter(|x| !matches!(x, Ok(x) if &x.box_type != b"sinf"))
+        .collect()
+}
+
+/// Move the preferred `sinf` box of every protected sample entry in front of the others, since Bento4 only
+/// reads the first `sinf` box. Returns `None` if nothing is moved or `data` can't be parsed.
+pub(crate) fn prefer_supported_sinf(data: &[u8]) -> Option<Vec<u8>> {
+    let mut reordered = None;
+
+    for (_, _, entry) in sample_entries(data).ok()? {
+        let Some(fields_size) = metadata::protected_entry_fields_size(&entry.box_type) else {
+            continue;
+        };
+
+        let sinfs = sinf_boxes(&entry, fields_size).ok()?;
+        let preferred = metadata::read_sinf(&entry).ok()??;
+
+        if sinfs.first()?.offset == preferred.offset {
+            continue;
+        }
+
+        // Boxes from the first `sinf` box up to the preferred one, which keep their sizes.
+        let first = sinfs[0].offset;
+        let moved = [preferred.data, &data[first..preferred.offset]].concat();
+        let reordered = reordered.get_or_insert_with(|| data.to_vec());
+        reordered[first..preferred.end()].copy_from_slice(&moved);
+    }
+
+    reordered
+}
+
+/// Sample entries holding several `sinf` boxes, see [MultipleSinf::strip](MultipleSinf::strip).
+#[derive(Default)]
+pub(crate) struct MultipleSinf(Vec<((usize, usize, usize), usize)>);
+
+impl MultipleSinf {
+    /// Find the protected sample entries of `data` holding several `sinf` boxes.
+    pub(crate) fn find(data: &[u8]) -> Self {
+        let mut entries = Vec::new();
+
+        for (position, _, entry) in sample_entries(data).unwrap_or_default() {
+            if let Some(fields_size) = metadata::protected_entry_fields_size(&entry.box_type)
+                && sinf_boxes(&entry, fields_size).is_ok_and(|x| x.len() > 1)
+            {
+                entries.push((position, fields_size));
+            }
+        }
+
+        Self(entries)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Remove the `sinf` boxes Bento4 leaves behind in decrypted sample entries, which only removes the first
+    /// one. Sample entries which are still protected, e.g. of tracks without keys, are left as they are.
+    pub(crate) fn strip(
+        &self,
+        decrypted_data: &mut Vec<u8>,
+        fragments_info: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let mut replacements = Vec::new();
+        // Removed bytes of every `stsd` box, which isn't resized by `rewrite::replace`.
+        let mut stsds = Vec::<(Mp4Box, usize)>::new();
+
+        for (position, stsd, entry) in sample_entries(decrypted_data)? {
+            let Some((_, fields_size)) = self.0.iter().find(|(x, _)| *x == position) else {
+                continue;
+            };
+
+            if metadata::protected_entry_fields_size(&entry.box_type).is_some() {
+                continue;
+            }
+
+            let sinfs = sinf_boxes(&entry, *fields_size)?;
+            let removed = sinfs.iter().map(|x| x.data.len()).sum::<usize>();
+
+            if removed == 0 {
+                continue;
+            }
+
+            for sinf in &sinfs {
+                replacements.push(Replacement::new(sinf, Vec::new()));
+            }
+
+            replacements.push(shrink(&entry, removed));
+
+            match stsds.iter_mut().find(|(x, _)| x.offset == stsd.offset) {
+                Some((_, x)) => *x += removed,
+                None => stsds.push((stsd, removed)),
+            }
+        }
+
+        for (stsd, removed) in &stsds {
+            replacements.push(shrink(stsd, *removed));
+        }
+
+        rewrite::replace(decrypted_data, fragments_info, replacements)
+    }
+}
+
+/// Rewrite the size of a box which isn't a container, once `removed` bytes of its children are removed.
+fn shrink(mp4_box: &Mp4Box, removed: usize) -> Replacement {
+    let size = (mp4_box.data.len() - removed) as u64;
+
+    // largesize
+    let (offset, data) = if mp4_box.data[..4] == [0, 0, 0, 1] {
+        (mp4_box.offset + 8, size.to_be_bytes().to_vec())
+    } else {
+        (mp4_box.offset, (size as u32).to_be_bytes().to_vec())
+    };
+
+    Replacement {
+        offset,
+        size: data.len(),
+        data,
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{
+    DecryptOptions, DecryptReport, Error, ErrorType, KeyMap, decrypt_key_pairs_into, iv,
+    needs_post_processing, post_process, prepare_report,
+};
+use std::{fmt, io};
+
+/// Receiver of the decrypted data stream, for storing it outside of a [Vec] owned by this crate
+/// (e.g. in a custom arena).
+///
+/// Decrypted data is passed in one or more chunks, in order.
+pub trait BufSink {
+    /// Store the next chunk of decrypted data. Returning an error stops storing further chunks
+    /// and it is returned from the decrypt function, as [ErrorType::OutOfMemory](crate::ErrorType::OutOfMemory)
+    /// for [io::ErrorKind::OutOfMemory] errors.
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()>;
+}
+
+/// Memory is reserved fallibly, failing with [io::ErrorKind::OutOfMemory] instead of aborting.
+impl BufSink for Vec<u8> {
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.try_reserve(chunk.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                format!("failed to allocate {} bytes.", chunk.len()),
+            )
+        })?;
+        self.extend_from_slice(chunk);
+        Ok(())
+    }
+}
+
+/// Decrypt encrypted mp4 data stream using given keys, passing the decrypted data to a sink
+/// instead of returning it.
+///
+/// See [mp4decrypt](crate::mp4decrypt) for details about the arguments.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::{BufSink, KeyMap};
+///
+/// /// Fixed capacity buffer.
+/// struct Arena {
+///     buf: [u8; 4096],
+///     len: usize,
+/// }
+///
+/// impl BufSink for Arena {
+///     fn write_chunk(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+///         let end = self.len + chunk.len();
+///         self.buf
+///             .get_mut(self.len..end)
+///             .ok_or_else(|| std::io::Error::other("arena is full"))?
+///             .copy_from_slice(chunk);
+///         self.len = end;
+///         Ok(())
+///     }
+/// }
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let mut arena = Arena { buf: [0; 4096], len: 0 };
+/// mp4decrypt::mp4decrypt_into(&[0, 0, 0, 112], &keys, None, &mut arena).unwrap();
+/// ```
+pub fn mp4decrypt_into<S: BufSink>(
+    data: &[u8],
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+    sink: &mut S,
+) -> Result<(), Error> {
+    decrypt_key_pairs_into(data, &keys.key_pairs(), fragments_info, None, sink)
+}
+
+/// Decrypt encrypted mp4 data stream using given keys and options, passing the decrypted data to a sink
+/// in chunks of [DecryptOptions::chunk_size](DecryptOptions::chunk_size) and returning a report about the decryption.
+///
+/// See [mp4decrypt_with_report](crate::mp4decrypt_with_report) for details. If
+/// [DecryptOptions::validate_structure](DecryptOptions::validate_structure) is enabled, the complete output is
+/// validated before anything is passed to the sink. The complete output is buffered as well by options which
+/// post-process it, i.e. [keep_emsg](DecryptOptions::keep_emsg) disabled, [strip_pssh](DecryptOptions::strip_pssh),
+/// [normalize_box_versions](DecryptOptions::normalize_box_versions) and
+/// [compute_crc32](DecryptOptions::compute_crc32). Removed or grown boxes move the sample offsets and drop
+/// the index boxes (`sidx`, `ssix` and `mfra`), which would no longer match the output.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{BufSink, DecryptOptions, KeyMap};
+///
+/// #[derive(Default)]
+/// struct Chunks(Vec<usize>);
+///
+/// impl BufSink for Chunks {
+///     fn write_chunk(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+///         self.0.push(chunk.len());
+///         Ok(())
+///     }
+/// }
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let options = DecryptOptions::new().chunk_size(512);
+/// let mut chunks = Chunks::default();
+/// mp4decrypt::mp4decrypt_into_with_options(&init, &keys, None, &options, &mut chunks).unwrap();
+///
+/// let (last, rest) = chunks.0.split_last().unwrap();
+/// assert!(rest.iter().all(|x| *x == 512));
+/// assert!(*last <= 512);
+/// ```
+pub fn mp4decrypt_into_with_options<S: BufSink>(
+    data: &[u8],
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+    options: &DecryptOptions,
+    sink: &mut S,
+) -> Result<DecryptReport, Error> {
+    let (key_pairs, mut report) = prepare_report(data, keys, fragments_info, options)?;
+    let (data, fragments_info) =
+        iv::override_constant_ivs(data, fragments_info, &options.constant_ivs)?;
+    let (data, fragments_info) = (data.as_ref(), fragments_info.as_deref());
+    let mut sink = ChunkedSink::new(sink, options.chunk_size);
+
+    if needs_post_processing(options) {
+        let mut decrypted_data = Vec::new();
+        decrypt_key_pairs_into(
+            data,
+            &key_pairs,
+            fragments_info,
+            options.progress.as_ref(),
+            &mut LimitedSink::new(&mut decrypted_data, options.max_output_bytes),
+        )?;
+
+        post_process(&mut decrypted_data, fragments_info, options, &mut report)?;
+
+        sink.write_chunk(&decrypted_data).map_err(Error::new_io)?;
+    } else {
+        decrypt_key_pairs_into(
+            data,
+            &key_pairs,
+            fragments_info,
+            options.progress.as_ref(),
+            &mut LimitedSink::new(&mut sink, options.max_output_bytes),
+        )?;
+    }
+
+    sink.finish().map_err(Error::new_io)?;
+    Ok(report)
+}
+
+/// Sink which fails once more than `limit` bytes would be stored in another sink,
+/// see [DecryptOptions::max_output_bytes](DecryptOptions::max_output_bytes).
+pub(crate) struct LimitedSink<'a> {
+    sink: &'a mut dyn BufSink,
+    limit: Option<usize>,
+    written: usize,
+}
+
+impl<'a> LimitedSink<'a> {
+    pub(crate) fn new(sink: &'a mut dyn BufSink, limit: Option<usize>) -> Self {
+        Self {
+            sink,
+            limit,
+            written: 0,
+        }
+    }
+}
+
+impl BufSink for LimitedSink<'_> {
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if let Some(limit) = self.limit
+            && self.written + chunk.len() > limit
+        {
+            return Err(io::Error::other(OutputLimit(limit)));
+        }
+
+        self.written += chunk.len();
+        self.sink.write_chunk(chunk)
+    }
+}
+
+/// I/O error payload of [LimitedSink](LimitedSink), which is turned into
+/// [ErrorType::OutputTooLarge](crate::ErrorType::OutputTooLarge).
+#[derive(Debug)]
+pub(crate) struct OutputLimit(pub(crate) usize);
+
+impl fmt::Display for OutputLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "output exceeds {} bytes", self.0)
+    }
+}
+
+impl std::error::Error for OutputLimit {}
+
+impl OutputLimit {
+    pub(crate) fn to_error(&self) -> Error {
+        Error {
+            msg: format!(
+                "decrypted data stream exceeds the maximum output size of {} bytes.",
+                self.0
+            ),
+            err_type: ErrorType::OutputTooLarge {
+                limit: self.0 as u64,
+            },
+            invalid_key: false,
+        }
+    }
+}
+
+/// Sink which coalesces or splits chunks into fixed size chunks before passing them to another sink.
+pub(crate) struct ChunkedSink<'a> {
+    sink: &'a mut dyn BufSink,
+    /// 0 passes chunks as is.
+    chunk_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl<'a> ChunkedSink<'a> {
+    pub(crate) fn new(sink: &'a mut dyn BufSink, chunk_size: usize) -> Self {
+        Self {
+            sink,
+            chunk_size,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Pass the remaining buffered data as the last chunk.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.sink.write_chunk(&self.buffer)
+    }
+}
+
+impl BufSink for ChunkedSink<'_> {
+    fn write_chunk(&mut self, mut chunk: &[u8]) -> io::Result<()> {
+        if self.chunk_size == 0 {
+            return self.sink.write_chunk(chunk);
+        }
+
+        if !self.buffer.is_empty() {
+            let len = (self.chunk_size - self.buffer.len()).min(chunk.len());
+            self.buffer.extend_from_slice(&chunk[..len]);
+            chunk = &chunk[len..];
+
+            if self.buffer.len() < self.chunk_size {
+                return Ok(());
+            }
+
+            self.sink.write_chunk(&self.buffer)?;
+            self.buffer.clear();
+        }
+
+        let mut chunks = chunk.chunks_exact(self.chunk_size);
+
+        for x in &mut chunks {
+            self.sink.write_chunk(x)?;
+        }
+
+        self.buffer.extend_from_slice(chunks.remainder());
+        Ok(())
+    }
+}
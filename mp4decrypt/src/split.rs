@@ -0,0 +1,397 @@
+use crate::{
+    Error, ErrorType, KeyMap,
+    boxes::{self, Mp4Box},
+    decrypt,
+};
+
+/// Extract the init segment of a fragmented file, i.e. every box up to and including the `moov` box
+/// (usually `ftyp` + `moov`). It can be used as `fragments_info` for decrypting other segments.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let mut data = init.clone();
+/// data.extend(std::fs::read("examples/sample/segment_0.m4s").unwrap());
+///
+/// assert_eq!(mp4decrypt::extract_init(&data).unwrap(), init);
+/// assert!(mp4decrypt::extract_init(&data[init.len()..]).is_err());
+/// ```
+pub fn extract_init(data: &[u8]) -> Result<Vec<u8>, Error> {
+    for mp4_box in boxes::boxes(data) {
+        let mp4_box = mp4_box?;
+
+        match &mp4_box.box_type {
+            b"moov" => return Ok(data[..mp4_box.end()].to_vec()),
+            b"moof" | b"mdat" => break,
+            _ => (),
+        }
+    }
+
+    Err(Error::new_format("no moov box found in the data stream."))
+}
+
+/// Split concatenated periods (e.g. of a multi-period DASH stream), where every period after the first one
+/// starts with a new `ftyp` or `moov` box following the fragments of the previous period.
+///
+/// Data which can't be parsed is split only up to the first malformed box.
+pub(crate) fn split_periods(data: &[u8]) -> Vec<&[u8]> {
+    let mut periods = Vec::new();
+    let mut start = 0;
+    let mut has_fragments = false;
+
+    for mp4_box in boxes::boxes(data) {
+        let Ok(mp4_box) = mp4_box else {
+            break;
+        };
+
+        match &mp4_box.box_type {
+            b"moof" => has_fragments = true,
+            b"ftyp" | b"moov" if has_fragments => {
+                periods.push(&data[start..mp4_box.offset]);
+                start = mp4_box.offset;
+                has_fragments = false;
+            }
+            _ => (),
+        }
+    }
+
+    periods.push(&data[start..]);
+    periods
+}
+
+/// Init segment and fragments of a fragmented file.
+pub(crate) struct Fragments<'a> {
+    /// Boxes up to and including the `moov` box, empty if there is no `moov` box before the first `moof` box.
+    pub(crate) init: &'a [u8],
+    /// Every `moof` box along with its `mdat` box, and any box between the previous `mdat` box and
+    /// the `moof` box (e.g. `styp` and `sidx`).
+    pub(crate) fragments: Vec<&'a [u8]>,
+}
+
+/// Split a fragmented file or media segments into its init segment and fragments.
+/// A trailing `mfra` box is left out, since it describes the complete file.
+pub(crate) fn split_fragments(data: &[u8]) -> Result<Fragments<'_>, Error> {
+    let top_level = boxes::boxes(data).collect::<Result<Vec<_>, _>>()?;
+    let init_end = top_level
+        .iter()
+        .take_while(|x| &x.box_type != b"moof")
+        .find(|x| &x.box_type == b"moov")
+        .map(|x| x.end())
+        .unwrap_or(0);
+
+    let mut starts = Vec::new();
+    // Start of the next fragment.
+    let mut start = init_end;
+    let mut end = data.len();
+
+    for mp4_box in top_level.iter().filter(|x| x.offset >= init_end) {
+        match &mp4_box.box_type {
+            b"moof" => starts.push(start),
+            b"mdat" => start = mp4_box.end(),
+            b"mfra" if !starts.is_empty() => end = mp4_box.offset,
+            _ => (),
+        }
+    }
+
+    let fragments = starts
+        .iter()
+        .enumerate()
+        .map(|(i, x)| &data[*x..starts.get(i + 1).copied().unwrap_or(end)])
+        .collect();
+
+    Ok(Fragments {
+        init: &data[..init_end],
+        fragments,
+    })
+}
+
+/// Count the fragments (top level `moof` boxes) of a fragmented file or media segments without decrypting them,
+/// e.g. for setting up a progress bar. Only box headers are read.
+///
+/// Boxes between fragments, such as `styp` and `sidx` of every media segment, aren't counted.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+/// let styp = [0, 0, 0, 16, b's', b't', b'y', b'p', b'm', b's', b'd', b'h', 0, 0, 0, 0];
+///
+/// let mut data = init.clone();
+///
+/// for _ in 0..3 {
+///     data.extend(styp);
+///     data.extend(&segment);
+/// }
+///
+/// assert_eq!(mp4decrypt::count_fragments(&data).unwrap(), 3);
+/// assert_eq!(mp4decrypt::count_fragments(&init).unwrap(), 0);
+/// ```
+pub fn count_fragments(data: &[u8]) -> Result<usize, Error> {
+    let mut count = 0;
+
+    for mp4_box in boxes::boxes(data) {
+        if &mp4_box?.box_type == b"moof" {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Decrypt only the first `max_fragments` fragments of a fragmented file, e.g. for generating previews.
+///
+/// The output holds the init segment followed by the decrypted fragments, so it is playable as a
+/// truncated file. Index boxes (`sidx`, `ssix` and `mfra`) are dropped, since they describe the complete file.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream.
+/// * `init` (optional) - Init segment, if `data` only holds media segments.
+/// * `keys` - Keys for decrypting the fragments.
+/// * `max_fragments` - Number of `moof` boxes to decrypt, 0 keeps only the init segment.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let mut data = init.clone();
+/// data.extend(&segment);
+/// let decrypted = mp4decrypt::decrypt_prefix(&data, None, &keys, 1).unwrap();
+///
+/// // two fragments
+/// data.extend(&segment);
+/// assert_eq!(mp4decrypt::decrypt_prefix(&data, None, &keys, 1).unwrap(), decrypted);
+/// assert!(mp4decrypt::decrypt_prefix(&segment, Some(&init), &keys, 0).unwrap().len() < init.len());
+/// ```
+pub fn decrypt_prefix(
+    data: &[u8],
+    init: Option<&[u8]>,
+    keys: &KeyMap,
+    max_fragments: usize,
+) -> Result<Vec<u8>, Error> {
+    let split = split_fragments(data)?;
+    // Non-fragmented files are decrypted completely.
+    let head = if split.fragments.is_empty() {
+        data
+    } else {
+        split.init
+    };
+    let prefix = join_parts(
+        [init.unwrap_or(head)]
+            .into_iter()
+            .chain(split.fragments.into_iter().take(max_fragments)),
+    )?;
+
+    decrypt(&prefix, keys, None)
+}
+
+/// Decrypt the fragments of a fragmented file starting from the `start_fragment`-th `moof` box (counting from 0),
+/// e.g. for resuming a download which failed after decrypting the previous fragments.
+///
+/// Only the decrypted tail is returned, without the init segment. Index boxes (`sidx`, `ssix` and `mfra`) are
+/// dropped, and absolute base data offsets of `tfhd` boxes are moved along with their `moof` box.
+/// An error is returned if `start_fragment` isn't less than the number of fragments,
+/// see [count_fragments](crate::count_fragments).
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream.
+/// * `init` (optional) - Init segment, if `data` only holds media segments.
+/// * `keys` - Keys for decrypting the fragments.
+/// * `start_fragment` - Index of the first fragment to decrypt.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+/// let options = mp4decrypt::DecryptOptions::new();
+/// let decrypted_segment =
+///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap();
+///
+/// let data = [init.clone(), segment.clone(), segment.clone()].concat();
+/// let tail = mp4decrypt::decrypt_from_fragment(&data, None, &keys, 1).unwrap();
+/// assert_eq!(tail, decrypted_segment);
+///
+/// let segments = [segment.clone(), segment].concat();
+/// let tail = mp4decrypt::decrypt_from_fragment(&segments, Some(&init), &keys, 0).unwrap();
+/// assert_eq!(tail, [decrypted_segment.clone(), decrypted_segment].concat());
+/// assert!(mp4decrypt::decrypt_from_fragment(&data, None, &keys, 2).is_err());
+/// ```
+pub fn decrypt_from_fragment(
+    data: &[u8],
+    init: Option<&[u8]>,
+    keys: &KeyMap,
+    start_fragment: usize,
+) -> Result<Vec<u8>, Error> {
+    let split = split_fragments(data)?;
+
+    if start_fragment >= split.fragments.len() {
+        return Err(Error::new_format(format!(
+            "cannot start decrypting from fragment {}, the data stream has {} fragments.",
+            start_fragment,
+            split.fragments.len()
+        )));
+    }
+
+    let init = match init {
+        Some(x) => x,
+        None if !split.init.is_empty() => split.init,
+        None => {
+            return Err(Error {
+                msg: "data stream has no moov box before its fragments, pass the init segment."
+                    .to_owned(),
+                err_type: ErrorType::MissingInitSegment,
+                invalid_key: false,
+            });
+        }
+    };
+
+    let mut tail = Vec::new();
+
+    for fragment in &split.fragments[start_fragment..] {
+        // Offsets of boxes are relative to the fragment, not to the data stream.
+        let fragment_offset = fragment.as_ptr() as usize - data.as_ptr() as usize;
+
+        for mp4_box in boxes::boxes(fragment) {
+            let mp4_box = mp4_box?;
+
+            match &mp4_box.box_type {
+                b"sidx" | b"ssix" | b"mfra" => (),
+                b"moof" => {
+                    let shift = tail.len() as i64 - (fragment_offset + mp4_box.offset) as i64;
+                    let start = tail.len();
+                    tail.extend(mp4_box.data);
+                    move_base_data_offsets(&mp4_box, shift, &mut tail[start..])?;
+                }
+                _ => tail.extend(mp4_box.data),
+            }
+        }
+    }
+
+    decrypt(&tail, keys, Some(init))
+}
+
+/// Assemble a decrypted init segment and decrypted fragments (e.g. decrypted in parallel or one by one using
+/// [SegmentDecryptor](crate::SegmentDecryptor)) into a single playable file.
+///
+/// Segment level boxes of fragments (`styp`, `sidx`, `ssix` and `mfra`) are dropped, since the `ftyp` box of
+/// the init segment describes the complete file. Absolute base data offsets of `tfhd` boxes are moved along with
+/// their `moof` box. An error is returned if a fragment isn't made of complete boxes.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let mut decryptor = mp4decrypt::SegmentDecryptor::new(&init, &keys).unwrap();
+/// let fragments = [&segment, &segment]
+///     .map(|x| decryptor.decrypt_next(x).unwrap())
+///     .to_vec();
+/// let assembled = mp4decrypt::assemble(&decryptor.decrypt_init().unwrap(), &fragments).unwrap();
+///
+/// let data = [init, segment.clone(), segment].concat();
+/// let options = mp4decrypt::DecryptOptions::new();
+/// assert_eq!(assembled, mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap());
+/// ```
+pub fn assemble(init: &[u8], fragments: &[Vec<u8>]) -> Result<Vec<u8>, Error> {
+    let mut assembled =
+        Vec::with_capacity(init.len() + fragments.iter().map(Vec::len).sum::<usize>());
+    assembled.extend(init);
+
+    for fragment in fragments {
+        for mp4_box in boxes::boxes(fragment) {
+            let mp4_box = mp4_box?;
+
+            match &mp4_box.box_type {
+                b"styp" | b"sidx" | b"ssix" | b"mfra" => (),
+                b"moof" => {
+                    let shift = assembled.len() as i64 - mp4_box.offset as i64;
+                    let start = assembled.len();
+                    assembled.extend(mp4_box.data);
+                    move_base_data_offsets(&mp4_box, shift, &mut assembled[start..])?;
+                }
+                _ => assembled.extend(mp4_box.data),
+            }
+        }
+    }
+
+    Ok(assembled)
+}
+
+/// Shift the absolute base data offsets of the `tfhd` boxes of a `moof` box, whose copy is `out`.
+fn move_base_data_offsets(moof: &Mp4Box, shift: i64, out: &mut [u8]) -> Result<(), Error> {
+    for traf in moof.children() {
+        let traf = traf?;
+
+        if &traf.box_type != b"traf" {
+            continue;
+        }
+
+        let Some(tfhd) = traf.child(b"tfhd")? else {
+            continue;
+        };
+
+        let payload = tfhd.payload();
+        if payload.get(3).is_none_or(|x| x & 0x01 == 0) {
+            continue;
+        }
+
+        let invalid = || {
+            Error::new_format(format!(
+                "tfhd box at offset {} has an invalid base data offset.",
+                tfhd.offset
+            ))
+        };
+
+        // version and flags, track_id
+        let pos = tfhd.offset + tfhd.header_size + 8 - moof.offset;
+        let field = out.get_mut(pos..pos + 8).ok_or_else(invalid)?;
+        let base_data_offset = u64::from_be_bytes(field.try_into().unwrap())
+            .checked_add_signed(shift)
+            .ok_or_else(invalid)?;
+        field.copy_from_slice(&base_data_offset.to_be_bytes());
+    }
+
+    Ok(())
+}
+
+/// Join an init segment and some of its fragments, dropping index boxes which describe the complete file.
+pub(crate) fn join_parts<'a>(parts: impl Iterator<Item = &'a [u8]>) -> Result<Vec<u8>, Error> {
+    let mut joined = Vec::new();
+
+    for part in parts {
+        for mp4_box in boxes::boxes(part) {
+            let mp4_box = mp4_box?;
+
+            if !matches!(&mp4_box.box_type, b"sidx" | b"ssix" | b"mfra") {
+                joined.extend(mp4_box.data);
+            }
+        }
+    }
+
+    Ok(joined)
+}
@@ -0,0 +1,440 @@
+use crate::{
+    DecryptOptions, Error, ErrorType, KeyMap, boxes, events, mp4decrypt_with_options,
+    sink::OutputLimit,
+};
+use std::io::{BufRead, Cursor, Read, Write};
+
+/// Number of bytes peeked by [decrypt_auto](decrypt_auto), i.e. the header of the first box.
+pub const AUTO_PEEK_SIZE: usize = 8;
+
+/// Boxes which start a media segment without an init segment.
+const SEGMENT_BOXES: [&[u8; 4]; 6] = [b"styp", b"sidx", b"ssix", b"emsg", b"prft", b"moof"];
+
+/// Whether a box of this type starts a media segment without an init segment.
+fn starts_segment(box_type: &[u8]) -> bool {
+    SEGMENT_BOXES.iter().any(|x| box_type == **x)
+}
+
+/// Decrypt a fragmented mp4 stream from a reader and write the decrypted stream to a writer,
+/// one fragment at a time.
+///
+/// Boxes before the first `moof` box are decrypted together as the init segment, and every following
+/// `moof` box along with the boxes up to the next `moof` box (usually just `mdat`) is decrypted as a fragment.
+/// Decrypted data is buffered until it exceeds [DecryptOptions::buffer_limit](DecryptOptions::buffer_limit),
+/// after which it is written to the writer. Since Bento4 decrypts a whole fragment at once, memory usage is
+/// bounded by the buffer limit plus the size of the largest fragment. Buffered data is written in chunks of
+/// [DecryptOptions::chunk_size](DecryptOptions::chunk_size), keeping any incomplete chunk buffered until the end.
+///
+/// Non-fragmented streams are read completely and decrypted at once. An `ftyp` or `moov` box after fragments
+/// starts a new period (e.g. of a multi-period DASH stream), which is decrypted using its own init segment.
+///
+/// Every CMAF chunk (`moof` and `mdat` boxes) of a low-latency stream is decrypted on its own, since each
+/// `moof` box starts a new fragment. Use [DecryptOptions::flush_cmaf_chunks](DecryptOptions::flush_cmaf_chunks)
+/// for writing every chunk as soon as it is read.
+///
+/// # Example
+///
+/// ```no_run
+/// use mp4decrypt::{DecryptOptions, KeyMap};
+/// use std::{fs::File, net::TcpStream};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let options = DecryptOptions::new().buffer_limit(1024 * 1024);
+/// mp4decrypt::decrypt_stream(
+///     File::open("encrypted.mp4").unwrap(),
+///     TcpStream::connect("127.0.0.1:8080").unwrap(),
+///     &keys,
+///     &options,
+/// )
+/// .unwrap();
+/// ```
+///
+/// Trait objects can be used as they are, e.g. a `Box<dyn Read>` response body of an HTTP client.
+///
+/// ```
+/// use mp4decrypt::{DecryptOptions, KeyMap};
+/// use std::io::{Cursor, Read};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let mut data = std::fs::read("examples/sample/init.mp4").unwrap();
+/// data.extend(std::fs::read("examples/sample/segment_0.m4s").unwrap());
+///
+/// // stand-in for a network body
+/// let body: Box<dyn Read> = Box::new(Cursor::new(data.clone()));
+/// let options = DecryptOptions::new();
+/// let mut decrypted_data = Vec::new();
+/// mp4decrypt::decrypt_stream(body, &mut decrypted_data, &keys, &options).unwrap();
+///
+/// let expected = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+/// assert_eq!(decrypted_data, expected);
+/// ```
+pub fn decrypt_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    keys: &KeyMap,
+    options: &DecryptOptions,
+) -> Result<(), Error> {
+    decrypt_dyn_stream(&mut reader, &mut writer, keys, None, options)
+}
+
+/// Decrypt a stream which is either a self-contained file or media segments without an init segment,
+/// same as [decrypt_auto_with_options](decrypt_auto_with_options) with default options.
+///
+/// The first [AUTO_PEEK_SIZE](AUTO_PEEK_SIZE) bytes (the header of the first box) are peeked to decide
+/// how the stream is decrypted. A stream starting with a `styp`, `sidx`, `ssix`, `emsg`, `prft` or `moof` box
+/// is decrypted using `init` as its fragments info and only the decrypted segments are written.
+/// Any other stream (usually starting with `ftyp` or `moov`) is decrypted using its own init segment,
+/// ignoring `init`. Peeked bytes are taken from the buffer of the reader without consuming them,
+/// unless the buffer holds fewer bytes. See [needs_fragments_info](needs_fragments_info) for data in memory.
+///
+/// # Arguments
+///
+/// * `reader` - Encrypted data stream.
+/// * `keys` - Keys for decrypting the stream.
+/// * `init` (optional) - Init segment, required if the stream starts with a media segment.
+/// * `writer` - Writer of the decrypted data stream.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{DecryptOptions, ErrorType, KeyMap};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+/// let options = DecryptOptions::new();
+///
+/// // media segment
+/// let mut decrypted_segment = Vec::new();
+/// mp4decrypt::decrypt_auto(segment.as_slice(), &keys, Some(&init), &mut decrypted_segment).unwrap();
+/// let expected = mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap();
+/// assert_eq!(decrypted_segment, expected);
+///
+/// let error = mp4decrypt::decrypt_auto(segment.as_slice(), &keys, None, &mut Vec::new()).unwrap_err();
+/// assert!(matches!(error.err_type, ErrorType::MissingInitSegment));
+///
+/// // self-contained file
+/// let mut data = init.clone();
+/// data.extend(&segment);
+/// let mut decrypted_data = Vec::new();
+/// mp4decrypt::decrypt_auto(data.as_slice(), &keys, Some(&init), &mut decrypted_data).unwrap();
+/// let expected = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+/// assert_eq!(decrypted_data, expected);
+/// ```
+pub fn decrypt_auto<R: BufRead, W: Write>(
+    reader: R,
+    keys: &KeyMap,
+    init: Option<&[u8]>,
+    writer: W,
+) -> Result<(), Error> {
+    decrypt_auto_with_options(reader, keys, init, writer, &DecryptOptions::default())
+}
+
+/// Decrypt a stream which is either a self-contained file or media segments without an init segment
+/// using given options, same as [decrypt_stream](decrypt_stream).
+///
+/// See [decrypt_auto](decrypt_auto) for how the stream is decrypted and details about the arguments.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{DecryptOptions, KeyMap};
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let options = DecryptOptions::new().chunk_size(1024);
+/// let mut decrypted_segment = Vec::new();
+/// mp4decrypt::decrypt_auto_with_options(
+///     segment.as_slice(),
+///     &keys,
+///     Some(&init),
+///     &mut decrypted_segment,
+///     &options,
+/// )
+/// .unwrap();
+///
+/// let expected = mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap();
+/// assert_eq!(decrypted_segment, expected);
+/// ```
+pub fn decrypt_auto_with_options<R: BufRead, W: Write>(
+    mut reader: R,
+    keys: &KeyMap,
+    init: Option<&[u8]>,
+    mut writer: W,
+    options: &DecryptOptions,
+) -> Result<(), Error> {
+    let mut header: Vec<u8> = Vec::with_capacity(AUTO_PEEK_SIZE);
+
+    // Bytes which are consumed from the reader while peeking, if its buffer is too small.
+    let mut prefix = Vec::new();
+
+    while header.len() < AUTO_PEEK_SIZE {
+        let buffer = reader.fill_buf().map_err(Error::new_io)?;
+
+        if buffer.is_empty() {
+            break;
+        }
+
+        if prefix.is_empty() && buffer.len() >= AUTO_PEEK_SIZE {
+            header.extend(&buffer[..AUTO_PEEK_SIZE]);
+            break;
+        }
+
+        let len = buffer.len().min(AUTO_PEEK_SIZE - header.len());
+        header.extend(&buffer[..len]);
+        prefix.extend(&buffer[..len]);
+        reader.consume(len);
+    }
+
+    let is_segment = header.len() == AUTO_PEEK_SIZE && starts_segment(&header[4..8]);
+    let fragments_info = match (is_segment, init) {
+        (true, None) => {
+            return Err(Error {
+                msg: "stream starts with a media segment but no init segment was given.".to_owned(),
+                err_type: ErrorType::MissingInitSegment,
+                invalid_key: false,
+            });
+        }
+        (true, Some(x)) => Some(x),
+        (false, _) => None,
+    };
+
+    let mut reader = Cursor::new(prefix).chain(reader);
+    decrypt_dyn_stream(&mut reader, &mut writer, keys, fragments_info, options)
+}
+
+/// Check whether decrypting the data requires an init segment to be given as `fragments_info`,
+/// i.e. whether it holds media segments without an init segment.
+///
+/// Returns `true` if a `moof` box comes before any `moov` box, or if there is no `moof` box but the data starts
+/// with a `styp`, `sidx`, `ssix`, `emsg` or `prft` box, just like [decrypt_auto](decrypt_auto) decides.
+/// Only box headers are read.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// assert!(mp4decrypt::needs_fragments_info(&segment).unwrap());
+/// assert!(!mp4decrypt::needs_fragments_info(&init).unwrap());
+/// assert!(!mp4decrypt::needs_fragments_info(&[init, segment].concat()).unwrap());
+/// assert!(mp4decrypt::needs_fragments_info(&[0, 0, 0, 32]).is_err());
+/// ```
+pub fn needs_fragments_info(data: &[u8]) -> Result<bool, Error> {
+    let mut first = None;
+
+    for mp4_box in boxes::boxes(data) {
+        let mp4_box = mp4_box?;
+
+        match &mp4_box.box_type {
+            b"moov" => return Ok(false),
+            b"moof" => return Ok(true),
+            x => {
+                first.get_or_insert(*x);
+            }
+        }
+    }
+
+    Ok(first.is_some_and(|x| starts_segment(&x)))
+}
+
+/// Same as [decrypt_stream](decrypt_stream) but without monomorphization for every reader and writer.
+/// Fragments info is used for the fragments before the first init segment of the stream, without writing it.
+fn decrypt_dyn_stream(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    keys: &KeyMap,
+    fragments_info: Option<&[u8]>,
+    options: &DecryptOptions,
+) -> Result<(), Error> {
+    let mut buffer = Vec::new();
+    // Size of the output written so far.
+    let mut written = 0;
+    let mut init = fragments_info.map(|x| x.to_vec()).unwrap_or_default();
+    // Whether init holds the given fragments info, which isn't written.
+    let mut fragments_info = fragments_info.is_some();
+    // Boxes before the first moof box which belong to the first fragment (e.g. styp), with fragments info.
+    let mut leading = Vec::new();
+    // Fragment being read, which is None until the first moof box.
+    let mut fragment: Option<Vec<u8>> = None;
+    // Whether a CMAF chunk was written, so that the following boxes belong to the next chunk.
+    let mut after_chunk = false;
+
+    while let Some(mp4_box) = read_box(reader)? {
+        let is_moof = &mp4_box[4..8] == b"moof";
+
+        // A new init segment after fragments starts a new period.
+        if matches!(&mp4_box[4..8], b"ftyp" | b"moov") {
+            if let Some(x) = fragment.take() {
+                buffer.extend(decrypt_fragment(&x, &init, keys, options)?);
+                init.clear();
+            } else if fragments_info || after_chunk {
+                buffer.append(&mut leading);
+                init.clear();
+            }
+
+            fragments_info = false;
+            after_chunk = false;
+        }
+
+        let is_mdat = &mp4_box[4..8] == b"mdat";
+        let mut chunk_end = false;
+
+        match (&mut fragment, is_moof) {
+            (None, false) if fragments_info || after_chunk => leading.extend(mp4_box),
+            (None, false) => init.extend(mp4_box),
+            (None, true) => {
+                if !init.is_empty() && !fragments_info && !after_chunk {
+                    buffer.extend(mp4decrypt_with_options(&init, keys, None, options)?);
+                }
+                leading.extend(mp4_box);
+                fragment = Some(std::mem::take(&mut leading));
+            }
+            (Some(x), true) => {
+                buffer.extend(decrypt_fragment(x, &init, keys, options)?);
+                *x = mp4_box;
+                chunk_end = options.flush_cmaf_chunks;
+            }
+            (Some(x), false) => x.extend(mp4_box),
+        }
+
+        // The first mdat box after a moof box ends a CMAF chunk.
+        if let Some(x) = fragment
+            .as_ref()
+            .filter(|_| is_mdat && options.flush_cmaf_chunks)
+        {
+            buffer.extend(decrypt_fragment(x, &init, keys, options)?);
+            fragment = None;
+            after_chunk = true;
+            chunk_end = true;
+        }
+
+        check_output_size(written + buffer.len(), options)?;
+
+        if chunk_end {
+            write_chunks(writer, &buffer, options.chunk_size)?;
+            writer.flush().map_err(Error::new_io)?;
+            written += buffer.len();
+            buffer.clear();
+        } else if buffer.len() > options.buffer_limit {
+            let len = match options.chunk_size {
+                0 => buffer.len(),
+                x => buffer.len() - buffer.len() % x,
+            };
+            write_chunks(writer, &buffer[..len], options.chunk_size)?;
+            buffer.drain(..len);
+            written += len;
+        }
+    }
+
+    match fragment {
+        Some(x) => buffer.extend(decrypt_fragment(&x, &init, keys, options)?),
+        None if fragments_info || after_chunk => buffer.append(&mut leading),
+        None if !init.is_empty() => {
+            buffer.extend(mp4decrypt_with_options(&init, keys, None, options)?)
+        }
+        None => (),
+    }
+
+    check_output_size(written + buffer.len(), options)?;
+    write_chunks(writer, &buffer, options.chunk_size)?;
+    writer.flush().map_err(Error::new_io)
+}
+
+/// Check the size of the complete output against [DecryptOptions::max_output_bytes](DecryptOptions::max_output_bytes).
+fn check_output_size(size: usize, options: &DecryptOptions) -> Result<(), Error> {
+    match options.max_output_bytes {
+        Some(limit) if size > limit => Err(OutputLimit(limit).to_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Write data in chunks of `chunk_size` (0 writes it at once).
+fn write_chunks(writer: &mut dyn Write, data: &[u8], chunk_size: usize) -> Result<(), Error> {
+    if chunk_size == 0 {
+        return writer.write_all(data).map_err(Error::new_io);
+    }
+
+    for chunk in data.chunks(chunk_size) {
+        writer.write_all(chunk).map_err(Error::new_io)?;
+    }
+
+    Ok(())
+}
+
+fn decrypt_fragment(
+    fragment: &[u8],
+    init: &[u8],
+    keys: &KeyMap,
+    options: &DecryptOptions,
+) -> Result<Vec<u8>, Error> {
+    let fragments_info = if init.is_empty() { None } else { Some(init) };
+    mp4decrypt_with_options(fragment, keys, fragments_info, options)
+}
+
+/// Read a complete box, returns `None` at the end of the stream.
+fn read_box(reader: &mut dyn Read) -> Result<Option<Vec<u8>>, Error> {
+    let mut header = vec![0; 8];
+
+    match events::read_full(reader, &mut header)? {
+        0 => return Ok(None),
+        8 => (),
+        x => {
+            return Err(Error::new_format(format!(
+                "expected a box header of 8 bytes but the stream ended after {} bytes.",
+                x
+            )));
+        }
+    }
+
+    let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) as u64 {
+        0 => {
+            reader.read_to_end(&mut header).map_err(Error::new_io)?;
+            return Ok(Some(header));
+        }
+        1 => {
+            header.resize(16, 0);
+            reader.read_exact(&mut header[8..]).map_err(Error::new_io)?;
+            u64::from_be_bytes(header[8..].try_into().unwrap())
+        }
+        x => x,
+    };
+
+    if size < header.len() as u64 {
+        return Err(Error::new_format(format!(
+            "box has an invalid size of {} bytes.",
+            size
+        )));
+    }
+
+    let remaining = size - header.len() as u64;
+    let read = reader
+        .take(remaining)
+        .read_to_end(&mut header)
+        .map_err(Error::new_io)?;
+
+    if read as u64 != remaining {
+        return Err(Error::new_format(format!(
+            "expected a box of {} bytes but the stream ended after {} bytes.",
+            size,
+            header.len()
+        )));
+    }
+
+    Ok(Some(header))
+}
@@ -0,0 +1,133 @@
+//! Assertions for test suites of crates built on top of this crate.
+//!
+//! A decrypted stream which doesn't match the expected one is reported by the first differing byte
+//! and the path of the box holding it, instead of a dump of both streams.
+
+use crate::{
+    DecryptOptions, KeyMap, boxes, metadata, mp4decrypt_with_options, validate::CONTAINERS,
+};
+
+/// Decrypt data and assert that the output is byte-identical to `expected`, e.g. to a file decrypted by
+/// Bento4 mp4decrypt cli.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream.
+/// * `keys` - Keys for decrypting the data stream.
+/// * `init` (optional) - Init segment, if `data` only holds media segments.
+/// * `expected` - Expected decrypted data stream.
+///
+/// # Panics
+///
+/// Panics if decryption fails, or with the location of the first difference (see [first_difference]) if
+/// the decrypted output doesn't match `expected`.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+/// let options = mp4decrypt::DecryptOptions::new();
+/// let expected = mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap();
+///
+/// mp4decrypt::testing::assert_decrypts_to(&segment, &keys, Some(&init), &expected);
+/// ```
+#[track_caller]
+pub fn assert_decrypts_to(data: &[u8], keys: &KeyMap, init: Option<&[u8]>, expected: &[u8]) {
+    let decrypted = match mp4decrypt_with_options(data, keys, init, &DecryptOptions::default()) {
+        Ok(x) => x,
+        Err(e) => panic!("decryption failed: {}", e),
+    };
+
+    if let Some(difference) = first_difference(&decrypted, expected) {
+        panic!(
+            "decrypted output doesn't match the expected output, {}",
+            difference
+        );
+    }
+}
+
+/// Describe the first difference between two mp4 byte streams, or return `None` if they are identical.
+///
+/// The description holds the offset of the first differing byte, the differing bytes and the path of
+/// the innermost box of `expected` holding that offset (e.g. `moof/traf/senc at offset 1140`).
+/// If one stream is a prefix of the other, the length of both streams and the box of the longer one
+/// following the end of the shorter one are described instead.
+///
+/// # Example
+///
+/// ```
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let mut changed = init.clone();
+/// changed[620] ^= 0xff;
+///
+/// assert!(mp4decrypt::testing::first_difference(&init, &init).is_none());
+///
+/// let difference = mp4decrypt::testing::first_difference(&changed, &init).unwrap();
+/// assert!(difference.starts_with("first difference at offset 620"));
+/// assert!(difference.contains("moov/trak/mdia/minf/stbl/stsd/encv/sinf/schi/tenc at offset 613"));
+/// ```
+pub fn first_difference(actual: &[u8], expected: &[u8]) -> Option<String> {
+    let Some(offset) = actual.iter().zip(expected).position(|(x, y)| x != y) else {
+        if actual.len() == expected.len() {
+            return None;
+        }
+
+        // The first byte which is missing from the shorter stream.
+        let (longer, offset) = if actual.len() < expected.len() {
+            (expected, actual.len())
+        } else {
+            (actual, expected.len())
+        };
+
+        return Some(format!(
+            "output is {} bytes but expected {} bytes, the shorter one ends at offset {} ({}).",
+            actual.len(),
+            expected.len(),
+            offset,
+            locate(longer, offset)
+        ));
+    };
+
+    Some(format!(
+        "first difference at offset {}, got 0x{:02x} but expected 0x{:02x} ({}).",
+        offset,
+        actual[offset],
+        expected[offset],
+        locate(expected, offset)
+    ))
+}
+
+/// Path of the innermost box holding `offset`, walking into container boxes.
+fn locate(data: &[u8], offset: usize) -> String {
+    let mut path = Vec::new();
+    let mut found = None;
+    let mut children = Some(boxes::boxes(data));
+
+    while let Some(siblings) = children
+        && let Some(mp4_box) = siblings
+            .map_while(Result::ok)
+            .find(|x| x.offset <= offset && offset < x.end())
+    {
+        path.push(boxes::fourcc(&mp4_box.box_type));
+        found = Some(mp4_box);
+
+        children = match &mp4_box.box_type {
+            x if CONTAINERS.contains(&x) => Some(mp4_box.children()),
+            // version, flags and entry_count
+            b"stsd" => Some(mp4_box.children_after(8)),
+            x => metadata::protected_entry_fields_size(x).map(|x| mp4_box.children_after(x)),
+        };
+    }
+
+    match found {
+        Some(x) => format!("{} at offset {}", path.join("/"), x.offset),
+        None => "outside of any box".to_owned(),
+    }
+}
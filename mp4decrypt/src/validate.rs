@@ -0,0 +1,363 @@
+use crate::{
+    Error, ErrorType, KeyId,
+    boxes::{self, Mp4Box},
+    keys,
+    metadata::{self, Mp4Metadata},
+    moof,
+    protection::PIFF_SAMPLE_ENCRYPTION,
+    reader::Reader,
+    split,
+};
+use std::collections::HashSet;
+
+/// Boxes which only contain other boxes.
+pub(crate) const CONTAINERS: [&[u8; 4]; 13] = [
+    b"dinf", b"edts", b"mdia", b"mfra", b"minf", b"moof", b"moov", b"mvex", b"schi", b"sinf",
+    b"stbl", b"traf", b"trak",
+];
+
+/// Check that every box size is consistent and sample data of every fragment lies within its `mdat` box.
+///
+/// # Arguments
+///
+/// * `data` - Decrypted data stream.
+/// * `fragments_info` (optional) - Init segment holding the track defaults of fragments in `data`.
+///
+/// Without fragments info, concatenated periods are checked using their own `moov` box.
+pub(crate) fn validate_structure(data: &[u8], fragments_info: Option<&[u8]>) -> Result<(), Error> {
+    walk(data, 0)?;
+
+    if fragments_info.is_some() {
+        return check_samples(data, fragments_info, 0);
+    }
+
+    for period in split::split_periods(data) {
+        check_samples(
+            period,
+            None,
+            period.as_ptr() as usize - data.as_ptr() as usize,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Check that sample data of every fragment lies within its `mdat` box, `base` is the offset of `data`.
+fn check_samples(data: &[u8], fragments_info: Option<&[u8]>, base: usize) -> Result<(), Error> {
+    let trex_sizes = moof::read_trex_sizes(fragments_info.unwrap_or(data))
+        .map_err(|_| malformed(*b"moov", base))?;
+    let top_level = boxes::boxes(data)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| malformed(*b"????", base))?;
+
+    for (i, moof) in top_level.iter().enumerate() {
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+
+        let fragments = moof::read_track_fragments(moof, &trex_sizes)
+            .map_err(|_| malformed(moof.box_type, base + moof.offset))?;
+        let mdat = top_level[i + 1..]
+            .iter()
+            .take_while(|x| &x.box_type != b"moof")
+            .find(|x| &x.box_type == b"mdat");
+
+        for run in fragments.iter().flat_map(|x| &x.runs) {
+            let in_mdat = |mdat: &Mp4Box| {
+                let start = (mdat.offset + mdat.header_size) as u64;
+                run.samples
+                    .iter()
+                    .all(|x| x.offset >= start && x.offset + x.size as u64 <= mdat.end() as u64)
+            };
+
+            if !mdat.is_some_and(in_mdat) {
+                return Err(malformed(run.mp4_box.box_type, base + run.mp4_box.offset));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that `data` has a `moov` box before its first `moof` box, so that it can be decrypted without fragments info.
+///
+/// Data which can't be parsed is left for Bento4 to handle.
+pub(crate) fn check_self_contained(data: &[u8]) -> Result<(), Error> {
+    let first = boxes::boxes(data)
+        .map_while(Result::ok)
+        .find(|x| &x.box_type == b"moov" || &x.box_type == b"moof");
+
+    match first {
+        Some(moof) if &moof.box_type == b"moof" => Err(Error {
+            msg: format!(
+                "fragment at offset {} has no init segment, pass it as fragments info.",
+                moof.offset
+            ),
+            err_type: ErrorType::MissingInitSegment,
+            invalid_key: false,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Check that every track fragmented in `data` is described by the `moov` box of `init`.
+///
+/// Data which can't be parsed is left for Bento4 to handle, only an obvious mismatch is reported.
+pub(crate) fn check_init_media(data: &[u8], init: &[u8]) -> Result<(), Error> {
+    match read_init_track_ids(init) {
+        Some(track_ids) => check_track_ids(data, &track_ids),
+        None => Ok(()),
+    }
+}
+
+/// Read track IDs from the `moov` box of an init segment, if it can be parsed.
+pub(crate) fn read_init_track_ids(init: &[u8]) -> Option<HashSet<u32>> {
+    let moov = boxes::find(boxes::boxes(init), b"moov").ok()??;
+    read_moov_track_ids(&moov).ok()
+}
+
+/// Check that every track fragmented in `data` is one of `track_ids`.
+pub(crate) fn check_track_ids(data: &[u8], track_ids: &HashSet<u32>) -> Result<(), Error> {
+    for moof in boxes::boxes(data) {
+        let Ok(moof) = moof else {
+            break;
+        };
+
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+
+        for traf in moof.children() {
+            let Ok(traf) = traf else {
+                break;
+            };
+
+            if &traf.box_type != b"traf" {
+                continue;
+            }
+
+            let Ok(Some(tfhd)) = traf.child(b"tfhd") else {
+                continue;
+            };
+            let mut reader = Reader::new(tfhd.payload());
+
+            let Ok(track_id) = reader.read_version_flags().and_then(|_| reader.read_u32()) else {
+                continue;
+            };
+
+            if !track_ids.contains(&track_id) {
+                return Err(Error {
+                    msg: format!(
+                        "track {} of the fragment at offset {} is not present in the init segment (fragments info).",
+                        track_id, moof.offset
+                    ),
+                    err_type: ErrorType::InitMediaMismatch,
+                    invalid_key: false,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that every fragment of a track encrypted with per sample IVs has a `senc` box (or PIFF sample encryption
+/// `uuid` box), or `saiz` and `saio` boxes referencing auxiliary information holding its IVs.
+/// Bento4 passes such fragments through without decrypting them.
+///
+/// Fragments with sample groups are skipped, since they can mark every sample as unencrypted.
+/// Data which can't be parsed is left for Bento4 to handle.
+pub(crate) fn check_sample_encryption(data: &[u8], init: &[u8]) -> Result<(), Error> {
+    let Ok(metadata) = Mp4Metadata::parse(init) else {
+        return Ok(());
+    };
+
+    let track_ids = metadata
+        .tenc()
+        .iter()
+        .filter(|x| x.is_protected && x.per_sample_iv_size != 0)
+        .map(|x| x.track_id)
+        .collect::<HashSet<_>>();
+
+    if track_ids.is_empty() {
+        return Ok(());
+    }
+
+    for moof in boxes::boxes(data) {
+        let Ok(moof) = moof else {
+            break;
+        };
+
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+
+        for traf in moof.children() {
+            let Ok(traf) = traf else {
+                break;
+            };
+
+            if &traf.box_type != b"traf" {
+                continue;
+            }
+
+            let children = traf.children().map_while(Result::ok).collect::<Vec<_>>();
+            let has = |box_type: &[u8; 4]| children.iter().any(|x| &x.box_type == box_type);
+
+            let Some(track_id) = children
+                .iter()
+                .find(|x| &x.box_type == b"tfhd")
+                .and_then(|x| x.payload().get(4..8))
+                .map(|x| u32::from_be_bytes(x.try_into().unwrap()))
+            else {
+                continue;
+            };
+
+            let has_samples = children.iter().any(|x| {
+                &x.box_type == b"trun" && x.payload().get(4..8).is_some_and(|x| x != [0; 4])
+            });
+
+            if !track_ids.contains(&track_id)
+                || !has_samples
+                || has(b"sbgp")
+                || has(b"senc")
+                || (has(b"saiz") && has(b"saio"))
+                || children
+                    .iter()
+                    .any(|x| x.user_type() == Some(PIFF_SAMPLE_ENCRYPTION))
+            {
+                continue;
+            }
+
+            return Err(Error {
+                msg: format!(
+                    "encrypted fragment of track {} at offset {} has no senc box, or saiz and saio boxes \
+                     holding its IVs.",
+                    track_id, moof.offset
+                ),
+                err_type: ErrorType::MissingIvs { track_id },
+                invalid_key: false,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that no protected sample entry of a track which has a key follows a clear sample entry.
+/// Bento4 numbers the protected sample entries of a track on their own, so fragments would be decrypted
+/// using the wrong sample entry (or not at all) instead of the one selected by their sample description index.
+///
+/// Data which can't be parsed is left for Bento4 to handle.
+pub(crate) fn check_sample_entries(
+    init: &[u8],
+    key_pairs: &[(String, String)],
+) -> Result<(), Error> {
+    let Ok(metadata) = Mp4Metadata::parse(init) else {
+        return Ok(());
+    };
+
+    let key_ids = key_pairs
+        .iter()
+        .filter_map(|(id, _)| keys::parse_key_id(id).ok())
+        .collect::<HashSet<_>>();
+
+    for track in metadata.tracks() {
+        let Some(first_clear) = track
+            .sample_entries
+            .iter()
+            .position(|x| !metadata::is_protected_entry(x))
+        else {
+            continue;
+        };
+
+        let has_key = key_ids.contains(&KeyId::Track(track.track_id))
+            || metadata
+                .tenc()
+                .iter()
+                .any(|x| x.track_id == track.track_id && key_ids.contains(&KeyId::Kid(x.kid)));
+
+        if has_key
+            && track.sample_entries[first_clear..]
+                .iter()
+                .any(metadata::is_protected_entry)
+        {
+            return Err(Error {
+                msg: format!(
+                    "track {} has a protected sample entry after a clear one, which can't be decrypted.",
+                    track.track_id
+                ),
+                err_type: ErrorType::UnsupportedSampleEntries {
+                    track_id: track.track_id,
+                },
+                invalid_key: false,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn read_moov_track_ids(moov: &Mp4Box) -> Result<HashSet<u32>, Error> {
+    let mut track_ids = HashSet::new();
+
+    for trak in moov.children() {
+        let trak = trak?;
+
+        if &trak.box_type != b"trak" {
+            continue;
+        }
+
+        let Some(tkhd) = trak.child(b"tkhd")? else {
+            continue;
+        };
+
+        let mut reader = Reader::new(tkhd.payload());
+        let (version, _) = reader.read_version_flags()?;
+        reader.skip(if version == 1 { 16 } else { 8 })?; // creation_time, modification_time
+        track_ids.insert(reader.read_u32()?);
+    }
+
+    Ok(track_ids)
+}
+
+fn walk(data: &[u8], base: usize) -> Result<(), Error> {
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let (box_type, header_size, size) = boxes::read_box(&data[pos..], base + pos, base == 0)
+            .map_err(|_| {
+                let box_type = data
+                    .get(pos + 4..pos + 8)
+                    .and_then(|x| x.try_into().ok())
+                    .unwrap_or(*b"????");
+                malformed(box_type, base + pos)
+            })?;
+
+        if CONTAINERS.contains(&&box_type) {
+            walk(
+                &data[pos + header_size..pos + size],
+                base + pos + header_size,
+            )?;
+        }
+
+        pos += size;
+    }
+
+    Ok(())
+}
+
+fn malformed(box_type: [u8; 4], offset: usize) -> Error {
+    Error {
+        msg: format!(
+            "decrypted output has a malformed {} box at offset {}.",
+            boxes::fourcc(&box_type),
+            offset
+        ),
+        err_type: ErrorType::MalformedOutput {
+            box_type,
+            offset: offset as u64,
+        },
+        invalid_key: false,
+    }
+}
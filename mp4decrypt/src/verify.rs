@@ -0,0 +1,730 @@
+use crate::{
+    DecryptOptions, Error, ErrorType, KeyMap, SegmentDecryptor, adts,
+    boxes::{self, Mp4Box},
+    decrypt, metadata,
+    moof::{self, FragmentSample},
+    mp4decrypt_with_options,
+    reader::{BitReader, Reader},
+    split,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Codec framing which can be checked for the samples of a decrypted track.
+enum Framing {
+    /// H.264 or H.265 samples of length prefixed NAL units.
+    Nal(NalConfig),
+    /// AAC samples, holding the channel configuration of the track.
+    Aac(u8),
+}
+
+/// Parameter sets of a H.264 or H.265 track, read from its decoder configuration and in-band parameter sets.
+struct NalConfig {
+    hevc: bool,
+    length_size: usize,
+    /// `log2_max_frame_num` and `separate_colour_plane_flag` of every H.264 SPS.
+    sps: HashMap<u32, (u32, bool)>,
+    /// SPS ID of every PPS, always 0 for H.265.
+    pps: HashMap<u32, u32>,
+}
+
+/// Verification result of a media segment, see [verify_directory](crate::verify_directory).
+#[derive(Debug)]
+pub enum VerifyResult {
+    /// The segment was decrypted and its samples have valid codec framing.
+    Passed,
+    /// The segment couldn't be decrypted, a track is left encrypted or a sample doesn't have valid framing.
+    Failed(Error),
+}
+
+impl VerifyResult {
+    pub fn is_passed(&self) -> bool {
+        matches!(self, Self::Passed)
+    }
+}
+
+/// Decrypt data stream with each candidate key map until one of them produces valid media,
+/// e.g. when keys from different license responses may be wrong.
+///
+/// Every candidate is used for a complete decryption with [validate_structure](DecryptOptions::validate_structure),
+/// then the decrypted media is verified: no track may be left encrypted, and the samples of H.264, H.265
+/// and AAC tracks must have valid codec framing. Other tracks are only checked structurally.
+///
+/// # Verification Heuristic
+///
+/// A wrong key produces a random looking output rather than an error, so framing is checked by looking at
+/// the few bits of every sample which are decrypted first: slice headers of video samples (first slice of
+/// a picture, known parameter set IDs and frame number of IDR pictures) and the first syntax element of
+/// AAC samples. This can't tell a wrong key apart when these bits are stored in the clear, which is common
+/// with `cbcs` content, and arbitrary slice order is rejected on purpose.
+///
+/// # Performance
+///
+/// Every candidate costs a complete decryption and a pass over the sample tables,
+/// so trying `n` key maps is up to `n` times slower than [mp4decrypt](crate::mp4decrypt).
+/// Put the most probable key map first, or try the candidates on the first fragments only by using
+/// [decrypt_prefix](crate::decrypt_prefix) output as `data`.
+///
+/// # Arguments
+///
+/// * `data` - Encrypted data stream.
+/// * `init` (optional) - Init segment, if `data` doesn't contain a `moov` box.
+///   Only `data` is decrypted and returned, same as [mp4decrypt](crate::mp4decrypt).
+/// * `candidates` - Key maps to try, in order.
+///
+/// Returns the index of the first valid candidate and its decrypted data, or
+/// [ErrorType::AllCandidatesFailed](ErrorType::AllCandidatesFailed) holding the error of every candidate.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::{ErrorType, KeyMap};
+///
+/// let mut wrong_keys = KeyMap::new();
+/// wrong_keys
+///     .insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacc")
+///     .unwrap();
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let (index, decrypted_data) =
+///     mp4decrypt::decrypt_try_keys(&segment, Some(&init), &[wrong_keys.clone(), keys.clone()])
+///         .unwrap();
+/// assert_eq!(index, 1);
+/// let options = mp4decrypt::DecryptOptions::new();
+/// assert_eq!(
+///     decrypted_data,
+///     mp4decrypt::mp4decrypt_with_options(&segment, &keys, Some(&init), &options).unwrap()
+/// );
+///
+/// let error = mp4decrypt::decrypt_try_keys(&segment, Some(&init), &[wrong_keys]).unwrap_err();
+/// let ErrorType::AllCandidatesFailed(errors) = error.err_type else {
+///     unreachable!()
+/// };
+/// assert!(matches!(errors[0].err_type, ErrorType::VerificationFailed { track_id: 1, .. }));
+/// ```
+pub fn decrypt_try_keys(
+    data: &[u8],
+    init: Option<&[u8]>,
+    candidates: &[KeyMap],
+) -> Result<(usize, Vec<u8>), Error> {
+    let options = DecryptOptions::new().validate_structure(true);
+    let mut errors = Vec::with_capacity(candidates.len());
+
+    for (i, keys) in candidates.iter().enumerate() {
+        let result =
+            mp4decrypt_with_options(data, keys, init, &options).and_then(|decrypted_data| {
+                // Track configs are read from the decrypted init segment, which has clear sample entries.
+                let decrypted_init = init
+                    .map(|x| SegmentDecryptor::new(x, keys)?.decrypt_init())
+                    .transpose()?;
+                verify_media(&decrypted_data, decrypted_init.as_deref())?;
+                Ok(decrypted_data)
+            });
+
+        match result {
+            Ok(decrypted_data) => return Ok((i, decrypted_data)),
+            Err(x) => errors.push(x),
+        }
+    }
+
+    Err(Error {
+        msg: format!(
+            "none of the {} candidate key maps produced valid media.",
+            candidates.len()
+        ),
+        err_type: ErrorType::AllCandidatesFailed(errors),
+        invalid_key: false,
+    })
+}
+
+/// Check whether keys appear to be correct by decrypting only the first fragment of every protected track
+/// and checking the codec framing of its samples, which is much faster than decrypting the complete file.
+///
+/// This is a heuristic, see [decrypt_try_keys](decrypt_try_keys) for what is checked and when a wrong key
+/// can't be detected. Bento4 decrypts complete fragments, so every sample of these fragments is checked
+/// instead of only the first one, since the first sample often has its slice headers stored in the clear.
+/// Non-fragmented files are decrypted completely.
+///
+/// Returns `false` if a track is left encrypted or a sample doesn't have valid framing, and an error if
+/// data stream can't be decrypted at all.
+///
+/// # Example
+///
+/// ```
+/// use mp4decrypt::KeyMap;
+///
+/// let init = std::fs::read("examples/sample/init.mp4").unwrap();
+/// let segment = std::fs::read("examples/sample/segment_0.m4s").unwrap();
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacb")
+///     .unwrap();
+/// assert!(mp4decrypt::quick_verify(&segment, Some(&init), &keys).unwrap());
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert("eb676abbcb345e96bbcf616630f1a3da", "100b6c20940f779a4589152b57d2dacc")
+///     .unwrap();
+/// assert!(!mp4decrypt::quick_verify(&segment, Some(&init), &keys).unwrap());
+///
+/// // no key for the track
+/// assert!(!mp4decrypt::quick_verify(&segment, Some(&init), &KeyMap::new()).unwrap());
+/// ```
+pub fn quick_verify(data: &[u8], init: Option<&[u8]>, keys: &KeyMap) -> Result<bool, Error> {
+    let split = split::split_fragments(data)?;
+
+    let decrypted_data = if split.fragments.is_empty() {
+        decrypt(data, keys, None)?
+    } else {
+        let init = init.unwrap_or(split.init);
+        let trex_sizes = moof::read_trex_sizes(init)?;
+        let mut remaining = metadata::list_tracks(init)?
+            .into_iter()
+            .filter(|x| x.is_protected())
+            .map(|x| x.track_id)
+            .collect::<HashSet<_>>();
+        let mut fragments = Vec::new();
+
+        for fragment in split.fragments {
+            if remaining.is_empty() {
+                break;
+            }
+
+            let mut has_new_track = false;
+
+            for moof in boxes::boxes(fragment) {
+                let moof = moof?;
+
+                if &moof.box_type == b"moof" {
+                    for x in moof::read_track_fragments(&moof, &trex_sizes)? {
+                        has_new_track |= remaining.remove(&x.track_id);
+                    }
+                }
+            }
+
+            if has_new_track {
+                fragments.push(fragment);
+            }
+        }
+
+        let prefix = split::join_parts([init].into_iter().chain(fragments))?;
+        decrypt(&prefix, keys, None)?
+    };
+
+    match verify_media(&decrypted_data, None) {
+        Ok(()) => Ok(true),
+        Err(Error {
+            err_type: ErrorType::VerificationFailed { .. } | ErrorType::MissingKeyForTrack(..),
+            ..
+        }) => Ok(false),
+        Err(x) => Err(x),
+    }
+}
+
+/// Check that no track of a decrypted data stream is left encrypted and its samples have valid codec framing,
+/// see [decrypt_try_keys](decrypt_try_keys) for the heuristic.
+///
+/// # Arguments
+///
+/// * `data` - Decrypted data stream.
+/// * `init` (optional) - Decrypted init segment of `data`, if `data` doesn't contain a `moov` box.
+pub(crate) fn verify_media(data: &[u8], init: Option<&[u8]>) -> Result<(), Error> {
+    let is_self_contained = init.is_none();
+    let init = init.unwrap_or(data);
+    let Some(moov) = boxes::find(boxes::boxes(init), b"moov")? else {
+        return Err(Error::new_format("no moov box found in the data stream."));
+    };
+
+    let mut framings = HashMap::new();
+    let mut checked_traks = Vec::new();
+
+    for trak in moov.children() {
+        let trak = trak?;
+
+        if &trak.box_type != b"trak" {
+            continue;
+        }
+
+        let track = metadata::read_trak(&trak)?;
+
+        if track.is_protected() {
+            return Err(still_encrypted(track.track_id, track.default_kid));
+        }
+
+        if let Some(framing) = read_framing(&trak, &track.sample_entry)? {
+            framings.insert(track.track_id, framing);
+            checked_traks.push(trak);
+        }
+    }
+
+    // Samples of a non-fragmented file are described by the sample tables of its moov box.
+    if is_self_contained {
+        for trak in checked_traks {
+            let track_id = metadata::read_trak(&trak)?.track_id;
+            let framing = framings.get_mut(&track_id).unwrap();
+
+            for sample in read_stbl_samples(&trak)? {
+                check_sample(data, &sample, track_id, framing)?;
+            }
+        }
+    }
+
+    let trex_sizes = moof::read_trex_sizes(init)?;
+
+    for moof in boxes::boxes(data) {
+        let moof = moof?;
+
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+
+        for fragment in moof::read_track_fragments(&moof, &trex_sizes)? {
+            // Bento4 keeps the encryption info of fragments which it doesn't decrypt.
+            if fragment
+                .mp4_box
+                .children()
+                .map_while(Result::ok)
+                .any(|x| matches!(&x.box_type, b"senc" | b"saiz"))
+            {
+                return Err(still_encrypted(fragment.track_id, None));
+            }
+
+            let Some(framing) = framings.get_mut(&fragment.track_id) else {
+                continue;
+            };
+
+            for sample in fragment.runs.iter().flat_map(|x| &x.samples) {
+                check_sample(data, sample, fragment.track_id, framing)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn still_encrypted(track_id: u32, kid: Option<[u8; 16]>) -> Error {
+    Error {
+        msg: format!("track {} is still encrypted after decryption.", track_id),
+        err_type: ErrorType::MissingKeyForTrack(track_id, kid),
+        invalid_key: false,
+    }
+}
+
+/// Read the codec framing of a clear sample entry, `None` if it can't be checked.
+fn read_framing(trak: &Mp4Box, sample_entry: &[u8; 4]) -> Result<Option<Framing>, Error> {
+    let config_type = match sample_entry {
+        b"avc1" | b"avc3" => b"avcC",
+        b"hvc1" | b"hev1" => b"hvcC",
+        // An unsupported AAC config isn't checked.
+        b"mp4a" => {
+            return Ok(adts::read_audio_config(trak)
+                .ok()
+                .map(|x| Framing::Aac(x.channel_configuration)));
+        }
+        _ => return Ok(None),
+    };
+
+    let Some(stsd) = [b"mdia", b"minf", b"stbl", b"stsd"].iter().try_fold(
+        Some(*trak),
+        |parent, x| match parent {
+            Some(parent) => parent.child(x),
+            None => Ok(None),
+        },
+    )?
+    else {
+        return Ok(None);
+    };
+
+    let Some(entry) = stsd.children_after(8).next().transpose()? else {
+        return Ok(None);
+    };
+
+    let Some(config) = boxes::find(entry.children_after(78), config_type)? else {
+        return Ok(None);
+    };
+
+    let mut reader = Reader::new(config.payload());
+    let mut nal_config = NalConfig {
+        hevc: config_type == b"hvcC",
+        length_size: 0,
+        sps: HashMap::new(),
+        pps: HashMap::new(),
+    };
+    let mut parameter_sets = Vec::new();
+
+    if nal_config.hevc {
+        reader.skip(21)?; // configurationVersion .. avgFrameRate
+        nal_config.length_size = (reader.read_u8()? & 0x03) as usize + 1;
+
+        for _ in 0..reader.read_u8()? {
+            reader.skip(1)?; // array_completeness, reserved, NAL_unit_type
+            for _ in 0..reader.read_u16()? {
+                let size = reader.read_u16()?;
+                parameter_sets.push(reader.read_bytes(size as usize)?);
+            }
+        }
+    } else {
+        reader.skip(4)?; // configurationVersion, profile, compatibility, level
+        nal_config.length_size = (reader.read_u8()? & 0x03) as usize + 1;
+
+        for _ in 0..2 {
+            let count = reader.read_u8()? & 0x1f;
+            for _ in 0..count {
+                let size = reader.read_u16()?;
+                parameter_sets.push(reader.read_bytes(size as usize)?);
+            }
+        }
+    }
+
+    for nal in parameter_sets {
+        // Parameter sets which can't be parsed make every slice referencing them invalid.
+        let _ = nal_config.read_parameter_set(nal);
+    }
+
+    Ok(Some(Framing::Nal(nal_config)))
+}
+
+fn check_sample(
+    data: &[u8],
+    sample: &FragmentSample,
+    track_id: u32,
+    framing: &mut Framing,
+) -> Result<(), Error> {
+    let Some(bytes) =
+        data.get(sample.offset as usize..(sample.offset + sample.size as u64) as usize)
+    else {
+        return Err(Error::new_format(format!(
+            "sample at offset {} of track {} is out of bounds.",
+            sample.offset, track_id
+        )));
+    };
+
+    let is_valid = match framing {
+        Framing::Nal(x) => x.check_sample(bytes),
+        // Raw data block starts with a SCE (mono and multichannel layouts) or CPE (stereo layout)
+        // element, optionally preceded by a DSE, PCE or FIL element.
+        Framing::Aac(channel_configuration) => match bytes.first().map(|x| x >> 5) {
+            None => true,
+            Some(4..=6) => true,
+            Some(0) => *channel_configuration != 2,
+            Some(1) => *channel_configuration == 2,
+            _ => false,
+        },
+    };
+
+    if is_valid {
+        return Ok(());
+    }
+
+    Err(Error {
+        msg: format!(
+            "sample at offset {} of track {} doesn't have a valid codec framing, the key may be wrong.",
+            sample.offset, track_id
+        ),
+        err_type: ErrorType::VerificationFailed {
+            track_id,
+            offset: sample.offset,
+        },
+        invalid_key: false,
+    })
+}
+
+impl NalConfig {
+    /// Check that NAL units fill the sample and its slice headers are consistent with the parameter sets.
+    fn check_sample(&mut self, sample: &[u8]) -> bool {
+        let mut reader = Reader::new(sample);
+        let mut first_slice = true;
+
+        while reader.remaining() > 0 {
+            let Ok(size) = reader.read_bytes(self.length_size) else {
+                return false;
+            };
+            let size = size.iter().fold(0, |x, y| (x << 8) | *y as usize);
+
+            let Ok(nal) = reader.read_bytes(size) else {
+                return false;
+            };
+
+            if nal.first().is_none_or(|x| x & 0x80 != 0) {
+                return false;
+            }
+
+            match self.check_nal(nal, first_slice) {
+                Ok(Some(true)) => first_slice = false,
+                Ok(Some(false)) | Err(_) => return false,
+                Ok(None) => (),
+            }
+        }
+
+        true
+    }
+
+    /// Check a slice NAL unit or read a parameter set, `None` if it isn't a slice.
+    fn check_nal(&mut self, nal: &[u8], first_slice: bool) -> Result<Option<bool>, Error> {
+        if self.hevc {
+            let nal_type = (nal[0] >> 1) & 0x3f;
+
+            if nal_type == 34 {
+                self.read_parameter_set(nal)?;
+            }
+
+            if !matches!(nal_type, 0..=9 | 16..=21) {
+                return Ok(None);
+            }
+
+            let rbsp = rbsp(nal.get(2..).unwrap_or_default(), 16);
+            let mut bits = BitReader::new(&rbsp);
+            let first_slice_segment_in_pic = bits.read(1)? == 1;
+            if (16..=23).contains(&nal_type) {
+                bits.read(1)?; // no_output_of_prior_pics_flag
+            }
+            let pps_id = bits.read_ue()?;
+
+            return Ok(Some(
+                self.pps.contains_key(&pps_id) && (!first_slice || first_slice_segment_in_pic),
+            ));
+        }
+
+        let nal_type = nal[0] & 0x1f;
+
+        if matches!(nal_type, 7 | 8) {
+            self.read_parameter_set(nal)?;
+        }
+
+        if !matches!(nal_type, 1 | 5) {
+            return Ok(None);
+        }
+
+        let rbsp = rbsp(&nal[1..], 32);
+        let mut bits = BitReader::new(&rbsp);
+        let first_mb_in_slice = bits.read_ue()?;
+        let slice_type = bits.read_ue()?;
+        let pps_id = bits.read_ue()?;
+
+        let Some((log2_max_frame_num, separate_colour_plane)) =
+            self.pps.get(&pps_id).and_then(|x| self.sps.get(x))
+        else {
+            return Ok(Some(false));
+        };
+
+        if slice_type > 9 || (first_slice && first_mb_in_slice != 0) {
+            return Ok(Some(false));
+        }
+
+        // IDR pictures only have I or SI slices and reset the frame number.
+        if nal_type == 5 {
+            if *separate_colour_plane {
+                bits.read(2)?; // colour_plane_id
+            }
+
+            let frame_num = bits.read(*log2_max_frame_num as usize)?;
+            return Ok(Some(matches!(slice_type % 5, 2 | 4) && frame_num == 0));
+        }
+
+        Ok(Some(true))
+    }
+
+    /// Read the IDs of a SPS or PPS NAL unit.
+    fn read_parameter_set(&mut self, nal: &[u8]) -> Result<(), Error> {
+        if self.hevc {
+            // Only PPS IDs are checked for H.265.
+            if nal.first().is_some_and(|x| (x >> 1) & 0x3f == 34) {
+                let rbsp = rbsp(nal.get(2..).unwrap_or_default(), 8);
+                self.pps.insert(BitReader::new(&rbsp).read_ue()?, 0);
+            }
+
+            return Ok(());
+        }
+
+        let rbsp = rbsp(nal.get(1..).unwrap_or_default(), usize::MAX);
+        let mut bits = BitReader::new(&rbsp);
+
+        match nal.first().map(|x| x & 0x1f) {
+            Some(7) => {
+                let profile_idc = bits.read(8)?;
+                bits.read(16)?; // constraint_set_flags, level_idc
+                let sps_id = bits.read_ue()?;
+                let mut separate_colour_plane = false;
+
+                if matches!(
+                    profile_idc,
+                    100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+                ) {
+                    let chroma_format_idc = bits.read_ue()?;
+                    if chroma_format_idc == 3 {
+                        separate_colour_plane = bits.read(1)? == 1;
+                    }
+                    bits.read_ue()?; // bit_depth_luma_minus8
+                    bits.read_ue()?; // bit_depth_chroma_minus8
+                    bits.read(1)?; // qpprime_y_zero_transform_bypass_flag
+
+                    if bits.read(1)? == 1 {
+                        let count = if chroma_format_idc == 3 { 12 } else { 8 };
+
+                        for i in 0..count {
+                            if bits.read(1)? == 1 {
+                                skip_scaling_list(&mut bits, if i < 6 { 16 } else { 64 })?;
+                            }
+                        }
+                    }
+                }
+
+                let log2_max_frame_num = bits.read_ue()? + 4;
+                self.sps
+                    .insert(sps_id, (log2_max_frame_num, separate_colour_plane));
+            }
+            Some(8) => {
+                let pps_id = bits.read_ue()?;
+                self.pps.insert(pps_id, bits.read_ue()?);
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+fn skip_scaling_list(bits: &mut BitReader, size: usize) -> Result<(), Error> {
+    let mut last_scale = 8;
+    let mut next_scale = 8;
+
+    for _ in 0..size {
+        if next_scale != 0 {
+            next_scale = (last_scale + bits.read_se()? + 256) % 256;
+        }
+
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove emulation prevention bytes from the first `limit` bytes of a NAL unit payload.
+fn rbsp(data: &[u8], limit: usize) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len().min(limit));
+    let mut zeros = 0;
+
+    for byte in data.iter().take(limit) {
+        if zeros >= 2 && *byte == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if *byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(*byte);
+    }
+
+    rbsp
+}
+
+/// Resolve the location of every sample of a non-fragmented track from its `stsz`, `stsc` and `stco` (or `co64`) boxes.
+pub(crate) fn read_stbl_samples(trak: &Mp4Box) -> Result<Vec<FragmentSample>, Error> {
+    let Some(stbl) = [b"mdia", b"minf", b"stbl"]
+        .iter()
+        .try_fold(Some(*trak), |parent, x| match parent {
+            Some(parent) => parent.child(x),
+            None => Ok(None),
+        })?
+    else {
+        return Ok(Vec::new());
+    };
+
+    let (Some(stsz), Some(stsc)) = (stbl.child(b"stsz")?, stbl.child(b"stsc")?) else {
+        return Ok(Vec::new());
+    };
+
+    let mut reader = Reader::new(stsz.payload());
+    reader.read_version_flags()?;
+    let sample_size = reader.read_u32()?;
+    let sample_count = reader.read_u32()?;
+
+    if sample_size == 0 && sample_count as usize > reader.remaining() / 4 {
+        return Err(Error::new_format(format!(
+            "stsz box at offset {} is too small for {} samples.",
+            stsz.offset, sample_count
+        )));
+    }
+
+    let mut sizes = Vec::with_capacity(if sample_size == 0 {
+        sample_count as usize
+    } else {
+        0
+    });
+    if sample_size == 0 {
+        for _ in 0..sample_count {
+            sizes.push(reader.read_u32()?);
+        }
+    }
+
+    let (chunk_box, offset_size) = match stbl.child(b"stco")? {
+        Some(x) => (x, 4),
+        None => match stbl.child(b"co64")? {
+            Some(x) => (x, 8),
+            None => return Ok(Vec::new()),
+        },
+    };
+
+    let mut reader = Reader::new(chunk_box.payload());
+    reader.read_version_flags()?;
+    let chunk_count = reader.read_u32()?;
+    let mut chunk_offsets =
+        Vec::with_capacity((chunk_count as usize).min(reader.remaining() / offset_size));
+
+    for _ in 0..chunk_count {
+        chunk_offsets.push(if offset_size == 4 {
+            reader.read_u32()? as u64
+        } else {
+            reader.read_u64()?
+        });
+    }
+
+    let mut reader = Reader::new(stsc.payload());
+    reader.read_version_flags()?;
+    let entry_count = reader.read_u32()?;
+    let mut entries = Vec::with_capacity((entry_count as usize).min(reader.remaining() / 12));
+
+    for _ in 0..entry_count {
+        let first_chunk = reader.read_u32()?;
+        let samples_per_chunk = reader.read_u32()?;
+        reader.skip(4)?; // sample_description_index
+        entries.push((first_chunk, samples_per_chunk));
+    }
+
+    // Samples of a constant size aren't bounded by the size of the stsz box.
+    let mut samples = Vec::with_capacity((sample_count as usize).min(sizes.len().max(1 << 16)));
+    let mut entry = 0;
+
+    for (i, chunk_offset) in chunk_offsets.into_iter().enumerate() {
+        while entries
+            .get(entry + 1)
+            .is_some_and(|x| x.0 as usize <= i + 1)
+        {
+            entry += 1;
+        }
+
+        let mut offset = chunk_offset;
+
+        for _ in 0..entries.get(entry).map_or(0, |x| x.1) {
+            if samples.len() == sample_count as usize {
+                return Ok(samples);
+            }
+
+            let size = sizes.get(samples.len()).copied().unwrap_or(sample_size);
+            samples.push(FragmentSample { offset, size });
+            offset = offset.checked_add(size as u64).ok_or_else(|| {
+                Error::new_format(format!(
+                    "{} box at offset {} has an invalid chunk offset.",
+                    boxes::fourcc(&chunk_box.box_type),
+                    chunk_box.offset
+                ))
+            })?;
+        }
+    }
+
+    Ok(samples)
+}
@@ -0,0 +1,152 @@
+use crate::{
+    Error,
+    boxes::{self, Mp4Box},
+    reader::Reader,
+    rewrite::{self, Replacement},
+};
+
+/// Rewrite the `tfdt` and `trun` boxes of a decrypted data stream which mixes versions 0 and 1 of them,
+/// see [DecryptOptions::normalize_box_versions](crate::DecryptOptions::normalize_box_versions).
+///
+/// Returns the box types whose versions are still mixed, i.e. `trun` boxes whose composition time offsets
+/// fit neither version. `fragments_info` holds the track defaults of fragments in `data`.
+pub(crate) fn normalize_box_versions(
+    data: &mut Vec<u8>,
+    fragments_info: Option<&[u8]>,
+) -> Result<Vec<[u8; 4]>, Error> {
+    let mut tfdts = Vec::new();
+    let mut truns = Vec::new();
+
+    for moof in boxes::boxes(data) {
+        let moof = moof?;
+
+        if &moof.box_type != b"moof" {
+            continue;
+        }
+
+        for traf in moof.children() {
+            let traf = traf?;
+
+            if &traf.box_type != b"traf" {
+                continue;
+            }
+
+            for child in traf.children() {
+                let child = child?;
+
+                match &child.box_type {
+                    b"tfdt" => tfdts.push(child),
+                    b"trun" => truns.push(child),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    let mut replacements = Vec::new();
+    let mut mixed = Vec::new();
+
+    // 64-bit decode times of version 1 can hold every decode time of version 0.
+    if is_mixed(&tfdts) {
+        for tfdt in tfdts.iter().filter(|x| version(x) == 0) {
+            let mut reader = Reader::new(tfdt.payload());
+            let (_, flags) = reader.read_version_flags()?;
+            let base_media_decode_time = reader.read_u32()? as u64;
+
+            let mut payload = (1 << 24 | flags).to_be_bytes().to_vec();
+            payload.extend_from_slice(&base_media_decode_time.to_be_bytes());
+            let mut new_tfdt = Vec::new();
+            boxes::write_box(&mut new_tfdt, b"tfdt", &payload);
+            replacements.push(Replacement::new(tfdt, new_tfdt));
+        }
+    }
+
+    // Composition time offsets are unsigned in version 0 and signed in version 1, but of the same size.
+    if is_mixed(&truns) {
+        let mut offsets = Vec::new();
+
+        for trun in &truns {
+            offsets.push((trun, composition_time_offsets(trun)?));
+        }
+
+        let fits = |version, fits: fn(&u32) -> bool| {
+            offsets
+                .iter()
+                .filter(|(x, _)| self::version(x) == version)
+                .all(|(_, x)| x.iter().all(fits))
+        };
+
+        let target = if fits(0, |x| *x <= i32::MAX as u32) {
+            Some(1)
+        } else if fits(1, |x| (*x as i32) >= 0) {
+            Some(0)
+        } else {
+            None
+        };
+
+        match target {
+            Some(target) => {
+                for trun in truns.iter().filter(|x| version(x) != target) {
+                    replacements.push(Replacement {
+                        offset: trun.offset + trun.header_size,
+                        size: 1,
+                        data: vec![target],
+                    });
+                }
+            }
+            None => mixed.push(*b"trun"),
+        }
+    }
+
+    rewrite::replace(data, fragments_info, replacements)?;
+    Ok(mixed)
+}
+
+fn version(mp4_box: &Mp4Box) -> u8 {
+    mp4_box.payload().first().copied().unwrap_or_default()
+}
+
+fn is_mixed(boxes: &[Mp4Box]) -> bool {
+    boxes.iter().any(|x| version(x) == 0) && boxes.iter().any(|x| version(x) == 1)
+}
+
+/// Composition time offsets of every sample of a `trun` box, as stored.
+fn composition_time_offsets(trun: &Mp4Box) -> Result<Vec<u32>, Error> {
+    let mut reader = Reader::new(trun.payload());
+    let (_, flags) = reader.read_version_flags()?;
+    let sample_count = reader.read_u32()?;
+
+    if flags & 0x800 == 0 {
+        return Ok(Vec::new());
+    }
+
+    if flags & 0x01 != 0 {
+        reader.skip(4)?; // data_offset
+    }
+    if flags & 0x04 != 0 {
+        reader.skip(4)?; // first_sample_flags
+    }
+
+    let field_size = [0x100, 0x200, 0x400, 0x800]
+        .iter()
+        .filter(|x| flags & **x != 0)
+        .count()
+        * 4;
+
+    if (sample_count as usize).saturating_mul(field_size) > reader.remaining() {
+        return Err(Error::new_format(format!(
+            "trun box at offset {} is too small for {} samples.",
+            trun.offset, sample_count
+        )));
+    }
+
+    let mut offsets = Vec::with_capacity(sample_count as usize);
+
+    for _ in 0..sample_count {
+        // sample_duration, sample_size, sample_flags
+        reader.skip(field_size - 4)?;
+        offsets.push(reader.read_u32()?);
+    }
+
+    Ok(offsets)
+}
@@ -0,0 +1,78 @@
+//! Assembling fragments decrypted one by one into a single file.
+
+mod common;
+
+use common::{keys, mp4_box, read};
+use mp4decrypt::DashOutput;
+
+#[test]
+fn assembled_equals_single_shot() {
+    let keys = keys();
+
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    // segments as downloaded, starting with their own segment type and index
+    let styp = mp4_box(b"styp", b"msdh\0\0\0\0msdhmsix");
+    let sidx = mp4_box(b"sidx", &[0; 24]);
+    let downloaded = [styp.clone(), sidx, segment.clone()].concat();
+
+    let DashOutput::PerSegment {
+        init: decrypted_init,
+        segments,
+    } = mp4decrypt::decrypt_dash_representation(
+        &init,
+        &[&downloaded, &downloaded, &downloaded],
+        &keys,
+        true,
+    )
+    .unwrap()
+    else {
+        unreachable!();
+    };
+    assert!(segments.iter().all(|x| x.starts_with(&styp)));
+
+    let data = [init, segment.clone(), segment.clone(), segment].concat();
+    let options = mp4decrypt::DecryptOptions::new();
+    let single_shot = mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &options).unwrap();
+    assert_eq!(
+        mp4decrypt::assemble(&decrypted_init, &segments).unwrap(),
+        single_shot
+    );
+}
+
+#[test]
+fn absolute_base_data_offsets() {
+    let init = mp4_box(b"ftyp", b"isom\0\0\0\x01isom");
+
+    // tfhd with a base data offset pointing to the mdat payload within its segment
+    let mut tfhd = vec![0, 0, 0, 1, 0, 0, 0, 1];
+    tfhd.extend(0_u64.to_be_bytes());
+    let moof = mp4_box(b"moof", &mp4_box(b"traf", &mp4_box(b"tfhd", &tfhd)));
+    let styp = mp4_box(b"styp", b"msdh\0\0\0\0");
+
+    let mut segment = [styp.clone(), moof.clone(), mp4_box(b"mdat", &[1, 2, 3])].concat();
+    let base_data_offset = (styp.len() + moof.len() + 8) as u64;
+    let tfhd_field = styp.len() + 8 + 8 + 8 + 8;
+    segment[tfhd_field..tfhd_field + 8].copy_from_slice(&base_data_offset.to_be_bytes());
+
+    let assembled = mp4decrypt::assemble(&init, &[segment.clone(), segment]).unwrap();
+    assert_eq!(assembled.len(), init.len() + 2 * (moof.len() + 11));
+
+    for (i, moof_offset) in [init.len(), init.len() + moof.len() + 11]
+        .into_iter()
+        .enumerate()
+    {
+        let field = moof_offset + 8 + 8 + 8 + 8;
+        let base_data_offset = u64::from_be_bytes(assembled[field..field + 8].try_into().unwrap());
+        assert_eq!(
+            base_data_offset as usize,
+            moof_offset + moof.len() + 8,
+            "fragment {}",
+            i
+        );
+        assert_eq!(&assembled[base_data_offset as usize..][..3], [1, 2, 3]);
+    }
+
+    // truncated fragment
+    assert!(mp4decrypt::assemble(&init, &[moof[..moof.len() - 1].to_vec()]).is_err());
+}
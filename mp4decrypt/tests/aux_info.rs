@@ -0,0 +1,82 @@
+//! IVs stored in sample auxiliary information referenced by `saiz` and `saio` boxes, without a `senc` box.
+
+mod common;
+
+use common::{KEY, keys, mdat_payload, read, u32_at};
+use mp4decrypt::{ErrorType, KeyMap};
+
+fn decrypt(data: &[u8], init: &[u8]) -> Result<Vec<u8>, mp4decrypt::Error> {
+    let keys = keys();
+
+    let options = mp4decrypt::DecryptOptions::new();
+    mp4decrypt::mp4decrypt_with_options(data, &keys, Some(init), &options)
+}
+
+/// Turn every child box of the `traf` box matching `hide` into a `free` box, keeping the offsets of
+/// everything else, so that `saio` offsets still point to the auxiliary information.
+fn hide_traf_boxes(segment: &[u8], hide: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    let mut data = segment.to_vec();
+    let traf = 8 + u32_at(&data, 8) as usize;
+    assert_eq!(&data[traf + 4..traf + 8], b"traf");
+
+    let end = traf + u32_at(&data, traf) as usize;
+    let mut offset = traf + 8;
+
+    while offset < end {
+        let size = u32_at(&data, offset) as usize;
+
+        if hide(&data[offset + 4..offset + 8]) {
+            data[offset + 4..offset + 8].copy_from_slice(b"free");
+        }
+
+        offset += size;
+    }
+
+    data
+}
+
+#[test]
+fn ivs_in_aux_info() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let expected = decrypt(&segment, &init).unwrap();
+
+    // senc box and PIFF sample encryption uuid box are hidden
+    let data = hide_traf_boxes(&segment, |x| x == b"senc" || x == b"uuid");
+    let aux_info = mp4decrypt::read_aux_info(&data, Some(&init))
+        .unwrap()
+        .unwrap();
+    let offset = aux_info.tracks[0].absolute_offsets().next().unwrap() as usize;
+    assert_eq!(&data[offset - 12..offset - 8], b"free");
+
+    let decrypted = decrypt(&data, &init).unwrap();
+    assert_eq!(mdat_payload(&decrypted), mdat_payload(&expected));
+
+    let mut keys = KeyMap::new();
+    keys.insert("1", KEY).unwrap();
+    let mut decryptor = mp4decrypt::SegmentDecryptor::new(&init, &keys).unwrap();
+    let decrypted = decryptor.decrypt_next(&data).unwrap();
+    assert_eq!(mdat_payload(&decrypted), mdat_payload(&expected));
+    assert!(mp4decrypt::quick_verify(&data, Some(&init), &keys).unwrap());
+}
+
+#[test]
+fn missing_ivs() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+
+    // Bento4 would pass the fragment through as it is.
+    let data = hide_traf_boxes(&segment, |x| matches!(x, b"senc" | b"uuid" | b"saio"));
+    let error = decrypt(&data, &init).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::MissingIvs { track_id: 1 }
+    ));
+
+    let mut joined = init.clone();
+    joined.extend(&data);
+    let options = mp4decrypt::DecryptOptions::new();
+    let error =
+        mp4decrypt::mp4decrypt_with_options(&joined, &KeyMap::new(), None, &options).unwrap_err();
+    assert!(matches!(error.err_type, ErrorType::MissingIvs { .. }));
+}
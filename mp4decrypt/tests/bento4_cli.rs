@@ -0,0 +1,90 @@
+//! Compare the output of this crate against the Bento4 `mp4decrypt` command line tool.
+//!
+//! Run with `cargo test --features bento4-cli-tests`, tests are skipped if `mp4decrypt` is not found in PATH.
+
+#![cfg(feature = "bento4-cli-tests")]
+
+mod common;
+
+use common::{KEY, KID};
+use mp4decrypt::KeyMap;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// Extra key for a track which isn't present, so that every key has to reach Bento4.
+const OTHER_KEY: (&str, &str) = ("2", "00000000000000000000000000000000");
+
+fn sample(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples/sample")
+        .join(name)
+}
+
+fn keys() -> KeyMap {
+    let mut keys = KeyMap::new();
+    keys.insert(OTHER_KEY.0, OTHER_KEY.1).unwrap();
+    keys.insert(KID, KEY).unwrap();
+    keys
+}
+
+/// Run the cli and return its output, or `None` if it isn't installed.
+fn bento4_cli(name: &str, args: &[&Path]) -> Option<Vec<u8>> {
+    if Command::new("mp4decrypt").output().is_err() {
+        eprintln!("skipping, mp4decrypt is not found in PATH.");
+        return None;
+    }
+
+    let output = env::temp_dir().join(format!("mp4decrypt-{}-{}.mp4", name, std::process::id()));
+    let status = Command::new("mp4decrypt")
+        .args(["--key", &format!("{}:{}", OTHER_KEY.0, OTHER_KEY.1)])
+        .args(["--key", &format!("{}:{}", KID, KEY)])
+        .args(args)
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success(), "mp4decrypt exited with {}", status);
+
+    let data = fs::read(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+    Some(data)
+}
+
+#[test]
+fn full_stream() {
+    let input = env::temp_dir().join(format!("mp4decrypt-input-{}.mp4", std::process::id()));
+    let mut data = fs::read(sample("init.mp4")).unwrap();
+    data.extend(fs::read(sample("segment_0.m4s")).unwrap());
+    fs::write(&input, &data).unwrap();
+
+    let expected = bento4_cli("full", &[&input]);
+    fs::remove_file(&input).unwrap();
+
+    if let Some(expected) = expected {
+        let decrypted_data =
+            mp4decrypt::mp4decrypt_with_options(&data, &keys(), None, &Default::default()).unwrap();
+        assert!(decrypted_data == expected, "output differs from mp4decrypt");
+    }
+}
+
+#[test]
+fn fragments_info() {
+    let init = sample("init.mp4");
+    let segment = sample("segment_0.m4s");
+
+    if let Some(expected) = bento4_cli(
+        "fragments",
+        &[Path::new("--fragments-info"), &init, &segment],
+    ) {
+        let decrypted_data = mp4decrypt::mp4decrypt_with_options(
+            &fs::read(segment).unwrap(),
+            &keys(),
+            Some(&fs::read(init).unwrap()),
+            &Default::default(),
+        )
+        .unwrap();
+        assert!(decrypted_data == expected, "output differs from mp4decrypt");
+    }
+}
@@ -0,0 +1,177 @@
+//! Box events of the streaming parser, compared with the box tree of `debug_dump`.
+
+mod common;
+
+use common::{mp4_box, read};
+use mp4decrypt::{BoxEvent, ErrorType};
+
+fn events(data: &[u8]) -> Result<Vec<BoxEvent>, mp4decrypt::Error> {
+    let mut events = Vec::new();
+    mp4decrypt::parse_boxes(data, |x| events.push(x))?;
+    Ok(events)
+}
+
+#[test]
+fn same_tree_as_debug_dump() {
+    let data = [read("init.mp4"), read("segment_0.m4s")].concat();
+    let mut tree = Vec::new();
+    mp4decrypt::debug_dump(&data, &mut tree).unwrap();
+
+    let events = events(&data).unwrap();
+    let mut lines = String::new();
+    let mut depths = Vec::new();
+
+    for event in &events {
+        match *event {
+            BoxEvent::Start {
+                box_type,
+                offset,
+                size,
+                depth,
+                ..
+            } => {
+                lines += &format!(
+                    "{:indent$}[{}] size={} offset={}\n",
+                    "",
+                    String::from_utf8_lossy(&box_type),
+                    size,
+                    offset,
+                    indent = depth * 2
+                );
+                depths.push((box_type, offset, depth));
+            }
+            BoxEvent::End {
+                box_type,
+                offset,
+                depth,
+            } => assert_eq!(depths.pop(), Some((box_type, offset, depth))),
+        }
+    }
+
+    assert_eq!(lines, String::from_utf8(tree).unwrap());
+    assert!(depths.is_empty());
+}
+
+#[test]
+fn subtitle_sample_entries() {
+    // reserved and data_reference_index, then namespace, schema_location and auxiliary_mime_types
+    let stpp = mp4_box(
+        b"stpp",
+        &[
+            &[0, 0, 0, 0, 0, 0, 0, 1][..],
+            b"http://www.w3.org/ns/ttml\0\0\0",
+            &mp4_box(b"btrt", &[0; 12]),
+        ]
+        .concat(),
+    );
+    let wvtt = mp4_box(
+        b"wvtt",
+        &[&[0, 0, 0, 0, 0, 0, 0, 1][..], &mp4_box(b"vttC", b"WEBVTT")].concat(),
+    );
+    let stsd = mp4_box(
+        b"stsd",
+        &[&[0, 0, 0, 0, 0, 0, 0, 2][..], &stpp, &wvtt].concat(),
+    );
+    let stbl = mp4_box(b"stbl", &stsd);
+
+    let types = events(&stbl)
+        .unwrap()
+        .into_iter()
+        .filter_map(|x| match x {
+            BoxEvent::Start { box_type, .. } => Some(box_type),
+            BoxEvent::End { .. } => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(types, [*b"stbl", *b"stsd", *b"stpp", *b"wvtt", *b"vttC"]);
+
+    let mut tree = Vec::new();
+    mp4decrypt::debug_dump(&stbl, &mut tree).unwrap();
+    assert_eq!(
+        String::from_utf8(tree).unwrap().lines().count(),
+        types.len()
+    );
+}
+
+#[test]
+fn largesize_and_size_zero() {
+    let free = mp4_box(b"free", &[0; 4]);
+    let mut moov = [
+        &1_u32.to_be_bytes()[..],
+        b"moov",
+        &(16 + free.len() as u64).to_be_bytes(),
+    ]
+    .concat();
+    moov.extend(&free);
+    let mdat = [&0_u32.to_be_bytes(), b"mdat", &[1; 100][..]].concat();
+
+    let events = events(&[moov, mdat].concat()).unwrap();
+    assert_eq!(
+        events,
+        [
+            BoxEvent::Start {
+                box_type: *b"moov",
+                offset: 0,
+                size: 28,
+                header_size: 16,
+                depth: 0,
+            },
+            BoxEvent::Start {
+                box_type: *b"free",
+                offset: 16,
+                size: 12,
+                header_size: 8,
+                depth: 1,
+            },
+            BoxEvent::End {
+                box_type: *b"free",
+                offset: 16,
+                depth: 1,
+            },
+            BoxEvent::End {
+                box_type: *b"moov",
+                offset: 0,
+                depth: 0,
+            },
+            BoxEvent::Start {
+                box_type: *b"mdat",
+                offset: 28,
+                size: 0,
+                header_size: 8,
+                depth: 0,
+            },
+            BoxEvent::End {
+                box_type: *b"mdat",
+                offset: 28,
+                depth: 0,
+            },
+        ]
+    );
+}
+
+#[test]
+fn malformed_boxes() {
+    let init = read("init.mp4");
+
+    // truncated in the middle of the moov box
+    let mut count = 0;
+    let error = mp4decrypt::parse_boxes(&init[..1000], |_| count += 1).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::MalformedBox {
+            box_type: [b'm', b'o', b'o', b'v'],
+            offset: 32,
+        }
+    ));
+    assert!(count > 0);
+
+    // child box past the end of its parent
+    let moov = mp4_box(b"moov", &mp4_box(b"free", &[0; 4])[..8]);
+    let error = events(&moov).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::MalformedBox {
+            box_type: [b'f', b'r', b'e', b'e'],
+            offset: 8,
+        }
+    ));
+}
@@ -0,0 +1,161 @@
+//! Feed random box headers to the pure Rust parsing helpers, which must skip unknown boxes by their size
+//! and report malformed ones without panicking or reading out of bounds.
+
+mod common;
+
+use common::{mp4_box, read};
+use mp4decrypt::{ErrorType, Mp4Metadata};
+
+/// Deterministic xorshift generator, so that failures can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// Random box with a plausible or broken size, type and payload.
+fn random_box(rng: &mut Rng, depth: u32) -> Vec<u8> {
+    const TYPES: [&[u8; 4]; 12] = [
+        b"free", b"skip", b"uuid", b"moov", b"trak", b"moof", b"traf", b"mdat", b"pssh", b"tenc",
+        b"sgpd", b"zzzz",
+    ];
+
+    let box_type = TYPES[rng.below(TYPES.len() as u64) as usize];
+    let payload: Vec<u8> = if depth < 3 && rng.below(2) == 0 {
+        (0..rng.below(3))
+            .flat_map(|_| random_box(rng, depth + 1))
+            .collect()
+    } else {
+        (0..rng.below(40)).map(|_| rng.next() as u8).collect()
+    };
+
+    let mut data = match rng.below(6) {
+        // zero size
+        0 => [0, 0, 0, 0].to_vec(),
+        // largesize
+        1 => {
+            let mut data = 1_u32.to_be_bytes().to_vec();
+            data.extend(box_type);
+            data.extend((16 + payload.len() as u64).to_be_bytes());
+            data.extend(&payload);
+            return data;
+        }
+        // larger than remaining
+        2 => (payload.len() as u32 + 8 + rng.below(64) as u32 + 1)
+            .to_be_bytes()
+            .to_vec(),
+        // random
+        3 => (rng.next() as u32).to_be_bytes().to_vec(),
+        _ => (8 + payload.len() as u32).to_be_bytes().to_vec(),
+    };
+
+    data.extend(box_type);
+    data.extend(payload);
+    data
+}
+
+#[test]
+fn random_box_headers() {
+    let mut rng = Rng(0x9e3779b97f4a7c15);
+
+    for _ in 0..2000 {
+        let mut data = (0..rng.below(4) + 1)
+            .flat_map(|_| random_box(&mut rng, 0))
+            .collect::<Vec<_>>();
+        // truncated box header
+        data.truncate(data.len() - rng.below(data.len() as u64 / 2 + 1) as usize);
+
+        let results = [
+            Mp4Metadata::parse(&data).err(),
+            mp4decrypt::is_encrypted(&data).err(),
+            mp4decrypt::collect_all_kids(&data).err(),
+            mp4decrypt::extract_init(&data).err(),
+            mp4decrypt::read_sidx(&data).err(),
+            mp4decrypt::read_ftyp(&data).err(),
+            mp4decrypt::read_aux_info(&data, None).err(),
+            mp4decrypt::count_fragments(&data).err(),
+            mp4decrypt::needs_fragments_info(&data).err(),
+            mp4decrypt::debug_dump(&data, &mut std::io::sink()).err(),
+        ];
+
+        for error in results.into_iter().flatten() {
+            assert!(
+                matches!(
+                    error.err_type,
+                    ErrorType::MalformedBox { .. } | ErrorType::InvalidFormat
+                ),
+                "unexpected error {:?}",
+                error
+            );
+        }
+    }
+}
+
+#[test]
+fn skip_unknown_boxes() {
+    let moov = read("init.mp4");
+
+    let mut uuid = [0xff; 16].to_vec();
+    uuid.extend([1, 2, 3]);
+    let mut largesize = 1_u32.to_be_bytes().to_vec();
+    largesize.extend(b"free");
+    largesize.extend(20_u64.to_be_bytes());
+    largesize.extend([0; 4]);
+
+    let mut data = mp4_box(b"skip", &[0; 7]);
+    data.extend(mp4_box(b"uuid", &uuid));
+    data.extend(largesize);
+    data.extend(mp4_box(b"zzzz", &[]));
+    data.extend(&moov);
+
+    let expected = Mp4Metadata::parse(&moov).unwrap();
+    assert_eq!(Mp4Metadata::parse(&data).unwrap(), expected);
+    assert!(mp4decrypt::is_encrypted(&data).unwrap());
+}
+
+#[test]
+fn malformed_sizes() {
+    let free = mp4_box(b"free", &[0; 8]);
+
+    // larger than remaining
+    let mut data = free.clone();
+    data.extend(32_u32.to_be_bytes());
+    data.extend(b"moov");
+    let error = Mp4Metadata::parse(&data).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::MalformedBox {
+            box_type: [b'm', b'o', b'o', b'v'],
+            offset: 16
+        }
+    ));
+
+    // zero size is only valid for a top level box
+    let mut data = mp4_box(b"moov", &mp4_box(b"free", &[]));
+    data.extend(0_u32.to_be_bytes());
+    data.extend(b"mdat");
+    assert!(Mp4Metadata::parse(&data).is_ok());
+
+    let data = mp4_box(b"moov", &[0, 0, 0, 0, b't', b'r', b'a', b'k']);
+    let error = Mp4Metadata::parse(&data).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::MalformedBox { offset: 8, .. }
+    ));
+
+    // truncated header
+    let error = Mp4Metadata::parse(&free[..6]).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::MalformedBox { offset: 0, .. }
+    ));
+}
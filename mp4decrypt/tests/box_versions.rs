@@ -0,0 +1,109 @@
+//! Sample fragments mixing versions 0 and 1 of `tfdt` and `trun` boxes, normalized in the decrypted output.
+//! The `tfdt` box of the sample fragment is version 1 and its `trun` box is version 0.
+
+mod common;
+
+use common::{keys, read, set_u32, u32_at};
+use mp4decrypt::DecryptOptions;
+
+/// Sample fragment with a version 0 `tfdt` box, which is 4 bytes smaller.
+fn tfdt_v0(segment: &[u8]) -> Vec<u8> {
+    let (tfdt, trun, saio) = (52, 72, 1413);
+    assert_eq!(&segment[tfdt + 4..tfdt + 9], b"tfdt\x01");
+
+    let time = u64::from_be_bytes(segment[tfdt + 12..tfdt + 20].try_into().unwrap());
+    let mut new_tfdt = [16_u32.to_be_bytes(), *b"tfdt", [0; 4]].concat();
+    new_tfdt.extend((time as u32).to_be_bytes());
+    let mut data = [&segment[..tfdt], &new_tfdt, &segment[tfdt + 20..]].concat();
+
+    // moof, traf, trun data_offset and saio offset, which are relative to the moof box
+    for offset in [0, 24, trun - 4 + 16, saio - 4 + 16] {
+        let value = u32_at(&data, offset) - 4;
+        set_u32(&mut data, offset, value);
+    }
+
+    data
+}
+
+/// Sample fragment with a `trun` box of the given version, whose first composition time offset is `offset`.
+fn trun(segment: &[u8], version: u8, offset: Option<u32>) -> Vec<u8> {
+    let trun = 72;
+    assert_eq!(&segment[trun + 4..trun + 8], b"trun");
+
+    let mut data = segment.to_vec();
+    data[trun + 8] = version;
+
+    if let Some(offset) = offset {
+        // version and flags, sample_count, data_offset, first_sample_flags, sample_duration and sample_size
+        set_u32(&mut data, trun + 8 + 16 + 8, offset);
+    }
+
+    data
+}
+
+fn decrypt(data: &[u8], options: &DecryptOptions) -> (Vec<u8>, mp4decrypt::DecryptReport) {
+    mp4decrypt::mp4decrypt_with_report(data, &keys(), None, options).unwrap()
+}
+
+#[test]
+fn mixed_tfdt_versions() {
+    let (init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+    let data = [&init, &segment, &tfdt_v0(&segment)[..]].concat();
+
+    // disabled by default
+    let (decrypted, _) = decrypt(&data, &DecryptOptions::new());
+    let expected = decrypt(
+        &[&init, &segment, &segment[..]].concat(),
+        &DecryptOptions::new(),
+    )
+    .0;
+    assert_eq!(decrypted.len(), expected.len() - 4);
+
+    let options = DecryptOptions::new()
+        .normalize_box_versions(true)
+        .validate_structure(true);
+    let (normalized, report) = decrypt(&data, &options);
+    assert_eq!(normalized, expected);
+    assert!(report.mixed_box_versions.is_empty());
+
+    let mut sink = Vec::new();
+    mp4decrypt::mp4decrypt_into_with_options(&data, &keys(), None, &options, &mut sink).unwrap();
+    assert_eq!(sink, expected);
+
+    // a single version is left as it is
+    let data = [&init, &tfdt_v0(&segment), &tfdt_v0(&segment)[..]].concat();
+    assert_eq!(
+        decrypt(&data, &options).0,
+        decrypt(&data, &DecryptOptions::new()).0
+    );
+}
+
+#[test]
+fn mixed_trun_versions() {
+    let (init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+    let options = DecryptOptions::new().normalize_box_versions(true);
+
+    // version 0 composition time offsets fit version 1
+    let v1 = trun(&segment, 1, None);
+    let (normalized, report) = decrypt(&[&init, &segment, &v1[..]].concat(), &options);
+    let expected = decrypt(&[&init, &v1, &v1[..]].concat(), &DecryptOptions::new()).0;
+    assert_eq!(normalized, expected);
+    assert!(report.mixed_box_versions.is_empty());
+
+    // a signed composition time offset which doesn't fit version 0 is kept
+    let v1 = trun(&segment, 1, Some(-1_i32 as u32));
+    let (normalized, _) = decrypt(&[&init, &segment, &v1[..]].concat(), &options);
+    let expected = decrypt(
+        &[&init, &trun(&segment, 1, None), &v1[..]].concat(),
+        &DecryptOptions::new(),
+    )
+    .0;
+    assert_eq!(normalized, expected);
+
+    // neither version fits both fragments
+    let v0 = trun(&segment, 0, Some(u32::MAX));
+    let data = [&init, &v0, &v1[..]].concat();
+    let (normalized, report) = decrypt(&data, &options);
+    assert_eq!(normalized, decrypt(&data, &DecryptOptions::new()).0);
+    assert_eq!(report.mixed_box_versions, [*b"trun"]);
+}
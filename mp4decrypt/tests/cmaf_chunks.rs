@@ -0,0 +1,105 @@
+//! CMAF chunks of a low-latency stream, which must be written before the next chunk is read.
+
+mod common;
+
+use common::{keys, read};
+use mp4decrypt::DecryptOptions;
+use std::{
+    cell::RefCell,
+    io::{Read, Write},
+    rc::Rc,
+};
+
+/// Reader of several parts, recording how many bytes were flushed before a part is read.
+struct Parts {
+    parts: Vec<Vec<u8>>,
+    pos: usize,
+    flushed: Rc<RefCell<usize>>,
+    flushed_before: Vec<usize>,
+}
+
+impl Read for Parts {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.parts.first().is_some_and(|x| x.len() == self.pos) {
+            self.parts.remove(0);
+            self.pos = 0;
+        }
+
+        let Some(part) = self.parts.first() else {
+            return Ok(0);
+        };
+
+        if self.pos == 0 {
+            self.flushed_before.push(*self.flushed.borrow());
+        }
+
+        let len = buf.len().min(part.len() - self.pos);
+        buf[..len].copy_from_slice(&part[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+struct Writer {
+    data: Vec<u8>,
+    flushed: Rc<RefCell<usize>>,
+}
+
+impl Write for Writer {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.data.write(data)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        *self.flushed.borrow_mut() = self.data.len();
+        Ok(())
+    }
+}
+
+fn decrypt_parts(parts: Vec<Vec<u8>>, options: &DecryptOptions) -> (Vec<u8>, Vec<usize>) {
+    let flushed = Rc::new(RefCell::new(0));
+    let mut reader = Parts {
+        parts,
+        pos: 0,
+        flushed: flushed.clone(),
+        flushed_before: Vec::new(),
+    };
+    let mut writer = Writer {
+        data: Vec::new(),
+        flushed,
+    };
+
+    mp4decrypt::decrypt_stream(&mut reader, &mut writer, &keys(), options).unwrap();
+    (writer.data, reader.flushed_before)
+}
+
+#[test]
+fn flush_cmaf_chunks() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let (moof, mdat) = segment.split_at(4705);
+    let styp = [&20_u32.to_be_bytes()[..], b"stypmsdh\0\0\0\0msdh"].concat();
+
+    let decrypt = |data: &[u8]| {
+        mp4decrypt::mp4decrypt_with_options(data, &keys(), None, &DecryptOptions::new()).unwrap()
+    };
+    let expected = decrypt(&[&init, &segment, &styp, &segment[..]].concat());
+    let first_chunk = decrypt(&[&init, &segment[..]].concat()).len();
+
+    // init, chunk 1, and chunk 2 split after its moof box
+    let parts = vec![
+        init.clone(),
+        segment.clone(),
+        [&styp[..], moof].concat(),
+        mdat.to_vec(),
+    ];
+    let options = DecryptOptions::new().flush_cmaf_chunks(true);
+    let (decrypted, flushed_before) = decrypt_parts(parts.clone(), &options);
+    assert_eq!(decrypted, expected);
+    assert_eq!(flushed_before, [0, 0, first_chunk, first_chunk]);
+
+    // only written once the next moof box or the end of the stream is read
+    let (decrypted, flushed_before) = decrypt_parts(parts, &DecryptOptions::new());
+    assert_eq!(decrypted, expected);
+    assert_eq!(flushed_before, [0, 0, 0, 0]);
+}
@@ -0,0 +1,66 @@
+//! Fixtures shared by the integration tests.
+
+// Every test crate uses a different part of this module.
+#![allow(dead_code)]
+
+use mp4decrypt::KeyMap;
+
+/// KID of the sample files.
+pub const KID: &str = "eb676abbcb345e96bbcf616630f1a3da";
+/// Key of the sample files.
+pub const KEY: &str = "100b6c20940f779a4589152b57d2dacb";
+
+/// Unity matrix of `mvhd` and `tkhd` boxes.
+pub const MATRIX: [u32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000];
+
+/// Read a file of `examples/sample`.
+pub fn read(path: &str) -> Vec<u8> {
+    std::fs::read(format!(
+        "{}/examples/sample/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        path
+    ))
+    .unwrap()
+}
+
+/// Key map holding the key of the sample files.
+pub fn keys() -> KeyMap {
+    let mut keys = KeyMap::new();
+    keys.insert(KID, KEY).unwrap();
+    keys
+}
+
+/// Box with a 32-bit size.
+pub fn mp4_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut data = (8 + payload.len() as u32).to_be_bytes().to_vec();
+    data.extend(box_type);
+    data.extend(payload);
+    data
+}
+
+/// Full box with a 32-bit size, followed by `fields`.
+pub fn full_box(box_type: &[u8; 4], version: u8, flags: u32, fields: &[u8]) -> Vec<u8> {
+    let mut payload = (((version as u32) << 24) | flags).to_be_bytes().to_vec();
+    payload.extend(fields);
+    mp4_box(box_type, &payload)
+}
+
+/// Big-endian bytes of every value.
+pub fn be32(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|x| x.to_be_bytes()).collect()
+}
+
+/// Big-endian 32-bit value at `offset`.
+pub fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Payload of the `mdat` box following the `moof` box of a sample segment.
+pub fn mdat_payload(segment: &[u8]) -> &[u8] {
+    &segment[u32_at(segment, 0) as usize + 8..]
+}
+
+/// Overwrite the big-endian 32-bit value at `offset`.
+pub fn set_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
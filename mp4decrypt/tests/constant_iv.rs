@@ -0,0 +1,220 @@
+//! Two `cbcs` audio tracks encrypted with different constant IVs, where the `tenc` boxes of the
+//! init segment don't carry the IVs which were used.
+
+mod common;
+
+use cbc::cipher::{BlockEncryptMut, KeyIvInit, block_padding::NoPadding};
+use common::{MATRIX, be32, full_box, mp4_box, read};
+use mp4decrypt::{DecryptOptions, ErrorType, KeyMap};
+use std::collections::HashMap;
+
+const KID: [u8; 16] = [0xeb; 16];
+const KEY: [u8; 16] = [0x10; 16];
+const SAMPLE_COUNT: usize = 4;
+const SAMPLE_SIZE: usize = 64;
+
+fn trak(track_id: u32, constant_iv: &[u8; 16]) -> Vec<u8> {
+    let mut tkhd = be32(&[0, 0, track_id, 0, 0, 0, 0]);
+    tkhd.extend([0, 0, 0, 0, 1, 0, 0, 0]); // layer, alternate_group, volume, reserved
+    tkhd.extend(be32(&MATRIX));
+    tkhd.extend(be32(&[0, 0]));
+
+    let mut hdlr = be32(&[0]);
+    hdlr.extend(b"soun");
+    hdlr.extend([0; 13]);
+
+    let mut tenc = vec![0, 0, 1, 0];
+    tenc.extend(KID);
+    tenc.push(16);
+    tenc.extend(constant_iv);
+
+    let mut schm = b"cbcs".to_vec();
+    schm.extend(be32(&[0x10000]));
+
+    let mut sinf = mp4_box(b"frma", b"mp4a");
+    sinf.extend(full_box(b"schm", 0, 0, &schm));
+    sinf.extend(mp4_box(b"schi", &full_box(b"tenc", 1, 0, &tenc)));
+
+    let mut enca = vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+    enca.extend([0, 2, 0, 16, 0, 0, 0, 0]); // channel_count, sample_size, pre_defined, reserved
+    enca.extend(be32(&[48000 << 16]));
+    enca.extend(mp4_box(b"sinf", &sinf));
+
+    let mut stsd = be32(&[1]);
+    stsd.extend(mp4_box(b"enca", &enca));
+
+    let mut stbl = full_box(b"stsd", 0, 0, &stsd);
+    stbl.extend(full_box(b"stts", 0, 0, &be32(&[0])));
+    stbl.extend(full_box(b"stsc", 0, 0, &be32(&[0])));
+    stbl.extend(full_box(b"stsz", 0, 0, &be32(&[0, 0])));
+    stbl.extend(full_box(b"stco", 0, 0, &be32(&[0])));
+
+    let dref = full_box(
+        b"dref",
+        0,
+        0,
+        &[&be32(&[1])[..], &full_box(b"url ", 0, 1, &[])].concat(),
+    );
+    let mut minf = full_box(b"smhd", 0, 0, &[0; 4]);
+    minf.extend(mp4_box(b"dinf", &dref));
+    minf.extend(mp4_box(b"stbl", &stbl));
+
+    let mut mdia = full_box(
+        b"mdhd",
+        0,
+        0,
+        &[&be32(&[0, 0, 48000, 0])[..], &[0x55, 0xc4, 0, 0]].concat(),
+    );
+    mdia.extend(full_box(b"hdlr", 0, 0, &hdlr));
+    mdia.extend(mp4_box(b"minf", &minf));
+
+    let mut trak = full_box(b"tkhd", 0, 3, &tkhd);
+    trak.extend(mp4_box(b"mdia", &mdia));
+    mp4_box(b"trak", &trak)
+}
+
+/// Init segment with the given constant IV in the `tenc` box of both tracks.
+fn init(constant_iv: &[u8; 16]) -> Vec<u8> {
+    let mut mvhd = be32(&[0, 0, 1000, 0, 0x10000]);
+    mvhd.extend([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // volume, reserved
+    mvhd.extend(be32(&MATRIX));
+    mvhd.extend([0; 24]);
+    mvhd.extend(be32(&[3]));
+
+    let mut moov = full_box(b"mvhd", 0, 0, &mvhd);
+    moov.extend(trak(1, constant_iv));
+    moov.extend(trak(2, constant_iv));
+    moov.extend(mp4_box(
+        b"mvex",
+        &[1, 2]
+            .iter()
+            .flat_map(|x| full_box(b"trex", 0, 0, &be32(&[*x, 1, 0, 0, 0])))
+            .collect::<Vec<_>>(),
+    ));
+
+    let mut data = mp4_box(b"ftyp", b"iso6\0\0\0\0iso6");
+    data.extend(mp4_box(b"moov", &moov));
+    data
+}
+
+fn plaintext(track_id: u32) -> Vec<u8> {
+    (0..SAMPLE_COUNT * SAMPLE_SIZE)
+        .map(|x| (x as u32 * 7 + track_id * 31) as u8)
+        .collect()
+}
+
+/// Fragment with every sample of both tracks, encrypted with a constant IV per track.
+fn fragment(ivs: &[[u8; 16]; 2]) -> Vec<u8> {
+    let traf = |track_id: u32, data_offset: u32| {
+        let mut trun = be32(&[SAMPLE_COUNT as u32, data_offset]);
+        trun.extend(be32(&[SAMPLE_SIZE as u32; SAMPLE_COUNT]));
+
+        let mut traf = full_box(b"tfhd", 0, 0x020000, &be32(&[track_id]));
+        traf.extend(full_box(b"trun", 0, 0x000201, &trun));
+        traf.extend(full_box(b"senc", 0, 0, &be32(&[SAMPLE_COUNT as u32])));
+        mp4_box(b"traf", &traf)
+    };
+
+    let moof_size = |offset| {
+        let mut moof = full_box(b"mfhd", 0, 0, &be32(&[1]));
+        moof.extend(traf(1, offset));
+        moof.extend(traf(2, offset + (SAMPLE_COUNT * SAMPLE_SIZE) as u32));
+        mp4_box(b"moof", &moof)
+    };
+    let offset = moof_size(0).len() as u32 + 8;
+    let mut data = moof_size(offset);
+
+    let mut mdat = Vec::new();
+
+    for (track_id, iv) in [1, 2].into_iter().zip(ivs) {
+        for sample in plaintext(track_id).chunks(SAMPLE_SIZE) {
+            let mut sample = sample.to_vec();
+            cbc::Encryptor::<aes::Aes128>::new(&KEY.into(), iv.into())
+                .encrypt_padded_mut::<NoPadding>(&mut sample, SAMPLE_SIZE)
+                .unwrap();
+            mdat.extend(sample);
+        }
+    }
+
+    data.extend(mp4_box(b"mdat", &mdat));
+    data
+}
+
+fn keys() -> KeyMap {
+    let mut keys = KeyMap::new();
+    keys.insert_kid(KID, KEY);
+    keys
+}
+
+fn mdat_payload(data: &[u8]) -> &[u8] {
+    &data[data.len() - SAMPLE_COUNT * SAMPLE_SIZE * 2..]
+}
+
+#[test]
+fn per_track_constant_ivs() {
+    let ivs = [[0x0a; 16], [0x0b; 16]];
+    let init = init(&[0; 16]);
+    let fragment = fragment(&ivs);
+    let expected = [plaintext(1), plaintext(2)].concat();
+
+    let options = DecryptOptions::new()
+        .constant_ivs(HashMap::from([(1, ivs[0].to_vec()), (2, ivs[1].to_vec())]));
+
+    // with fragments info
+    let decrypted =
+        mp4decrypt::mp4decrypt_with_options(&fragment, &keys(), Some(&init), &options).unwrap();
+    assert_eq!(mdat_payload(&decrypted), expected);
+
+    // self-contained
+    let data = [init.clone(), fragment.clone()].concat();
+    let decrypted = mp4decrypt::mp4decrypt_with_options(&data, &keys(), None, &options).unwrap();
+    assert_eq!(mdat_payload(&decrypted), expected);
+
+    let mut decrypted = Vec::new();
+    mp4decrypt::mp4decrypt_into_with_options(&data, &keys(), None, &options, &mut decrypted)
+        .unwrap();
+    assert_eq!(mdat_payload(&decrypted), expected);
+
+    // IVs of the tenc boxes are wrong
+    let options = DecryptOptions::new();
+    let decrypted =
+        mp4decrypt::mp4decrypt_with_options(&fragment, &keys(), Some(&init), &options).unwrap();
+    assert_ne!(mdat_payload(&decrypted), expected);
+
+    // a single IV override only fixes its own track
+    let options = DecryptOptions::new().constant_ivs(HashMap::from([(2, ivs[1].to_vec())]));
+    let decrypted =
+        mp4decrypt::mp4decrypt_with_options(&fragment, &keys(), Some(&init), &options).unwrap();
+    let (track_1, track_2) = mdat_payload(&decrypted).split_at(SAMPLE_COUNT * SAMPLE_SIZE);
+    assert_ne!(track_1, plaintext(1));
+    assert_eq!(track_2, plaintext(2));
+}
+
+#[test]
+fn invalid_constant_ivs() {
+    let init = init(&[0; 16]);
+    let fragment = fragment(&[[0x0a; 16], [0x0b; 16]]);
+
+    let options = DecryptOptions::new().constant_ivs(HashMap::from([(1, vec![0x0a; 8])]));
+    let error =
+        mp4decrypt::mp4decrypt_with_options(&fragment, &keys(), Some(&init), &options).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::IvLengthMismatch {
+            expected: 16,
+            got: 8
+        }
+    ));
+
+    let options = DecryptOptions::new().constant_ivs(HashMap::from([(3, vec![0x0a; 16])]));
+    let error =
+        mp4decrypt::mp4decrypt_with_options(&fragment, &keys(), Some(&init), &options).unwrap_err();
+    assert!(matches!(error.err_type, ErrorType::InvalidFormat));
+
+    // the sample file uses per sample IVs
+    let init = read("init.mp4");
+    let options = DecryptOptions::new().constant_ivs(HashMap::from([(1, vec![0; 8])]));
+    let error =
+        mp4decrypt::mp4decrypt_with_options(&init, &KeyMap::new(), None, &options).unwrap_err();
+    assert!(matches!(error.err_type, ErrorType::InvalidFormat));
+}
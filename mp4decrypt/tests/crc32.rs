@@ -0,0 +1,56 @@
+//! CRC-32 of decrypted samples, checked against a reference implementation.
+
+mod common;
+
+use common::{keys, read};
+use mp4decrypt::DecryptOptions;
+
+/// Payload of the last `mdat` box, which holds every sample of the segment.
+fn mdat_payload(data: &[u8]) -> &[u8] {
+    let mut offset = 0;
+
+    loop {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+
+        if &data[offset + 4..offset + 8] == b"mdat" {
+            return &data[offset + 8..offset + size];
+        }
+
+        offset += size;
+    }
+}
+
+#[test]
+fn crc32_of_decrypted_samples() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let options = DecryptOptions::new().compute_crc32(true);
+
+    let (decrypted, report) =
+        mp4decrypt::mp4decrypt_with_report(&segment, &keys(), Some(&init), &options).unwrap();
+    let expected = crc32fast::hash(mdat_payload(&decrypted));
+    assert_eq!(report.tracks[&1].crc32, Some(expected));
+    assert_eq!(report.tracks[&1].samples_decrypted, 100);
+
+    // init segment and media segment joined together, streamed into a sink
+    let data = [init.clone(), segment.clone()].concat();
+    let mut decrypted = Vec::new();
+    let report =
+        mp4decrypt::mp4decrypt_into_with_options(&data, &keys(), None, &options, &mut decrypted)
+            .unwrap();
+    assert_eq!(report.tracks[&1].crc32, Some(expected));
+
+    // two periods
+    let data = [data.clone(), data].concat();
+    let (_, report) = mp4decrypt::mp4decrypt_with_report(&data, &keys(), None, &options).unwrap();
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(mdat_payload(&decrypted));
+    hasher.update(mdat_payload(&decrypted));
+    assert_eq!(report.tracks[&1].crc32, Some(hasher.finalize()));
+
+    // not computed by default
+    let options = DecryptOptions::new().collect_stats(true);
+    let (_, report) =
+        mp4decrypt::mp4decrypt_with_report(&segment, &keys(), Some(&init), &options).unwrap();
+    assert_eq!(report.tracks[&1].crc32, None);
+}
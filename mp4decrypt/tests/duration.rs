@@ -0,0 +1,58 @@
+//! Total media duration of the decryption report, read from the sample timing or the `mvhd` box.
+
+mod common;
+
+use common::{keys, read, u32_at};
+use mp4decrypt::DecryptOptions;
+
+fn duration_seconds(data: &[u8], init: Option<&[u8]>) -> Option<f64> {
+    let options = DecryptOptions::new();
+    let (_, report) = mp4decrypt::mp4decrypt_with_report(data, &keys(), init, &options).unwrap();
+    report.duration_seconds
+}
+
+/// Sum of the sample durations of the `trun` box of the sample fragment, in the timescale of its track.
+fn trun_duration(segment: &[u8]) -> u64 {
+    let trun = 72;
+    assert_eq!(&segment[trun + 4..trun + 8], b"trun");
+
+    // version and flags, sample_count, data_offset and first_sample_flags, then duration, size and
+    // composition time offset of every sample
+    (0..u32_at(segment, trun + 12) as usize)
+        .map(|i| u32_at(segment, trun + 24 + i * 12) as u64)
+        .sum()
+}
+
+#[test]
+fn fragment_timing() {
+    let (init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+    let timescale = mp4decrypt::list_tracks(&init).unwrap()[0].timescale as f64;
+    let expected = trun_duration(&segment) as f64 / timescale;
+
+    assert_eq!(duration_seconds(&segment, Some(&init)), Some(expected));
+    assert_eq!(
+        duration_seconds(&[&init, &segment[..]].concat(), None),
+        Some(expected)
+    );
+
+    // consecutive fragments
+    let mut data = [&init, &segment, &segment[..]].concat();
+    let tfdt = init.len() + segment.len() + 52;
+    assert_eq!(&data[tfdt + 4..tfdt + 9], b"tfdt\x01");
+    let time = u64::from_be_bytes(data[tfdt + 12..tfdt + 20].try_into().unwrap());
+    let next = time + trun_duration(&segment);
+    data[tfdt + 12..tfdt + 20].copy_from_slice(&next.to_be_bytes());
+    assert_eq!(duration_seconds(&data, None), Some(expected * 2.0));
+}
+
+#[test]
+fn movie_header() {
+    let mut init = read("init.mp4");
+    // mvhd timescale of 1000 and duration of 210280
+    assert_eq!(duration_seconds(&init, None), Some(210.28));
+
+    let mvhd = 40;
+    assert_eq!(&init[mvhd + 4..mvhd + 8], b"mvhd");
+    init[mvhd + 24..mvhd + 28].copy_from_slice(&[0; 4]);
+    assert_eq!(duration_seconds(&init, None), None);
+}
@@ -0,0 +1,66 @@
+//! Key maps read from `KID:KEY` files, as written by common streaming tools.
+
+use mp4decrypt::{ErrorType, KeyId, KeyMap};
+use std::{fs, path::PathBuf};
+
+fn write_keys_file(name: &str, content: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("mp4decrypt-{}-{}.keys", name, std::process::id()));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn read_keys_file(name: &str, content: &str) -> Result<KeyMap, mp4decrypt::Error> {
+    let path = write_keys_file(name, content);
+    let keys = KeyMap::from_keys_file(&path);
+    fs::remove_file(path).unwrap();
+    keys
+}
+
+#[test]
+fn representative_files() {
+    let kid = mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3da").unwrap();
+    let key = mp4decrypt::parse_hex16("100b6c20940f779a4589152b57d2dacb").unwrap();
+
+    let keys = read_keys_file(
+        "single",
+        "eb676abbcb345e96bbcf616630f1a3da:100b6c20940f779a4589152b57d2dacb",
+    )
+    .unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys.get(&KeyId::Kid(kid)), Some(&key));
+
+    let content = "\
+        # keys of the video and audio tracks\r\n\
+        \r\n\
+        EB676ABBCB345E96BBCF616630F1A3DA : 100B6C20940F779A4589152B57D2DACB  # video\r\n\
+        \t eb676abb-cb34-5e96-bbcf-616630f1a3db:10 0b 6c 20 94 0f 77 9a 45 89 15 2b 57 d2 da cb\r\n\
+        2:100b6c20940f779a4589152b57d2dacb\r\n\
+        \n";
+    let keys = read_keys_file("commented", content).unwrap();
+    assert_eq!(keys.len(), 3);
+    assert_eq!(keys.get(&KeyId::Kid(kid)), Some(&key));
+    assert_eq!(keys.get(&KeyId::Track(2)), Some(&key));
+
+    assert!(read_keys_file("empty", "\n# no keys\n").unwrap().is_empty());
+}
+
+#[test]
+fn malformed_lines() {
+    let content = "\
+        eb676abbcb345e96bbcf616630f1a3da:100b6c20940f779a4589152b57d2dacb\n\
+        \n\
+        eb676abbcb345e96bbcf616630f1a3db 100b6c20940f779a4589152b57d2dacb\n";
+    let error = read_keys_file("separator", content).unwrap_err();
+    assert!(error.msg.starts_with("line 3 of "));
+    assert!(matches!(error.err_type, ErrorType::InvalidFormat));
+    assert!(error.invalid_key);
+
+    let content = "eb676abbcb345e96bbcf616630f1a3da:100b6c20940f779a4589152b57d2dac\n";
+    let error = read_keys_file("short-key", content).unwrap_err();
+    assert!(error.msg.starts_with("line 1 of "));
+    assert!(error.msg.ends_with(": invalid hex format for key."));
+
+    let error = KeyMap::from_keys_file("/nonexistent/mp4decrypt.keys").unwrap_err();
+    assert!(matches!(error.err_type, ErrorType::Io(_)));
+}
@@ -0,0 +1,111 @@
+//! Decryptions recorded with the `metrics` facade, using a recorder which keeps every value in memory.
+
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use common::{keys, read};
+use metrics::{
+    Counter, CounterFn, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Values(Mutex<Vec<(String, f64)>>);
+
+struct Handle(String, Arc<Values>);
+
+impl CounterFn for Handle {
+    fn increment(&self, value: u64) {
+        self.1
+            .0
+            .lock()
+            .unwrap()
+            .push((self.0.clone(), value as f64));
+    }
+
+    fn absolute(&self, _: u64) {}
+}
+
+impl HistogramFn for Handle {
+    fn record(&self, value: f64) {
+        self.1.0.lock().unwrap().push((self.0.clone(), value));
+    }
+}
+
+#[derive(Default)]
+struct MemoryRecorder(Arc<Values>);
+
+impl MemoryRecorder {
+    fn handle(&self, key: &Key) -> Arc<Handle> {
+        let labels = key
+            .labels()
+            .map(|x| format!("{}={}", x.key(), x.value()))
+            .collect::<Vec<_>>();
+        let name = match labels.is_empty() {
+            true => key.name().to_owned(),
+            false => format!("{}{{{}}}", key.name(), labels.join(",")),
+        };
+        Arc::new(Handle(name, self.0.clone()))
+    }
+
+    /// Sum of every value recorded with the name.
+    fn sum(&self, name: &str) -> f64 {
+        self.0
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(x, _)| x == name)
+            .map(|(_, x)| x)
+            .sum()
+    }
+}
+
+impl Recorder for MemoryRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        Counter::from_arc(self.handle(key))
+    }
+
+    fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+        Gauge::noop()
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(self.handle(key))
+    }
+}
+
+#[test]
+fn record_decryptions() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let keys = keys();
+
+    let recorder = MemoryRecorder::default();
+    let decrypted_data = metrics::with_local_recorder(&recorder, || {
+        // fragments without init segment
+        mp4decrypt::mp4decrypt_code(&segment, &keys, None).unwrap_err();
+        mp4decrypt::mp4decrypt_vectored(&[&segment[..]], &keys, Some(&init)).unwrap()
+    });
+
+    assert_eq!(recorder.sum("mp4decrypt_decryptions_total{result=ok}"), 1.0);
+    assert_eq!(
+        recorder.sum("mp4decrypt_decryptions_total{result=missing_init_segment}"),
+        1.0
+    );
+    assert_eq!(
+        recorder.sum("mp4decrypt_input_bytes_total"),
+        2.0 * segment.len() as f64
+    );
+    assert_eq!(
+        recorder.sum("mp4decrypt_output_bytes_total"),
+        decrypted_data.len() as f64
+    );
+    assert!(recorder.sum("mp4decrypt_duration_seconds") > 0.0);
+}
@@ -0,0 +1,322 @@
+//! Adversarial inputs fed to the public functions, which return errors for them and never panic.
+//!
+//! Inputs are the sample init segment and media segment with every box size and every aligned 32-bit and
+//! 64-bit field overwritten by boundary values, truncated at every box boundary, a few crafted fragments and
+//! random bytes. Only panics are checked, whether a function returns `Ok` or `Err` for an input doesn't matter.
+//!
+//! Aborts can't be caught and aren't covered, see the crate documentation. Bento4 aborts on some of the
+//! mutated init segments, so only the mutated fragments are decrypted.
+
+mod common;
+
+use common::{be32, full_box, keys, read};
+use mp4decrypt::{DecryptOptions, Mp4File, Mp4Metadata};
+use std::{
+    collections::HashMap,
+    io::{self, Cursor},
+    panic::{self, AssertUnwindSafe},
+};
+
+/// Offsets of every box in `data`, walking into containers and sample entries.
+fn box_offsets(data: &[u8]) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    // the mdat box of a fragment is truncated
+    let _ = mp4decrypt::parse_boxes(data, |event| {
+        if let mp4decrypt::BoxEvent::Start { offset, .. } = event {
+            offsets.push(offset as usize);
+        }
+    });
+    offsets
+}
+
+/// Copies of `data` with every box size and every aligned 32-bit field replaced by boundary values,
+/// and truncated at every box boundary.
+fn mutations(data: &[u8]) -> Vec<Vec<u8>> {
+    let offsets = box_offsets(data);
+    let mut mutations = Vec::new();
+
+    for offset in &offsets {
+        for size in [0, 1, 7, 8, 9, u32::MAX] {
+            let mut mutation = data.to_vec();
+            mutation[*offset..*offset + 4].copy_from_slice(&u32::to_be_bytes(size));
+            mutations.push(mutation);
+        }
+
+        // largesize
+        let mut mutation = data.to_vec();
+        mutation[*offset..*offset + 4].copy_from_slice(&1_u32.to_be_bytes());
+        mutations.push(mutation);
+
+        for cut in [*offset, *offset + 4, *offset + 9] {
+            mutations.push(data[..cut.min(data.len())].to_vec());
+        }
+    }
+
+    for offset in (0..data.len() - 4).step_by(4) {
+        for value in [0, 0x7fff_ffff, u32::MAX] {
+            let mut mutation = data.to_vec();
+            mutation[offset..offset + 4].copy_from_slice(&u32::to_be_bytes(value));
+            mutations.push(mutation);
+        }
+
+        // 64-bit fields
+        if offset + 8 <= data.len() {
+            let mut mutation = data.to_vec();
+            mutation[offset..offset + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+            mutations.push(mutation);
+        }
+    }
+
+    mutations
+}
+
+/// Insert `bytes` at `offset` of the sample media segment, growing the `moof`, `traf` and the box at `child`.
+fn insert(fragment: &[u8], offset: usize, bytes: &[u8], child: usize) -> Vec<u8> {
+    let mut data = [&fragment[..offset], bytes, &fragment[offset..]].concat();
+
+    for offset in [0, 24, child] {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap());
+        data[offset..offset + 4].copy_from_slice(&(size + bytes.len() as u32).to_be_bytes());
+    }
+
+    data
+}
+
+/// Fragments with fields which can't be reached by mutating a single field, in front of the mutations
+/// of the fragment.
+fn crafted(fragment: &[u8]) -> Vec<Vec<u8>> {
+    const TFHD: usize = 32;
+    const TRUN: usize = 72;
+
+    // trun box without sample fields and the maximum sample count
+    let mut many_samples = fragment.to_vec();
+    many_samples[TRUN + 8..TRUN + 16].copy_from_slice(&[0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff]);
+
+    // tfhd box with the maximum base data offset
+    let mut base_data_offset = insert(fragment, TFHD + 16, &u64::MAX.to_be_bytes(), TFHD);
+    base_data_offset[TFHD + 11] |= 0x01;
+
+    // sidx box with the maximum first offset, referencing the fragment
+    let mut sidx = be32(&[1, 25000]); // reference_ID, timescale
+    sidx.extend(0_u64.to_be_bytes()); // earliest_presentation_time
+    sidx.extend(u64::MAX.to_be_bytes()); // first_offset
+    sidx.extend([0, 0, 0, 1]); // reserved, reference_count
+    sidx.extend((fragment.len() as u32).to_be_bytes());
+    sidx.extend([0, 1, 0x86, 0xa0, 0x90, 0, 0, 0]); // subsegment_duration, SAP
+    let sidx = [&full_box(b"sidx", 1, 0, &sidx), fragment].concat();
+
+    vec![many_samples, base_data_offset, sidx]
+}
+
+/// Deterministic random bytes, with box headers of common box types sprinkled in.
+fn random_inputs() -> Vec<Vec<u8>> {
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut inputs = vec![Vec::new(), vec![0], vec![0; 7], vec![0; 8], vec![0xff; 64]];
+
+    for i in 0..200 {
+        let mut input = (0..next() % 512).map(|_| next() as u8).collect::<Vec<_>>();
+
+        if input.len() >= 8 {
+            let box_type = [
+                &b"moov"[..],
+                b"moof",
+                b"traf",
+                b"trun",
+                b"stsd",
+                b"pssh",
+                b"sidx",
+            ][i % 7];
+            input[4..8].copy_from_slice(box_type);
+        }
+
+        inputs.push(input);
+    }
+
+    inputs
+}
+
+/// Call `f` and return a description of the panic if it panics.
+fn catch(name: &str, f: impl FnOnce()) -> Option<String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).err().map(|e| {
+        let msg = e
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| e.downcast_ref::<&str>().map(|x| x.to_string()))
+            .unwrap_or_default();
+        format!("{}: {}", name, msg)
+    })
+}
+
+/// Functions which only parse their input.
+fn parse(data: &[u8], init: &[u8]) -> Vec<Option<String>> {
+    vec![
+        catch("list_tracks", || drop(mp4decrypt::list_tracks(data))),
+        catch("Mp4Metadata::parse", || drop(Mp4Metadata::parse(data))),
+        catch("Mp4File::new", || drop(Mp4File::new(data))),
+        catch("read_ftyp", || drop(mp4decrypt::read_ftyp(data))),
+        catch("is_encrypted", || drop(mp4decrypt::is_encrypted(data))),
+        catch("collect_all_kids", || {
+            drop(mp4decrypt::collect_all_kids(data))
+        }),
+        catch("extract_pssh", || drop(mp4decrypt::extract_pssh(data))),
+        catch("widevine_content_id", || {
+            drop(mp4decrypt::widevine_content_id(data))
+        }),
+        catch("keys_cover_pssh", || {
+            drop(mp4decrypt::keys_cover_pssh(data, &keys()))
+        }),
+        catch("read_original_format", || {
+            drop(mp4decrypt::read_original_format(data, 1))
+        }),
+        catch("read_aux_info", || {
+            drop(mp4decrypt::read_aux_info(data, Some(init)))
+        }),
+        catch("read_aux_info init", || {
+            drop(mp4decrypt::read_aux_info(init, Some(data)))
+        }),
+        catch("read_sidx", || drop(mp4decrypt::read_sidx(data))),
+        catch("debug_dump", || {
+            drop(mp4decrypt::debug_dump(data, &mut io::sink()))
+        }),
+        catch("parse_boxes", || drop(mp4decrypt::parse_boxes(data, drop))),
+        catch("count_fragments", || {
+            drop(mp4decrypt::count_fragments(data))
+        }),
+        catch("extract_init", || drop(mp4decrypt::extract_init(data))),
+        catch("needs_fragments_info", || {
+            drop(mp4decrypt::needs_fragments_info(data))
+        }),
+        catch("assemble", || {
+            drop(mp4decrypt::assemble(init, &[data.to_vec()]))
+        }),
+        catch("remap_track_ids", || {
+            drop(mp4decrypt::remap_track_ids(
+                &mut data.to_vec(),
+                &HashMap::from([(1, 2)]),
+            ))
+        }),
+    ]
+}
+
+/// Functions which decrypt their input with Bento4 and parse their input or output.
+fn decrypt(data: &[u8], init: &[u8]) -> Vec<Option<String>> {
+    let keys = keys();
+    let options = DecryptOptions::new()
+        .validate_structure(true)
+        .collect_stats(true)
+        .strip_pssh(true)
+        .normalize_box_versions(true);
+
+    vec![
+        catch("mp4decrypt_with_report", || {
+            drop(mp4decrypt::mp4decrypt_with_report(
+                data,
+                &keys,
+                Some(init),
+                &options,
+            ))
+        }),
+        catch("mp4decrypt_with_report self-contained", || {
+            drop(mp4decrypt::mp4decrypt_with_report(
+                data, &keys, None, &options,
+            ))
+        }),
+        catch("decrypt_stream", || {
+            drop(mp4decrypt::decrypt_stream(
+                Cursor::new(data),
+                io::sink(),
+                &keys,
+                &options,
+            ))
+        }),
+        catch("decrypt_samples", || {
+            if let Ok(samples) = mp4decrypt::decrypt_samples(data, Some(init), &keys, 1) {
+                samples.for_each(drop);
+            }
+        }),
+        catch("decrypt_and_refragment", || {
+            drop(mp4decrypt::decrypt_and_refragment(
+                data,
+                Some(init),
+                &keys,
+                1.0,
+            ))
+        }),
+        catch("decrypt_split_tracks", || {
+            drop(mp4decrypt::decrypt_split_tracks(data, Some(init), &keys))
+        }),
+        catch("decrypt_audio_to_adts", || {
+            drop(mp4decrypt::decrypt_audio_to_adts(data, Some(init), &keys))
+        }),
+        catch("decrypt_prefix", || {
+            drop(mp4decrypt::decrypt_prefix(data, Some(init), &keys, 1))
+        }),
+        catch("decrypt_fragment_at", || {
+            if let Ok(Some(index)) = mp4decrypt::read_sidx(data) {
+                for entry in &index.entries {
+                    drop(mp4decrypt::decrypt_fragment_at(
+                        Cursor::new(data),
+                        entry,
+                        init,
+                        &keys,
+                    ));
+                }
+            }
+        }),
+        catch("quick_verify", || {
+            drop(mp4decrypt::quick_verify(data, Some(init), &keys))
+        }),
+    ]
+}
+
+fn check(
+    results: impl IntoIterator<Item = Option<String>>,
+    input: usize,
+    panics: &mut Vec<String>,
+) {
+    for msg in results.into_iter().flatten() {
+        panics.push(format!("input {}: {}", input, msg));
+    }
+}
+
+#[test]
+fn adversarial_inputs_never_panic() {
+    let (init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+    // the moof box and the start of its mdat box, which is enough to be parsed
+    let fragment = &segment[..4705 + 4096];
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| ()));
+
+    let mut panics = Vec::new();
+    let inputs = [
+        crafted(fragment),
+        mutations(&init),
+        mutations(fragment),
+        random_inputs(),
+    ]
+    .concat();
+
+    for (i, input) in inputs.iter().enumerate() {
+        check(parse(input, &init), i, &mut panics);
+        check(parse(&[&init, &input[..]].concat(), &init), i, &mut panics);
+    }
+
+    for (i, input) in [crafted(fragment), mutations(fragment)]
+        .concat()
+        .iter()
+        .enumerate()
+    {
+        check(decrypt(input, &init), i, &mut panics);
+    }
+
+    panic::set_hook(hook);
+    panics.dedup();
+    assert!(panics.is_empty(), "{:#?}", &panics[..panics.len().min(50)]);
+}
@@ -0,0 +1,88 @@
+//! Sample entries restored from the `frma` box, also when a sample entry holds several `sinf` boxes.
+
+mod common;
+
+use common::{keys, read, u32_at};
+use mp4decrypt::DecryptOptions;
+
+fn decrypt(data: &[u8], fragments_info: Option<&[u8]>) -> Vec<u8> {
+    mp4decrypt::mp4decrypt_with_options(data, &keys(), fragments_info, &DecryptOptions::new())
+        .unwrap()
+}
+
+fn find(data: &[u8], box_type: &[u8; 4]) -> usize {
+    data.windows(4).position(|x| x == box_type).unwrap() - 4
+}
+
+/// Init segment whose sample entry holds a `sinf` box of an unknown scheme in front of the `cenc` one.
+fn unknown_sinf_first(init: &[u8]) -> Vec<u8> {
+    let sinf = find(init, b"sinf");
+    let size = u32_at(init, sinf) as usize;
+
+    let mut unknown = init[sinf..sinf + size].to_vec();
+    let frma = find(&unknown, b"frma");
+    unknown[frma + 8..frma + 12].copy_from_slice(b"hvc1");
+    let schm = find(&unknown, b"schm");
+    unknown[schm + 12..schm + 16].copy_from_slice(b"zzzz");
+
+    let mut data = [&init[..sinf], &unknown, &init[sinf..]].concat();
+
+    // every box holding the sample entry
+    for box_type in [
+        b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd", b"encv",
+    ] {
+        let offset = data[..sinf]
+            .windows(4)
+            .rposition(|x| x == box_type)
+            .unwrap()
+            - 4;
+        let value = u32_at(&data, offset) + size as u32;
+        data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    data
+}
+
+#[test]
+fn frma_is_honored() {
+    let (mut init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+    let frma = find(&init, b"frma");
+    init[frma + 8..frma + 12].copy_from_slice(b"avc3");
+
+    assert_eq!(
+        &mp4decrypt::read_original_format(&init, 1).unwrap(),
+        b"avc3"
+    );
+    assert!(mp4decrypt::read_original_format(&init, 2).is_err());
+
+    let decrypted_data = decrypt(&[&init, &segment[..]].concat(), None);
+    let tracks = mp4decrypt::list_tracks(&decrypted_data).unwrap();
+    assert_eq!(&tracks[0].sample_entry, b"avc3");
+    assert_eq!(
+        &mp4decrypt::read_original_format(&decrypted_data, 1).unwrap(),
+        b"avc3"
+    );
+}
+
+#[test]
+fn multiple_sinf_boxes() {
+    let (init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+    let multiple = unknown_sinf_first(&init);
+
+    assert_eq!(
+        &mp4decrypt::read_original_format(&multiple, 1).unwrap(),
+        b"avc1"
+    );
+    let tracks = mp4decrypt::list_tracks(&multiple).unwrap();
+    assert_eq!(tracks[0].scheme, Some(mp4decrypt::Scheme::Cenc));
+
+    // self-contained
+    let expected = decrypt(&[&init, &segment[..]].concat(), None);
+    let decrypted_data = decrypt(&[&multiple, &segment[..]].concat(), None);
+    assert!(decrypted_data == expected);
+
+    // fragments info
+    let expected = decrypt(&segment, Some(&init));
+    let decrypted_data = decrypt(&segment, Some(&multiple));
+    assert!(decrypted_data == expected);
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::{keys, read};
+use mp4decrypt::{DecryptOptions, ErrorType};
+
+fn is_too_large(error: mp4decrypt::Error, limit: usize) -> bool {
+    matches!(error.err_type, ErrorType::OutputTooLarge { limit: x } if x == limit as u64)
+}
+
+#[test]
+fn max_output_bytes() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let data = [init.clone(), segment.clone(), segment].concat();
+
+    let expected =
+        mp4decrypt::mp4decrypt_with_options(&data, &keys(), None, &DecryptOptions::new()).unwrap();
+    let size = expected.len();
+
+    // exactly at the limit
+    let options = DecryptOptions::new().max_output_bytes(size);
+    let decrypted = mp4decrypt::mp4decrypt_with_options(&data, &keys(), None, &options).unwrap();
+    assert_eq!(decrypted, expected);
+
+    let options = DecryptOptions::new().max_output_bytes(size - 1);
+    let error = mp4decrypt::mp4decrypt_with_options(&data, &keys(), None, &options).unwrap_err();
+    assert!(is_too_large(error, size - 1));
+
+    // nothing beyond the limit reaches the sink
+    let options = DecryptOptions::new().max_output_bytes(size - 1);
+    let mut decrypted = Vec::new();
+    let error =
+        mp4decrypt::mp4decrypt_into_with_options(&data, &keys(), None, &options, &mut decrypted)
+            .unwrap_err();
+    assert!(is_too_large(error, size - 1));
+    assert!(decrypted.is_empty());
+
+    let options = options.validate_structure(true);
+    let error =
+        mp4decrypt::mp4decrypt_into_with_options(&data, &keys(), None, &options, &mut decrypted)
+            .unwrap_err();
+    assert!(is_too_large(error, size - 1));
+
+    // every fragment fits, but the complete stream doesn't
+    let options = DecryptOptions::new()
+        .buffer_limit(0)
+        .max_output_bytes(size - 1);
+    let error =
+        mp4decrypt::decrypt_stream(data.as_slice(), Vec::new(), &keys(), &options).unwrap_err();
+    assert!(is_too_large(error, size - 1));
+
+    let options = DecryptOptions::new().buffer_limit(0).max_output_bytes(size);
+    let mut decrypted = Vec::new();
+    mp4decrypt::decrypt_stream(data.as_slice(), &mut decrypted, &keys(), &options).unwrap();
+    assert_eq!(decrypted, expected);
+}
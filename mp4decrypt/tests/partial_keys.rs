@@ -0,0 +1,255 @@
+//! A video and an audio track encrypted with different KIDs, decrypted with the audio key only.
+
+mod common;
+
+use cbc::cipher::{BlockEncryptMut, KeyIvInit, block_padding::NoPadding};
+use common::{MATRIX, be32, full_box, mp4_box};
+use mp4decrypt::{DecryptOptions, ErrorType, KeyMap, MissingKey, MissingKeyPolicy, Mp4Metadata};
+
+const VIDEO_KID: [u8; 16] = [0x01; 16];
+const VIDEO_KEY: [u8; 16] = [0x11; 16];
+const AUDIO_KID: [u8; 16] = [0x02; 16];
+const AUDIO_KEY: [u8; 16] = [0x22; 16];
+const IV: [u8; 16] = [0x0a; 16];
+const SAMPLE_COUNT: usize = 4;
+const SAMPLE_SIZE: usize = 64;
+
+/// Track 1 is an `encv` video track and track 2 an `enca` audio track, both `cbcs` with a constant IV.
+fn trak(track_id: u32) -> Vec<u8> {
+    let video = track_id == 1;
+    let (kid, original_format) = if video {
+        (VIDEO_KID, b"avc1")
+    } else {
+        (AUDIO_KID, b"mp4a")
+    };
+
+    let mut tkhd = be32(&[0, 0, track_id, 0, 0, 0, 0]);
+    tkhd.extend([0, 0, 0, 0, if video { 0 } else { 1 }, 0, 0, 0]); // layer, alternate_group, volume, reserved
+    tkhd.extend(be32(&MATRIX));
+    tkhd.extend(be32(&if video {
+        [640 << 16, 360 << 16]
+    } else {
+        [0, 0]
+    }));
+
+    let mut hdlr = be32(&[0]);
+    hdlr.extend(if video { b"vide" } else { b"soun" });
+    hdlr.extend([0; 13]);
+
+    let mut tenc = vec![0, 0, 1, 0];
+    tenc.extend(kid);
+    tenc.push(16);
+    tenc.extend(IV);
+
+    let mut schm = b"cbcs".to_vec();
+    schm.extend(be32(&[0x10000]));
+
+    let mut sinf = mp4_box(b"frma", original_format);
+    sinf.extend(full_box(b"schm", 0, 0, &schm));
+    sinf.extend(mp4_box(b"schi", &full_box(b"tenc", 1, 0, &tenc)));
+
+    let mut entry = vec![0, 0, 0, 0, 0, 0, 0, 1];
+
+    if video {
+        entry.extend([0; 16]); // pre_defined, reserved
+        entry.extend([2, 128, 1, 104]); // width, height
+        entry.extend(be32(&[0x480000, 0x480000, 0]));
+        entry.extend([0, 1]); // frame_count
+        entry.extend([0; 32]); // compressorname
+        entry.extend([0, 24, 0xff, 0xff]); // depth, pre_defined
+    } else {
+        entry.extend([0; 8]);
+        entry.extend([0, 2, 0, 16, 0, 0, 0, 0]); // channel_count, sample_size, pre_defined, reserved
+        entry.extend(be32(&[48000 << 16]));
+    }
+
+    entry.extend(mp4_box(b"sinf", &sinf));
+
+    let mut stsd = be32(&[1]);
+    stsd.extend(mp4_box(if video { b"encv" } else { b"enca" }, &entry));
+
+    let mut stbl = full_box(b"stsd", 0, 0, &stsd);
+    stbl.extend(full_box(b"stts", 0, 0, &be32(&[0])));
+    stbl.extend(full_box(b"stsc", 0, 0, &be32(&[0])));
+    stbl.extend(full_box(b"stsz", 0, 0, &be32(&[0, 0])));
+    stbl.extend(full_box(b"stco", 0, 0, &be32(&[0])));
+
+    let dref = full_box(
+        b"dref",
+        0,
+        0,
+        &[&be32(&[1])[..], &full_box(b"url ", 0, 1, &[])].concat(),
+    );
+    let mut minf = if video {
+        full_box(b"vmhd", 0, 1, &[0; 8])
+    } else {
+        full_box(b"smhd", 0, 0, &[0; 4])
+    };
+    minf.extend(mp4_box(b"dinf", &dref));
+    minf.extend(mp4_box(b"stbl", &stbl));
+
+    let mut mdia = full_box(
+        b"mdhd",
+        0,
+        0,
+        &[&be32(&[0, 0, 48000, 0])[..], &[0x55, 0xc4, 0, 0]].concat(),
+    );
+    mdia.extend(full_box(b"hdlr", 0, 0, &hdlr));
+    mdia.extend(mp4_box(b"minf", &minf));
+
+    let mut trak = full_box(b"tkhd", 0, 3, &tkhd);
+    trak.extend(mp4_box(b"mdia", &mdia));
+    mp4_box(b"trak", &trak)
+}
+
+fn init() -> Vec<u8> {
+    let mut mvhd = be32(&[0, 0, 1000, 0, 0x10000]);
+    mvhd.extend([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // volume, reserved
+    mvhd.extend(be32(&MATRIX));
+    mvhd.extend([0; 24]);
+    mvhd.extend(be32(&[3]));
+
+    let mut moov = full_box(b"mvhd", 0, 0, &mvhd);
+    moov.extend(trak(1));
+    moov.extend(trak(2));
+    moov.extend(mp4_box(
+        b"mvex",
+        &[1, 2]
+            .iter()
+            .flat_map(|x| full_box(b"trex", 0, 0, &be32(&[*x, 1, 0, 0, 0])))
+            .collect::<Vec<_>>(),
+    ));
+
+    let mut data = mp4_box(b"ftyp", b"iso6\0\0\0\0iso6");
+    data.extend(mp4_box(b"moov", &moov));
+    data
+}
+
+fn plaintext(track_id: u32) -> Vec<u8> {
+    (0..SAMPLE_COUNT * SAMPLE_SIZE)
+        .map(|x| (x as u32 * 7 + track_id * 31) as u8)
+        .collect()
+}
+
+/// Fragment with every sample of both tracks, each encrypted with the key of its track.
+fn fragment() -> Vec<u8> {
+    let traf = |track_id: u32, data_offset: u32| {
+        let mut trun = be32(&[SAMPLE_COUNT as u32, data_offset]);
+        trun.extend(be32(&[SAMPLE_SIZE as u32; SAMPLE_COUNT]));
+
+        let mut traf = full_box(b"tfhd", 0, 0x020000, &be32(&[track_id]));
+        traf.extend(full_box(b"trun", 0, 0x000201, &trun));
+        traf.extend(full_box(b"senc", 0, 0, &be32(&[SAMPLE_COUNT as u32])));
+        mp4_box(b"traf", &traf)
+    };
+
+    let moof_size = |offset| {
+        let mut moof = full_box(b"mfhd", 0, 0, &be32(&[1]));
+        moof.extend(traf(1, offset));
+        moof.extend(traf(2, offset + (SAMPLE_COUNT * SAMPLE_SIZE) as u32));
+        mp4_box(b"moof", &moof)
+    };
+    let offset = moof_size(0).len() as u32 + 8;
+    let mut data = moof_size(offset);
+
+    let mut mdat = Vec::new();
+
+    for (track_id, key) in [(1, VIDEO_KEY), (2, AUDIO_KEY)] {
+        for sample in plaintext(track_id).chunks(SAMPLE_SIZE) {
+            let mut sample = sample.to_vec();
+            cbc::Encryptor::<aes::Aes128>::new(&key.into(), &IV.into())
+                .encrypt_padded_mut::<NoPadding>(&mut sample, SAMPLE_SIZE)
+                .unwrap();
+            mdat.extend(sample);
+        }
+    }
+
+    data.extend(mp4_box(b"mdat", &mdat));
+    data
+}
+
+fn audio_keys() -> KeyMap {
+    let mut keys = KeyMap::new();
+    keys.insert_kid(AUDIO_KID, AUDIO_KEY);
+    keys
+}
+
+/// Samples of both tracks.
+fn mdat_payload(data: &[u8]) -> (&[u8], &[u8]) {
+    data[data.len() - SAMPLE_COUNT * SAMPLE_SIZE * 2..].split_at(SAMPLE_COUNT * SAMPLE_SIZE)
+}
+
+#[test]
+fn audio_key_only() {
+    let data = [init(), fragment()].concat();
+    let options = DecryptOptions::new().validate_structure(true);
+    let (decrypted, report) =
+        mp4decrypt::mp4decrypt_with_report(&data, &audio_keys(), None, &options).unwrap();
+
+    let (video, audio) = mdat_payload(&decrypted);
+    assert_ne!(video, plaintext(1));
+    assert_eq!(video, mdat_payload(&data).0);
+    assert_eq!(audio, plaintext(2));
+
+    assert_eq!(report.decrypted_kids, [AUDIO_KID]);
+    assert_eq!(report.encrypted_kids, [VIDEO_KID]);
+    assert!(report.missing_keys.is_empty());
+
+    // the video track keeps its protection boxes, the audio track is made clear
+    let metadata = Mp4Metadata::parse(&decrypted).unwrap();
+    let tracks = metadata.tracks();
+    assert_eq!(tracks[0].sample_entries, [*b"encv"]);
+    assert!(tracks[0].is_protected());
+    assert_eq!(tracks[1].sample_entries, [*b"mp4a"]);
+    assert!(!tracks[1].is_protected());
+    assert!(mp4decrypt::is_encrypted(&decrypted).unwrap());
+
+    // the video track can still be decrypted later on
+    let mut keys = KeyMap::new();
+    keys.insert_kid(VIDEO_KID, VIDEO_KEY);
+    let (decrypted, report) =
+        mp4decrypt::mp4decrypt_with_report(&decrypted, &keys, None, &options).unwrap();
+    assert_eq!(mdat_payload(&decrypted).0, plaintext(1));
+    assert_eq!(mdat_payload(&decrypted).1, plaintext(2));
+    assert_eq!(report.decrypted_kids, [VIDEO_KID]);
+    assert!(report.encrypted_kids.is_empty());
+    assert!(!mp4decrypt::is_encrypted(&decrypted).unwrap());
+}
+
+#[test]
+fn audio_key_only_with_fragments_info() {
+    let options = DecryptOptions::new();
+    let (decrypted, report) =
+        mp4decrypt::mp4decrypt_with_report(&fragment(), &audio_keys(), Some(&init()), &options)
+            .unwrap();
+
+    let (video, audio) = mdat_payload(&decrypted);
+    assert_ne!(video, plaintext(1));
+    assert_eq!(audio, plaintext(2));
+    assert_eq!(report.decrypted_kids, [AUDIO_KID]);
+    assert_eq!(report.encrypted_kids, [VIDEO_KID]);
+}
+
+#[test]
+fn missing_key_policy() {
+    let data = [init(), fragment()].concat();
+
+    let options = DecryptOptions::new().on_missing_key(MissingKeyPolicy::WarnReport);
+    let (_, report) =
+        mp4decrypt::mp4decrypt_with_report(&data, &audio_keys(), None, &options).unwrap();
+    assert_eq!(
+        report.missing_keys,
+        [MissingKey {
+            track_id: 1,
+            kid: Some(VIDEO_KID)
+        }]
+    );
+
+    let options = DecryptOptions::new().on_missing_key(MissingKeyPolicy::Fail);
+    let error =
+        mp4decrypt::mp4decrypt_with_report(&data, &audio_keys(), None, &options).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::MissingKeyForTrack(1, Some(VIDEO_KID))
+    ));
+}
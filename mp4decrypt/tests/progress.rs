@@ -0,0 +1,125 @@
+//! Progress of a non-fragmented file with a single clear audio track, which Bento4 reports per sample.
+
+mod common;
+
+use common::{MATRIX, be32, full_box, mp4_box};
+use mp4decrypt::{DecryptOptions, KeyMap};
+use std::sync::{Arc, Mutex};
+
+const SAMPLE_COUNT: u32 = 10;
+const SAMPLE_SIZE: u32 = 16;
+
+/// Non-fragmented file whose samples are stored in a single chunk at `chunk_offset`.
+fn file(chunk_offset: u32) -> Vec<u8> {
+    let mut mvhd = be32(&[0, 0, 1000, 0, 0x10000]);
+    mvhd.extend([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // volume, reserved
+    mvhd.extend(be32(&MATRIX));
+    mvhd.extend([0; 24]);
+    mvhd.extend(be32(&[2]));
+
+    let mut tkhd = be32(&[0, 0, 1, 0, 0, 0, 0]);
+    tkhd.extend([0, 0, 0, 0, 1, 0, 0, 0]); // layer, alternate_group, volume, reserved
+    tkhd.extend(be32(&MATRIX));
+    tkhd.extend(be32(&[0, 0]));
+    let tkhd = mp4_box(b"tkhd", &[&[0, 0, 0, 3][..], &tkhd].concat());
+
+    let mut hdlr = be32(&[0]);
+    hdlr.extend(b"soun");
+    hdlr.extend([0; 13]);
+
+    let mut mp4a = vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+    mp4a.extend([0, 2, 0, 16, 0, 0, 0, 0]); // channel_count, sample_size, pre_defined, reserved
+    mp4a.extend(be32(&[48000 << 16]));
+
+    let mut stbl = full_box(
+        b"stsd",
+        0,
+        0,
+        &[&be32(&[1])[..], &mp4_box(b"mp4a", &mp4a)].concat(),
+    );
+    stbl.extend(full_box(b"stts", 0, 0, &be32(&[1, SAMPLE_COUNT, 1024])));
+    stbl.extend(full_box(b"stsc", 0, 0, &be32(&[1, 1, SAMPLE_COUNT, 1])));
+    stbl.extend(full_box(b"stsz", 0, 0, &be32(&[SAMPLE_SIZE, SAMPLE_COUNT])));
+    stbl.extend(full_box(b"stco", 0, 0, &be32(&[1, chunk_offset])));
+
+    let dref = full_box(
+        b"dref",
+        0,
+        0,
+        &[&be32(&[1])[..], &mp4_box(b"url ", &[0, 0, 0, 1])].concat(),
+    );
+    let mut minf = full_box(b"smhd", 0, 0, &[0; 4]);
+    minf.extend(mp4_box(b"dinf", &dref));
+    minf.extend(mp4_box(b"stbl", &stbl));
+
+    let mut mdia = full_box(
+        b"mdhd",
+        0,
+        0,
+        &[
+            &be32(&[0, 0, 48000, SAMPLE_COUNT * 1024])[..],
+            &[0x55, 0xc4, 0, 0],
+        ]
+        .concat(),
+    );
+    mdia.extend(full_box(b"hdlr", 0, 0, &hdlr));
+    mdia.extend(mp4_box(b"minf", &minf));
+
+    let mut trak = tkhd;
+    trak.extend(mp4_box(b"mdia", &mdia));
+
+    let mut moov = full_box(b"mvhd", 0, 0, &mvhd);
+    moov.extend(mp4_box(b"trak", &trak));
+
+    let mut data = mp4_box(b"ftyp", b"isom\0\0\0\0isom");
+    data.extend(mp4_box(b"moov", &moov));
+    data.extend(mp4_box(
+        b"mdat",
+        &(0..SAMPLE_COUNT * SAMPLE_SIZE)
+            .map(|x| x as u8)
+            .collect::<Vec<_>>(),
+    ));
+    data
+}
+
+fn progress(interval: u32) -> (Vec<u8>, Vec<(u32, u32)>) {
+    let data = file(file(0).len() as u32 - SAMPLE_COUNT * SAMPLE_SIZE);
+    let steps = Arc::new(Mutex::new(Vec::new()));
+    let steps_clone = steps.clone();
+    let options = DecryptOptions::new().on_progress(interval, move |step, total| {
+        steps_clone.lock().unwrap().push((step, total));
+    });
+
+    let decrypted_data =
+        mp4decrypt::mp4decrypt_with_options(&data, &KeyMap::new(), None, &options).unwrap();
+    let steps = steps.lock().unwrap().clone();
+    (decrypted_data, steps)
+}
+
+#[test]
+fn progress_interval() {
+    let (decrypted_data, steps) = progress(1);
+    assert_eq!(
+        steps,
+        (1..=SAMPLE_COUNT)
+            .map(|x| (x, SAMPLE_COUNT))
+            .collect::<Vec<_>>()
+    );
+
+    let (_, steps) = progress(4);
+    assert_eq!(steps, [(4, 10), (8, 10), (10, 10)]);
+
+    // disabled by default
+    let (default_data, steps) = progress(0);
+    assert!(steps.is_empty());
+    assert_eq!(default_data, decrypted_data);
+}
+
+#[test]
+fn progress_panic() {
+    let data = file(file(0).len() as u32 - SAMPLE_COUNT * SAMPLE_SIZE);
+    let options = DecryptOptions::new().on_progress(5, |_, _| panic!("progress"));
+    let error =
+        mp4decrypt::mp4decrypt_with_options(&data, &KeyMap::new(), None, &options).unwrap_err();
+    assert!(matches!(error.err_type, mp4decrypt::ErrorType::Panicked));
+}
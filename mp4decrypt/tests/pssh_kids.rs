@@ -0,0 +1,112 @@
+//! KIDs read from version 1 `pssh` boxes and from Widevine and PlayReady system specific data.
+
+mod common;
+
+use common::full_box;
+use mp4decrypt::KeyMap;
+
+const KID_1: [u8; 16] = [0x01; 16];
+const KID_2: [u8; 16] = *b"0123456789abcdef";
+const WIDEVINE: [u8; 16] = 0xedef8ba979d64acea3c827dcd51d21ed_u128.to_be_bytes();
+const PLAYREADY: [u8; 16] = 0x9a04f07998404286ab92e65be0885f95_u128.to_be_bytes();
+
+fn pssh(version: u8, system_id: [u8; 16], kids: &[[u8; 16]], data: &[u8]) -> Vec<u8> {
+    let mut fields = system_id.to_vec();
+    if version > 0 {
+        fields.extend((kids.len() as u32).to_be_bytes());
+        fields.extend(kids.concat());
+    }
+    fields.extend((data.len() as u32).to_be_bytes());
+    fields.extend(data);
+    full_box(b"pssh", version, 0, &fields)
+}
+
+/// `WidevinePsshData` with an `algorithm` field, `key_id` fields and a `content_id` field.
+fn widevine(kids: &[[u8; 16]]) -> Vec<u8> {
+    let mut data = vec![0x08, 0x01];
+    for kid in kids {
+        data.extend([0x12, 0x10]);
+        data.extend(kid);
+    }
+    data.extend([0x22, 0x04]);
+    data.extend(b"test");
+    data
+}
+
+/// PlayReady object with a single `WRMHEADER` record.
+fn playready(header: &str) -> Vec<u8> {
+    let header = header
+        .encode_utf16()
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<_>>();
+    let mut data = ((10 + header.len()) as u32).to_le_bytes().to_vec();
+    data.extend(1_u16.to_le_bytes());
+    data.extend(1_u16.to_le_bytes());
+    data.extend((header.len() as u16).to_le_bytes());
+    data.extend(header);
+    data
+}
+
+/// Base64 encoded GUID of a KID, whose first three fields are little endian.
+fn guid(kid: [u8; 16]) -> &'static str {
+    match kid {
+        KID_1 => "AQEBAQEBAQEBAQEBAQEBAQ==",
+        KID_2 => "MzIxMDU0NzY4OWFiY2RlZg==",
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn key_ids() {
+    let header = format!(
+        "<WRMHEADER version=\"4.3.0.0\"><DATA><PROTECTINFO><KIDS>\
+         <KID ALGID=\"AESCTR\" VALUE=\"{}\"></KID><KID VALUE=\"{}\" ALGID=\"AESCBC\" />\
+         </KIDS></PROTECTINFO></DATA></WRMHEADER>",
+        guid(KID_1),
+        guid(KID_2)
+    );
+    let data = [
+        pssh(1, [0xff; 16], &[KID_1], b""),
+        pssh(0, WIDEVINE, &[], &widevine(&[KID_2, KID_1])),
+        pssh(0, PLAYREADY, &[], &playready(&header)),
+        pssh(1, WIDEVINE, &[KID_2], &widevine(&[KID_2])),
+        // not parsed
+        pssh(0, WIDEVINE, &[], &[0x12, 0x20, 0x01]),
+        pssh(0, PLAYREADY, &[], &[0; 3]),
+    ]
+    .concat();
+
+    let key_ids = mp4decrypt::extract_pssh(&data)
+        .unwrap()
+        .iter()
+        .map(|x| x.key_ids())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        key_ids,
+        [
+            vec![KID_1],
+            vec![KID_2, KID_1],
+            vec![KID_1, KID_2],
+            vec![KID_2],
+            vec![],
+            vec![]
+        ]
+    );
+
+    let mut keys = KeyMap::new();
+    keys.insert_kid(KID_2, [0x22; 16]);
+    let coverage = mp4decrypt::keys_cover_pssh(&data, &keys).unwrap();
+    assert_eq!(coverage.kids, [KID_1, KID_2]);
+    assert_eq!(coverage.missing_kids, [KID_1]);
+}
+
+#[test]
+fn playready_v4_0() {
+    let header = format!(
+        "<WRMHEADER version=\"4.0.0.0\"><DATA><KID> {} </KID></DATA></WRMHEADER>",
+        guid(KID_2)
+    );
+    let data = pssh(0, PLAYREADY, &[], &playready(&header));
+    let pssh = mp4decrypt::extract_pssh(&data).unwrap();
+    assert_eq!(pssh[0].key_ids(), [KID_2]);
+}
@@ -0,0 +1,71 @@
+//! Re-fragmenting a decrypted data stream into segments of a target duration.
+
+mod common;
+
+use common::{keys, read};
+use mp4decrypt::Sample;
+
+fn samples(data: &[u8], init: &[u8]) -> Vec<Sample> {
+    mp4decrypt::decrypt_samples(data, Some(init), &keys(), 1)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+/// Two copies of the media segment, the second one following the first in decode time.
+fn two_fragments(segment: &[u8]) -> Vec<u8> {
+    let mut second = segment.to_vec();
+    // tfdt version 1
+    let time = u64::from_be_bytes(second[64..72].try_into().unwrap());
+    second[64..72].copy_from_slice(&(time + 100_000).to_be_bytes());
+    [segment, &second].concat()
+}
+
+#[test]
+fn segments_start_at_sync_samples() {
+    let (init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+    let data = two_fragments(&segment);
+    let expected = samples(&data, &init);
+    assert_eq!(expected.iter().filter(|x| x.is_sync).count(), 2);
+    assert_eq!(expected[100].dts - expected[0].dts, 100_000);
+
+    // a single sync sample in every 4 seconds
+    let segments = mp4decrypt::decrypt_and_refragment(&data, Some(&init), &keys(), 0.5).unwrap();
+    assert_eq!(segments.len(), 3);
+
+    let mut refragmented = Vec::new();
+
+    for segment in &segments[1..] {
+        let samples = samples(segment, &segments[0]);
+        assert!(samples[0].is_sync);
+        refragmented.extend(samples);
+    }
+
+    assert!(refragmented == expected);
+
+    let segments = mp4decrypt::decrypt_and_refragment(&data, Some(&init), &keys(), 10.0).unwrap();
+    assert_eq!(segments.len(), 2);
+
+    // self-contained
+    let self_contained =
+        mp4decrypt::decrypt_and_refragment(&[&init, &data[..]].concat(), None, &keys(), 10.0)
+            .unwrap();
+    assert!(self_contained[1..] == segments[1..]);
+}
+
+#[test]
+fn invalid_arguments() {
+    let (init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+
+    for seconds in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        assert!(
+            mp4decrypt::decrypt_and_refragment(&segment, Some(&init), &keys(), seconds).is_err()
+        );
+    }
+
+    let err = mp4decrypt::decrypt_and_refragment(&segment, None, &keys(), 1.0).unwrap_err();
+    assert!(matches!(
+        err.err_type,
+        mp4decrypt::ErrorType::MissingInitSegment
+    ));
+}
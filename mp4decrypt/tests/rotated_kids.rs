@@ -0,0 +1,37 @@
+//! KIDs of rotated keys, referenced by `seig` sample group descriptions of fragments.
+
+mod common;
+
+use common::{KID, full_box, mp4_box, read};
+
+#[test]
+fn seig_sample_groups() {
+    let init = read("init.mp4");
+    let default_kid = mp4decrypt::parse_hex16(KID).unwrap();
+    let rotated_kid = mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3db").unwrap();
+
+    // seig grouping type, entry size of 20 bytes, 1 entry
+    let mut sgpd = b"seig".to_vec();
+    sgpd.extend([0, 0, 0, 20, 0, 0, 0, 1]);
+    sgpd.extend([0, 0, 1, 8]); // reserved, pattern, is protected, per sample IV size
+    sgpd.extend(rotated_kid);
+    let sgpd = full_box(b"sgpd", 1, 0, &sgpd);
+
+    let mut data = init.clone();
+    data.extend(mp4_box(b"moof", &mp4_box(b"traf", &sgpd)));
+    assert_eq!(
+        mp4decrypt::collect_all_kids(&data).unwrap(),
+        [default_kid, rotated_kid].into()
+    );
+
+    // sample group descriptions of the init segment
+    let moov = [
+        &init[..32],
+        &mp4_box(b"moov", &[&init[40..], &sgpd[..]].concat()),
+    ]
+    .concat();
+    assert_eq!(
+        mp4decrypt::collect_all_kids(&moov).unwrap(),
+        [default_kid, rotated_kid].into()
+    );
+}
@@ -0,0 +1,157 @@
+//! Tracks with more than one sample entry in their `stsd` box, built from the sample init segment
+//! by duplicating its only `encv` sample entry.
+
+mod common;
+
+use common::{KEY, KID, keys, mdat_payload, read, set_u32, u32_at};
+use mp4decrypt::{ErrorType, KeyMap, Mp4Metadata};
+
+fn decrypt(data: &[u8], keys: &KeyMap, init: Option<&[u8]>) -> Result<Vec<u8>, mp4decrypt::Error> {
+    let options = mp4decrypt::DecryptOptions::new();
+    mp4decrypt::mp4decrypt_with_options(data, keys, init, &options)
+}
+
+/// Offsets of the boxes along a path of box types, starting from the top level.
+fn find_path(data: &[u8], path: &[&[u8; 4]]) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let (mut offset, mut end) = (0, data.len());
+
+    for box_type in path {
+        loop {
+            assert!(
+                offset < end,
+                "no {} box found",
+                String::from_utf8_lossy(*box_type)
+            );
+
+            if &&data[offset + 4..offset + 8] == box_type {
+                break;
+            }
+
+            offset += u32_at(data, offset) as usize;
+        }
+
+        offsets.push(offset);
+        end = offset + u32_at(data, offset) as usize;
+        // version, flags and entry_count of stsd
+        offset += if *box_type == b"stsd" { 16 } else { 8 };
+    }
+
+    offsets
+}
+
+/// Insert bytes at an offset, growing the boxes which contain it.
+fn insert(data: &[u8], at: usize, bytes: &[u8], parents: &[usize]) -> Vec<u8> {
+    let mut data = [&data[..at], bytes, &data[at..]].concat();
+
+    for parent in parents {
+        let size = u32_at(&data, *parent) + bytes.len() as u32;
+        set_u32(&mut data, *parent, size);
+    }
+
+    data
+}
+
+/// Init segment with a copy of its sample entry, optionally made clear, placed before or after it.
+/// Fragments use the sample entry at `index` (starting from 1).
+fn init_with_entries(clear_copy: bool, copy_first: bool, index: u32) -> Vec<u8> {
+    let init = read("init.mp4");
+    let parents = find_path(
+        &init,
+        &[
+            b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd", b"encv",
+        ],
+    );
+    let (stsd, entry) = (parents[5], parents[6]);
+    let mut copy = init[entry..entry + u32_at(&init, entry) as usize].to_vec();
+
+    if clear_copy {
+        copy[4..8].copy_from_slice(b"avc1");
+        // sinf directly follows the 78 bytes of encv fields and the avcC box
+        let avcc = 8 + 78;
+        let sinf = avcc + u32_at(&copy, avcc) as usize;
+        assert_eq!(&copy[sinf + 4..sinf + 8], b"sinf");
+        copy[sinf + 4..sinf + 8].copy_from_slice(b"free");
+    }
+
+    let at = if copy_first {
+        entry
+    } else {
+        entry + copy.len()
+    };
+    let mut init = insert(&init, at, &copy, &parents[..6]);
+    set_u32(&mut init, stsd + 12, 2);
+
+    let trex = find_path(&init, &[b"moov", b"mvex", b"trex"])[2];
+    set_u32(&mut init, trex + 16, index);
+    init
+}
+
+#[test]
+fn protected_sample_entries() {
+    let segment = read("segment_0.m4s");
+    let expected = decrypt(&segment, &keys(), Some(&read("init.mp4"))).unwrap();
+
+    for index in [1, 2] {
+        let init = init_with_entries(false, false, index);
+        let metadata = Mp4Metadata::parse(&init).unwrap();
+        assert_eq!(metadata.tracks()[0].sample_entries, [*b"encv", *b"encv"]);
+        assert_eq!(metadata.tenc().len(), 2);
+
+        let decrypted = decrypt(&segment, &keys(), Some(&init)).unwrap();
+        assert_eq!(mdat_payload(&decrypted), mdat_payload(&expected));
+
+        // both sample entries are made clear
+        let decrypted_init = decrypt(&init, &keys(), None).unwrap();
+        let metadata = Mp4Metadata::parse(&decrypted_init).unwrap();
+        assert_eq!(metadata.tracks()[0].sample_entries, [*b"avc1", *b"avc1"]);
+    }
+}
+
+#[test]
+fn clear_sample_entry_before_protected() {
+    let segment = read("segment_0.m4s");
+    let expected = decrypt(&segment, &keys(), Some(&read("init.mp4"))).unwrap();
+
+    // A protected entry after a clear one is misnumbered by Bento4, which leaves the fragment encrypted.
+    let init = init_with_entries(true, true, 2);
+    let metadata = Mp4Metadata::parse(&init).unwrap();
+    let track = &metadata.tracks()[0];
+    assert_eq!(track.sample_entries, [*b"avc1", *b"encv"]);
+    assert!(track.is_protected());
+    assert_eq!(
+        track.default_kid,
+        Some(mp4decrypt::parse_hex16(KID).unwrap())
+    );
+
+    let raw = mp4decrypt::decrypt_raw(
+        &segment,
+        &[&std::ffi::CString::new(KID).unwrap()],
+        &[&std::ffi::CString::new(KEY).unwrap()],
+        Some(&init),
+    )
+    .unwrap();
+    assert_ne!(mdat_payload(&raw), mdat_payload(&expected));
+
+    let error = decrypt(&segment, &keys(), Some(&init)).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::UnsupportedSampleEntries { track_id: 1 }
+    ));
+
+    let error = mp4decrypt::SegmentDecryptor::new(&init, &keys())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::UnsupportedSampleEntries { track_id: 1 }
+    ));
+
+    // nothing is decrypted without a key for the track
+    assert!(decrypt(&segment, &KeyMap::new(), Some(&init)).is_ok());
+
+    // a clear entry after the protected one is supported
+    let init = init_with_entries(true, false, 1);
+    let decrypted = decrypt(&segment, &keys(), Some(&init)).unwrap();
+    assert_eq!(mdat_payload(&decrypted), mdat_payload(&expected));
+}
@@ -0,0 +1,176 @@
+//! Samples marked as unencrypted by a `seig` sample group of an otherwise encrypted track,
+//! which Bento4 would decrypt using the `tenc` box of the track.
+
+mod common;
+
+use common::{KEY, KID, keys, mp4_box, read, u32_at};
+use mp4decrypt::ErrorType;
+
+/// Index of the sample which is stored in the clear.
+const CLEAR_SAMPLE: usize = 3;
+
+fn decrypt(data: &[u8], init: Option<&[u8]>) -> Result<Vec<u8>, mp4decrypt::Error> {
+    let options = mp4decrypt::DecryptOptions::new();
+    mp4decrypt::mp4decrypt_with_options(data, &keys(), init, &options)
+}
+
+/// Offset and size of the first child box of a type, within a container starting at `offset`.
+fn child(data: &[u8], offset: usize, box_type: &[u8; 4]) -> (usize, usize) {
+    let end = offset + u32_at(data, offset) as usize;
+    let mut offset = offset + 8;
+
+    while offset < end {
+        let size = u32_at(data, offset) as usize;
+
+        if &data[offset + 4..offset + 8] == box_type {
+            return (offset, size);
+        }
+
+        offset += size;
+    }
+
+    panic!("no {} box found", String::from_utf8_lossy(box_type));
+}
+
+/// Range of every sample within the `mdat` payload of a segment.
+fn sample_ranges(segment: &[u8]) -> Vec<std::ops::Range<usize>> {
+    let (traf, _) = child(segment, 0, b"traf");
+    let (trun, _) = child(segment, traf, b"trun");
+    let flags = u32_at(segment, trun + 8) & 0xffffff;
+    let sample_count = u32_at(segment, trun + 12) as usize;
+    let mut offset = trun + 16;
+
+    if flags & 0x01 != 0 {
+        offset += 4;
+    }
+    if flags & 0x04 != 0 {
+        offset += 4;
+    }
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+
+    for _ in 0..sample_count {
+        if flags & 0x100 != 0 {
+            offset += 4;
+        }
+        let size = u32_at(segment, offset) as usize;
+        offset += [0x200, 0x400, 0x800]
+            .iter()
+            .filter(|x| flags & **x != 0)
+            .count()
+            * 4;
+
+        ranges.push(start..start + size);
+        start += size;
+    }
+
+    ranges
+}
+
+fn mdat_payload(segment: &[u8]) -> &[u8] {
+    let moof_size = u32_at(segment, 0) as usize;
+    &segment[moof_size + 8..]
+}
+
+/// Store a sample of the segment in the clear, and map it to a `seig` sample group description
+/// appended to the end of its `traf` box.
+fn partially_protected_segment(seig_entry: &[u8]) -> Vec<u8> {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let decrypted = decrypt(&segment, Some(&init)).unwrap();
+    let range = sample_ranges(&segment)[CLEAR_SAMPLE].clone();
+
+    let mut sgpd = vec![1, 0, 0, 0, b's', b'e', b'i', b'g', 0, 0, 0, 20, 0, 0, 0, 1];
+    sgpd.extend(seig_entry);
+    let mut sbgp = vec![0, 0, 0, 0, b's', b'e', b'i', b'g', 0, 0, 0, 2];
+    sbgp.extend((CLEAR_SAMPLE as u32).to_be_bytes());
+    sbgp.extend(0_u32.to_be_bytes());
+    sbgp.extend(1_u32.to_be_bytes());
+    sbgp.extend(0x10001_u32.to_be_bytes());
+    let mut groups = mp4_box(b"sgpd", &sgpd);
+    groups.extend(mp4_box(b"sbgp", &sbgp));
+
+    let moof_size = u32_at(&segment, 0) as usize;
+    let (traf, traf_size) = child(&segment, 0, b"traf");
+    let (trun, _) = child(&segment, traf, b"trun");
+    assert_eq!(u32_at(&segment, trun + 8) & 0x01, 0x01);
+
+    let mut data = segment[..traf + traf_size].to_vec();
+    data.extend(&groups);
+    data.extend(&segment[traf + traf_size..]);
+
+    for (offset, value) in [
+        (0, moof_size),
+        (traf, traf_size),
+        (trun + 16, u32_at(&segment, trun + 16) as usize),
+    ] {
+        let value = value as u32 + groups.len() as u32;
+        data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    let mdat = moof_size + groups.len() + 8;
+    data[mdat + range.start..mdat + range.end].copy_from_slice(&mdat_payload(&decrypted)[range]);
+    data
+}
+
+#[test]
+fn unencrypted_samples_pass_through() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let expected = decrypt(&segment, Some(&init)).unwrap();
+
+    // clear sample, no IV size and no KID
+    let data = partially_protected_segment(&[0; 20]);
+    let range = sample_ranges(&data)[CLEAR_SAMPLE].clone();
+    assert_eq!(
+        &mdat_payload(&data)[range.clone()],
+        &mdat_payload(&expected)[range.clone()]
+    );
+
+    // Bento4 alone decrypts the clear sample as well.
+    let raw = mp4decrypt::decrypt_raw(
+        &data,
+        &[&std::ffi::CString::new(KID).unwrap()],
+        &[&std::ffi::CString::new(KEY).unwrap()],
+        Some(&init),
+    )
+    .unwrap();
+    assert_ne!(
+        &mdat_payload(&raw)[range.clone()],
+        &mdat_payload(&expected)[range]
+    );
+
+    let decrypted = decrypt(&data, Some(&init)).unwrap();
+    assert_eq!(mdat_payload(&decrypted), mdat_payload(&expected));
+
+    let mut decryptor = mp4decrypt::SegmentDecryptor::new(&init, &keys()).unwrap();
+    let decrypted = decryptor.decrypt_next(&data).unwrap();
+    assert_eq!(mdat_payload(&decrypted), mdat_payload(&expected));
+
+    // init segment and media segment joined together
+    let mut joined = init.clone();
+    joined.extend(&data);
+    let decrypted = decrypt(&joined, None).unwrap();
+    let decrypted_init = mp4decrypt::extract_init(&decrypted).unwrap();
+    assert_eq!(
+        mdat_payload(&decrypted[decrypted_init.len()..]),
+        mdat_payload(&expected)
+    );
+}
+
+#[test]
+fn mixed_kids_sample_group() {
+    let init = read("init.mp4");
+
+    // protected sample, IV size of 8 bytes and a different KID than the other samples
+    let mut entry = vec![0, 0, 1, 8];
+    entry.extend(mp4decrypt::parse_hex16("eb676abbcb345e96bbcf616630f1a3db").unwrap());
+    let data = partially_protected_segment(&entry);
+
+    let error = decrypt(&data, Some(&init)).unwrap_err();
+    assert!(matches!(
+        error.err_type,
+        ErrorType::UnsupportedSampleGroup { track_id: 1 }
+    ));
+}
@@ -0,0 +1,70 @@
+//! Decrypted samples of a track, which are read lazily one fragment at a time.
+
+mod common;
+
+use common::{keys, read, u32_at};
+use mp4decrypt::{ErrorType, Sample};
+
+fn samples(data: &[u8], init: Option<&[u8]>) -> Vec<Sample> {
+    mp4decrypt::decrypt_samples(data, init, &keys(), 1)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+#[test]
+fn sample_timing() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let (trun, mdat) = (72, 4705);
+
+    let samples = samples(&segment, Some(&init));
+    assert_eq!(samples.len(), u32_at(&segment, trun + 12) as usize);
+    assert_eq!(
+        samples.iter().map(|x| x.data.len()).sum::<usize>(),
+        u32_at(&segment, mdat) as usize - 8
+    );
+
+    // sample_duration, sample_size and sample_composition_time_offset of every sample (flags 0xb05)
+    let fields = |i: usize| trun + 8 + 16 + i * 12;
+    assert_eq!(samples[0].dts, 0);
+    assert_eq!(samples[1].dts, u32_at(&segment, fields(0)) as u64);
+    assert_eq!(
+        samples[1].pts,
+        samples[1].dts + u32_at(&segment, fields(1) + 8) as u64
+    );
+
+    // same samples from a self-contained stream, whose tfdt boxes restart the decode time
+    let data = [&init, &segment, &segment[..]].concat();
+    assert_eq!(
+        self::samples(&data, None),
+        [samples.clone(), samples].concat()
+    );
+}
+
+#[test]
+fn decrypt_lazily() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+
+    // second fragment of an unknown track, which fails to decrypt
+    let mut unknown = segment.clone();
+    unknown[32 + 12..32 + 16].copy_from_slice(&9_u32.to_be_bytes());
+    let data = [&segment, &unknown[..]].concat();
+
+    let keys = keys();
+    let mut samples = mp4decrypt::decrypt_samples(&data, Some(&init), &keys, 1).unwrap();
+    let count = self::samples(&segment, Some(&init)).len();
+    assert!(samples.by_ref().take(count).all(|x| x.is_ok()));
+
+    let error = samples.next().unwrap().unwrap_err();
+    assert!(matches!(error.err_type, ErrorType::InitMediaMismatch));
+    assert!(samples.next().is_none());
+
+    // unknown track and missing init segment
+    assert!(mp4decrypt::decrypt_samples(&segment, Some(&init), &keys, 2).is_err());
+    let error = mp4decrypt::decrypt_samples(&segment, None, &keys, 1)
+        .err()
+        .unwrap();
+    assert!(matches!(error.err_type, ErrorType::MissingInitSegment));
+}
@@ -0,0 +1,188 @@
+//! `pssh` boxes stripped from the `moov` and `moof` boxes of the decrypted output, whose sample offsets
+//! must be moved by the removed bytes.
+
+mod common;
+
+use common::{MATRIX, be32, full_box, keys, mp4_box, read, u32_at};
+use mp4decrypt::{DecryptOptions, KeyMap};
+
+const SAMPLE_COUNT: u32 = 10;
+const SAMPLE_SIZE: u32 = 16;
+
+fn add_u32(data: &mut [u8], offset: usize, value: u32) {
+    let value = u32_at(data, offset) + value;
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// A version 0 Widevine `pssh` box.
+fn pssh() -> Vec<u8> {
+    let mut payload = vec![0xed, 0xef, 0x8b, 0xa9, 0x79, 0xd6, 0x4a, 0xce];
+    payload.extend([0xa3, 0xc8, 0x27, 0xdc, 0xd5, 0x1d, 0x21, 0xed]);
+    payload.extend(be32(&[4]));
+    payload.extend(b"data");
+    full_box(b"pssh", 0, 0, &payload)
+}
+
+fn strip(data: &[u8], keys: &KeyMap, init: Option<&[u8]>) -> Vec<u8> {
+    let options = DecryptOptions::new()
+        .strip_pssh(true)
+        .validate_structure(true);
+    mp4decrypt::mp4decrypt_with_options(data, keys, init, &options).unwrap()
+}
+
+#[test]
+fn strip_moov_pssh() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let data = [init.clone(), segment.clone()].concat();
+    let options = DecryptOptions::new();
+    let decrypted = mp4decrypt::mp4decrypt_with_options(&data, &keys(), None, &options).unwrap();
+    assert_eq!(mp4decrypt::extract_pssh(&decrypted).unwrap().len(), 2);
+
+    // both pssh boxes are at the end of the moov box, which follows the ftyp box
+    let moov = u32_at(&decrypted, 0) as usize;
+    let pssh = moov + u32_at(&decrypted, moov) as usize - 802 - 91;
+    assert_eq!(&decrypted[pssh + 4..pssh + 8], b"pssh");
+
+    let mut expected = decrypted.clone();
+    expected.drain(pssh..pssh + 802 + 91);
+    let moov_size = u32_at(&expected, moov) - 802 - 91;
+    expected[moov..moov + 4].copy_from_slice(&moov_size.to_be_bytes());
+
+    let stripped = strip(&data, &keys(), None);
+    assert!(mp4decrypt::extract_pssh(&stripped).unwrap().is_empty());
+    assert_eq!(stripped, expected);
+
+    // disabled by default, and nothing to strip from media segments
+    assert_eq!(
+        mp4decrypt::mp4decrypt_with_options(&segment, &keys(), Some(&init), &options).unwrap(),
+        strip(&segment, &keys(), Some(&init))
+    );
+}
+
+#[test]
+fn strip_moof_pssh() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let expected =
+        mp4decrypt::mp4decrypt_with_options(&segment, &keys(), Some(&init), &DecryptOptions::new())
+            .unwrap();
+
+    // pssh box after the mfhd box, moving the moof relative data offsets of the trun and saio boxes
+    let pssh = pssh();
+    let mut with_pssh = [&segment[..24], &pssh, &segment[24..]].concat();
+    let size = pssh.len() as u32;
+    add_u32(&mut with_pssh, 0, size); // moof
+    add_u32(&mut with_pssh, 72 + size as usize + 16, size); // trun data_offset
+    add_u32(&mut with_pssh, 1413 + size as usize + 16, size); // saio offset
+
+    let decrypted = mp4decrypt::mp4decrypt_with_options(
+        &with_pssh,
+        &keys(),
+        Some(&init),
+        &DecryptOptions::new(),
+    )
+    .unwrap();
+    assert_eq!(mp4decrypt::extract_pssh(&decrypted).unwrap().len(), 1);
+    assert_eq!(strip(&with_pssh, &keys(), Some(&init)), expected);
+
+    let mut sink = Vec::new();
+    let options = DecryptOptions::new().strip_pssh(true);
+    mp4decrypt::mp4decrypt_into_with_options(&with_pssh, &keys(), Some(&init), &options, &mut sink)
+        .unwrap();
+    assert_eq!(sink, expected);
+}
+
+/// Non-fragmented file with a clear audio track whose samples are stored in a single chunk after the
+/// `moov` box.
+fn file(with_pssh: bool) -> Vec<u8> {
+    let file = |chunk_offset: u32| {
+        let mut mvhd = be32(&[0, 0, 1000, 0, 0x10000]);
+        mvhd.extend([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // volume, reserved
+        mvhd.extend(be32(&MATRIX));
+        mvhd.extend([0; 24]);
+        mvhd.extend(be32(&[2]));
+
+        let mut tkhd = be32(&[0, 0, 1, 0, 0, 0, 0]);
+        tkhd.extend([0, 0, 0, 0, 1, 0, 0, 0]); // layer, alternate_group, volume, reserved
+        tkhd.extend(be32(&MATRIX));
+        tkhd.extend(be32(&[0, 0]));
+        let tkhd = mp4_box(b"tkhd", &[&[0, 0, 0, 3][..], &tkhd].concat());
+
+        let mut hdlr = be32(&[0]);
+        hdlr.extend(b"soun");
+        hdlr.extend([0; 13]);
+
+        let mut mp4a = vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        mp4a.extend([0, 2, 0, 16, 0, 0, 0, 0]); // channel_count, sample_size, pre_defined, reserved
+        mp4a.extend(be32(&[48000 << 16]));
+
+        let mut stbl = full_box(
+            b"stsd",
+            0,
+            0,
+            &[&be32(&[1])[..], &mp4_box(b"mp4a", &mp4a)].concat(),
+        );
+        stbl.extend(full_box(b"stts", 0, 0, &be32(&[1, SAMPLE_COUNT, 1024])));
+        stbl.extend(full_box(b"stsc", 0, 0, &be32(&[1, 1, SAMPLE_COUNT, 1])));
+        stbl.extend(full_box(b"stsz", 0, 0, &be32(&[SAMPLE_SIZE, SAMPLE_COUNT])));
+        stbl.extend(full_box(b"stco", 0, 0, &be32(&[1, chunk_offset])));
+
+        let dref = full_box(
+            b"dref",
+            0,
+            0,
+            &[&be32(&[1])[..], &mp4_box(b"url ", &[0, 0, 0, 1])].concat(),
+        );
+        let mut minf = full_box(b"smhd", 0, 0, &[0; 4]);
+        minf.extend(mp4_box(b"dinf", &dref));
+        minf.extend(mp4_box(b"stbl", &stbl));
+
+        let mut mdia = full_box(
+            b"mdhd",
+            0,
+            0,
+            &[
+                &be32(&[0, 0, 48000, SAMPLE_COUNT * 1024])[..],
+                &[0x55, 0xc4, 0, 0],
+            ]
+            .concat(),
+        );
+        mdia.extend(full_box(b"hdlr", 0, 0, &hdlr));
+        mdia.extend(mp4_box(b"minf", &minf));
+
+        let mut trak = tkhd;
+        trak.extend(mp4_box(b"mdia", &mdia));
+
+        let mut moov = full_box(b"mvhd", 0, 0, &mvhd);
+        if with_pssh {
+            moov.extend(pssh());
+        }
+        moov.extend(mp4_box(b"trak", &trak));
+
+        let mut data = mp4_box(b"ftyp", b"isom\0\0\0\0isom");
+        data.extend(mp4_box(b"moov", &moov));
+        data.extend(mp4_box(
+            b"mdat",
+            &(0..SAMPLE_COUNT * SAMPLE_SIZE)
+                .map(|x| x as u8)
+                .collect::<Vec<_>>(),
+        ));
+        data
+    };
+
+    file(file(0).len() as u32 - SAMPLE_COUNT * SAMPLE_SIZE)
+}
+
+#[test]
+fn strip_non_fragmented_pssh() {
+    let options = DecryptOptions::new();
+    let expected =
+        mp4decrypt::mp4decrypt_with_options(&file(false), &KeyMap::new(), None, &options).unwrap();
+    let decrypted =
+        mp4decrypt::mp4decrypt_with_options(&file(true), &KeyMap::new(), None, &options).unwrap();
+    assert_eq!(mp4decrypt::extract_pssh(&decrypted).unwrap().len(), 1);
+
+    // the chunk offset of the stco box is moved by the removed pssh box
+    assert_eq!(strip(&file(true), &KeyMap::new(), None), expected);
+}
@@ -0,0 +1,58 @@
+#![cfg(feature = "testing")]
+
+mod common;
+
+use common::{keys, read};
+use mp4decrypt::{DecryptOptions, testing};
+
+fn panic_message(f: impl FnOnce() + std::panic::UnwindSafe) -> String {
+    let payload = std::panic::catch_unwind(f).unwrap_err();
+    payload.downcast_ref::<String>().unwrap().clone()
+}
+
+#[test]
+fn mismatching_output() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let options = DecryptOptions::new();
+    let expected =
+        mp4decrypt::mp4decrypt_with_options(&segment, &keys(), Some(&init), &options).unwrap();
+
+    let moof_size = u32::from_be_bytes(expected[..4].try_into().unwrap()) as usize;
+    let mut changed = expected.clone();
+    changed[moof_size + 100] ^= 1;
+
+    let message = panic_message(|| {
+        testing::assert_decrypts_to(&segment, &keys(), Some(&init), &changed);
+    });
+    assert_eq!(
+        message,
+        format!(
+            "decrypted output doesn't match the expected output, first difference at offset {}, \
+             got 0x{:02x} but expected 0x{:02x} (mdat at offset {}).",
+            moof_size + 100,
+            expected[moof_size + 100],
+            changed[moof_size + 100],
+            moof_size
+        )
+    );
+
+    // truncated expected output
+    let difference = testing::first_difference(&expected, &expected[..moof_size]).unwrap();
+    assert_eq!(
+        difference,
+        format!(
+            "output is {} bytes but expected {} bytes, the shorter one ends at offset {} (mdat at offset {}).",
+            expected.len(),
+            moof_size,
+            moof_size,
+            moof_size
+        )
+    );
+
+    // no init segment
+    let message = panic_message(|| {
+        testing::assert_decrypts_to(&segment, &keys(), None, &expected);
+    });
+    assert!(message.starts_with("decryption failed: "));
+}
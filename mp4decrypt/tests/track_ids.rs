@@ -0,0 +1,84 @@
+//! Tracks of a decrypted data stream renumbered with `remap_track_ids`, whose metadata and fragments must
+//! still refer to the same tracks.
+
+mod common;
+
+use common::{keys, read, u32_at};
+use mp4decrypt::{DecryptOptions, KeyMap, Mp4Metadata};
+use std::collections::HashMap;
+
+fn decrypted() -> Vec<u8> {
+    let keys = keys();
+
+    let data = [read("init.mp4"), read("segment_0.m4s")].concat();
+    mp4decrypt::mp4decrypt_with_options(&data, &keys, None, &DecryptOptions::new()).unwrap()
+}
+
+/// Offset of the first box of the given type, found by its type alone.
+fn find(data: &[u8], box_type: &[u8; 4]) -> usize {
+    data.windows(4).position(|x| x == box_type).unwrap() - 4
+}
+
+#[test]
+fn remap_track_ids() {
+    let mut decrypted = decrypted();
+    // next_track_ID of the mvhd box (version 0), which is unknown (all 1s) in the sample
+    let next_track_id = find(&decrypted, b"mvhd") + 12 + 16 + 76;
+    assert_eq!(u32_at(&decrypted, next_track_id), u32::MAX);
+    decrypted[next_track_id..next_track_id + 4].copy_from_slice(&2_u32.to_be_bytes());
+
+    let mut remapped = decrypted.clone();
+    mp4decrypt::remap_track_ids(&mut remapped, &HashMap::from([(1, 7), (2, 9)])).unwrap();
+    assert_eq!(remapped.len(), decrypted.len());
+
+    let before = Mp4Metadata::parse(&decrypted).unwrap();
+    let after = Mp4Metadata::parse(&remapped).unwrap();
+    assert_eq!(after.tracks().len(), 1);
+    assert_eq!(after.tracks()[0].track_id, 7);
+    assert_eq!(after.tracks()[0].timescale, before.tracks()[0].timescale);
+    assert_eq!(
+        after.tracks()[0].sample_entries,
+        before.tracks()[0].sample_entries
+    );
+
+    // next_track_ID is raised, and the trex and tfhd boxes follow the track
+    assert_eq!(u32_at(&remapped, next_track_id), 8);
+    assert_eq!(u32_at(&remapped, find(&remapped, b"trex") + 12), 7);
+    assert_eq!(u32_at(&remapped, find(&remapped, b"tfhd") + 12), 7);
+
+    // fragments are still found for the renumbered track
+    let split = mp4decrypt::decrypt_split_tracks(&remapped, None, &KeyMap::new()).unwrap();
+    let mut expected = mp4decrypt::decrypt_split_tracks(&decrypted, None, &KeyMap::new())
+        .unwrap()
+        .remove(&1)
+        .unwrap();
+    mp4decrypt::remap_track_ids(&mut expected, &HashMap::from([(1, 7)])).unwrap();
+    assert_eq!(split[&7], expected);
+
+    // every period is renumbered on its own
+    let mut periods = [&decrypted[..], &decrypted].concat();
+    mp4decrypt::remap_track_ids(&mut periods, &HashMap::from([(1, 7)])).unwrap();
+    assert_eq!(periods, [&remapped[..], &remapped].concat());
+}
+
+#[test]
+fn duplicate_track_ids() {
+    let decrypted = decrypted();
+
+    // moov box holding the trak box twice
+    let moov = find(&decrypted, b"moov");
+    let trak = find(&decrypted, b"trak");
+    let trak_size = u32_at(&decrypted, trak) as usize;
+    let mut data = [
+        &decrypted[..trak + trak_size],
+        &decrypted[trak..trak + trak_size],
+        &decrypted[trak + trak_size..],
+    ]
+    .concat();
+    let moov_size = u32_at(&data, moov) + trak_size as u32;
+    data[moov..moov + 4].copy_from_slice(&moov_size.to_be_bytes());
+
+    let error = mp4decrypt::remap_track_ids(&mut data.clone(), &HashMap::new()).unwrap_err();
+    assert!(error.msg.contains("track id 1"));
+    assert!(mp4decrypt::remap_track_ids(&mut data, &HashMap::from([(1, 2)])).is_err());
+}
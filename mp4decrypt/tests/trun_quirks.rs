@@ -0,0 +1,106 @@
+//! Fragments with data offsets which some packagers write differently from the specification, located the
+//! same way by the parsing helpers as by Bento4.
+
+mod common;
+
+use common::{keys, read, set_u32, u32_at};
+use mp4decrypt::{DecryptOptions, Sample};
+
+// Offsets of the boxes of the sample media segment.
+const TRAF: usize = 24;
+const TFHD: usize = 32;
+const TRUN: usize = 72;
+const SAIO: usize = 1413;
+const MDAT: usize = 4705;
+
+/// Resize the `moof`, `traf` and a child box of the media segment, shifting the `saio` offset along.
+fn resize(segment: &mut [u8], child: usize, change: i32) {
+    for offset in [0, TRAF, child] {
+        let size = u32_at(segment, offset).wrapping_add_signed(change);
+        set_u32(segment, offset, size);
+    }
+
+    let saio_entry = (SAIO as isize + change as isize) as usize + 16;
+    let offset = u32_at(segment, saio_entry).wrapping_add_signed(change);
+    set_u32(segment, saio_entry, offset);
+}
+
+/// `trun` box without a data offset, its samples start at the `mdat` box following the `moof` box.
+fn without_data_offset(segment: &[u8]) -> Vec<u8> {
+    let data_offset = TRUN + 16;
+    let mut quirky = [&segment[..data_offset], &segment[data_offset + 4..]].concat();
+    quirky[TRUN + 11] &= !0x01;
+    resize(&mut quirky, TRUN, -4);
+    quirky
+}
+
+/// `trun` box with a data offset of 0, read by Bento4 the same as a missing one.
+fn zero_data_offset(segment: &[u8]) -> Vec<u8> {
+    let mut quirky = segment.to_vec();
+    set_u32(&mut quirky, TRUN + 16, 0);
+    quirky
+}
+
+/// `tfhd` box with an absolute base data offset and without the default-base-is-moof flag, the `saio` box
+/// still relative to the `moof` box.
+fn absolute_base_data_offset(segment: &[u8]) -> Vec<u8> {
+    let base_data_offset = (MDAT + 8 + 8) as u64;
+    let track_id = TFHD + 16;
+    let mut quirky = [
+        &segment[..track_id],
+        &base_data_offset.to_be_bytes(),
+        &segment[track_id..],
+    ]
+    .concat();
+    set_u32(&mut quirky, TFHD + 8, 0x000021);
+    set_u32(&mut quirky, TRUN + 8 + 16, 0);
+    resize(&mut quirky, TFHD, 8);
+    quirky
+}
+
+fn samples(data: &[u8], init: &[u8]) -> Vec<Sample> {
+    mp4decrypt::decrypt_samples(data, Some(init), &keys(), 1)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+#[test]
+fn quirky_data_offsets() {
+    let (init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+    let expected = samples(&segment, &init);
+    let expected_ivs = &segment[SAIO + 36..SAIO + 44];
+
+    for (name, quirky) in [
+        ("without data offset", without_data_offset(&segment)),
+        ("zero data offset", zero_data_offset(&segment)),
+        (
+            "absolute base data offset",
+            absolute_base_data_offset(&segment),
+        ),
+    ] {
+        // samples of the data stream decrypted by Bento4
+        assert!(samples(&quirky, &init) == expected, "{}", name);
+
+        // IVs in the senc box
+        let aux_info = mp4decrypt::read_aux_info(&quirky, Some(&init))
+            .unwrap()
+            .unwrap();
+        let offset = aux_info.tracks[0].absolute_offsets().next().unwrap() as usize;
+        assert_eq!(&quirky[offset..offset + 8], expected_ivs, "{}", name);
+
+        let options = DecryptOptions::new().validate_structure(true);
+        let decrypted_data =
+            mp4decrypt::mp4decrypt_with_options(&quirky, &keys(), Some(&init), &options).unwrap();
+        let decrypted_segment =
+            mp4decrypt::mp4decrypt_with_options(&segment, &keys(), Some(&init), &options).unwrap();
+        // sample data of the mdat box
+        let payload_size = segment.len() - MDAT - 8;
+        assert!(
+            decrypted_data[decrypted_data.len() - payload_size..]
+                == decrypted_segment[decrypted_segment.len() - payload_size..],
+            "{}",
+            name
+        );
+    }
+}
@@ -0,0 +1,32 @@
+//! Streams ending in the middle of a box header.
+
+mod common;
+
+use common::{keys, read};
+use mp4decrypt::DecryptOptions;
+
+#[test]
+fn partial_box_header() {
+    let init = read("init.mp4");
+    let segment = read("segment_0.m4s");
+    let data = [init, segment].concat();
+    let options = DecryptOptions::new();
+
+    let mut decrypted = Vec::new();
+    mp4decrypt::decrypt_stream(data.as_slice(), &mut decrypted, &keys(), &options).unwrap();
+    assert!(!decrypted.is_empty());
+
+    for trailing in 1..8 {
+        let truncated = [&data[..], &[0; 7][..trailing]].concat();
+        let error = mp4decrypt::decrypt_stream(truncated.as_slice(), Vec::new(), &keys(), &options)
+            .unwrap_err();
+        assert!(error.is_format_error());
+        assert_eq!(
+            error.msg,
+            format!(
+                "expected a box header of 8 bytes but the stream ended after {} bytes.",
+                trailing
+            )
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! Verification of a directory holding an init segment, media segments and other files.
+
+mod common;
+
+use common::{KEY, KID};
+use mp4decrypt::{KeyMap, VerifyResult};
+use std::{fs, path::PathBuf};
+
+fn read(path: &str) -> Vec<u8> {
+    fs::read(format!(
+        "{}/examples/sample/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        path
+    ))
+    .unwrap()
+}
+
+fn keys(key: &str) -> KeyMap {
+    let mut keys = KeyMap::new();
+    keys.insert(KID, key).unwrap();
+    keys
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mp4decrypt-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("subdir")).unwrap();
+    dir
+}
+
+#[test]
+fn segments_are_verified() {
+    let (init, segment) = (read("init.mp4"), read("segment_0.m4s"));
+    let dir = temp_dir("verify-directory");
+
+    fs::write(dir.join("init.mp4"), &init).unwrap();
+    fs::write(dir.join("manifest.mpd"), "<?xml version=\"1.0\"?>").unwrap();
+    fs::write(dir.join("segment_0.m4s"), &segment).unwrap();
+    fs::write(dir.join("segment_1.m4s"), &segment[..segment.len() - 100]).unwrap();
+    fs::write(dir.join("full.mp4"), [&init, &segment[..]].concat()).unwrap();
+    fs::write(dir.join("subdir/segment_2.m4s"), &segment).unwrap();
+
+    let results = mp4decrypt::verify_directory(&dir, &keys(KEY)).unwrap();
+    let names = results
+        .iter()
+        .map(|(x, _)| x.file_name().unwrap().to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["full.mp4", "segment_0.m4s", "segment_1.m4s"]);
+    assert!(results[0].1.is_passed());
+    assert!(results[1].1.is_passed());
+    assert!(matches!(results[2].1, VerifyResult::Failed(_)));
+
+    // wrong key
+    let results = mp4decrypt::verify_directory(&dir, &keys(&KEY.replace('b', "c"))).unwrap();
+    assert!(results.iter().all(|(_, x)| !x.is_passed()));
+
+    fs::write(dir.join("init_1.mp4"), &init).unwrap();
+    assert!(mp4decrypt::verify_directory(&dir, &keys(KEY)).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}